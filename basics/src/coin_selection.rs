@@ -0,0 +1,585 @@
+use std::fmt;
+
+use bitcoincore_rpc::bitcoin::consensus::encode::{serialize, VarInt};
+use bitcoincore_rpc::bitcoin::{OutPoint, Script, TxOut};
+use bitcoincore_rpc::json::ListUnspentResultEntry;
+use secp256k1::rand::seq::SliceRandom;
+use secp256k1::rand::thread_rng;
+
+// Bitcoin Core's default, in sat/kvB
+const DUST_RELAY_FEE: u64 = 3_000;
+// Branch and bound gives up after this many steps
+const BNB_MAX_TRIES: usize = 100_000;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Algorithm {
+    // Looks for inputs adding up to the target without change, and falls
+    // back to `LargestFirst` when there are none
+    BranchAndBound,
+    LargestFirst,
+    // Random picks, then more random picks while they bring the inputs
+    // closer to twice the target
+    RandomImprove,
+}
+
+#[derive(Clone, Debug)]
+pub struct Utxo {
+    pub outpoint: OutPoint,
+    pub txout: TxOut,
+    // Weight of the input spending this, signatures included
+    pub weight: usize,
+}
+
+impl Utxo {
+    // `None` for outputs the wallet doesn't know how to weigh
+    pub fn from_unspent(entry: &ListUnspentResultEntry) -> Option<Utxo> {
+        Some(Utxo {
+            outpoint: OutPoint::new(entry.txid, entry.vout),
+            txout: TxOut {
+                value: entry.amount.to_sat(),
+                script_pubkey: entry.script_pub_key.clone(),
+            },
+            weight: input_weight(&entry.script_pub_key)?,
+        })
+    }
+
+    // What it adds once paying for its own input
+    fn effective_value(&self, feerate: f64) -> i64 {
+        self.txout.value as i64 - fee(self.weight, feerate) as i64
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Selection {
+    pub inputs: Vec<Utxo>,
+    // Value of the change output, if there's one
+    pub change: Option<u64>,
+    pub fee: u64,
+    // Of the whole tx, signed, change output included
+    pub weight: usize,
+}
+
+#[derive(Clone, Debug)]
+pub enum SelectionError {
+    InsufficientFunds { needed: u64, available: u64 },
+    // Change to a script `input_weight` can't weigh
+    UnsupportedChangeScript(Script),
+}
+
+impl fmt::Display for SelectionError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SelectionError::InsufficientFunds { needed, available } => write!(
+                f,
+                "Insufficient funds: need {} sat, have {} sat",
+                needed, available
+            ),
+            SelectionError::UnsupportedChangeScript(script) => {
+                write!(f, "Can't weigh an input spending change script {:x}", script)
+            }
+        }
+    }
+}
+
+impl std::error::Error for SelectionError {}
+
+// Weight of an input spending `script_pubkey`, with the largest signatures
+// the wallet makes. P2SH is taken to be P2SH-P2WPKH, the only kind the wallet
+// has keys for.
+pub fn input_weight(script_pubkey: &Script) -> Option<usize> {
+    // Outpoint, nSequence and the scriptSig length
+    let base = 32 + 4 + 4 + 1;
+    let (script_sig, witness) = if script_pubkey.is_v0_p2wpkh() {
+        // Items count, then a 72 byte signature and a 33 byte key
+        (0, 1 + 73 + 34)
+    } else if script_pubkey.is_v1_p2tr() {
+        // A key path spend with the default sighash
+        (0, 1 + 65)
+    } else if script_pubkey.is_p2sh() {
+        (23, 1 + 73 + 34)
+    } else if script_pubkey.is_p2pkh() {
+        // Legacy inputs in a segwit tx still have an empty witness
+        (73 + 34, 1)
+    } else {
+        return None;
+    };
+    Some((base + script_sig) * 4 + witness)
+}
+
+// Weight of an input spending an `m` of n `redeem_script` behind
+// `script_pubkey`, which can be P2SH, P2WSH or P2SH-P2WSH
+pub fn multisig_input_weight(
+    script_pubkey: &Script,
+    redeem_script: &Script,
+    m: usize,
+) -> Option<usize> {
+    // Outpoint and nSequence
+    let base = 32 + 4 + 4;
+    // The empty CHECKMULTISIG dummy, then a 72 byte signature each
+    let sigs = 1 + m * 73;
+    let witness = VarInt(m as u64 + 2).len()
+        + sigs
+        + VarInt(redeem_script.len() as u64).len()
+        + redeem_script.len();
+    let p2wsh = redeem_script.to_v0_p2wsh();
+    if *script_pubkey == redeem_script.to_p2sh() {
+        let push = match redeem_script.len() {
+            0..=75 => 1,
+            76..=255 => 2,
+            _ => 3,
+        };
+        let script_sig = sigs + push + redeem_script.len();
+        Some((base + VarInt(script_sig as u64).len() + script_sig) * 4 + 1)
+    } else if *script_pubkey == p2wsh {
+        Some((base + 1) * 4 + witness)
+    } else if *script_pubkey == p2wsh.to_p2sh() {
+        // The scriptSig pushes the 34 byte P2WSH program
+        Some((base + 1 + 35) * 4 + witness)
+    } else {
+        None
+    }
+}
+
+// Weight of a tx spending `inputs` to `outputs`, once signed
+pub fn tx_weight(inputs: &[Utxo], outputs: &[TxOut]) -> usize {
+    let base = 4
+        + VarInt(inputs.len() as u64).len()
+        + VarInt(outputs.len() as u64).len()
+        + outputs.iter().map(|o| serialize(o).len()).sum::<usize>()
+        + 4;
+    // Segwit marker and flag
+    base * 4 + 2 + inputs.iter().map(|i| i.weight).sum::<usize>()
+}
+
+// `feerate` is in sat/vB
+pub fn fee(weight: usize, feerate: f64) -> u64 {
+    (weight as f64 * feerate / 4.0).ceil() as u64
+}
+
+// Outputs below this aren't relayed, the same way Bitcoin Core works it out
+pub fn dust_threshold(script_pubkey: &Script) -> u64 {
+    let txout = TxOut {
+        value: 0,
+        script_pubkey: script_pubkey.clone(),
+    };
+    let spend_size = if script_pubkey.is_witness_program() {
+        32 + 4 + 1 + 107 / 4 + 4
+    } else {
+        32 + 4 + 1 + 107 + 4
+    };
+    (serialize(&txout).len() + spend_size) as u64 * DUST_RELAY_FEE / 1000
+}
+
+// Picks inputs out of `utxos` paying for `outputs` at `feerate` sat/vB, with
+// change to `change_script` unless it would be dust or cost more than it's
+// worth
+pub fn select(
+    utxos: &[Utxo],
+    outputs: &[TxOut],
+    change_script: &Script,
+    feerate: f64,
+    algorithm: Algorithm,
+) -> Result<Selection, SelectionError> {
+    // Inputs with a negative effective value only make things worse
+    let candidates: Vec<Utxo> = utxos
+        .iter()
+        .filter(|u| u.effective_value(feerate) > 0)
+        .cloned()
+        .collect();
+    let change_output = TxOut {
+        value: 0,
+        script_pubkey: change_script.clone(),
+    };
+    let change_weight = serialize(&change_output).len() * 4;
+    // The change output now, and the input spending it later
+    let change_input_weight = input_weight(change_script)
+        .ok_or_else(|| SelectionError::UnsupportedChangeScript(change_script.clone()))?;
+    let cost_of_change = fee(change_weight, feerate) + fee(change_input_weight, feerate);
+
+    // The effective values of the inputs have to cover this
+    let output_value: u64 = outputs.iter().map(|o| o.value).sum();
+    let mut target = output_value + fee(tx_weight(&[], outputs), feerate);
+    let available: i64 = candidates.iter().map(|u| u.effective_value(feerate)).sum();
+    let (inputs, input_value, weight, excess) = loop {
+        if available < target as i64 {
+            return Err(SelectionError::InsufficientFunds {
+                needed: target,
+                available: available.max(0) as u64,
+            });
+        }
+
+        let selected = match algorithm {
+            Algorithm::BranchAndBound => {
+                branch_and_bound(&candidates, target, cost_of_change, feerate)
+                    .unwrap_or_else(|| largest_first(&candidates, target, feerate))
+            }
+            Algorithm::LargestFirst => largest_first(&candidates, target, feerate),
+            Algorithm::RandomImprove => random_improve(&candidates, target, feerate),
+        };
+
+        let inputs: Vec<Utxo> = selected
+            .into_iter()
+            .map(|i| candidates[i].clone())
+            .collect();
+        let input_value: u64 = inputs.iter().map(|u| u.txout.value).sum();
+        let weight = tx_weight(&inputs, outputs);
+        let needed = output_value + fee(weight, feerate);
+        match input_value.checked_sub(needed) {
+            Some(excess) => break (inputs, input_value, weight, excess),
+            // Past 252 inputs their count takes 3 bytes instead of the 1 the
+            // target allows for, so try again aiming that much higher
+            None => target += needed - input_value,
+        }
+    };
+
+    let change_fee = fee(change_weight, feerate);
+    let change = excess.saturating_sub(change_fee);
+    if excess > cost_of_change && change >= dust_threshold(change_script) {
+        Ok(Selection {
+            inputs,
+            change: Some(change),
+            fee: input_value - output_value - change,
+            weight: weight + change_weight,
+        })
+    } else {
+        // Too little to bother, it goes to the miners
+        Ok(Selection {
+            inputs,
+            change: None,
+            fee: input_value - output_value,
+            weight,
+        })
+    }
+}
+
+// Indices of the largest inputs, just enough of them
+fn largest_first(utxos: &[Utxo], target: u64, feerate: f64) -> Vec<usize> {
+    let mut order: Vec<usize> = (0..utxos.len()).collect();
+    order.sort_by_key(|i| -utxos[*i].effective_value(feerate));
+    take_until(&order, utxos, target, feerate)
+}
+
+fn take_until(order: &[usize], utxos: &[Utxo], target: u64, feerate: f64) -> Vec<usize> {
+    let mut selected = Vec::new();
+    let mut total = 0;
+    for i in order {
+        if total >= target as i64 {
+            break;
+        }
+        total += utxos[*i].effective_value(feerate);
+        selected.push(*i);
+    }
+    selected
+}
+
+// Depth first search for inputs adding up to between `target` and
+// `target + cost_of_change`, preferring the least waste
+fn branch_and_bound(
+    utxos: &[Utxo],
+    target: u64,
+    cost_of_change: u64,
+    feerate: f64,
+) -> Option<Vec<usize>> {
+    let mut order: Vec<usize> = (0..utxos.len()).collect();
+    order.sort_by_key(|i| -utxos[*i].effective_value(feerate));
+    let values: Vec<i64> = order
+        .iter()
+        .map(|i| utxos[*i].effective_value(feerate))
+        .collect();
+    // What's left from each depth on, to prune branches that can't make it
+    let mut remaining = vec![0; values.len() + 1];
+    for i in (0..values.len()).rev() {
+        remaining[i] = remaining[i + 1] + values[i];
+    }
+
+    let mut search = Bnb {
+        values: &values,
+        remaining,
+        target: target as i64,
+        upper: (target + cost_of_change) as i64,
+        tries: 0,
+        current: Vec::new(),
+        best: None,
+    };
+    search.explore(0, 0);
+    search
+        .best
+        .map(|(_, picked)| picked.iter().map(|i| order[*i]).collect())
+}
+
+struct Bnb<'a> {
+    values: &'a [i64],
+    remaining: Vec<i64>,
+    target: i64,
+    upper: i64,
+    tries: usize,
+    current: Vec<usize>,
+    // Waste and picks of the best match so far
+    best: Option<(i64, Vec<usize>)>,
+}
+
+impl<'a> Bnb<'a> {
+    fn explore(&mut self, depth: usize, value: i64) {
+        self.tries += 1;
+        if self.tries > BNB_MAX_TRIES
+            || matches!(self.best, Some((0, _)))
+            || value > self.upper
+            || value + self.remaining[depth] < self.target
+        {
+            return;
+        }
+        if value >= self.target {
+            let waste = value - self.target;
+            if self.best.as_ref().is_none_or(|(w, _)| waste < *w) {
+                self.best = Some((waste, self.current.clone()));
+            }
+            return;
+        }
+
+        // With the input at `depth`, then without. Running out of inputs
+        // gets pruned above.
+        self.current.push(depth);
+        self.explore(depth + 1, value + self.values[depth]);
+        self.current.pop();
+        self.explore(depth + 1, value);
+    }
+}
+
+// Cardano's random improve: random inputs until the target is met, then
+// more while they move the total closer to twice the target without going
+// past three times it
+fn random_improve(utxos: &[Utxo], target: u64, feerate: f64) -> Vec<usize> {
+    let mut order: Vec<usize> = (0..utxos.len()).collect();
+    order.shuffle(&mut thread_rng());
+    let mut selected = take_until(&order, utxos, target, feerate);
+    let mut total: i64 = selected
+        .iter()
+        .map(|i| utxos[*i].effective_value(feerate))
+        .sum();
+
+    let ideal = 2 * target as i64;
+    let limit = 3 * target as i64;
+    for i in order.iter().skip(selected.len()) {
+        let with = total + utxos[*i].effective_value(feerate);
+        if with <= limit && (ideal - with).abs() < (ideal - total).abs() {
+            selected.push(*i);
+            total = with;
+        }
+    }
+    selected
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bitcoincore_rpc::bitcoin::hashes::hex::FromHex;
+
+    fn script(hex: &str) -> Script {
+        Script::from(Vec::<u8>::from_hex(hex).unwrap())
+    }
+
+    fn p2wpkh() -> Script {
+        script("00140000000000000000000000000000000000000000")
+    }
+
+    fn utxos(values: &[u64]) -> Vec<Utxo> {
+        values
+            .iter()
+            .map(|value| Utxo {
+                outpoint: OutPoint::null(),
+                txout: TxOut {
+                    value: *value,
+                    script_pubkey: p2wpkh(),
+                },
+                weight: input_weight(&p2wpkh()).unwrap(),
+            })
+            .collect()
+    }
+
+    fn output(value: u64) -> TxOut {
+        TxOut {
+            value,
+            script_pubkey: p2wpkh(),
+        }
+    }
+
+    fn values(selection: &Selection) -> Vec<u64> {
+        let mut values: Vec<u64> = selection.inputs.iter().map(|u| u.txout.value).collect();
+        values.sort_unstable();
+        values
+    }
+
+    // Whatever the inputs bring in is paid out, as change or as fee
+    fn assert_balanced(selection: &Selection, output_value: u64) {
+        let input_value: u64 = selection.inputs.iter().map(|u| u.txout.value).sum();
+        assert_eq!(
+            input_value,
+            output_value + selection.change.unwrap_or(0) + selection.fee
+        );
+    }
+
+    #[test]
+    fn branch_and_bound_finds_a_changeless_match() {
+        let utxos = utxos(&[50_000, 30_000, 20_000, 1_000_000]);
+        let selection = select(
+            &utxos,
+            &[output(79_800)],
+            &p2wpkh(),
+            1.0,
+            Algorithm::BranchAndBound,
+        )
+        .unwrap();
+        assert_eq!(values(&selection), vec![30_000, 50_000]);
+        assert_eq!(selection.change, None);
+        assert_eq!(selection.fee, 200);
+    }
+
+    #[test]
+    fn branch_and_bound_falls_back_to_largest_first() {
+        let utxos = utxos(&[1_000_000, 500_000]);
+        let selection = select(
+            &utxos,
+            &[output(79_800)],
+            &p2wpkh(),
+            1.0,
+            Algorithm::BranchAndBound,
+        )
+        .unwrap();
+        assert_eq!(values(&selection), vec![1_000_000]);
+        assert!(selection.change.is_some());
+        assert_balanced(&selection, 79_800);
+    }
+
+    #[test]
+    fn largest_first_makes_change() {
+        let utxos = utxos(&[50_000, 30_000, 20_000, 1_000_000]);
+        let selection = select(
+            &utxos,
+            &[output(79_800)],
+            &p2wpkh(),
+            1.0,
+            Algorithm::LargestFirst,
+        )
+        .unwrap();
+        assert_eq!(values(&selection), vec![1_000_000]);
+        assert!(selection.change.is_some());
+        assert_eq!(selection.fee, fee(selection.weight, 1.0));
+        assert_balanced(&selection, 79_800);
+    }
+
+    #[test]
+    fn random_improve_balances() {
+        let utxos = utxos(&[50_000, 30_000, 20_000, 1_000_000, 120_000, 7_000]);
+        for _ in 0..50 {
+            let selection = select(
+                &utxos,
+                &[output(79_800)],
+                &p2wpkh(),
+                2.0,
+                Algorithm::RandomImprove,
+            )
+            .unwrap();
+            assert_balanced(&selection, 79_800);
+        }
+    }
+
+    #[test]
+    fn dust_change_goes_to_the_fee() {
+        let utxos = utxos(&[100_000]);
+        let selection = select(
+            &utxos,
+            &[output(99_700)],
+            &p2wpkh(),
+            1.0,
+            Algorithm::LargestFirst,
+        )
+        .unwrap();
+        assert_eq!(selection.change, None);
+        assert_eq!(selection.fee, 300);
+        assert!(300 - fee(selection.weight, 1.0) < dust_threshold(&p2wpkh()));
+    }
+
+    #[test]
+    fn uneconomical_inputs_are_left_out() {
+        // 50 sat doesn't pay for its own input at 1 sat/vB
+        let utxos = utxos(&[50, 1_000_000]);
+        let selection = select(
+            &utxos,
+            &[output(10_000)],
+            &p2wpkh(),
+            1.0,
+            Algorithm::LargestFirst,
+        )
+        .unwrap();
+        assert_eq!(values(&selection), vec![1_000_000]);
+    }
+
+    #[test]
+    fn insufficient_funds() {
+        let utxos = utxos(&[50_000, 30_000]);
+        let res = select(
+            &utxos,
+            &[output(80_000)],
+            &p2wpkh(),
+            1.0,
+            Algorithm::LargestFirst,
+        );
+        assert!(matches!(
+            res,
+            Err(SelectionError::InsufficientFunds { needed: 80_042, available: 79_864 })
+        ));
+    }
+
+    #[test]
+    fn unsupported_change_script() {
+        let p2wsh = script("00200000000000000000000000000000000000000000000000000000000000000000");
+        let res = select(
+            &utxos(&[1_000_000]),
+            &[output(10_000)],
+            &p2wsh,
+            1.0,
+            Algorithm::LargestFirst,
+        );
+        assert!(matches!(res, Err(SelectionError::UnsupportedChangeScript(s)) if s == p2wsh));
+    }
+
+    #[test]
+    fn multisig_input_weights() {
+        // 2 of 3 with compressed keys
+        let redeem_script = script("5221020000000000000000000000000000000000000000000000000000000000000001210200000000000000000000000000000000000000000000000000000000000000022102000000000000000000000000000000000000000000000000000000000000000353ae");
+        assert_eq!(redeem_script.len(), 105);
+        let p2sh = redeem_script.to_p2sh();
+        let p2wsh = redeem_script.to_v0_p2wsh();
+        // A 147 + 2 + 105 byte scriptSig, long enough for a 3 byte length
+        assert_eq!(
+            multisig_input_weight(&p2sh, &redeem_script, 2),
+            Some((40 + 3 + 254) * 4 + 1)
+        );
+        assert_eq!(
+            multisig_input_weight(&p2wsh, &redeem_script, 2),
+            Some(41 * 4 + 1 + 147 + 1 + 105)
+        );
+        assert_eq!(
+            multisig_input_weight(&p2wsh.to_p2sh(), &redeem_script, 2),
+            Some(76 * 4 + 1 + 147 + 1 + 105)
+        );
+        assert_eq!(multisig_input_weight(&p2wpkh(), &redeem_script, 2), None);
+    }
+
+    // 253 inputs cover the target exactly, until their count takes 3 bytes
+    #[test]
+    fn input_count_varint_is_paid_for() {
+        let utxos = utxos(&[1_000; 300]);
+        let selection = select(
+            &utxos,
+            &[output(235_754)],
+            &p2wpkh(),
+            1.0,
+            Algorithm::LargestFirst,
+        )
+        .unwrap();
+        assert_eq!(selection.inputs.len(), 254);
+        assert!(selection.fee >= fee(selection.weight, 1.0));
+        assert_balanced(&selection, 235_754);
+    }
+}
@@ -0,0 +1,59 @@
+use std::fmt;
+
+use bitcoincore_rpc::bitcoin::Txid;
+
+// Bitcoin Core's default incremental relay fee, in sat/vB. A replacement has
+// to pay at least this much on top of what it replaces.
+pub const INCREMENTAL_RELAY_FEERATE: f64 = 1.0;
+
+#[derive(Debug)]
+pub enum FeeBumpError {
+    // Only txs with an input below 0xfffffffe can be replaced
+    NotReplaceable(Txid),
+    // Nothing of ours to take the extra fee out of
+    NoChange(Txid),
+    // Change to a script `coin_selection::input_weight` can't weigh
+    UnsupportedChange(Txid),
+    // Taking the fee out of the change would leave dust
+    InsufficientChange {
+        txid: Txid,
+        change: u64,
+        needed: u64,
+    },
+    Rpc(String),
+}
+
+impl fmt::Display for FeeBumpError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            FeeBumpError::NotReplaceable(txid) => {
+                write!(f, "{} doesn't signal BIP125 replaceability", txid)
+            }
+            FeeBumpError::NoChange(txid) => write!(f, "{} has no change output", txid),
+            FeeBumpError::UnsupportedChange(txid) => {
+                write!(f, "Can't weigh an input spending the change of {}", txid)
+            }
+            FeeBumpError::InsufficientChange {
+                txid,
+                change,
+                needed,
+            } => write!(
+                f,
+                "Change of {} is {} sat, bumping needs {} sat more",
+                txid, change, needed
+            ),
+            FeeBumpError::Rpc(e) => write!(f, "RPC error: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for FeeBumpError {}
+
+// In sat/vB
+pub fn feerate(fee: u64, vsize: u64) -> f64 {
+    fee as f64 / vsize as f64
+}
+
+pub fn vsize(weight: usize) -> u64 {
+    weight.div_ceil(4) as u64
+}
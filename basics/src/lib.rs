@@ -1,11 +1,501 @@
-use bitcoincore_rpc::Client;
+use std::env;
+use std::path::Path;
+
+use bitcoincore_rpc::bitcoin::{
+    Address, Amount, BlockHash, Network, OutPoint, PackedLockTime, Script, Sequence, Transaction,
+    TxIn, TxOut, Txid, Witness,
+};
+use bitcoincore_rpc::json::GetWalletInfoResult;
+use bitcoincore_rpc::{Auth, Client, RpcApi};
+use serde_json::{json, Value};
+
+pub mod coin_selection;
+pub mod fee_bump;
+pub mod message;
+pub mod regtest;
+pub mod report;
+pub mod test_node;
+pub mod tx_builder;
+pub mod wallet;
+#[cfg(feature = "zmq")]
+pub mod zmq;
+
+use coin_selection::{Algorithm, Selection, SelectionError, Utxo};
+use fee_bump::{FeeBumpError, INCREMENTAL_RELAY_FEERATE};
+use regtest::RegtestControl;
+use report::{Balances, TxRecord, UtxoRecord, WalletReport};
+use tx_builder::BuildError;
+use wallet::{DescriptorImport, WalletError, WalletOptions};
+
+pub use test_node::TestNode;
+pub use wallet::WalletHandle;
+
+// Matches the bitcoind setup the workshop nodes use, see `ln/ln-nodes/*/config`
+pub const RPC_URL: &str = "http://localhost:18443";
+pub const RPC_USER: &str = "user";
+pub const RPC_PASSWORD: &str = "userBTCNode@123";
+// Overrides `RPC_URL`, for a shared node on another port
+pub const RPC_URL_VAR: &str = "BITCOIND_RPC_URL";
+
+// sat/vB, fee estimation has no data to work with in regtest
+pub const DEFAULT_FEERATE: f64 = 2.0;
+// listtransactions caps how many it returns at once
+const HISTORY_PAGE: usize = 1000;
 
 pub trait BitcoinClient {
     fn setup() -> Client;
+
+    // Returns a client with a custom RPC path, e.g. `wallet/<name>`
+    fn with_custom_path(path: &str) -> Client;
+
+    // Loads a wallet in the node, creating it if it doesn't exist. The info
+    // comes from the node at `rpc_url()`, a `TestNode`'s wallets are loaded
+    // with `TestNode::wallet`.
+    fn load_wallet_in_node(&self, wallet_name: &str) -> GetWalletInfoResult;
+
+    // Errors if the wallet already exists
+    fn create_wallet_in_node(
+        &self,
+        wallet_name: &str,
+        options: &WalletOptions,
+    ) -> Result<(), WalletError>;
+
+    // Leaves the wallet loaded, loading it from disk or creating it with
+    // `options` if need be
+    fn ensure_wallet(&self, wallet_name: &str, options: &WalletOptions) -> Result<(), WalletError>;
+
+    fn unload_wallet_in_node(&self, wallet_name: &str) -> Result<(), WalletError>;
+
+    // Restores a backup as `wallet_name` and loads it
+    fn restore_wallet_in_node(&self, wallet_name: &str, backup: &Path) -> Result<(), WalletError>;
+
+    // These two need a client on the wallet's path, like a `WalletHandle`
+    fn import_into_wallet(&self, imports: &[DescriptorImport]) -> Result<(), WalletError>;
+
+    // `path` is on the node's filesystem
+    fn backup_wallet_to(&self, path: &Path) -> Result<(), WalletError>;
+
+    fn get_dough_if_broke(&self);
+
+    fn mine_blocks(&self, n: u64) -> Vec<BlockHash>;
+
+    // Sends `amount` BTC and mines the tx
+    fn transfer(&self, address: &Address, amount: f64) -> Txid;
+
+    // Sends `amount` at `feerate` sat/vB without mining, so the tx can be
+    // bumped while it waits. Its inputs signal BIP125.
+    fn send_unconfirmed(
+        &self,
+        address: &Address,
+        amount: Amount,
+        feerate: f64,
+    ) -> Result<Txid, SelectionError>;
+
+    // Confirmed wallet outputs coin selection knows how to weigh
+    fn spendable_utxos(&self) -> Vec<Utxo>;
+
+    // Picks wallet inputs for `outputs` at `feerate` sat/vB, with change to
+    // a fresh address, and signs the tx with the wallet
+    fn fund_transaction(
+        &self,
+        outputs: Vec<TxOut>,
+        feerate: f64,
+        algorithm: Algorithm,
+    ) -> Result<(Transaction, Selection), SelectionError>;
+
+    // Replaces an unconfirmed wallet tx with one paying `new_feerate` sat/vB,
+    // the extra fee coming out of its change
+    fn bump_fee_rbf(&self, txid: &Txid, new_feerate: f64) -> Result<Txid, FeeBumpError>;
+
+    // Spends the change of an unconfirmed wallet tx with a child paying
+    // enough for both to reach `new_package_feerate` sat/vB
+    fn bump_fee_cpfp(&self, txid: &Txid, new_package_feerate: f64) -> Result<Txid, FeeBumpError>;
+
+    // Feerate of a mempool tx together with its unconfirmed ancestors
+    fn package_feerate(&self, txid: &Txid) -> f64;
+
+    // Broadcasts a tx signed outside the wallet, after checking with
+    // testmempoolaccept so a rejection comes back with its reason
+    fn accept_and_send(&self, tx: &Transaction) -> Result<Txid, BuildError>;
+
+    // History, balances and UTXOs of the wallet, unconfirmed included
+    fn wallet_report(&self) -> WalletReport;
+}
+
+// The node `setup` and `with_custom_path` connect to
+pub fn rpc_url() -> String {
+    env::var(RPC_URL_VAR).unwrap_or_else(|_| RPC_URL.to_owned())
 }
 
 impl BitcoinClient for Client {
     fn setup() -> Self {
-        unimplemented!()
+        Client::new(
+            &rpc_url(),
+            Auth::UserPass(RPC_USER.to_owned(), RPC_PASSWORD.to_owned()),
+        )
+        .expect("Failed to create RPC client")
+    }
+
+    fn with_custom_path(path: &str) -> Self {
+        Client::new(
+            &format!("{}/{}", rpc_url(), path),
+            Auth::UserPass(RPC_USER.to_owned(), RPC_PASSWORD.to_owned()),
+        )
+        .expect("Failed to create RPC client")
+    }
+
+    fn load_wallet_in_node(&self, wallet_name: &str) -> GetWalletInfoResult {
+        self.ensure_wallet(wallet_name, &WalletOptions::default())
+            .unwrap_or_else(|e| panic!("Failed to load {}: {}", wallet_name, e));
+        // With several wallets loaded, wallet RPCs need the wallet path
+        WalletHandle::new(wallet_name)
+            .get_wallet_info()
+            .expect("Failed to get wallet info")
+    }
+
+    fn create_wallet_in_node(
+        &self,
+        wallet_name: &str,
+        options: &WalletOptions,
+    ) -> Result<(), WalletError> {
+        // The client's create_wallet has no say over descriptors
+        let args = [
+            json!(wallet_name),
+            json!(options.disable_private_keys),
+            json!(options.blank),
+            json!(options.passphrase.clone().unwrap_or_default()),
+            json!(false),
+            json!(options.descriptors),
+        ];
+        self.call::<Value>("createwallet", &args)
+            .map(|_| ())
+            .map_err(|e| WalletError::Rpc(e.to_string()))
+    }
+
+    fn ensure_wallet(&self, wallet_name: &str, options: &WalletOptions) -> Result<(), WalletError> {
+        let loaded = self
+            .list_wallets()
+            .map_err(|e| WalletError::Rpc(e.to_string()))?;
+        if loaded.iter().any(|w| w == wallet_name) || self.load_wallet(wallet_name).is_ok() {
+            return Ok(());
+        }
+        // Not on disk either
+        self.create_wallet_in_node(wallet_name, options)
+    }
+
+    fn unload_wallet_in_node(&self, wallet_name: &str) -> Result<(), WalletError> {
+        self.unload_wallet(Some(wallet_name))
+            .map(|_| ())
+            .map_err(|e| WalletError::Rpc(e.to_string()))
+    }
+
+    fn restore_wallet_in_node(&self, wallet_name: &str, backup: &Path) -> Result<(), WalletError> {
+        let args = [json!(wallet_name), json!(backup.display().to_string())];
+        self.call::<Value>("restorewallet", &args)
+            .map(|_| ())
+            .map_err(|e| WalletError::Rpc(e.to_string()))
+    }
+
+    fn import_into_wallet(&self, imports: &[DescriptorImport]) -> Result<(), WalletError> {
+        let requests: Vec<Value> = imports.iter().map(|i| i.to_json()).collect();
+        let results: Vec<Value> = self
+            .call("importdescriptors", &[json!(requests)])
+            .map_err(|e| WalletError::Rpc(e.to_string()))?;
+        // One result per request, in order
+        for (import, result) in imports.iter().zip(results) {
+            if result["success"] != json!(true) {
+                return Err(WalletError::Import {
+                    desc: import.desc.clone(),
+                    error: result["error"]["message"]
+                        .as_str()
+                        .unwrap_or("no reason given")
+                        .to_owned(),
+                });
+            }
+        }
+        Ok(())
+    }
+
+    fn backup_wallet_to(&self, path: &Path) -> Result<(), WalletError> {
+        let path = path.to_str().expect("Backup path isn't UTF-8");
+        self.backup_wallet(Some(path))
+            .map_err(|e| WalletError::Rpc(e.to_string()))
+    }
+
+    fn get_dough_if_broke(&self) {
+        let balance = self.get_balance(None, None).expect("Failed to get balance");
+        if balance > Amount::ZERO {
+            return;
+        }
+        let address = self
+            .get_new_address(None, None)
+            .expect("Failed to get new address");
+        self.mine_past_maturity(&address);
+    }
+
+    fn mine_blocks(&self, n: u64) -> Vec<BlockHash> {
+        let address = self
+            .get_new_address(None, None)
+            .expect("Failed to get new address");
+        self.mine_to(n, &address)
+    }
+
+    fn transfer(&self, address: &Address, amount: f64) -> Txid {
+        let amount = Amount::from_btc(amount).expect("Invalid amount");
+        let txid = self
+            .send_unconfirmed(address, amount, DEFAULT_FEERATE)
+            .unwrap_or_else(|e| panic!("Can't transfer {}: {}", amount, e));
+        self.mine_blocks(1);
+        txid
+    }
+
+    fn send_unconfirmed(
+        &self,
+        address: &Address,
+        amount: Amount,
+        feerate: f64,
+    ) -> Result<Txid, SelectionError> {
+        let output = TxOut {
+            value: amount.to_sat(),
+            script_pubkey: address.script_pubkey(),
+        };
+        let (tx, _) = self.fund_transaction(vec![output], feerate, Algorithm::BranchAndBound)?;
+        Ok(self
+            .send_raw_transaction(&tx)
+            .expect("Failed to send transaction"))
+    }
+
+    fn spendable_utxos(&self) -> Vec<Utxo> {
+        self.list_unspent(Some(1), None, None, None, None)
+            .expect("Failed to list unspent outputs")
+            .iter()
+            .filter(|entry| entry.spendable && entry.safe)
+            .filter_map(Utxo::from_unspent)
+            .collect()
+    }
+
+    fn fund_transaction(
+        &self,
+        mut outputs: Vec<TxOut>,
+        feerate: f64,
+        algorithm: Algorithm,
+    ) -> Result<(Transaction, Selection), SelectionError> {
+        let change_script = change_address(self).script_pubkey();
+        let selection = coin_selection::select(
+            &self.spendable_utxos(),
+            &outputs,
+            &change_script,
+            feerate,
+            algorithm,
+        )?;
+        if let Some(change) = selection.change {
+            outputs.push(TxOut {
+                value: change,
+                script_pubkey: change_script,
+            });
+        }
+
+        let tx = Transaction {
+            version: 2,
+            lock_time: PackedLockTime::ZERO,
+            input: selection
+                .inputs
+                .iter()
+                .map(|utxo| TxIn {
+                    previous_output: utxo.outpoint,
+                    script_sig: Script::new(),
+                    sequence: Sequence::ENABLE_RBF_NO_LOCKTIME,
+                    witness: Witness::default(),
+                })
+                .collect(),
+            output: outputs,
+        };
+        Ok((sign_with_wallet(self, &tx), selection))
+    }
+
+    fn bump_fee_rbf(&self, txid: &Txid, new_feerate: f64) -> Result<Txid, FeeBumpError> {
+        let (tx, old_fee) = wallet_tx(self, txid)?;
+        if !tx.input.iter().any(|input| input.sequence.is_rbf()) {
+            return Err(FeeBumpError::NotReplaceable(*txid));
+        }
+        let change_index = change_output(self, &tx).ok_or(FeeBumpError::NoChange(*txid))?;
+
+        // BIP125 wants the replacement to pay for its own relay on top of
+        // the fee of the original
+        // Re-signing can make each signature a byte longer
+        let vsize = fee_bump::vsize(tx.weight() + tx.input.len());
+        let new_fee = (new_feerate * vsize as f64).ceil() as u64;
+        let min_fee = old_fee + (INCREMENTAL_RELAY_FEERATE * vsize as f64).ceil() as u64;
+        let extra = new_fee.max(min_fee) - old_fee;
+
+        let mut replacement = tx.clone();
+        for input in replacement.input.iter_mut() {
+            input.script_sig = Script::new();
+            input.witness = Witness::default();
+        }
+        let change = &mut replacement.output[change_index];
+        if change.value < extra + coin_selection::dust_threshold(&change.script_pubkey) {
+            return Err(FeeBumpError::InsufficientChange {
+                txid: *txid,
+                change: change.value,
+                needed: extra,
+            });
+        }
+        change.value -= extra;
+
+        self.send_raw_transaction(&sign_with_wallet(self, &replacement))
+            .map_err(|e| FeeBumpError::Rpc(e.to_string()))
+    }
+
+    fn bump_fee_cpfp(&self, txid: &Txid, new_package_feerate: f64) -> Result<Txid, FeeBumpError> {
+        let (parent, parent_fee) = wallet_tx(self, txid)?;
+        let vout = change_output(self, &parent).ok_or(FeeBumpError::NoChange(*txid))?;
+        let prevout = parent.output[vout].clone();
+        let utxo = Utxo {
+            outpoint: OutPoint::new(*txid, vout as u32),
+            weight: coin_selection::input_weight(&prevout.script_pubkey)
+                .ok_or(FeeBumpError::UnsupportedChange(*txid))?,
+            txout: prevout,
+        };
+        let output = TxOut {
+            value: 0,
+            script_pubkey: change_address(self).script_pubkey(),
+        };
+
+        let child_vsize = fee_bump::vsize(coin_selection::tx_weight(
+            std::slice::from_ref(&utxo),
+            std::slice::from_ref(&output),
+        ));
+        let package_vsize = fee_bump::vsize(parent.weight()) + child_vsize;
+        let package_fee = (new_package_feerate * package_vsize as f64).ceil() as u64;
+        // The child has to be worth relaying on its own too
+        let min_fee = (INCREMENTAL_RELAY_FEERATE * child_vsize as f64).ceil() as u64;
+        let child_fee = package_fee.saturating_sub(parent_fee).max(min_fee);
+        if utxo.txout.value < child_fee + coin_selection::dust_threshold(&output.script_pubkey) {
+            return Err(FeeBumpError::InsufficientChange {
+                txid: *txid,
+                change: utxo.txout.value,
+                needed: child_fee,
+            });
+        }
+
+        let child = Transaction {
+            version: 2,
+            lock_time: PackedLockTime::ZERO,
+            input: vec![TxIn {
+                previous_output: utxo.outpoint,
+                script_sig: Script::new(),
+                sequence: Sequence::ENABLE_RBF_NO_LOCKTIME,
+                witness: Witness::default(),
+            }],
+            output: vec![TxOut {
+                value: utxo.txout.value - child_fee,
+                ..output
+            }],
+        };
+        self.send_raw_transaction(&sign_with_wallet(self, &child))
+            .map_err(|e| FeeBumpError::Rpc(e.to_string()))
+    }
+
+    fn package_feerate(&self, txid: &Txid) -> f64 {
+        let entry = self
+            .get_mempool_entry(txid)
+            .expect("Failed to get mempool entry");
+        fee_bump::feerate(entry.fees.ancestor.to_sat(), entry.ancestor_size)
+    }
+
+    fn accept_and_send(&self, tx: &Transaction) -> Result<Txid, BuildError> {
+        let results = self
+            .test_mempool_accept(&[tx])
+            .map_err(|e| BuildError::Rpc(e.to_string()))?;
+        let result = results.first().expect("One result per tx");
+        if !result.allowed {
+            let reason = result.reject_reason.clone().unwrap_or_default();
+            return Err(BuildError::Rejected(reason));
+        }
+        self.send_raw_transaction(tx)
+            .map_err(|e| BuildError::Rpc(e.to_string()))
+    }
+
+    fn wallet_report(&self) -> WalletReport {
+        let mut transactions = Vec::new();
+        loop {
+            let page = self
+                .list_transactions(None, Some(HISTORY_PAGE), Some(transactions.len()), None)
+                .expect("Failed to list transactions");
+            let done = page.len() < HISTORY_PAGE;
+            // Each page is oldest first, and pages go back in time
+            let mut records: Vec<TxRecord> = page.iter().map(TxRecord::from_rpc).collect();
+            records.append(&mut transactions);
+            transactions = records;
+            if done {
+                break;
+            }
+        }
+        let balances = self.get_balances().expect("Failed to get balances");
+        // The typed listunspent has no `parent_descs`
+        let utxos = self
+            .call::<Vec<Value>>("listunspent", &[json!(0)])
+            .expect("Failed to list unspent outputs")
+            .into_iter()
+            .map(|entry| {
+                let mut utxo = UtxoRecord::from_unspent(
+                    &serde_json::from_value(entry.clone()).expect("Invalid listunspent entry"),
+                );
+                utxo.parent_descriptors =
+                    serde_json::from_value(entry["parent_descs"].clone()).unwrap_or_default();
+                utxo
+            })
+            .collect();
+        WalletReport {
+            transactions,
+            balances: Balances::from_rpc(&balances),
+            utxos,
+        }
     }
 }
+
+// bitcoincore-rpc has no getrawchangeaddress
+fn change_address(client: &Client) -> Address {
+    client
+        .call("getrawchangeaddress", &[])
+        .expect("Failed to get change address")
+}
+
+fn sign_with_wallet(client: &Client, tx: &Transaction) -> Transaction {
+    let signed = client
+        .sign_raw_transaction_with_wallet(tx, None, None)
+        .expect("Failed to sign transaction");
+    assert!(signed.complete, "Wallet couldn't sign every input");
+    signed.transaction().expect("Invalid signed transaction")
+}
+
+// An unconfirmed wallet tx and the fee it pays
+fn wallet_tx(client: &Client, txid: &Txid) -> Result<(Transaction, u64), FeeBumpError> {
+    let info = client
+        .get_transaction(txid, None)
+        .map_err(|e| FeeBumpError::Rpc(e.to_string()))?;
+    // Only txs spending our own coins have a fee the wallet knows about
+    let fee = info
+        .fee
+        .ok_or_else(|| FeeBumpError::Rpc(format!("{} doesn't spend wallet coins", txid)))?;
+    let tx = info
+        .transaction()
+        .map_err(|e| FeeBumpError::Rpc(e.to_string()))?;
+    Ok((tx, fee.abs().to_sat() as u64))
+}
+
+fn change_output(client: &Client, tx: &Transaction) -> Option<usize> {
+    tx.output.iter().position(|output| {
+        let address = match Address::from_script(&output.script_pubkey, Network::Regtest) {
+            Ok(address) => address,
+            Err(_) => return false,
+        };
+        // `GetAddressInfoResult` leaves out `ischange`
+        client
+            .call::<Value>("getaddressinfo", &[json!(address)])
+            .map(|info| info["ischange"] == json!(true))
+            .unwrap_or(false)
+    })
+}
+
@@ -0,0 +1,523 @@
+// Proving control of an address by signing a message with its keys, BIP137
+// for P2PKH and BIP322 for the rest. Like `tx_builder` it all works offline.
+use std::fmt;
+
+use bitcoincore_rpc::bitcoin::blockdata::opcodes::all::OP_RETURN;
+use bitcoincore_rpc::bitcoin::blockdata::script::{Builder, Instruction};
+use bitcoincore_rpc::bitcoin::consensus::encode::{deserialize, serialize, VarInt};
+use bitcoincore_rpc::bitcoin::hashes::{sha256, sha256d, Hash, HashEngine};
+use bitcoincore_rpc::bitcoin::util::psbt::PartiallySignedTransaction;
+use bitcoincore_rpc::bitcoin::{
+    Address, AddressType, EcdsaSig, EcdsaSighashType, Network, OutPoint, PackedLockTime, PublicKey,
+    SchnorrSig, Script, Sequence, Transaction, TxIn, TxOut, Witness, XOnlyPublicKey,
+};
+use secp256k1::ecdsa::{RecoverableSignature, RecoveryId};
+use secp256k1::{Message, Secp256k1, SecretKey, Signing, Verification};
+
+use crate::tx_builder::{self, BuildError, Spend, SpendKind, TxBuilder};
+
+const LEGACY_PREFIX: &[u8] = b"\x18Bitcoin Signed Message:\n";
+const BIP322_TAG: &[u8] = b"BIP0322-signed-message";
+// BIP137 header of P2PKH signatures, plus the recovery id and 4 more for
+// compressed keys
+const LEGACY_HEADER: u8 = 27;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SignatureFormat {
+    // BIP137, P2PKH only
+    Legacy,
+    // BIP322 witness of the `to_sign` tx, segwit and taproot only
+    Simple,
+    // BIP322 `to_sign` tx as a whole
+    Full,
+}
+
+#[derive(Debug)]
+pub enum MessageError {
+    // Not for this format or this kind of address
+    Unsupported(String),
+    // The spend kind doesn't lock coins to the address
+    KindMismatch,
+    NotEnoughSigs { threshold: usize, found: usize },
+    Decode(String),
+    Invalid(String),
+    Build(BuildError),
+}
+
+impl fmt::Display for MessageError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            MessageError::Unsupported(e) => write!(f, "Unsupported: {}", e),
+            MessageError::KindMismatch => write!(f, "Spend kind doesn't match the address"),
+            MessageError::NotEnoughSigs { threshold, found } => {
+                write!(f, "Needs {} signatures, only has {}", threshold, found)
+            }
+            MessageError::Decode(e) => write!(f, "Can't decode signature: {}", e),
+            MessageError::Invalid(e) => write!(f, "Invalid signature: {}", e),
+            MessageError::Build(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl std::error::Error for MessageError {}
+
+// `keys` has to hold the key of a single key `kind`, or enough of the
+// multisig ones
+pub fn sign_message<C: Signing + Verification>(
+    secp: &Secp256k1<C>,
+    message: &str,
+    address: &Address,
+    kind: &SpendKind,
+    keys: &[SecretKey],
+    format: SignatureFormat,
+) -> Result<String, MessageError> {
+    if format == SignatureFormat::Legacy {
+        return sign_legacy(secp, message, address, kind, keys);
+    }
+    if let Some(script) = kind.multi_script() {
+        check_kind(address, kind, &script)?;
+    }
+    let to_spend = to_spend(&address.script_pubkey(), message);
+    let tx = TxBuilder::new()
+        .version(0)
+        .spend(
+            Spend::new(
+                OutPoint::new(to_spend.txid(), 0),
+                to_spend.output[0].clone(),
+                kind.clone(),
+            )
+            .sequence(Sequence(0)),
+        )
+        .output(op_return(), 0)
+        .sign(secp, keys)
+        .map_err(MessageError::Build)?;
+    encode(&tx, format)
+}
+
+// Takes any of the formats, telling them apart by their length and what
+// they decode to
+pub fn verify_message<C: Verification>(
+    secp: &Secp256k1<C>,
+    message: &str,
+    address: &Address,
+    signature: &str,
+) -> Result<(), MessageError> {
+    let bytes =
+        base64::decode(signature.trim()).map_err(|e| MessageError::Decode(e.to_string()))?;
+    if address.address_type() == Some(AddressType::P2pkh) && bytes.len() == 65 {
+        return verify_legacy(secp, message, address, &bytes);
+    }
+
+    let to_spend = to_spend(&address.script_pubkey(), message);
+    let mut tx = to_sign(&to_spend);
+    if let Ok(witness) = deserialize::<Witness>(&bytes) {
+        tx.input[0].witness = witness;
+    } else {
+        let full: Transaction =
+            deserialize(&bytes).map_err(|e| MessageError::Decode(e.to_string()))?;
+        let mut unsigned = full.clone();
+        for input in unsigned.input.iter_mut() {
+            input.script_sig = Script::new();
+            input.witness = Witness::default();
+        }
+        if unsigned != tx {
+            return Err(MessageError::Invalid(
+                "Not the to_sign tx of this message and address".to_owned(),
+            ));
+        }
+        tx = full;
+    }
+    verify_to_sign(secp, &to_spend.output[0], &tx)
+}
+
+// Bitcoin Core's signmessage hash
+pub fn legacy_message_hash(message: &str) -> sha256d::Hash {
+    let mut engine = sha256d::Hash::engine();
+    engine.input(LEGACY_PREFIX);
+    engine.input(&serialize(&VarInt(message.len() as u64)));
+    engine.input(message.as_bytes());
+    sha256d::Hash::from_engine(engine)
+}
+
+// Tagged hash, like the BIP340 ones
+pub fn bip322_message_hash(message: &str) -> sha256::Hash {
+    let tag = sha256::Hash::hash(BIP322_TAG);
+    let mut engine = sha256::Hash::engine();
+    engine.input(&tag[..]);
+    engine.input(&tag[..]);
+    engine.input(message.as_bytes());
+    sha256::Hash::from_engine(engine)
+}
+
+// The virtual tx paying to `script_pubkey`, committing to the message
+pub fn to_spend(script_pubkey: &Script, message: &str) -> Transaction {
+    let script_sig = Builder::new()
+        .push_int(0)
+        .push_slice(&bip322_message_hash(message)[..])
+        .into_script();
+    Transaction {
+        version: 0,
+        lock_time: PackedLockTime::ZERO,
+        input: vec![TxIn {
+            previous_output: OutPoint::null(),
+            script_sig,
+            sequence: Sequence(0),
+            witness: Witness::default(),
+        }],
+        output: vec![TxOut {
+            value: 0,
+            script_pubkey: script_pubkey.clone(),
+        }],
+    }
+}
+
+// The virtual tx spending `to_spend`, unsigned. Signing it is what proves
+// control of the address.
+pub fn to_sign(to_spend: &Transaction) -> Transaction {
+    Transaction {
+        version: 0,
+        lock_time: PackedLockTime::ZERO,
+        input: vec![TxIn {
+            previous_output: OutPoint::new(to_spend.txid(), 0),
+            script_sig: Script::new(),
+            sequence: Sequence(0),
+            witness: Witness::default(),
+        }],
+        output: vec![TxOut {
+            value: 0,
+            script_pubkey: op_return(),
+        }],
+    }
+}
+
+// For multisig signers who don't share their keys: each of them signs a
+// copy with `sign_message_psbt`, then the copies are combined with
+// `PartiallySignedTransaction::combine` and finalized
+pub fn message_psbt(
+    message: &str,
+    address: &Address,
+    kind: &SpendKind,
+) -> Result<PartiallySignedTransaction, MessageError> {
+    let script = kind
+        .multi_script()
+        .ok_or_else(|| MessageError::Unsupported("Only multisig goes through PSBTs".to_owned()))?;
+    check_kind(address, kind, &script)?;
+    let to_spend = to_spend(&address.script_pubkey(), message);
+    let mut psbt = PartiallySignedTransaction::from_unsigned_tx(to_sign(&to_spend))
+        .expect("to_sign is unsigned");
+    let input = &mut psbt.inputs[0];
+    if let SpendKind::P2wshMulti { .. } = kind {
+        input.witness_utxo = Some(to_spend.output[0].clone());
+        input.witness_script = Some(script);
+    } else {
+        input.non_witness_utxo = Some(to_spend);
+        input.redeem_script = Some(script);
+    }
+    Ok(psbt)
+}
+
+// Adds the signature of `secret`, which has to be one of the multisig keys
+pub fn sign_message_psbt<C: Signing>(
+    secp: &Secp256k1<C>,
+    psbt: &mut PartiallySignedTransaction,
+    secret: &SecretKey,
+) -> Result<(), MessageError> {
+    let spend = psbt_spend(psbt)?;
+    let script = spend.kind.multi_script().unwrap();
+    let pk = PublicKey::new(secret.public_key(secp));
+    if !multi_keys(&spend.kind).contains(&pk) {
+        return Err(MessageError::Build(BuildError::MissingKey { input: 0 }));
+    }
+    let sig = tx_builder::sign_input(secp, &psbt.unsigned_tx, 0, &spend, &script, secret)
+        .map_err(MessageError::Build)?;
+    let sig = EcdsaSig::from_slice(&sig).expect("Just made it");
+    psbt.inputs[0].partial_sigs.insert(pk, sig);
+    Ok(())
+}
+
+// Takes the first `threshold` signatures in script order
+pub fn finalize_message_psbt(
+    psbt: &PartiallySignedTransaction,
+    format: SignatureFormat,
+) -> Result<String, MessageError> {
+    let spend = psbt_spend(psbt)?;
+    let script = spend.kind.multi_script().unwrap();
+    let threshold = match spend.kind {
+        SpendKind::P2shMulti { threshold, .. } | SpendKind::P2wshMulti { threshold, .. } => {
+            threshold
+        }
+        _ => unreachable!(),
+    };
+    let sigs: Vec<Vec<u8>> = multi_keys(&spend.kind)
+        .iter()
+        .filter_map(|pk| psbt.inputs[0].partial_sigs.get(pk))
+        .map(|sig| sig.to_vec())
+        .take(threshold)
+        .collect();
+    if sigs.len() < threshold {
+        return Err(MessageError::NotEnoughSigs {
+            threshold,
+            found: sigs.len(),
+        });
+    }
+    let mut tx = psbt.unsigned_tx.clone();
+    tx_builder::finish_multisig(&mut tx.input[0], spend.kind.is_segwit(), &script, sigs);
+    encode(&tx, format)
+}
+
+fn sign_legacy<C: Signing>(
+    secp: &Secp256k1<C>,
+    message: &str,
+    address: &Address,
+    kind: &SpendKind,
+    keys: &[SecretKey],
+) -> Result<String, MessageError> {
+    if *kind != SpendKind::P2pkh {
+        return Err(MessageError::Unsupported(
+            "BIP137 signatures are for P2PKH, use BIP322".to_owned(),
+        ));
+    }
+    let sk = keys
+        .iter()
+        .find(|sk| {
+            Address::p2pkh(&PublicKey::new(sk.public_key(secp)), address.network) == *address
+        })
+        .ok_or(MessageError::Build(BuildError::MissingKey { input: 0 }))?;
+    let msg = Message::from_slice(&legacy_message_hash(message)[..]).expect("Hash is 32 bytes");
+    let (recid, compact) = secp.sign_ecdsa_recoverable(&msg, sk).serialize_compact();
+    // Keys from `SecretKey` are always compressed
+    let mut bytes = vec![LEGACY_HEADER + 4 + recid.to_i32() as u8];
+    bytes.extend_from_slice(&compact);
+    Ok(base64::encode(bytes))
+}
+
+fn verify_legacy<C: Verification>(
+    secp: &Secp256k1<C>,
+    message: &str,
+    address: &Address,
+    bytes: &[u8],
+) -> Result<(), MessageError> {
+    let (recid, compressed) = match bytes[0] {
+        27..=30 => (bytes[0] - LEGACY_HEADER, false),
+        31..=34 => (bytes[0] - LEGACY_HEADER - 4, true),
+        header => {
+            return Err(MessageError::Unsupported(format!(
+                "BIP137 header {} isn't for P2PKH",
+                header
+            )))
+        }
+    };
+    let recid =
+        RecoveryId::from_i32(recid as i32).map_err(|e| MessageError::Decode(e.to_string()))?;
+    let sig = RecoverableSignature::from_compact(&bytes[1..], recid)
+        .map_err(|e| MessageError::Decode(e.to_string()))?;
+    let msg = Message::from_slice(&legacy_message_hash(message)[..]).expect("Hash is 32 bytes");
+    let inner = secp
+        .recover_ecdsa(&msg, &sig)
+        .map_err(|e| MessageError::Invalid(e.to_string()))?;
+    let signer = Address::p2pkh(&PublicKey { compressed, inner }, address.network);
+    if signer != *address {
+        return Err(MessageError::Invalid(format!("Signed by {}", signer)));
+    }
+    Ok(())
+}
+
+// What the script interpreter would check for the address types we sign
+// for. Multisig is taken to be the `multi_script` kind.
+fn verify_to_sign<C: Verification>(
+    secp: &Secp256k1<C>,
+    prevout: &TxOut,
+    tx: &Transaction,
+) -> Result<(), MessageError> {
+    let input = &tx.input[0];
+    let spk = &prevout.script_pubkey;
+    let witness = input.witness.to_vec();
+    let invalid = |e: &str| Err(MessageError::Invalid(e.to_owned()));
+    if spk.is_p2pkh() || spk.is_p2sh() {
+        if !witness.is_empty() {
+            return invalid("Witness on a legacy input");
+        }
+    } else if !input.script_sig.is_empty() {
+        return invalid("scriptSig on a segwit input");
+    }
+
+    if spk.is_p2pkh() || spk.is_v0_p2wpkh() {
+        let items = if spk.is_p2pkh() {
+            pushes(&input.script_sig)?
+        } else {
+            witness
+        };
+        let (sig, pk) = match &items[..] {
+            [sig, pk] => (sig, pk),
+            _ => return invalid("Expected a signature and a key"),
+        };
+        let pk = PublicKey::from_slice(pk).map_err(|e| MessageError::Decode(e.to_string()))?;
+        let kind = if spk.is_p2pkh() {
+            SpendKind::P2pkh
+        } else {
+            SpendKind::P2wpkh
+        };
+        if kind.address(Some(&pk)).script_pubkey() != *spk {
+            return invalid("Key doesn't match the address");
+        }
+        let script_code = Address::p2pkh(&pk, Network::Regtest).script_pubkey();
+        verify_ecdsa(secp, tx, &script_code, kind.is_segwit(), sig, &pk)
+    } else if spk.is_p2sh() || spk.is_v0_p2wsh() {
+        let mut items = if spk.is_p2sh() {
+            pushes(&input.script_sig)?
+        } else {
+            witness
+        };
+        let script = Script::from(items.pop().unwrap_or_default());
+        let sigs = match items.split_first() {
+            Some((dummy, sigs)) if dummy.is_empty() => sigs,
+            _ => return invalid("Missing the empty CHECKMULTISIG dummy"),
+        };
+        let locks = if spk.is_p2sh() {
+            Script::new_p2sh(&script.script_hash())
+        } else {
+            Script::new_v0_p2wsh(&script.wscript_hash())
+        };
+        if locks != *spk {
+            return invalid("Script doesn't match the address");
+        }
+        let (threshold, pubkeys) = tx_builder::parse_multi_script(&script).ok_or_else(|| {
+            MessageError::Unsupported("Only multisig scripts are checked".to_owned())
+        })?;
+        if sigs.len() != threshold {
+            return Err(MessageError::NotEnoughSigs {
+                threshold,
+                found: sigs.len(),
+            });
+        }
+        // Like CHECKMULTISIG, each signature has to match a key after the
+        // one the previous signature matched
+        let mut keys = pubkeys.iter();
+        for sig in sigs {
+            if !keys.any(|pk| verify_ecdsa(secp, tx, &script, spk.is_v0_p2wsh(), sig, pk).is_ok()) {
+                return invalid("Signatures don't match the keys in order");
+            }
+        }
+        Ok(())
+    } else if spk.is_v1_p2tr() {
+        let sig = match &witness[..] {
+            [sig] => {
+                SchnorrSig::from_slice(sig).map_err(|e| MessageError::Decode(e.to_string()))?
+            }
+            _ => return invalid("Only key path spends are checked"),
+        };
+        let output_key = XOnlyPublicKey::from_slice(&spk.as_bytes()[2..])
+            .map_err(|e| MessageError::Decode(e.to_string()))?;
+        let sighash = tx_builder::taproot_sighash(tx, 0, &[prevout], sig.hash_ty)
+            .map_err(MessageError::Build)?;
+        let msg = Message::from_slice(&sighash[..]).expect("Sighash is 32 bytes");
+        secp.verify_schnorr(&sig.sig, &msg, &output_key)
+            .map_err(|e| MessageError::Invalid(e.to_string()))
+    } else {
+        Err(MessageError::Unsupported(format!(
+            "Can't check signatures for {}",
+            spk
+        )))
+    }
+}
+
+fn verify_ecdsa<C: Verification>(
+    secp: &Secp256k1<C>,
+    tx: &Transaction,
+    script_code: &Script,
+    segwit: bool,
+    sig: &[u8],
+    pk: &PublicKey,
+) -> Result<(), MessageError> {
+    let sig = EcdsaSig::from_slice(sig).map_err(|e| MessageError::Decode(e.to_string()))?;
+    // Whatever is being spent is worth nothing
+    let sighash = if segwit {
+        tx_builder::segwit_sighash(tx, 0, script_code, 0, sig.hash_ty)
+    } else {
+        tx_builder::legacy_sighash(tx, 0, script_code, sig.hash_ty)
+    }
+    .map_err(MessageError::Build)?;
+    let msg = Message::from_slice(&sighash[..]).expect("Sighash is 32 bytes");
+    secp.verify_ecdsa(&msg, &sig.sig, &pk.inner)
+        .map_err(|e| MessageError::Invalid(e.to_string()))
+}
+
+// The multisig kind the PSBT's input was set up for by `message_psbt`
+fn psbt_spend(psbt: &PartiallySignedTransaction) -> Result<Spend, MessageError> {
+    let input = &psbt.inputs[0];
+    let outpoint = psbt.unsigned_tx.input[0].previous_output;
+    let (script, prevout) = match (&input.witness_script, &input.redeem_script) {
+        (Some(script), _) => (script, input.witness_utxo.clone()),
+        (None, Some(script)) => (
+            script,
+            input
+                .non_witness_utxo
+                .as_ref()
+                .and_then(|tx| tx.output.get(outpoint.vout as usize).cloned()),
+        ),
+        (None, None) => {
+            return Err(MessageError::Unsupported(
+                "PSBT isn't for a multisig message".to_owned(),
+            ))
+        }
+    };
+    let prevout = prevout.ok_or_else(|| MessageError::Decode("PSBT lacks the UTXO".to_owned()))?;
+    let (threshold, pubkeys) = tx_builder::parse_multi_script(script)
+        .ok_or_else(|| MessageError::Unsupported("Not a multisig script".to_owned()))?;
+    let kind = if input.witness_script.is_some() {
+        SpendKind::P2wshMulti { threshold, pubkeys }
+    } else {
+        SpendKind::P2shMulti { threshold, pubkeys }
+    };
+    Ok(Spend::new(outpoint, prevout, kind)
+        .sequence(Sequence(0))
+        .sighash_type(EcdsaSighashType::All))
+}
+
+fn multi_keys(kind: &SpendKind) -> &[PublicKey] {
+    match kind {
+        SpendKind::P2shMulti { pubkeys, .. } | SpendKind::P2wshMulti { pubkeys, .. } => pubkeys,
+        _ => &[],
+    }
+}
+
+fn check_kind(address: &Address, kind: &SpendKind, script: &Script) -> Result<(), MessageError> {
+    let script_pubkey = match kind {
+        SpendKind::P2shMulti { .. } => Script::new_p2sh(&script.script_hash()),
+        _ => Script::new_v0_p2wsh(&script.wscript_hash()),
+    };
+    if script_pubkey != address.script_pubkey() {
+        return Err(MessageError::KindMismatch);
+    }
+    Ok(())
+}
+
+fn encode(tx: &Transaction, format: SignatureFormat) -> Result<String, MessageError> {
+    match format {
+        SignatureFormat::Simple if tx.input[0].witness.is_empty() => Err(
+            MessageError::Unsupported("Legacy scripts need full signatures".to_owned()),
+        ),
+        SignatureFormat::Simple => Ok(base64::encode(serialize(&tx.input[0].witness))),
+        SignatureFormat::Full => Ok(base64::encode(serialize(tx))),
+        SignatureFormat::Legacy => Err(MessageError::Unsupported(
+            "BIP137 signatures are for P2PKH, use BIP322".to_owned(),
+        )),
+    }
+}
+
+fn op_return() -> Script {
+    Builder::new().push_opcode(OP_RETURN).into_script()
+}
+
+// The data pushed by a push only scriptSig
+fn pushes(script_sig: &Script) -> Result<Vec<Vec<u8>>, MessageError> {
+    script_sig
+        .instructions()
+        .map(|instruction| match instruction {
+            Ok(Instruction::PushBytes(bytes)) => Ok(bytes.to_vec()),
+            _ => Err(MessageError::Invalid(
+                "scriptSig isn't push only".to_owned(),
+            )),
+        })
+        .collect()
+}
@@ -0,0 +1,127 @@
+// Chain control for regtest, so tests can set up reorgs and timelocks on
+// purpose instead of mining ad hoc
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use bitcoincore_rpc::bitcoin::{Address, BlockHash, Txid};
+use bitcoincore_rpc::{Client, RpcApi};
+
+// Blocks before a coinbase output can be spent
+pub const COINBASE_MATURITY: u64 = 100;
+// Mining too many blocks in one call runs into the RPC client's timeout,
+// which is the error 03-balance runs into the first time
+const MINE_BATCH: u64 = 25;
+
+pub trait RegtestControl {
+    fn mine_to(&self, n: u64, address: &Address) -> Vec<BlockHash>;
+
+    // Mines one block at a time until `txid` is in one, and returns that
+    // block. Panics if it isn't in the mempool or not mined by `max_blocks`.
+    fn mine_until_confirmed(&self, txid: &Txid, max_blocks: u64) -> BlockHash;
+
+    // Mines a block to `address` and enough on top for its coinbase to be
+    // spendable
+    fn mine_past_maturity(&self, address: &Address) -> Vec<BlockHash>;
+
+    // Disconnects the last `depth` blocks, returning them from the tip down.
+    // Their txs go back to the mempool.
+    fn invalidate_blocks(&self, depth: u64) -> Vec<BlockHash>;
+
+    // Replaces the last `depth` blocks with `depth + 1` new ones, returning
+    // the new blocks
+    fn reorg(&self, depth: u64) -> Vec<BlockHash>;
+
+    // 0 goes back to the system clock
+    fn set_mock_time(&self, time: u64);
+
+    // Moves the node's clock `secs` past the later of now and the tip's
+    // timestamp, and returns the new time. Median time past only catches up
+    // as blocks get mined.
+    fn advance_time(&self, secs: u64) -> u64;
+}
+
+impl RegtestControl for Client {
+    fn mine_to(&self, n: u64, address: &Address) -> Vec<BlockHash> {
+        let mut hashes = Vec::new();
+        let mut left = n;
+        while left > 0 {
+            let batch = left.min(MINE_BATCH);
+            hashes.extend(
+                self.generate_to_address(batch, address)
+                    .expect("Failed to generate blocks"),
+            );
+            left -= batch;
+        }
+        hashes
+    }
+
+    fn mine_until_confirmed(&self, txid: &Txid, max_blocks: u64) -> BlockHash {
+        let address = fresh_address(self);
+        for _ in 0..max_blocks {
+            let mempool = self.get_raw_mempool().expect("Failed to get mempool");
+            assert!(mempool.contains(txid), "{} isn't in the mempool", txid);
+            let hash = self.mine_to(1, &address)[0];
+            let block = self.get_block(&hash).expect("Failed to get block");
+            if block.txdata.iter().any(|tx| tx.txid() == *txid) {
+                return hash;
+            }
+        }
+        panic!("{} not mined in {} blocks", txid, max_blocks);
+    }
+
+    fn mine_past_maturity(&self, address: &Address) -> Vec<BlockHash> {
+        let mut hashes = self.mine_to(1, address);
+        hashes.extend(self.mine_to(COINBASE_MATURITY, &fresh_address(self)));
+        hashes
+    }
+
+    fn invalidate_blocks(&self, depth: u64) -> Vec<BlockHash> {
+        let height = self.get_block_count().expect("Failed to get block count");
+        assert!(depth <= height, "Can't invalidate the genesis block");
+        let disconnected: Vec<BlockHash> = (0..depth)
+            .map(|i| {
+                self.get_block_hash(height - i)
+                    .expect("Failed to get block hash")
+            })
+            .collect();
+        // Invalidating a block takes all its descendants with it
+        if let Some(oldest) = disconnected.last() {
+            self.invalidate_block(oldest)
+                .expect("Failed to invalidate block");
+        }
+        disconnected
+    }
+
+    fn reorg(&self, depth: u64) -> Vec<BlockHash> {
+        self.invalidate_blocks(depth);
+        // A new address keeps the new blocks from coming out identical to
+        // the invalidated ones
+        self.mine_to(depth + 1, &fresh_address(self))
+    }
+
+    fn set_mock_time(&self, time: u64) {
+        self.call::<()>("setmocktime", &[time.into()])
+            .expect("Failed to set mock time");
+    }
+
+    fn advance_time(&self, secs: u64) -> u64 {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .expect("Clock is before 1970")
+            .as_secs();
+        let tip = self.get_best_block_hash().expect("Failed to get tip");
+        let tip_time = self
+            .get_block_header_info(&tip)
+            .expect("Failed to get block header")
+            .time as u64;
+        let time = now.max(tip_time) + secs;
+        self.set_mock_time(time);
+        time
+    }
+}
+
+// Somewhere for the coinbases of filler blocks to go
+fn fresh_address(client: &Client) -> Address {
+    client
+        .get_new_address(None, None)
+        .expect("Failed to get new address")
+}
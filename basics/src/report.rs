@@ -0,0 +1,289 @@
+// A snapshot of a wallet's history, balances and coins, for checking the
+// books after a regtest scenario
+use std::collections::BTreeMap;
+
+use bitcoincore_rpc::bitcoin::Txid;
+use bitcoincore_rpc::json::{
+    GetBalancesResult, GetTransactionResultDetailCategory, ListTransactionResult,
+    ListUnspentResultEntry,
+};
+use serde::Serialize;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Direction {
+    Incoming,
+    Outgoing,
+    // Mined by the wallet, mature or not
+    Coinbase,
+}
+
+impl Direction {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Direction::Incoming => "incoming",
+            Direction::Outgoing => "outgoing",
+            Direction::Coinbase => "coinbase",
+        }
+    }
+}
+
+// One output paid to or from the wallet. A tx to ourselves shows up twice,
+// once each way.
+#[derive(Clone, Debug, Serialize)]
+pub struct TxRecord {
+    pub txid: Txid,
+    pub vout: u32,
+    pub direction: Direction,
+    pub address: Option<String>,
+    // Negative when outgoing
+    pub amount_sat: i64,
+    // Only known for outgoing, and negative like the amount
+    pub fee_sat: Option<i64>,
+    // Negative for txs conflicting with the chain
+    pub confirmations: i32,
+    pub label: Option<String>,
+    pub time: u64,
+}
+
+impl TxRecord {
+    pub fn from_rpc(entry: &ListTransactionResult) -> TxRecord {
+        let detail = &entry.detail;
+        TxRecord {
+            txid: entry.info.txid,
+            vout: detail.vout,
+            direction: match detail.category {
+                GetTransactionResultDetailCategory::Send => Direction::Outgoing,
+                GetTransactionResultDetailCategory::Receive => Direction::Incoming,
+                _ => Direction::Coinbase,
+            },
+            address: detail.address.as_ref().map(|a| a.to_string()),
+            amount_sat: detail.amount.to_sat(),
+            fee_sat: detail.fee.map(|fee| fee.to_sat()),
+            confirmations: entry.info.confirmations,
+            label: detail.label.clone().filter(|l| !l.is_empty()),
+            time: entry.info.time,
+        }
+    }
+}
+
+// In sats
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize)]
+pub struct Balances {
+    // Confirmed, or unconfirmed change of our own
+    pub trusted: u64,
+    // Unconfirmed from someone else
+    pub untrusted_pending: u64,
+    // Coinbases short of 100 confirmations
+    pub immature: u64,
+}
+
+impl Balances {
+    pub fn from_rpc(balances: &GetBalancesResult) -> Balances {
+        Balances {
+            trusted: balances.mine.trusted.to_sat(),
+            untrusted_pending: balances.mine.untrusted_pending.to_sat(),
+            immature: balances.mine.immature.to_sat(),
+        }
+    }
+
+    pub fn total(&self) -> u64 {
+        self.trusted + self.untrusted_pending + self.immature
+    }
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct UtxoRecord {
+    pub txid: Txid,
+    pub vout: u32,
+    pub amount_sat: u64,
+    pub confirmations: u32,
+    pub address: Option<String>,
+    pub label: Option<String>,
+    pub descriptor: Option<String>,
+    // The wallet's descriptors the output's address comes from, the
+    // `parent_descs` of listunspent. Empty before bitcoind 24.
+    pub parent_descriptors: Vec<String>,
+}
+
+impl UtxoRecord {
+    pub fn from_unspent(entry: &ListUnspentResultEntry) -> UtxoRecord {
+        UtxoRecord {
+            txid: entry.txid,
+            vout: entry.vout,
+            amount_sat: entry.amount.to_sat(),
+            confirmations: entry.confirmations,
+            address: entry.address.as_ref().map(|a| a.to_string()),
+            label: entry.label.clone().filter(|l| !l.is_empty()),
+            descriptor: entry.descriptor.clone(),
+            parent_descriptors: Vec::new(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct UtxoGroup {
+    pub key: String,
+    pub total_sat: u64,
+    pub utxos: Vec<UtxoRecord>,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct WalletReport {
+    // Oldest first
+    pub transactions: Vec<TxRecord>,
+    pub balances: Balances,
+    pub utxos: Vec<UtxoRecord>,
+}
+
+impl WalletReport {
+    pub fn by_address(&self) -> Vec<UtxoGroup> {
+        self.group_utxos(|utxo| utxo.address.clone().unwrap_or_default())
+    }
+
+    // Coins from the same wallet descriptors end up together. Without
+    // `parent_descriptors` to go by, the key comes from `descriptor_group`.
+    pub fn by_descriptor(&self) -> Vec<UtxoGroup> {
+        self.group_utxos(|utxo| {
+            if !utxo.parent_descriptors.is_empty() {
+                return utxo.parent_descriptors.join(" ");
+            }
+            utxo.descriptor
+                .as_deref()
+                .map(descriptor_group)
+                .unwrap_or_default()
+        })
+    }
+
+    // What went in less what went out, fees included
+    pub fn net_flow(&self) -> i64 {
+        let amounts: i64 = self.transactions.iter().map(|tx| tx.amount_sat).sum();
+        // Every outgoing record of a tx repeats its whole fee
+        let mut fees = BTreeMap::new();
+        for tx in self.transactions.iter() {
+            if let Some(fee) = tx.fee_sat {
+                fees.insert(tx.txid, fee);
+            }
+        }
+        amounts + fees.values().sum::<i64>()
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).expect("Failed to serialize report")
+    }
+
+    // The transaction history, one row per `TxRecord`
+    pub fn to_csv(&self) -> String {
+        let mut csv =
+            "txid,vout,direction,address,amount_sat,fee_sat,confirmations,label,time\n".to_owned();
+        for tx in self.transactions.iter() {
+            let row = [
+                tx.txid.to_string(),
+                tx.vout.to_string(),
+                tx.direction.as_str().to_owned(),
+                tx.address.clone().unwrap_or_default(),
+                tx.amount_sat.to_string(),
+                tx.fee_sat.map(|fee| fee.to_string()).unwrap_or_default(),
+                tx.confirmations.to_string(),
+                tx.label.clone().unwrap_or_default(),
+                tx.time.to_string(),
+            ];
+            let row: Vec<String> = row.iter().map(|field| csv_field(field)).collect();
+            csv.push_str(&row.join(","));
+            csv.push('\n');
+        }
+        csv
+    }
+
+    fn group_utxos<F: Fn(&UtxoRecord) -> String>(&self, key: F) -> Vec<UtxoGroup> {
+        let mut groups: BTreeMap<String, Vec<UtxoRecord>> = BTreeMap::new();
+        for utxo in self.utxos.iter() {
+            groups.entry(key(utxo)).or_default().push(utxo.clone());
+        }
+        groups
+            .into_iter()
+            .map(|(key, utxos)| UtxoGroup {
+                key,
+                total_sat: utxos.iter().map(|u| u.amount_sat).sum(),
+                utxos,
+            })
+            .collect()
+    }
+}
+
+// listunspent gives the descriptor of each single address, like
+// `wpkh([d34db33f/84'/1'/0'/0/5]02..)#checksum`. Swapping each key for the
+// parent of its origin, `wpkh([d34db33f/84'/1'/0'/0/*])`, gives the same key
+// for every address of a ranged descriptor. The key is not a descriptor
+// itself, the xpubs aren't in there to recover. Keys without an origin stay
+// as they are.
+pub fn descriptor_group(desc: &str) -> String {
+    let mut rest = desc.split('#').next().unwrap_or(desc);
+    let mut group = String::new();
+    while let Some(start) = rest.find('[') {
+        let end = match rest[start..].find(']') {
+            Some(end) => start + end,
+            None => break,
+        };
+        let origin = &rest[start + 1..end];
+        let parent = match origin.rfind('/') {
+            Some(last) => &origin[..last],
+            None => origin,
+        };
+        group.push_str(&rest[..start]);
+        group.push_str(&format!("[{}/*]", parent));
+        // The key itself runs up to the next argument or closing paren
+        let key_end = rest[end..]
+            .find([',', ')'])
+            .map(|i| end + i)
+            .unwrap_or(rest.len());
+        rest = &rest[key_end..];
+    }
+    group.push_str(rest);
+    group
+}
+
+fn csv_field(field: &str) -> String {
+    if field.contains([',', '"', '\n']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_owned()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn descriptor_group_single_key() {
+        assert_eq!(
+            descriptor_group("wpkh([d34db33f/84'/1'/0'/0/5]02c6047f9441ed7d6d3045406e95c07cd85c778e4b8cef3ca7abac09b95c709ee5)#abcdefgh"),
+            "wpkh([d34db33f/84'/1'/0'/0/*])"
+        );
+        assert_eq!(
+            descriptor_group("sh(wpkh([d34db33f/49'/1'/0'/1/2]02c6047f9441ed7d6d3045406e95c07cd85c778e4b8cef3ca7abac09b95c709ee5))"),
+            "sh(wpkh([d34db33f/49'/1'/0'/1/*]))"
+        );
+    }
+
+    #[test]
+    fn descriptor_group_every_origin() {
+        let first = "wsh(multi(2,[d34db33f/48'/1'/0'/2'/0/3]02c6047f9441ed7d6d3045406e95c07cd85c778e4b8cef3ca7abac09b95c709ee5,[f00dbabe/48'/1'/0'/2'/0/3]03774ae7f858a9411e5ef4246b70c65aac5649980be5c17891bbec17895da008cb))#abcdefgh";
+        let other = "wsh(multi(2,[d34db33f/48'/1'/0'/2'/0/4]02e493dbf1c10d80f3581e4904930b1404cc6c13900ee0758474fa94abe8c4cd13,[f00dbabe/48'/1'/0'/2'/0/4]022f8bde4d1a07209355b4a7250a5c5128e88b84bddc619ab7cba8d569b240efe4))";
+        assert_eq!(
+            descriptor_group(first),
+            "wsh(multi(2,[d34db33f/48'/1'/0'/2'/0/*],[f00dbabe/48'/1'/0'/2'/0/*]))"
+        );
+        assert_eq!(descriptor_group(first), descriptor_group(other));
+        // Same first key, different cosigner
+        let cosigner = first.replace("f00dbabe", "cafebabe");
+        assert_ne!(descriptor_group(first), descriptor_group(&cosigner));
+    }
+
+    #[test]
+    fn descriptor_group_without_origin() {
+        let desc = "wpkh(02c6047f9441ed7d6d3045406e95c07cd85c778e4b8cef3ca7abac09b95c709ee5)";
+        assert_eq!(descriptor_group(&format!("{}#abcdefgh", desc)), desc);
+    }
+}
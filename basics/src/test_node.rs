@@ -0,0 +1,172 @@
+// A bitcoind of its own for every test, so tests can run in parallel
+// without a node set up by hand or wallets left over from other runs
+use std::env;
+use std::fs;
+use std::net::TcpListener;
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, Stdio};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use bitcoincore_rpc::{Auth, Client, RpcApi};
+
+use crate::wallet::{WalletHandle, WalletOptions};
+use crate::{BitcoinClient, RPC_PASSWORD, RPC_USER};
+
+// Path to the bitcoind binary, `bitcoind` from PATH if unset
+pub const BITCOIND_EXE_VAR: &str = "BITCOIND_EXE";
+const STARTUP_TIMEOUT: Duration = Duration::from_secs(30);
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(10);
+
+// Tells apart the datadirs of nodes in the same process
+static NODE_COUNT: AtomicUsize = AtomicUsize::new(0);
+
+pub struct TestNode {
+    process: Child,
+    datadir: PathBuf,
+    rpc_port: u16,
+    p2p_port: u16,
+}
+
+impl TestNode {
+    pub fn new() -> TestNode {
+        TestNode::with_args(&[])
+    }
+
+    // `args` go to bitcoind on top of the defaults, e.g. ZMQ endpoints
+    pub fn with_args(args: &[&str]) -> TestNode {
+        let exe = env::var(BITCOIND_EXE_VAR).unwrap_or_else(|_| "bitcoind".to_owned());
+        let datadir = env::temp_dir().join(format!(
+            "bitcoin_basics-{}-{}",
+            std::process::id(),
+            NODE_COUNT.fetch_add(1, Ordering::SeqCst)
+        ));
+        // Left over from a crashed run with the same pid
+        let _ = fs::remove_dir_all(&datadir);
+        fs::create_dir_all(&datadir).expect("Failed to create datadir");
+        let rpc_port = free_port();
+        let p2p_port = free_port();
+
+        let process = Command::new(&exe)
+            .arg("-regtest")
+            .arg(format!("-datadir={}", datadir.display()))
+            .arg(format!("-rpcport={}", rpc_port))
+            .arg(format!("-port={}", p2p_port))
+            // Otherwise every node also binds the same onion port
+            .arg(format!("-bind=127.0.0.1:{}", p2p_port))
+            .arg("-listenonion=0")
+            .arg(format!("-rpcuser={}", RPC_USER))
+            .arg(format!("-rpcpassword={}", RPC_PASSWORD))
+            // Fee estimation has nothing to go on in a fresh regtest chain
+            .arg("-fallbackfee=0.0002")
+            .arg("-txindex")
+            .args(args)
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()
+            .unwrap_or_else(|e| panic!("Failed to launch {}: {}", exe, e));
+
+        let mut node = TestNode {
+            process,
+            datadir,
+            rpc_port,
+            p2p_port,
+        };
+        node.wait_for_rpc();
+        node
+    }
+
+    pub fn rpc_url(&self) -> String {
+        format!("http://127.0.0.1:{}", self.rpc_port)
+    }
+
+    pub fn rpc_port(&self) -> u16 {
+        self.rpc_port
+    }
+
+    pub fn p2p_port(&self) -> u16 {
+        self.p2p_port
+    }
+
+    pub fn datadir(&self) -> &Path {
+        &self.datadir
+    }
+
+    // A client for node wide calls
+    pub fn client(&self) -> Client {
+        self.client_at(&self.rpc_url())
+    }
+
+    // A client for `wallet_name`, created if it doesn't exist yet
+    pub fn wallet(&self, wallet_name: &str) -> WalletHandle {
+        self.wallet_with(wallet_name, &WalletOptions::default())
+    }
+
+    // Same, creating it with `options`
+    pub fn wallet_with(&self, wallet_name: &str, options: &WalletOptions) -> WalletHandle {
+        self.client()
+            .ensure_wallet(wallet_name, options)
+            .unwrap_or_else(|e| panic!("Failed to load {}: {}", wallet_name, e));
+        WalletHandle::at(&self.rpc_url(), wallet_name)
+    }
+
+    fn client_at(&self, url: &str) -> Client {
+        Client::new(
+            url,
+            Auth::UserPass(RPC_USER.to_owned(), RPC_PASSWORD.to_owned()),
+        )
+        .expect("Failed to create RPC client")
+    }
+
+    // The RPC server comes up before it's ready, and errors out while it
+    // loads
+    fn wait_for_rpc(&mut self) {
+        let client = self.client();
+        let start = Instant::now();
+        loop {
+            if client.get_blockchain_info().is_ok() {
+                return;
+            }
+            if let Some(status) = self.process.try_wait().expect("Failed to poll bitcoind") {
+                panic!("bitcoind exited with {} before its RPC was up", status);
+            }
+            if start.elapsed() > STARTUP_TIMEOUT {
+                panic!("bitcoind RPC not up after {:?}", STARTUP_TIMEOUT);
+            }
+            thread::sleep(Duration::from_millis(100));
+        }
+    }
+}
+
+impl Default for TestNode {
+    fn default() -> Self {
+        TestNode::new()
+    }
+}
+
+impl Drop for TestNode {
+    fn drop(&mut self) {
+        // Stopping cleanly first, so the datadir isn't in use when removed
+        let _ = self.client().stop();
+        let start = Instant::now();
+        while start.elapsed() < SHUTDOWN_TIMEOUT {
+            if let Ok(Some(_)) = self.process.try_wait() {
+                break;
+            }
+            thread::sleep(Duration::from_millis(100));
+        }
+        let _ = self.process.kill();
+        let _ = self.process.wait();
+        let _ = fs::remove_dir_all(&self.datadir);
+    }
+}
+
+// The OS hands out a free port for port 0. Another process could grab it
+// before bitcoind does, but that's unlikely enough for tests.
+pub fn free_port() -> u16 {
+    TcpListener::bind("127.0.0.1:0")
+        .and_then(|listener| listener.local_addr())
+        .expect("Failed to find a free port")
+        .port()
+}
@@ -0,0 +1,550 @@
+// Builds and signs transactions without the wallet, so everything but
+// broadcasting works offline
+use std::fmt;
+
+use bitcoincore_rpc::bitcoin::blockdata::opcodes::all::OP_CHECKMULTISIG;
+use bitcoincore_rpc::bitcoin::blockdata::script::{Builder, Instruction};
+use bitcoincore_rpc::bitcoin::util::schnorr::{SchnorrSig, TapTweak};
+use bitcoincore_rpc::bitcoin::util::sighash::{Prevouts, SchnorrSighashType, SighashCache};
+use bitcoincore_rpc::bitcoin::util::taproot::TapSighashHash;
+use bitcoincore_rpc::bitcoin::{
+    Address, EcdsaSig, EcdsaSighashType, Network, OutPoint, PackedLockTime, PublicKey, Script,
+    Sequence, Sighash, Transaction, TxIn, TxOut, Witness,
+};
+use secp256k1::{KeyPair, Message, Secp256k1, SecretKey, Signing, Verification};
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum SpendKind {
+    P2pkh,
+    P2wpkh,
+    P2shMulti {
+        threshold: usize,
+        pubkeys: Vec<PublicKey>,
+    },
+    P2wshMulti {
+        threshold: usize,
+        pubkeys: Vec<PublicKey>,
+    },
+    // Key path only, with no script tree like BIP86
+    P2tr,
+}
+
+impl SpendKind {
+    // The redeem or witness script of multisig kinds
+    pub fn multi_script(&self) -> Option<Script> {
+        match self {
+            SpendKind::P2shMulti { threshold, pubkeys }
+            | SpendKind::P2wshMulti { threshold, pubkeys } => {
+                Some(multi_script(*threshold, pubkeys))
+            }
+            _ => None,
+        }
+    }
+
+    // Where to send coins for this kind of spend, `pubkey` is only used by
+    // the single key kinds
+    pub fn address(&self, pubkey: Option<&PublicKey>) -> Address {
+        match self {
+            SpendKind::P2pkh => {
+                Address::p2pkh(pubkey.expect("P2PKH needs a key"), Network::Regtest)
+            }
+            SpendKind::P2wpkh => {
+                Address::p2wpkh(pubkey.expect("P2WPKH needs a key"), Network::Regtest)
+                    .expect("Uncompressed keys can't be used in segwit")
+            }
+            SpendKind::P2shMulti { .. } => {
+                Address::p2sh(&self.multi_script().unwrap(), Network::Regtest)
+                    .expect("Redeem script too large")
+            }
+            SpendKind::P2wshMulti { .. } => {
+                Address::p2wsh(&self.multi_script().unwrap(), Network::Regtest)
+            }
+            SpendKind::P2tr => {
+                let internal_key = pubkey
+                    .expect("P2TR needs a key")
+                    .inner
+                    .x_only_public_key()
+                    .0;
+                Address::p2tr(
+                    &Secp256k1::verification_only(),
+                    internal_key,
+                    None,
+                    Network::Regtest,
+                )
+            }
+        }
+    }
+
+    // Signs with BIP143 sighashes, taproot has its own
+    pub(crate) fn is_segwit(&self) -> bool {
+        matches!(self, SpendKind::P2wpkh | SpendKind::P2wshMulti { .. })
+    }
+}
+
+// An output to spend and how
+#[derive(Clone, Debug)]
+pub struct Spend {
+    pub outpoint: OutPoint,
+    pub prevout: TxOut,
+    pub kind: SpendKind,
+    pub sequence: Sequence,
+    pub sighash_type: EcdsaSighashType,
+}
+
+impl Spend {
+    pub fn new(outpoint: OutPoint, prevout: TxOut, kind: SpendKind) -> Self {
+        Spend {
+            outpoint,
+            prevout,
+            kind,
+            sequence: Sequence::ENABLE_RBF_NO_LOCKTIME,
+            sighash_type: EcdsaSighashType::All,
+        }
+    }
+
+    pub fn sequence(mut self, sequence: Sequence) -> Self {
+        self.sequence = sequence;
+        self
+    }
+
+    pub fn sighash_type(mut self, sighash_type: EcdsaSighashType) -> Self {
+        self.sighash_type = sighash_type;
+        self
+    }
+}
+
+#[derive(Debug)]
+pub enum BuildError {
+    // None of the keys given can sign the input
+    MissingKey {
+        input: usize,
+    },
+    NotEnoughKeys {
+        input: usize,
+        threshold: usize,
+        found: usize,
+    },
+    Sighash(String),
+    // testmempoolaccept turned it down
+    Rejected(String),
+    Rpc(String),
+}
+
+impl fmt::Display for BuildError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            BuildError::MissingKey { input } => write!(f, "No key for input {}", input),
+            BuildError::NotEnoughKeys {
+                input,
+                threshold,
+                found,
+            } => write!(
+                f,
+                "Input {} needs {} signatures, only have keys for {}",
+                input, threshold, found
+            ),
+            BuildError::Sighash(e) => write!(f, "Sighash error: {}", e),
+            BuildError::Rejected(reason) => write!(f, "Rejected by the mempool: {}", reason),
+            BuildError::Rpc(e) => write!(f, "RPC error: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for BuildError {}
+
+#[derive(Clone, Debug)]
+pub struct TxBuilder {
+    version: i32,
+    lock_time: PackedLockTime,
+    spends: Vec<Spend>,
+    outputs: Vec<TxOut>,
+}
+
+impl Default for TxBuilder {
+    fn default() -> Self {
+        TxBuilder::new()
+    }
+}
+
+impl TxBuilder {
+    pub fn new() -> Self {
+        TxBuilder {
+            version: 2,
+            lock_time: PackedLockTime::ZERO,
+            spends: Vec::new(),
+            outputs: Vec::new(),
+        }
+    }
+
+    pub fn version(mut self, version: i32) -> Self {
+        self.version = version;
+        self
+    }
+
+    pub fn lock_time(mut self, lock_time: u32) -> Self {
+        self.lock_time = PackedLockTime(lock_time);
+        self
+    }
+
+    pub fn spend(mut self, spend: Spend) -> Self {
+        self.spends.push(spend);
+        self
+    }
+
+    pub fn output(mut self, script_pubkey: Script, value: u64) -> Self {
+        self.outputs.push(TxOut {
+            value,
+            script_pubkey,
+        });
+        self
+    }
+
+    pub fn unsigned_tx(&self) -> Transaction {
+        Transaction {
+            version: self.version,
+            lock_time: self.lock_time,
+            input: self
+                .spends
+                .iter()
+                .map(|spend| TxIn {
+                    previous_output: spend.outpoint,
+                    script_sig: Script::new(),
+                    sequence: spend.sequence,
+                    witness: Witness::default(),
+                })
+                .collect(),
+            output: self.outputs.clone(),
+        }
+    }
+
+    // Signs every input with whichever of `keys` it needs. Multisig inputs
+    // take the first `threshold` matching keys, in script order.
+    pub fn sign<C: Signing + Verification>(
+        &self,
+        secp: &Secp256k1<C>,
+        keys: &[SecretKey],
+    ) -> Result<Transaction, BuildError> {
+        let keys: Vec<(SecretKey, PublicKey)> = keys
+            .iter()
+            .map(|sk| (*sk, PublicKey::new(sk.public_key(secp))))
+            .collect();
+        let unsigned = self.unsigned_tx();
+        let mut tx = unsigned.clone();
+
+        for (i, spend) in self.spends.iter().enumerate() {
+            let input = &mut tx.input[i];
+            match &spend.kind {
+                SpendKind::P2pkh | SpendKind::P2wpkh | SpendKind::P2tr => {
+                    let (sk, pk) = keys
+                        .iter()
+                        .find(|(_, pk)| {
+                            spend.kind.address(Some(pk)).script_pubkey()
+                                == spend.prevout.script_pubkey
+                        })
+                        .ok_or(BuildError::MissingKey { input: i })?;
+                    if spend.kind == SpendKind::P2tr {
+                        let prevouts: Vec<&TxOut> =
+                            self.spends.iter().map(|spend| &spend.prevout).collect();
+                        let sig = sign_taproot_input(secp, &unsigned, i, &prevouts, spend, sk)?;
+                        input.witness = Witness::from_vec(vec![sig]);
+                        continue;
+                    }
+                    let script_code = Address::p2pkh(pk, Network::Regtest).script_pubkey();
+                    let sig = sign_input(secp, &unsigned, i, spend, &script_code, sk)?;
+                    if spend.kind.is_segwit() {
+                        input.witness = Witness::from_vec(vec![sig, pk.to_bytes()]);
+                    } else {
+                        input.script_sig =
+                            Builder::new().push_slice(&sig).push_key(pk).into_script();
+                    }
+                }
+                SpendKind::P2shMulti { threshold, pubkeys }
+                | SpendKind::P2wshMulti { threshold, pubkeys } => {
+                    let script = multi_script(*threshold, pubkeys);
+                    let signers: Vec<&SecretKey> = pubkeys
+                        .iter()
+                        .filter_map(|pk| keys.iter().find(|(_, k)| k == pk).map(|(sk, _)| sk))
+                        .take(*threshold)
+                        .collect();
+                    if signers.len() < *threshold {
+                        return Err(BuildError::NotEnoughKeys {
+                            input: i,
+                            threshold: *threshold,
+                            found: signers.len(),
+                        });
+                    }
+                    let sigs = signers
+                        .into_iter()
+                        .map(|sk| sign_input(secp, &unsigned, i, spend, &script, sk))
+                        .collect::<Result<Vec<_>, _>>()?;
+                    finish_multisig(input, spend.kind.is_segwit(), &script, sigs);
+                }
+            }
+        }
+        Ok(tx)
+    }
+}
+
+// `OP_<threshold> <pubkeys..> OP_<n> OP_CHECKMULTISIG`
+pub fn multi_script(threshold: usize, pubkeys: &[PublicKey]) -> Script {
+    let mut builder = Builder::new().push_int(threshold as i64);
+    for pk in pubkeys {
+        builder = builder.push_key(pk);
+    }
+    builder
+        .push_int(pubkeys.len() as i64)
+        .push_opcode(OP_CHECKMULTISIG)
+        .into_script()
+}
+
+// The other way around, `None` for anything but a plain multisig script
+pub fn parse_multi_script(script: &Script) -> Option<(usize, Vec<PublicKey>)> {
+    let instructions = script.instructions().collect::<Result<Vec<_>, _>>().ok()?;
+    let (first, rest) = instructions.split_first()?;
+    let (last, rest) = rest.split_last()?;
+    let (n, keys) = rest.split_last()?;
+    let small_int = |instruction: &Instruction| match instruction {
+        Instruction::Op(op) if (0x51..=0x60).contains(&op.to_u8()) => {
+            Some((op.to_u8() - 0x50) as usize)
+        }
+        _ => None,
+    };
+    if *last != Instruction::Op(OP_CHECKMULTISIG) {
+        return None;
+    }
+    let (threshold, n) = (small_int(first)?, small_int(n)?);
+    let pubkeys = keys
+        .iter()
+        .map(|instruction| match instruction {
+            Instruction::PushBytes(bytes) => PublicKey::from_slice(bytes).ok(),
+            _ => None,
+        })
+        .collect::<Option<Vec<_>>>()?;
+    if pubkeys.len() != n || threshold > n {
+        return None;
+    }
+    Some((threshold, pubkeys))
+}
+
+// Puts `sigs`, in script order, where CHECKMULTISIG looks for them. The
+// leading empty item is eaten by its off-by-one bug.
+pub(crate) fn finish_multisig(input: &mut TxIn, segwit: bool, script: &Script, sigs: Vec<Vec<u8>>) {
+    if segwit {
+        let mut witness = vec![vec![]];
+        witness.extend(sigs);
+        witness.push(script.to_bytes());
+        input.witness = Witness::from_vec(witness);
+    } else {
+        let mut builder = Builder::new().push_int(0);
+        for sig in sigs.iter() {
+            builder = builder.push_slice(sig);
+        }
+        input.script_sig = builder.push_slice(script.as_bytes()).into_script();
+    }
+}
+
+// Pre-segwit sighash, `script_code` being the script pubkey or redeem script
+pub fn legacy_sighash(
+    tx: &Transaction,
+    input: usize,
+    script_code: &Script,
+    sighash_type: EcdsaSighashType,
+) -> Result<Sighash, BuildError> {
+    legacy_sighash_raw(tx, input, script_code, sighash_type.to_u32())
+}
+
+// Legacy sighashes commit to whatever 4 bytes the signature says, not just
+// the six standard flags
+fn legacy_sighash_raw(
+    tx: &Transaction,
+    input: usize,
+    script_code: &Script,
+    sighash_type: u32,
+) -> Result<Sighash, BuildError> {
+    SighashCache::new(tx)
+        .legacy_signature_hash(input, script_code, sighash_type)
+        .map_err(|e| BuildError::Sighash(e.to_string()))
+}
+
+// BIP143 sighash, which also commits to the value being spent
+pub fn segwit_sighash(
+    tx: &Transaction,
+    input: usize,
+    script_code: &Script,
+    value: u64,
+    sighash_type: EcdsaSighashType,
+) -> Result<Sighash, BuildError> {
+    SighashCache::new(tx)
+        .segwit_signature_hash(input, script_code, value, sighash_type)
+        .map_err(|e| BuildError::Sighash(e.to_string()))
+}
+
+// BIP341 sighash of a key path spend, committing to every input's prevout
+pub fn taproot_sighash(
+    tx: &Transaction,
+    input: usize,
+    prevouts: &[&TxOut],
+    sighash_type: SchnorrSighashType,
+) -> Result<TapSighashHash, BuildError> {
+    SighashCache::new(tx)
+        .taproot_key_spend_signature_hash(input, &Prevouts::All(prevouts), sighash_type)
+        .map_err(|e| BuildError::Sighash(e.to_string()))
+}
+
+// DER signature with the sighash type byte appended
+pub(crate) fn sign_input<C: Signing>(
+    secp: &Secp256k1<C>,
+    tx: &Transaction,
+    i: usize,
+    spend: &Spend,
+    script_code: &Script,
+    sk: &SecretKey,
+) -> Result<Vec<u8>, BuildError> {
+    let sighash = if spend.kind.is_segwit() {
+        segwit_sighash(tx, i, script_code, spend.prevout.value, spend.sighash_type)?
+    } else {
+        legacy_sighash(tx, i, script_code, spend.sighash_type)?
+    };
+    let msg = Message::from_slice(&sighash[..]).expect("Sighash is 32 bytes");
+    let sig = EcdsaSig {
+        sig: secp.sign_ecdsa(&msg, sk),
+        hash_ty: spend.sighash_type,
+    };
+    Ok(sig.to_vec())
+}
+
+// Schnorr signature by the tweaked key, with the sighash type byte appended
+// unless it's SIGHASH_DEFAULT
+fn sign_taproot_input<C: Signing + Verification>(
+    secp: &Secp256k1<C>,
+    tx: &Transaction,
+    i: usize,
+    prevouts: &[&TxOut],
+    spend: &Spend,
+    sk: &SecretKey,
+) -> Result<Vec<u8>, BuildError> {
+    // SIGHASH_ALL is implied by the shorter signature
+    let hash_ty = match spend.sighash_type {
+        EcdsaSighashType::All => SchnorrSighashType::Default,
+        other => SchnorrSighashType::from_consensus_u8(other.to_u32() as u8)
+            .map_err(|e| BuildError::Sighash(e.to_string()))?,
+    };
+    let sighash = taproot_sighash(tx, i, prevouts, hash_ty)?;
+    let msg = Message::from_slice(&sighash[..]).expect("Sighash is 32 bytes");
+    let keypair = KeyPair::from_secret_key(secp, sk)
+        .tap_tweak(secp, None)
+        .to_inner();
+    let sig = SchnorrSig {
+        sig: secp.sign_schnorr_no_aux_rand(&msg, &keypair),
+        hash_ty,
+    };
+    Ok(sig.to_vec())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bitcoincore_rpc::bitcoin::consensus::encode::deserialize;
+    use bitcoincore_rpc::bitcoin::hashes::hex::FromHex;
+    use bitcoincore_rpc::bitcoin::hashes::Hash;
+
+    fn tx(hex: &str) -> Transaction {
+        deserialize(&Vec::<u8>::from_hex(hex).unwrap()).unwrap()
+    }
+
+    fn script(hex: &str) -> Script {
+        Script::from(Vec::<u8>::from_hex(hex).unwrap())
+    }
+
+    // The native P2WPKH example of BIP143
+    const BIP143_TX: &str = "0100000002fff7f7881a8099afa6940d42d1e7f6362bec38171ea3edf433541db4e4ad969f0000000000eeffffffef51e1b804cc89d182d279655c3aa89e815b1b309fe287d9b2b55d57b90ec68a0100000000ffffffff02202cb206000000001976a9148280b37df378db99f66f85c95a783a76ac7a6d5988ac9093510d000000001976a9143bde42dbee7e4dbe6a21b2d50ce2f0167faa815988ac11000000";
+    const BIP143_SCRIPT_CODE: &str = "76a9141d0f172a0ecb48aee1be1f2687d2963ae33f71a188ac";
+
+    const FLAGS: [EcdsaSighashType; 6] = [
+        EcdsaSighashType::All,
+        EcdsaSighashType::None,
+        EcdsaSighashType::Single,
+        EcdsaSighashType::AllPlusAnyoneCanPay,
+        EcdsaSighashType::NonePlusAnyoneCanPay,
+        EcdsaSighashType::SinglePlusAnyoneCanPay,
+    ];
+
+    #[test]
+    fn segwit_sighash_bip143_p2wpkh() {
+        let sighash = segwit_sighash(
+            &tx(BIP143_TX),
+            1,
+            &script(BIP143_SCRIPT_CODE),
+            600_000_000,
+            EcdsaSighashType::All,
+        )
+        .unwrap();
+        // BIP143 lists the hash in byte order, not reversed like txids
+        assert_eq!(
+            sighash[..],
+            Vec::<u8>::from_hex("c37af31116d1b27caf68aae9e3ac82f1477929014d5b917657d0eb49478cb670")
+                .unwrap()[..]
+        );
+    }
+
+    // Generated with Bitcoin Core, one per standard flag
+    #[test]
+    fn segwit_sighash_flags() {
+        let tx = tx("0200000001cf309ee0839b8aaa3fbc84f8bd32e9c6357e99b49bf6a3af90308c68e762f1d70100000000feffffff0288528c61000000001600146e8d9e07c543a309dcdeba8b50a14a991a658c5be0aebb0000000000160014698d8419804a5d5994704d47947889ff7620c004db000000");
+        let script_code = script("76a91462744660c6b5133ddeaacbc57d2dc2d7b14d0b0688ac");
+        let expected = [
+            "0a1bc2758dbb5b3a56646f8cafbf63f410cc62b77a482f8b87552683300a7711",
+            "3e275ac8b084f79f756dcd535bffb615cc94a685eefa244d9031eaf22e4cec12",
+            "191a08165ffacc3ea55753b225f323c35fd00d9cc0268081a4a501921fc6ec14",
+            "4b6b612530f94470bbbdef18f57f2990d56b239f41b8728b9a49dc8121de4559",
+            "a7e916d3acd4bb97a21e6793828279aeab02162adf8099ea4f309af81f3d5adb",
+            "d9276e2a48648ddb53a4aaa58314fc2b8067c13013e1913ffb67e0988ce82c78",
+        ];
+        for (flag, expected) in FLAGS.into_iter().zip(expected) {
+            let sighash = segwit_sighash(&tx, 0, &script_code, 1_648_888_940, flag).unwrap();
+            assert_eq!(sighash, Sighash::from_hex(expected).unwrap(), "{:?}", flag);
+        }
+    }
+
+    // Vectors from Bitcoin Core's sighash.json. The hash types are random,
+    // and the expected hashes are in Core's reversed byte order.
+    #[test]
+    fn legacy_sighash_core_vectors() {
+        let vectors: [(&str, &str, usize, i32, &str); 6] = [
+            ("d3b7421e011f4de0f1cea9ba7458bf3486bee722519efab711a963fa8c100970cf7488b7bb0200000003525352dcd61b300148be5d05000000000000000000", "535251536aac536a", 0, -1960128125, "29aa6d2d752d3310eba20442770ad345b7f6a35f96161ede5f07b33e92053e2a"),
+            ("c363a70c01ab174230bbe4afe0c3efa2d7f2feaf179431359adedccf30d1f69efe0c86ed390200000002ab51558648fe0231318b04000000000151662170000000000008ac5300006a63acac00000000", "", 0, 2146479410, "191ab180b0d753763671717d051f138d4866b7cb0d1d4811472e64de595d2c70"),
+            ("a43f85f701ffa54a3cc57177510f3ea28ecb6db0d4431fc79171cad708a6054f6e5b4f89170000000008ac6a006a536551652bebeaa2013e779c05000000000665ac5363635100000000", "ac", 0, 2028978692, "58294f0d7f2e68fe1fd30c01764fe1619bcc7961d68968944a0e263af6550437"),
+            ("73107cbd025c22ebc8c3e0a47b2a760739216a528de8d4dab5d45cbeb3051cebae73b01ca10200000007ab6353656a636affffffffe26816dffc670841e6a6c8c61c586da401df1261a330a6c6b3dd9f9a0789bc9e000000000800ac6552ac6aac51ffffffff0174a8f0010000000004ac52515100000000", "5163ac63635151ac", 1, 1190874345, "06e328de263a87b09beabe222a21627a6ea5c7f560030da31610c4611f4a46bc"),
+            ("a93e93440250f97012d466a6cc24839f572def241c814fe6ae94442cf58ea33eb0fdd9bcc1030000000600636a0065acffffffff5dee3a6e7e5ad6310dea3e5b3ddda1a56bf8de7d3b75889fc024b5e233ec10f80300000007ac53635253ab53ffffffff0160468b04000000000800526a5300ac526a00000000", "ac00636a53", 1, 1773442520, "5c9d3a2ce9365bb72cfabbaa4579c843bb8abf200944612cf8ae4b56a908bcbd"),
+            ("9e45d9aa0248c16dbd7f435e8c54ae1ad086de50c7b25795a704f3d8e45e1886386c653fbf01000000025352fb4a1acefdd27747b60d1fb79b96d14fb88770c75e0da941b7803a513e6d4c908c6445c7010000000163ffffffff014069a8010000000001520a794fb3", "51ac005363", 1, -719113284, "0d31a221c69bd322ef7193dd7359ddfefec9e0a1521d4a8740326d46e44a5d6a"),
+        ];
+        for (tx_hex, script_hex, input, hash_type, expected) in vectors {
+            let mut expected = Vec::<u8>::from_hex(expected).unwrap();
+            expected.reverse();
+            let sighash =
+                legacy_sighash_raw(&tx(tx_hex), input, &script(script_hex), hash_type as u32)
+                    .unwrap();
+            assert_eq!(sighash, Sighash::from_slice(&expected).unwrap());
+        }
+    }
+
+    #[test]
+    fn every_flag_commits_to_something_different() {
+        let tx = tx(BIP143_TX);
+        let script_code = script(BIP143_SCRIPT_CODE);
+        let mut legacy: Vec<_> = FLAGS
+            .iter()
+            .map(|flag| legacy_sighash(&tx, 0, &script_code, *flag).unwrap())
+            .collect();
+        let mut segwit: Vec<_> = FLAGS
+            .iter()
+            .map(|flag| segwit_sighash(&tx, 1, &script_code, 600_000_000, *flag).unwrap())
+            .collect();
+        legacy.sort();
+        legacy.dedup();
+        segwit.sort();
+        segwit.dedup();
+        assert_eq!(legacy.len(), FLAGS.len());
+        assert_eq!(segwit.len(), FLAGS.len());
+    }
+}
@@ -0,0 +1,202 @@
+// Wallet management beyond the one "test_wallet": several named wallets,
+// watch-only ones and clients scoped to each
+use std::fmt;
+use std::ops::Deref;
+use std::sync::Arc;
+
+use bitcoincore_rpc::{Auth, Client};
+use serde_json::{json, Value};
+
+use crate::{rpc_url, RPC_PASSWORD, RPC_USER};
+
+// What the workshop used before there was a choice
+pub const DEFAULT_WALLET_NAME: &str = "test_wallet";
+
+#[derive(Clone, Debug)]
+pub struct WalletOptions {
+    // Descriptor wallet, or legacy BDB one
+    pub descriptors: bool,
+    pub disable_private_keys: bool,
+    // Without keys or descriptors until some are imported
+    pub blank: bool,
+    pub passphrase: Option<String>,
+}
+
+impl Default for WalletOptions {
+    fn default() -> Self {
+        WalletOptions {
+            descriptors: true,
+            disable_private_keys: false,
+            blank: false,
+            passphrase: None,
+        }
+    }
+}
+
+impl WalletOptions {
+    // Newer bitcoind needs `-deprecatedrpc=create_bdb` for these
+    pub fn legacy() -> Self {
+        WalletOptions {
+            descriptors: false,
+            ..WalletOptions::default()
+        }
+    }
+
+    // For descriptors imported later on
+    pub fn watch_only() -> Self {
+        WalletOptions {
+            disable_private_keys: true,
+            blank: true,
+            ..WalletOptions::default()
+        }
+    }
+
+    pub fn blank(mut self) -> Self {
+        self.blank = true;
+        self
+    }
+
+    pub fn passphrase(mut self, passphrase: &str) -> Self {
+        self.passphrase = Some(passphrase.to_owned());
+        self
+    }
+}
+
+// One request of importdescriptors
+#[derive(Clone, Debug)]
+pub struct DescriptorImport {
+    // With its checksum
+    pub desc: String,
+    pub range: Option<(u32, u32)>,
+    pub internal: bool,
+    pub active: bool,
+    // Rescans from this block time, `None` for no rescan
+    pub timestamp: Option<u64>,
+    pub label: Option<String>,
+}
+
+impl DescriptorImport {
+    pub fn new(desc: &str) -> Self {
+        DescriptorImport {
+            desc: desc.to_owned(),
+            range: None,
+            internal: false,
+            active: false,
+            timestamp: None,
+            label: None,
+        }
+    }
+
+    pub fn range(mut self, start: u32, end: u32) -> Self {
+        self.range = Some((start, end));
+        self
+    }
+
+    // For change
+    pub fn internal(mut self) -> Self {
+        self.internal = true;
+        self
+    }
+
+    // Hands out new addresses from this descriptor
+    pub fn active(mut self) -> Self {
+        self.active = true;
+        self
+    }
+
+    pub fn rescan_from(mut self, timestamp: u64) -> Self {
+        self.timestamp = Some(timestamp);
+        self
+    }
+
+    pub fn label(mut self, label: &str) -> Self {
+        self.label = Some(label.to_owned());
+        self
+    }
+
+    pub fn to_json(&self) -> Value {
+        let mut request = json!({
+            "desc": self.desc,
+            "internal": self.internal,
+            "active": self.active,
+        });
+        request["timestamp"] = match self.timestamp {
+            Some(timestamp) => json!(timestamp),
+            None => json!("now"),
+        };
+        if let Some((start, end)) = self.range {
+            request["range"] = json!([start, end]);
+        }
+        if let Some(label) = &self.label {
+            request["label"] = json!(label);
+        }
+        request
+    }
+}
+
+#[derive(Debug)]
+pub enum WalletError {
+    Rpc(String),
+    Import { desc: String, error: String },
+}
+
+impl fmt::Display for WalletError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            WalletError::Rpc(e) => write!(f, "RPC error: {}", e),
+            WalletError::Import { desc, error } => {
+                write!(f, "Failed to import {}: {}", desc, error)
+            }
+        }
+    }
+}
+
+impl std::error::Error for WalletError {}
+
+// A client on one wallet's RPC path, cheap to clone and fine to share between
+// threads. Derefs to `Client`, so `RpcApi` and `BitcoinClient` calls go
+// straight to the wallet.
+#[derive(Clone)]
+pub struct WalletHandle {
+    name: String,
+    client: Arc<Client>,
+}
+
+impl WalletHandle {
+    // On the node at `rpc_url()`
+    pub fn new(wallet_name: &str) -> WalletHandle {
+        WalletHandle::at(&rpc_url(), wallet_name)
+    }
+
+    pub fn at(node_url: &str, wallet_name: &str) -> WalletHandle {
+        let client = Client::new(
+            &format!("{}/wallet/{}", node_url, wallet_name),
+            Auth::UserPass(RPC_USER.to_owned(), RPC_PASSWORD.to_owned()),
+        )
+        .expect("Failed to create RPC client");
+        WalletHandle {
+            name: wallet_name.to_owned(),
+            client: Arc::new(client),
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+}
+
+impl Deref for WalletHandle {
+    type Target = Client;
+
+    fn deref(&self) -> &Client {
+        &self.client
+    }
+}
+
+impl fmt::Debug for WalletHandle {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("WalletHandle")
+            .field("name", &self.name)
+            .finish()
+    }
+}
@@ -0,0 +1,209 @@
+// Chain and mempool notifications from bitcoind's ZMQ publisher, instead of
+// polling RPC
+use std::fmt;
+use std::time::Duration;
+
+use bitcoincore_rpc::bitcoin::consensus::encode::deserialize;
+use bitcoincore_rpc::bitcoin::hashes::Hash;
+use bitcoincore_rpc::bitcoin::{Block, BlockHash, Transaction, Txid};
+use zeromq::{Socket, SocketRecv, SubSocket};
+
+// Where bitcoind is usually told to publish, with `-zmqpub<topic>=`
+pub const ZMQ_URL: &str = "tcp://127.0.0.1:28332";
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Topic {
+    RawBlock,
+    RawTx,
+    HashBlock,
+    Sequence,
+}
+
+pub const ALL_TOPICS: [Topic; 4] = [
+    Topic::RawBlock,
+    Topic::RawTx,
+    Topic::HashBlock,
+    Topic::Sequence,
+];
+
+impl Topic {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Topic::RawBlock => "rawblock",
+            Topic::RawTx => "rawtx",
+            Topic::HashBlock => "hashblock",
+            Topic::Sequence => "sequence",
+        }
+    }
+
+    // The bitcoind option publishing this topic to `endpoint`
+    pub fn bitcoind_arg(self, endpoint: &str) -> String {
+        format!("-zmqpub{}={}", self.as_str(), endpoint)
+    }
+
+    fn from_bytes(bytes: &[u8]) -> Option<Topic> {
+        ALL_TOPICS
+            .into_iter()
+            .find(|topic| topic.as_str().as_bytes() == bytes)
+    }
+}
+
+#[derive(Clone, Debug)]
+pub enum ChainEvent {
+    RawBlock(Block),
+    // Sent for txs entering the mempool and for those in connected blocks
+    RawTx(Transaction),
+    HashBlock(BlockHash),
+    // The rest come from the sequence topic
+    BlockConnected(BlockHash),
+    BlockDisconnected(BlockHash),
+    MempoolAdded { txid: Txid, sequence: u64 },
+    // Only for evictions and replacements, not for txs mined in a block
+    MempoolRemoved { txid: Txid, sequence: u64 },
+}
+
+#[derive(Debug)]
+pub enum SubscriberError {
+    Socket(String),
+    Decode(String),
+    Timeout,
+}
+
+impl fmt::Display for SubscriberError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SubscriberError::Socket(e) => write!(f, "ZMQ socket error: {}", e),
+            SubscriberError::Decode(e) => write!(f, "Invalid ZMQ message: {}", e),
+            SubscriberError::Timeout => write!(f, "Timed out waiting for ZMQ event"),
+        }
+    }
+}
+
+impl std::error::Error for SubscriberError {}
+
+pub struct Subscriber {
+    socket: SubSocket,
+}
+
+impl Subscriber {
+    // bitcoind only publishes the topics it was started with options for,
+    // see `Topic::bitcoind_arg`
+    pub async fn connect(endpoint: &str, topics: &[Topic]) -> Result<Subscriber, SubscriberError> {
+        let mut socket = SubSocket::new();
+        tokio::time::timeout(CONNECT_TIMEOUT, socket.connect(endpoint))
+            .await
+            .map_err(|_| SubscriberError::Socket(format!("Can't reach {}", endpoint)))?
+            .map_err(|e| SubscriberError::Socket(e.to_string()))?;
+        for topic in topics {
+            socket
+                .subscribe(topic.as_str())
+                .await
+                .map_err(|e| SubscriberError::Socket(e.to_string()))?;
+        }
+        Ok(Subscriber { socket })
+    }
+
+    pub async fn next(&mut self) -> Result<ChainEvent, SubscriberError> {
+        let message = self
+            .socket
+            .recv()
+            .await
+            .map_err(|e| SubscriberError::Socket(e.to_string()))?;
+        // Topic, body, then a sequence number per topic we don't need
+        let (topic, body) = match (message.get(0), message.get(1)) {
+            (Some(topic), Some(body)) => (topic, body),
+            _ => return Err(SubscriberError::Decode("Missing frames".to_owned())),
+        };
+        let topic = Topic::from_bytes(topic).ok_or_else(|| {
+            SubscriberError::Decode(format!("Unknown topic {}", String::from_utf8_lossy(topic)))
+        })?;
+        parse(topic, body)
+    }
+
+    // Needs the sequence topic. Subscribe before sending `txid`, or its
+    // event could go by before anyone listens.
+    pub async fn wait_for_mempool(
+        &mut self,
+        txid: &Txid,
+        timeout: Duration,
+    ) -> Result<(), SubscriberError> {
+        tokio::time::timeout(timeout, async {
+            loop {
+                if let ChainEvent::MempoolAdded { txid: added, .. } = self.next().await? {
+                    if added == *txid {
+                        return Ok(());
+                    }
+                }
+            }
+        })
+        .await
+        .map_err(|_| SubscriberError::Timeout)?
+    }
+
+    // The next block connected, from either the hashblock or the sequence
+    // topic
+    pub async fn wait_for_block(
+        &mut self,
+        timeout: Duration,
+    ) -> Result<BlockHash, SubscriberError> {
+        tokio::time::timeout(timeout, async {
+            loop {
+                match self.next().await? {
+                    ChainEvent::HashBlock(hash) | ChainEvent::BlockConnected(hash) => {
+                        return Ok(hash)
+                    }
+                    ChainEvent::RawBlock(block) => return Ok(block.block_hash()),
+                    _ => {}
+                }
+            }
+        })
+        .await
+        .map_err(|_| SubscriberError::Timeout)?
+    }
+}
+
+fn parse(topic: Topic, body: &[u8]) -> Result<ChainEvent, SubscriberError> {
+    let decode = |e: &dyn fmt::Display| SubscriberError::Decode(e.to_string());
+    match topic {
+        Topic::RawBlock => deserialize(body)
+            .map(ChainEvent::RawBlock)
+            .map_err(|e| decode(&e)),
+        Topic::RawTx => deserialize(body)
+            .map(ChainEvent::RawTx)
+            .map_err(|e| decode(&e)),
+        Topic::HashBlock => Ok(ChainEvent::HashBlock(rpc_order_hash(body)?)),
+        // A hash, a label and for mempool events the mempool sequence number
+        Topic::Sequence => {
+            if body.len() < 33 {
+                return Err(decode(&"Short sequence message"));
+            }
+            let (hash, label) = (&body[..32], body[32]);
+            let mempool_sequence = || {
+                body.get(33..41)
+                    .map(|bytes| u64::from_le_bytes(bytes.try_into().unwrap()))
+                    .ok_or_else(|| decode(&"Missing mempool sequence"))
+            };
+            match label {
+                b'C' => Ok(ChainEvent::BlockConnected(rpc_order_hash(hash)?)),
+                b'D' => Ok(ChainEvent::BlockDisconnected(rpc_order_hash(hash)?)),
+                b'A' => Ok(ChainEvent::MempoolAdded {
+                    txid: rpc_order_hash(hash)?,
+                    sequence: mempool_sequence()?,
+                }),
+                b'R' => Ok(ChainEvent::MempoolRemoved {
+                    txid: rpc_order_hash(hash)?,
+                    sequence: mempool_sequence()?,
+                }),
+                other => Err(decode(&format!("Unknown sequence label {}", other as char))),
+            }
+        }
+    }
+}
+
+// ZMQ sends hashes the way RPC shows them, backwards from how they're hashed
+fn rpc_order_hash<H: Hash>(bytes: &[u8]) -> Result<H, SubscriberError> {
+    let mut bytes = bytes.to_vec();
+    bytes.reverse();
+    H::from_slice(&bytes).map_err(|e| SubscriberError::Decode(e.to_string()))
+}
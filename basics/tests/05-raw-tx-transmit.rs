@@ -27,8 +27,10 @@
 
 use std::ops::Sub;
 
-use bitcoin_basics::BitcoinClient;
-use bitcoincore_rpc::{bitcoin::Amount, Client, RpcApi};
+use bitcoin_basics::coin_selection::{self, Utxo};
+use bitcoin_basics::{BitcoinClient, DEFAULT_FEERATE};
+use bitcoincore_rpc::bitcoin::{Amount, TxOut};
+use bitcoincore_rpc::{Client, RpcApi};
 
 fn main() {
     let client = Client::setup();
@@ -41,11 +43,18 @@ fn main() {
         .unwrap();
     assert!(utxos.len() > 0);
     let utxo = utxos.first().unwrap();
-    // Set aside some fee
-    let amount = utxo.amount.sub(Amount::from_sat(100_000));
+    let address = client.get_new_address(None, None).unwrap();
 
+    // Set aside the fee for one input and one output, see 07
+    let input = Utxo::from_unspent(utxo).unwrap();
+    let output = TxOut {
+        value: 0,
+        script_pubkey: address.script_pubkey(),
+    };
+    let weight = coin_selection::tx_weight(&[input], &[output]);
+    let fee = coin_selection::fee(weight, DEFAULT_FEERATE);
+    let amount = utxo.amount.sub(Amount::from_sat(fee));
 
-    let address = client.get_new_address(None, None).unwrap();
     client.transmit_raw_transaction(utxos.first().unwrap(), &address, amount);
 
     let bal = client.get_received_by_address(&address, None).unwrap();
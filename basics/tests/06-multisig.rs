@@ -73,8 +73,10 @@
 
 use std::ops::Sub;
 
-use bitcoincore_rpc::{bitcoin::Amount, Client, RpcApi};
-use bitcoin_basics::BitcoinClient;
+use bitcoincore_rpc::bitcoin::{Amount, OutPoint, TxOut};
+use bitcoincore_rpc::{Client, RpcApi};
+use bitcoin_basics::coin_selection::{self, Utxo};
+use bitcoin_basics::{BitcoinClient, DEFAULT_FEERATE};
 use secp256k1::{rand, Secp256k1};
 
 fn main() {
@@ -100,7 +102,25 @@ fn main() {
     let (vout, value, txid, res) = client.multi_sig_tx(2, &pub_keys);
 
     let to = client.get_new_address(None, None).unwrap();
-    let amount = Amount::from_sat(value).sub(Amount::from_sat(100_000));
+
+    // Set aside the fee for spending 2 of 3 to one output, see 07
+    let script_pubkey = res.address.script_pubkey();
+    let input = Utxo {
+        outpoint: OutPoint::new(txid, vout as u32),
+        weight: coin_selection::multisig_input_weight(&script_pubkey, &res.redeem_script, 2)
+            .unwrap(),
+        txout: TxOut {
+            value,
+            script_pubkey,
+        },
+    };
+    let output = TxOut {
+        value: 0,
+        script_pubkey: to.script_pubkey(),
+    };
+    let weight = coin_selection::tx_weight(&[input], &[output]);
+    let fee = coin_selection::fee(weight, DEFAULT_FEERATE);
+    let amount = Amount::from_sat(value).sub(Amount::from_sat(fee));
 
     // Spend the from multi sig address
     client.spend_multisig(txid, vout, &to, amount, res, signers);
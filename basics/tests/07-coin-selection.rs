@@ -0,0 +1,143 @@
+// Coin selection
+//
+// Instead of setting aside a made up fee, pick the inputs and work out the
+// fee from the weight of the transaction.
+//
+//  pub fn select(
+//      utxos: &[Utxo],
+//      outputs: &[TxOut],
+//      change_script: &Script,
+//      feerate: f64,
+//      algorithm: Algorithm,
+//  ) -> Result<Selection, SelectionError>;
+//
+//  pub trait BitcoinClient {
+//      fn fund_transaction(
+//          &self,
+//          outputs: Vec<TxOut>,
+//          feerate: f64,
+//          algorithm: Algorithm,
+//      ) -> Result<(Transaction, Selection), SelectionError>;
+//  }
+//
+//  `select` supports branch and bound, largest first and random improve.
+//  Change goes to a fresh wallet address, unless it would be dust or costs
+//  more to create and spend than it's worth.
+//
+// RESOURCES:
+//
+//  - https://bitcoin.stackexchange.com/questions/32145/what-are-the-current-rules-for-coin-selection
+//
+//  - https://murch.one/erhardt2016coinselection.pdf
+//
+//  - https://cips.cardano.org/cips/cip2/#random-improve
+//
+//  - https://github.com/bitcoin/bips/blob/master/bip-0141.mediawiki#transaction-size-calculations
+//
+//  - https://github.com/bitcoin/bitcoin/blob/master/src/policy/policy.cpp
+//
+
+use bitcoin_basics::coin_selection::{self, Algorithm, Utxo};
+use bitcoin_basics::BitcoinClient;
+use bitcoincore_rpc::bitcoin::{Amount, OutPoint, TxOut};
+use bitcoincore_rpc::{Client, RpcApi};
+
+fn main() {
+    let client = Client::setup();
+    let wallet_name = "test_wallet";
+    client.load_wallet_in_node(wallet_name);
+    client.get_dough_if_broke();
+
+    let address = client.get_new_address(None, None).unwrap();
+    let change_script = client.get_raw_change_address(None).unwrap().script_pubkey();
+    let utxo = |value: u64| Utxo {
+        outpoint: OutPoint::null(),
+        txout: TxOut {
+            value,
+            script_pubkey: address.script_pubkey(),
+        },
+        weight: coin_selection::input_weight(&address.script_pubkey()).unwrap(),
+    };
+    let utxos = vec![utxo(50_000), utxo(30_000), utxo(20_000), utxo(1_000_000)];
+    let output = TxOut {
+        value: 79_800,
+        script_pubkey: address.script_pubkey(),
+    };
+
+    // 50k and 30k cover it with no change worth making
+    let selection = coin_selection::select(
+        &utxos,
+        &[output.clone()],
+        &change_script,
+        1.0,
+        Algorithm::BranchAndBound,
+    )
+    .unwrap();
+    let mut values: Vec<u64> = selection.inputs.iter().map(|u| u.txout.value).collect();
+    values.sort_unstable();
+    assert_eq!(values, vec![30_000, 50_000]);
+    assert_eq!(selection.change, None);
+    assert_eq!(selection.fee, 200);
+
+    // The 1M output alone, with change
+    let selection = coin_selection::select(
+        &utxos,
+        &[output.clone()],
+        &change_script,
+        1.0,
+        Algorithm::LargestFirst,
+    )
+    .unwrap();
+    assert_eq!(selection.inputs.len(), 1);
+    let change = selection.change.unwrap();
+    assert_eq!(1_000_000, 79_800 + change + selection.fee);
+    assert_eq!(selection.fee, coin_selection::fee(selection.weight, 1.0));
+
+    for _ in 0..10 {
+        let selection = coin_selection::select(
+            &utxos,
+            &[output.clone()],
+            &change_script,
+            1.0,
+            Algorithm::RandomImprove,
+        )
+        .unwrap();
+        let total: u64 = selection.inputs.iter().map(|u| u.txout.value).sum();
+        assert_eq!(
+            total,
+            79_800 + selection.change.unwrap_or(0) + selection.fee
+        );
+    }
+
+    // Not enough
+    let too_much = TxOut {
+        value: 2_000_000,
+        ..output
+    };
+    assert!(coin_selection::select(
+        &utxos,
+        &[too_much],
+        &change_script,
+        1.0,
+        Algorithm::LargestFirst
+    )
+    .is_err());
+
+    // From the wallet, the fee matches the size bitcoind sees within a few
+    // sats of signature length slack
+    let to = client.get_new_address(None, None).unwrap();
+    let output = TxOut {
+        value: Amount::ONE_BTC.to_sat(),
+        script_pubkey: to.script_pubkey(),
+    };
+    let (tx, selection) = client
+        .fund_transaction(vec![output], 5.0, Algorithm::BranchAndBound)
+        .unwrap();
+    assert!(tx.weight() <= selection.weight);
+    assert!(selection.fee >= coin_selection::fee(tx.weight(), 5.0));
+    client.send_raw_transaction(&tx).unwrap();
+    client.mine_blocks(1);
+
+    let bal = client.get_received_by_address(&to, None).unwrap();
+    assert_eq!(bal, Amount::ONE_BTC);
+}
@@ -0,0 +1,122 @@
+// Fee bumping
+//
+// A transaction paying too little sits in the mempool. There are two ways to
+// get it mined without waiting: replace it with one paying more (RBF), or
+// spend its change with a child paying for both (CPFP).
+//
+//  pub trait BitcoinClient {
+//      fn send_unconfirmed(
+//          &self,
+//          address: &Address,
+//          amount: Amount,
+//          feerate: f64,
+//      ) -> Result<Txid, SelectionError>;
+//      fn bump_fee_rbf(&self, txid: &Txid, new_feerate: f64) -> Result<Txid, FeeBumpError>;
+//      fn bump_fee_cpfp(&self, txid: &Txid, new_package_feerate: f64) -> Result<Txid, FeeBumpError>;
+//      fn package_feerate(&self, txid: &Txid) -> f64;
+//  }
+//
+//  The replacement takes the extra fee out of the change, and has to pay at
+//  least the incremental relay fee on top of the original. The child spends
+//  the change of the parent to a fresh change address.
+//
+//  `transfer` mines its tx straight away, so there's nothing left to bump.
+//  `send_unconfirmed` leaves it in the mempool instead. A raw tx like the
+//  one from exercise 05 is only replaceable if one of its inputs has a
+//  sequence below 0xfffffffe, e.g. `Sequence::ENABLE_RBF_NO_LOCKTIME`.
+//
+// RESOURCES:
+//
+//  - https://github.com/bitcoin/bips/blob/master/bip-0125.mediawiki
+//
+//  - https://bitcoinops.org/en/topics/replace-by-fee/
+//
+//  - https://bitcoinops.org/en/topics/cpfp/
+//
+
+use bitcoin_basics::coin_selection::Algorithm;
+use bitcoin_basics::fee_bump::{self, FeeBumpError};
+use bitcoin_basics::BitcoinClient;
+use bitcoincore_rpc::bitcoin::{Amount, Sequence, TxOut};
+use bitcoincore_rpc::{Client, RpcApi};
+
+fn main() {
+    let client = Client::setup();
+    let wallet_name = "test_wallet";
+    client.load_wallet_in_node(wallet_name);
+    client.get_dough_if_broke();
+
+    let to = client.get_new_address(None, None).unwrap();
+    let output = TxOut {
+        value: Amount::ONE_BTC.to_sat(),
+        script_pubkey: to.script_pubkey(),
+    };
+
+    // RBF: no mining until the replacement is in
+    let (tx, _) = client
+        .fund_transaction(vec![output.clone()], 1.0, Algorithm::LargestFirst)
+        .unwrap();
+    let original = client.send_raw_transaction(&tx).unwrap();
+    let replacement = client.bump_fee_rbf(&original, 10.0).unwrap();
+
+    let mempool = client.get_raw_mempool().unwrap();
+    assert!(!mempool.contains(&original));
+    assert!(mempool.contains(&replacement));
+    assert!(client.package_feerate(&replacement) >= 10.0);
+
+    // The same outputs still get paid
+    let replaced = client.get_raw_transaction(&replacement, None).unwrap();
+    assert!(replaced.output.contains(&output));
+    client.mine_blocks(1);
+    let bal = client.get_received_by_address(&to, None).unwrap();
+    assert_eq!(bal, Amount::ONE_BTC);
+
+    // CPFP: the parent stays, the child pulls the package up
+    let (tx, _) = client
+        .fund_transaction(vec![output.clone()], 1.0, Algorithm::LargestFirst)
+        .unwrap();
+    let parent = client.send_raw_transaction(&tx).unwrap();
+    assert!(client.package_feerate(&parent) < 2.0);
+    let child = client.bump_fee_cpfp(&parent, 20.0).unwrap();
+
+    let mempool = client.get_raw_mempool().unwrap();
+    assert!(mempool.contains(&parent));
+    assert!(mempool.contains(&child));
+    let entry = client.get_mempool_entry(&child).unwrap();
+    assert_eq!(entry.ancestor_count, 2);
+    assert!(client.package_feerate(&child) >= 20.0);
+
+    let child_fee = entry.fees.base.to_sat();
+    let child_vsize = entry.vsize;
+    assert!(fee_bump::feerate(child_fee, child_vsize) > 20.0);
+    client.mine_blocks(1);
+    assert!(client.get_raw_mempool().unwrap().is_empty());
+
+    // Final sequence numbers opt out of replacement
+    let (mut tx, _) = client
+        .fund_transaction(vec![output], 1.0, Algorithm::LargestFirst)
+        .unwrap();
+    for input in tx.input.iter_mut() {
+        input.sequence = Sequence::MAX;
+        input.script_sig = Default::default();
+        input.witness = Default::default();
+    }
+    let tx = client
+        .sign_raw_transaction_with_wallet(&tx, None, None)
+        .unwrap()
+        .transaction()
+        .unwrap();
+    let txid = client.send_raw_transaction(&tx).unwrap();
+    assert!(matches!(
+        client.bump_fee_rbf(&txid, 10.0),
+        Err(FeeBumpError::NotReplaceable(_))
+    ));
+    client.mine_blocks(1);
+
+    // Left in the mempool, unlike with `transfer`
+    let txid = client.send_unconfirmed(&to, Amount::ONE_BTC, 1.0).unwrap();
+    assert!(client.get_raw_mempool().unwrap().contains(&txid));
+    let tx = client.get_raw_transaction(&txid, None).unwrap();
+    assert!(tx.input.iter().all(|input| input.sequence.is_rbf()));
+    client.mine_blocks(1);
+}
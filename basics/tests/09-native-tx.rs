@@ -0,0 +1,150 @@
+// Building transactions without the wallet
+//
+// So far bitcoind has built and signed everything for us. This time we put
+// the transaction together ourselves, work out what each input signs and
+// sign it with our own keys. The node only gets to check and broadcast it.
+//
+//  pub struct TxBuilder { .. }
+//
+//  impl TxBuilder {
+//      pub fn spend(self, spend: Spend) -> Self;
+//      pub fn output(self, script_pubkey: Script, value: u64) -> Self;
+//      pub fn sign<C: Signing + Verification>(
+//          &self,
+//          secp: &Secp256k1<C>,
+//          keys: &[SecretKey],
+//      ) -> Result<Transaction, BuildError>;
+//  }
+//
+//  pub fn legacy_sighash(..) -> Result<Sighash, BuildError>;
+//  pub fn segwit_sighash(..) -> Result<Sighash, BuildError>;
+//
+//  pub trait BitcoinClient {
+//      fn accept_and_send(&self, tx: &Transaction) -> Result<Txid, BuildError>;
+//  }
+//
+//  Inputs can be P2PKH, P2WPKH, P2SH multisig, P2WSH multisig or taproot key
+//  path spends, each with its own sighash type.
+//
+// RESOURCES:
+//
+//  - https://en.bitcoin.it/wiki/OP_CHECKSIG
+//
+//  - https://github.com/bitcoin/bips/blob/master/bip-0143.mediawiki
+//
+//  - https://github.com/bitcoin/bips/blob/master/bip-0016.mediawiki
+//
+//  - https://developer.bitcoin.org/reference/rpc/testmempoolaccept.html
+//
+
+use bitcoin_basics::tx_builder::{BuildError, Spend, SpendKind, TxBuilder};
+use bitcoin_basics::BitcoinClient;
+use bitcoincore_rpc::bitcoin::{Address, Amount, EcdsaSighashType, OutPoint, PublicKey};
+use bitcoincore_rpc::{Client, RpcApi};
+use secp256k1::{rand, Secp256k1};
+
+fn main() {
+    let flags = [
+        EcdsaSighashType::All,
+        EcdsaSighashType::None,
+        EcdsaSighashType::Single,
+        EcdsaSighashType::AllPlusAnyoneCanPay,
+        EcdsaSighashType::NonePlusAnyoneCanPay,
+        EcdsaSighashType::SinglePlusAnyoneCanPay,
+    ];
+
+    // Coins locked four ways, spent in one tx
+    let client = Client::setup();
+    let wallet_name = "test_wallet";
+    client.load_wallet_in_node(wallet_name);
+    client.get_dough_if_broke();
+
+    let secp = Secp256k1::new();
+    let mut secrets = vec![];
+    let mut pubkeys = vec![];
+    for _ in 0..5 {
+        let (sk, pk) = secp.generate_keypair(&mut rand::thread_rng());
+        secrets.push(sk);
+        pubkeys.push(PublicKey::new(pk));
+    }
+    let multisig = pubkeys[2..5].to_vec();
+    let kinds = [
+        (SpendKind::P2pkh, Some(pubkeys[0])),
+        (SpendKind::P2wpkh, Some(pubkeys[1])),
+        (
+            SpendKind::P2shMulti {
+                threshold: 2,
+                pubkeys: multisig.clone(),
+            },
+            None,
+        ),
+        (
+            SpendKind::P2wshMulti {
+                threshold: 2,
+                pubkeys: multisig,
+            },
+            None,
+        ),
+    ];
+
+    let mut builder = TxBuilder::new();
+    for ((kind, pubkey), flag) in kinds.into_iter().zip(flags) {
+        let address = kind.address(pubkey.as_ref());
+        let spend = fund(&client, &address, kind).sighash_type(flag);
+        builder = builder.spend(spend);
+    }
+    // Enough outputs for the SINGLE flags to have one each
+    let to: Vec<Address> = (0..4)
+        .map(|_| client.get_new_address(None, None).unwrap())
+        .collect();
+    for address in to.iter() {
+        builder = builder.output(address.script_pubkey(), 9_900_000);
+    }
+
+    // One of the multisig keys is enough for neither
+    let not_enough = [secrets[0], secrets[1], secrets[2]];
+    assert!(matches!(
+        builder.sign(&secp, &not_enough),
+        Err(BuildError::NotEnoughKeys { input: 2, .. })
+    ));
+
+    let keys = [secrets[0], secrets[1], secrets[2], secrets[4]];
+    let tx = builder.sign(&secp, &keys).unwrap();
+    // Signing is deterministic
+    assert_eq!(tx, builder.sign(&secp, &keys).unwrap());
+
+    // Tampering with a committed output gets it rejected
+    let mut tampered = tx.clone();
+    tampered.output[0].value -= 1;
+    assert!(matches!(
+        client.accept_and_send(&tampered),
+        Err(BuildError::Rejected(_))
+    ));
+
+    client.accept_and_send(&tx).unwrap();
+    client.mine_blocks(1);
+    for address in to.iter() {
+        let bal = client.get_received_by_address(address, None).unwrap();
+        assert_eq!(bal, Amount::from_sat(9_900_000));
+    }
+}
+
+// Sends 0.1 BTC to `address` and returns the output to spend
+fn fund(client: &Client, address: &Address, kind: SpendKind) -> Spend {
+    let txid = client.transfer(address, 0.1);
+    let tx = client
+        .get_transaction(&txid, None)
+        .unwrap()
+        .transaction()
+        .unwrap();
+    let vout = tx
+        .output
+        .iter()
+        .position(|o| o.script_pubkey == address.script_pubkey())
+        .unwrap();
+    Spend::new(
+        OutPoint::new(txid, vout as u32),
+        tx.output[vout].clone(),
+        kind,
+    )
+}
@@ -0,0 +1,88 @@
+// Chain control
+//
+// Regtest lets us do things to the chain no real network would: mine on
+// demand, throw blocks away and move the clock. Tests about confirmations,
+// reorgs and timelocks need all three.
+//
+//  pub trait RegtestControl {
+//      fn mine_to(&self, n: u64, address: &Address) -> Vec<BlockHash>;
+//      fn mine_until_confirmed(&self, txid: &Txid, max_blocks: u64) -> BlockHash;
+//      fn mine_past_maturity(&self, address: &Address) -> Vec<BlockHash>;
+//      fn invalidate_blocks(&self, depth: u64) -> Vec<BlockHash>;
+//      fn reorg(&self, depth: u64) -> Vec<BlockHash>;
+//      fn set_mock_time(&self, time: u64);
+//      fn advance_time(&self, secs: u64) -> u64;
+//  }
+//
+// RESOURCES:
+//
+//  - https://developer.bitcoin.org/reference/rpc/generatetoaddress.html
+//
+//  - https://developer.bitcoin.org/reference/rpc/invalidateblock.html
+//
+//  - https://developer.bitcoin.org/reference/rpc/setmocktime.html
+//
+//  - https://github.com/bitcoin/bips/blob/master/bip-0113.mediawiki
+//
+
+use bitcoin_basics::regtest::{RegtestControl, COINBASE_MATURITY};
+use bitcoin_basics::BitcoinClient;
+use bitcoincore_rpc::bitcoin::Amount;
+use bitcoincore_rpc::{Client, RpcApi};
+
+fn main() {
+    let client = Client::setup();
+    let wallet_name = "test_wallet";
+    client.load_wallet_in_node(wallet_name);
+    client.get_dough_if_broke();
+
+    // A coinbase only counts once it's matured
+    let miner = client.get_new_address(None, None).unwrap();
+    let hashes = client.mine_to(COINBASE_MATURITY, &miner);
+    assert_eq!(hashes.len() as u64, COINBASE_MATURITY);
+    let immature = client
+        .list_unspent(Some(1), None, Some(&[&miner]), None, None)
+        .unwrap();
+    assert!(immature.is_empty());
+    let mature = client.get_new_address(None, None).unwrap();
+    let hashes = client.mine_past_maturity(&mature);
+    assert_eq!(hashes.len() as u64, COINBASE_MATURITY + 1);
+    let unspent = client
+        .list_unspent(Some(1), None, Some(&[&mature]), None, None)
+        .unwrap();
+    assert_eq!(unspent.len(), 1);
+    assert_eq!(unspent[0].confirmations as u64, COINBASE_MATURITY + 1);
+
+    // Mining until confirmed
+    let to = client.get_new_address(None, None).unwrap();
+    let txid = client
+        .send_to_address(&to, Amount::ONE_BTC, None, None, None, None, None, None)
+        .unwrap();
+    let block = client.mine_until_confirmed(&txid, 10);
+    assert_eq!(client.get_best_block_hash().unwrap(), block);
+
+    // Reorg it out, then back in on a longer chain
+    let height = client.get_block_count().unwrap();
+    let disconnected = client.invalidate_blocks(1);
+    assert_eq!(disconnected, vec![block]);
+    assert_eq!(client.get_block_count().unwrap(), height - 1);
+    assert!(client.get_raw_mempool().unwrap().contains(&txid));
+    client.reconsider_block(&block).unwrap();
+    assert_eq!(client.get_best_block_hash().unwrap(), block);
+
+    let new_blocks = client.reorg(3);
+    assert_eq!(new_blocks.len(), 4);
+    assert_eq!(client.get_block_count().unwrap(), height + 1);
+    assert!(!new_blocks.contains(&block));
+    // The tx made it into the new chain
+    let tx = client.get_transaction(&txid, None).unwrap();
+    assert!(tx.info.confirmations > 0);
+    assert_ne!(tx.info.blockhash, Some(block));
+
+    // Time travel
+    let time = client.advance_time(60 * 60 * 24);
+    let hash = client.mine_blocks(1)[0];
+    let header = client.get_block_header_info(&hash).unwrap();
+    assert!(header.time as u64 >= time);
+    client.set_mock_time(0);
+}
@@ -0,0 +1,71 @@
+// A node of our own
+//
+// Every test so far talks to the one bitcoind on port 18443, so they share
+// wallets and chain, and a stuck node means restarting it by hand. Instead,
+// each test can start a bitcoind of its own.
+//
+//  pub struct TestNode { .. }
+//
+//  impl TestNode {
+//      pub fn new() -> TestNode;
+//      pub fn client(&self) -> Client;
+//      pub fn wallet(&self, wallet_name: &str) -> Client;
+//  }
+//
+//  `new` runs `bitcoind -regtest` in a temporary datadir with free RPC and
+//  P2P ports, and waits for its RPC to be ready. Dropping the node stops it
+//  and deletes the datadir.
+//
+// RESOURCES:
+//
+//  - https://doc.rust-lang.org/std/process/struct.Command.html
+//
+//  - https://github.com/bitcoin/bitcoin/blob/master/doc/bitcoin-conf.md
+//
+//  - https://doc.rust-lang.org/std/ops/trait.Drop.html
+//
+
+use std::thread;
+
+use bitcoin_basics::regtest::RegtestControl;
+use bitcoin_basics::{BitcoinClient, TestNode};
+use bitcoincore_rpc::bitcoin::Amount;
+use bitcoincore_rpc::RpcApi;
+
+fn main() {
+    let a = TestNode::new();
+    let b = TestNode::new();
+    assert_ne!(a.rpc_port(), b.rpc_port());
+    assert_ne!(a.p2p_port(), b.p2p_port());
+    assert_ne!(a.datadir(), b.datadir());
+
+    // Fresh chains, each with a wallet of its own
+    assert_eq!(a.client().get_block_count().unwrap(), 0);
+    let wallet_a = a.wallet("test_wallet");
+    let wallet_b = b.wallet("test_wallet");
+    wallet_a.get_dough_if_broke();
+    assert!(wallet_a.get_balance(None, None).unwrap() > Amount::ZERO);
+    assert_eq!(wallet_b.get_balance(None, None).unwrap(), Amount::ZERO);
+    assert_eq!(b.client().get_block_count().unwrap(), 0);
+
+    // Nodes in parallel threads don't get in each other's way
+    let handles: Vec<_> = (0..3)
+        .map(|_| {
+            thread::spawn(|| {
+                let node = TestNode::new();
+                let wallet = node.wallet("test_wallet");
+                let address = wallet.get_new_address(None, None).unwrap();
+                node.client().mine_to(10, &address);
+                node.client().get_block_count().unwrap()
+            })
+        })
+        .collect();
+    for handle in handles {
+        assert_eq!(handle.join().unwrap(), 10);
+    }
+
+    let datadir = a.datadir().to_owned();
+    drop(wallet_a);
+    drop(a);
+    assert!(!datadir.exists());
+}
@@ -0,0 +1,100 @@
+// Chain events
+//
+// Polling RPC for new blocks and transactions is slow and wasteful. bitcoind
+// can publish them over ZMQ instead, as soon as they happen.
+//
+//  pub struct Subscriber { .. }
+//
+//  impl Subscriber {
+//      pub async fn connect(endpoint: &str, topics: &[Topic]) -> Result<Subscriber, SubscriberError>;
+//      pub async fn next(&mut self) -> Result<ChainEvent, SubscriberError>;
+//      pub async fn wait_for_mempool(&mut self, txid: &Txid, timeout: Duration) -> Result<(), SubscriberError>;
+//      pub async fn wait_for_block(&mut self, timeout: Duration) -> Result<BlockHash, SubscriberError>;
+//  }
+//
+//  Subscribes to the rawblock, rawtx, hashblock and sequence topics.
+//
+//  This one needs the `zmq` feature: `cargo test --features zmq`
+//
+// RESOURCES:
+//
+//  - https://github.com/bitcoin/bitcoin/blob/master/doc/zmq.md
+//
+//  - https://docs.rs/zeromq/0.3.3/zeromq/struct.SubSocket.html
+//
+
+use std::time::Duration;
+
+use bitcoin_basics::regtest::RegtestControl;
+use bitcoin_basics::test_node::free_port;
+use bitcoin_basics::zmq::{ChainEvent, Subscriber, ALL_TOPICS};
+use bitcoin_basics::{BitcoinClient, TestNode};
+use bitcoincore_rpc::bitcoin::Amount;
+use bitcoincore_rpc::RpcApi;
+
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+#[tokio::main(flavor = "current_thread")]
+async fn main() {
+    let endpoint = format!("tcp://127.0.0.1:{}", free_port());
+    let args: Vec<String> = ALL_TOPICS
+        .iter()
+        .map(|topic| topic.bitcoind_arg(&endpoint))
+        .collect();
+    let args: Vec<&str> = args.iter().map(|arg| arg.as_str()).collect();
+    let node = TestNode::with_args(&args);
+    let wallet = node.wallet("test_wallet");
+    wallet.get_dough_if_broke();
+
+    let mut events = Subscriber::connect(&endpoint, &ALL_TOPICS).await.unwrap();
+    // Anything published before the subscription goes through is lost
+    tokio::time::sleep(Duration::from_millis(500)).await;
+
+    let to = wallet.get_new_address(None, None).unwrap();
+    let txid = wallet
+        .send_to_address(&to, Amount::ONE_BTC, None, None, None, None, None, None)
+        .unwrap();
+    events.wait_for_mempool(&txid, TIMEOUT).await.unwrap();
+
+    let hash = wallet.mine_blocks(1)[0];
+    let (mut raw, mut hashed, mut connected) = (false, false, false);
+    while !(raw && hashed && connected) {
+        let event = tokio::time::timeout(TIMEOUT, events.next())
+            .await
+            .unwrap()
+            .unwrap();
+        match event {
+            ChainEvent::RawBlock(block) => {
+                assert_eq!(block.block_hash(), hash);
+                assert!(block.txdata.iter().any(|tx| tx.txid() == txid));
+                raw = true;
+            }
+            ChainEvent::HashBlock(h) => {
+                assert_eq!(h, hash);
+                hashed = true;
+            }
+            ChainEvent::BlockConnected(h) => {
+                assert_eq!(h, hash);
+                connected = true;
+            }
+            _ => {}
+        }
+    }
+
+    // Reorgs show up as disconnects
+    wallet.invalidate_blocks(1);
+    loop {
+        let event = tokio::time::timeout(TIMEOUT, events.next())
+            .await
+            .unwrap()
+            .unwrap();
+        if let ChainEvent::BlockDisconnected(h) = event {
+            assert_eq!(h, hash);
+            break;
+        }
+    }
+    assert_eq!(
+        wallet.mine_blocks(1)[0],
+        events.wait_for_block(TIMEOUT).await.unwrap()
+    );
+}
@@ -0,0 +1,134 @@
+// Keeping the books
+//
+// After a scenario has run we want to know where the coins went: what came
+// in and out, what it cost, and which coins are left.
+//
+//  pub trait BitcoinClient {
+//      fn wallet_report(&self) -> WalletReport;
+//  }
+//
+//  impl WalletReport {
+//      pub fn by_address(&self) -> Vec<UtxoGroup>;
+//      pub fn by_descriptor(&self) -> Vec<UtxoGroup>;
+//      pub fn net_flow(&self) -> i64;
+//      pub fn to_json(&self) -> String;
+//      pub fn to_csv(&self) -> String;
+//  }
+//
+//  Transactions come with their direction, fee, confirmations and label.
+//  Balances are split into trusted, untrusted pending and immature. UTXOs
+//  group by the wallet descriptors listunspent names in `parent_descs`.
+//
+// RESOURCES:
+//
+//  - https://developer.bitcoin.org/reference/rpc/listtransactions.html
+//
+//  - https://developer.bitcoin.org/reference/rpc/getbalances.html
+//
+//  - https://developer.bitcoin.org/reference/rpc/listunspent.html
+//
+//  - https://github.com/bitcoin/bitcoin/blob/master/doc/descriptors.md
+//
+
+use bitcoin_basics::regtest::{RegtestControl, COINBASE_MATURITY};
+use bitcoin_basics::report::{descriptor_group, Direction};
+use bitcoin_basics::{BitcoinClient, TestNode};
+use bitcoincore_rpc::bitcoin::Amount;
+use bitcoincore_rpc::RpcApi;
+
+fn main() {
+    let node = TestNode::new();
+    let alice = node.wallet("alice");
+    let bob = node.wallet("bob");
+
+    // One mature coinbase and a hundred immature ones
+    let mined = alice.get_new_address(Some("mining"), None).unwrap();
+    alice.mine_to(COINBASE_MATURITY + 1, &mined);
+    let report = alice.wallet_report();
+    assert_eq!(
+        report.balances.trusted,
+        Amount::from_btc(50.0).unwrap().to_sat()
+    );
+    assert!(report.balances.immature > 0);
+    assert_eq!(report.transactions.len() as u64, COINBASE_MATURITY + 1);
+    assert!(report
+        .transactions
+        .iter()
+        .all(|tx| tx.direction == Direction::Coinbase && tx.label.as_deref() == Some("mining")));
+
+    // Alice pays Bob, unconfirmed for now
+    let to_bob = bob.get_new_address(Some("from alice"), None).unwrap();
+    let txid = alice
+        .send_to_address(&to_bob, Amount::ONE_BTC, None, None, None, None, None, None)
+        .unwrap();
+    let report = bob.wallet_report();
+    assert_eq!(report.balances.untrusted_pending, Amount::ONE_BTC.to_sat());
+    assert_eq!(report.balances.trusted, 0);
+    let received = &report.transactions[0];
+    assert_eq!(received.txid, txid);
+    assert_eq!(received.direction, Direction::Incoming);
+    assert_eq!(received.confirmations, 0);
+    assert_eq!(received.label.as_deref(), Some("from alice"));
+
+    let report = alice.wallet_report();
+    let sent = report
+        .transactions
+        .iter()
+        .find(|tx| tx.txid == txid && tx.direction == Direction::Outgoing)
+        .unwrap();
+    assert_eq!(sent.amount_sat, -(Amount::ONE_BTC.to_sat() as i64));
+    let fee = -sent.fee_sat.unwrap();
+    assert!(fee > 0);
+    // Change comes back as trusted right away
+    assert_eq!(
+        report.balances.trusted,
+        Amount::from_btc(49.0).unwrap().to_sat() - fee as u64
+    );
+
+    alice.mine_to(1, &mined);
+    let report = bob.wallet_report();
+    assert_eq!(report.balances.trusted, Amount::ONE_BTC.to_sat());
+    assert_eq!(report.transactions[0].confirmations, 1);
+    assert_eq!(report.net_flow(), Amount::ONE_BTC.to_sat() as i64);
+
+    // Bob's coins, two ways
+    let again = bob.get_new_address(None, None).unwrap();
+    alice
+        .send_to_address(&again, Amount::ONE_BTC, None, None, None, None, None, None)
+        .unwrap();
+    alice.mine_to(1, &mined);
+    let report = bob.wallet_report();
+    let by_address = report.by_address();
+    assert_eq!(by_address.len(), 2);
+    assert!(by_address
+        .iter()
+        .all(|group| group.total_sat == Amount::ONE_BTC.to_sat()));
+    // Both came to the same receive descriptor
+    let by_descriptor = report.by_descriptor();
+    assert_eq!(by_descriptor.len(), 1);
+    assert_eq!(by_descriptor[0].total_sat, 2 * Amount::ONE_BTC.to_sat());
+    assert_eq!(
+        descriptor_group("wpkh([d34db33f/84'/1'/0'/0/5]02c6047f9441ed7d6d3045406e95c07cd85c778e4b8cef3ca7abac09b95c709ee5)#abcdefgh"),
+        "wpkh([d34db33f/84'/1'/0'/0/*])"
+    );
+
+    // Exports
+    let csv = report.to_csv();
+    let mut lines = csv.lines();
+    assert_eq!(
+        lines.next().unwrap(),
+        "txid,vout,direction,address,amount_sat,fee_sat,confirmations,label,time"
+    );
+    assert_eq!(lines.count(), report.transactions.len());
+    assert!(csv.contains("from alice"));
+
+    let json: serde_json::Value = serde_json::from_str(&report.to_json()).unwrap();
+    assert_eq!(
+        json["transactions"].as_array().unwrap().len(),
+        report.transactions.len()
+    );
+    assert_eq!(
+        json["balances"]["trusted"],
+        serde_json::json!(2 * Amount::ONE_BTC.to_sat())
+    );
+}
@@ -0,0 +1,117 @@
+// More than one wallet
+//
+// A node can have many wallets loaded at once: some with keys, some only
+// watching descriptors whose keys live elsewhere. Calls meant for a wallet
+// then have to go to its own RPC path, `/wallet/<name>`.
+//
+//  pub trait BitcoinClient {
+//      fn create_wallet_in_node(&self, wallet_name: &str, options: &WalletOptions) -> Result<(), WalletError>;
+//      fn ensure_wallet(&self, wallet_name: &str, options: &WalletOptions) -> Result<(), WalletError>;
+//      fn unload_wallet_in_node(&self, wallet_name: &str) -> Result<(), WalletError>;
+//      fn restore_wallet_in_node(&self, wallet_name: &str, backup: &Path) -> Result<(), WalletError>;
+//      fn import_into_wallet(&self, imports: &[DescriptorImport]) -> Result<(), WalletError>;
+//      fn backup_wallet_to(&self, path: &Path) -> Result<(), WalletError>;
+//  }
+//
+//  pub struct WalletHandle { .. }
+//
+//  A `WalletHandle` is a client on one wallet's path that can be cloned and
+//  shared between threads.
+//
+// RESOURCES:
+//
+//  - https://developer.bitcoin.org/reference/rpc/createwallet.html
+//
+//  - https://developer.bitcoin.org/reference/rpc/importdescriptors.html
+//
+//  - https://developer.bitcoin.org/reference/rpc/listdescriptors.html
+//
+//  - https://developer.bitcoin.org/reference/rpc/backupwallet.html
+//
+//  - https://github.com/bitcoin/bitcoin/blob/master/doc/managing-wallets.md
+//
+
+use std::collections::HashSet;
+use std::thread;
+
+use bitcoin_basics::wallet::{DescriptorImport, WalletError, WalletOptions};
+use bitcoin_basics::{BitcoinClient, TestNode};
+use bitcoincore_rpc::bitcoin::Amount;
+use bitcoincore_rpc::RpcApi;
+use serde_json::Value;
+
+fn main() {
+    let node = TestNode::new();
+    let client = node.client();
+
+    let alice = node.wallet("alice");
+    alice.get_dough_if_broke();
+    let info = alice.get_wallet_info().unwrap();
+    assert_eq!(info.wallet_name, "alice");
+
+    // Creating it twice fails
+    assert!(matches!(
+        client.create_wallet_in_node("alice", &WalletOptions::default()),
+        Err(WalletError::Rpc(_))
+    ));
+
+    // Blank wallets have nothing to hand out addresses from
+    let blank = node.wallet_with("blank", &WalletOptions::default().blank());
+    assert!(blank.get_new_address(None, None).is_err());
+
+    // A watch-only copy of Alice's receive addresses
+    let descriptors: Value = alice.call("listdescriptors", &[]).unwrap();
+    let receive = descriptors["descriptors"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .find(|d| {
+            d["desc"].as_str().unwrap().starts_with("wpkh(") && d["internal"] == Value::Bool(false)
+        })
+        .unwrap();
+    let range = receive["range"].as_array().unwrap();
+    let import = DescriptorImport::new(receive["desc"].as_str().unwrap())
+        .range(0, range[1].as_u64().unwrap() as u32)
+        .active()
+        .rescan_from(0);
+    let watch = node.wallet_with("watch", &WalletOptions::watch_only());
+    watch.import_into_wallet(&[import]).unwrap();
+    assert!(!watch.get_wallet_info().unwrap().private_keys_enabled);
+    assert_eq!(
+        watch.get_balance(None, None).unwrap(),
+        alice.get_balance(None, None).unwrap()
+    );
+    // Without private keys there's no signing
+    let to = alice.get_new_address(None, None).unwrap();
+    assert!(watch
+        .send_to_address(&to, Amount::ONE_BTC, None, None, None, None, None, None)
+        .is_err());
+
+    // Bad descriptors say why
+    assert!(matches!(
+        watch.import_into_wallet(&[DescriptorImport::new("wpkh(nonsense)")]),
+        Err(WalletError::Import { .. })
+    ));
+
+    // Back up, unload, restore under another name
+    let backup = node.datadir().join("alice.bak");
+    alice.backup_wallet_to(&backup).unwrap();
+    let balance = alice.get_balance(None, None).unwrap();
+    client.unload_wallet_in_node("alice").unwrap();
+    assert!(!client.list_wallets().unwrap().contains(&"alice".to_owned()));
+    client
+        .restore_wallet_in_node("alice_restored", &backup)
+        .unwrap();
+    let restored = node.wallet("alice_restored");
+    assert_eq!(restored.get_balance(None, None).unwrap(), balance);
+
+    // One handle shared between threads
+    let handles: Vec<_> = (0..8)
+        .map(|_| {
+            let wallet = restored.clone();
+            thread::spawn(move || wallet.get_new_address(None, None).unwrap())
+        })
+        .collect();
+    let addresses: HashSet<_> = handles.into_iter().map(|h| h.join().unwrap()).collect();
+    assert_eq!(addresses.len(), 8);
+}
@@ -0,0 +1,194 @@
+// Signing messages
+//
+// Coins aren't the only way to show an address is ours: signing a message
+// with its keys proves the same without moving anything. P2PKH addresses
+// have had Bitcoin Core's `signmessage` format (BIP137) for ages. BIP322
+// covers the rest by signing a virtual transaction spending from the
+// address, so anything that can spend from it can sign for it.
+//
+//  pub fn sign_message<C: Signing + Verification>(
+//      secp: &Secp256k1<C>,
+//      message: &str,
+//      address: &Address,
+//      kind: &SpendKind,
+//      keys: &[SecretKey],
+//      format: SignatureFormat,
+//  ) -> Result<String, MessageError>;
+//
+//  pub fn verify_message<C: Verification>(
+//      secp: &Secp256k1<C>,
+//      message: &str,
+//      address: &Address,
+//      signature: &str,
+//  ) -> Result<(), MessageError>;
+//
+//  A "simple" BIP322 signature is only the witness, a "full" one the whole
+//  signed transaction, which legacy scripts like P2SH multisig need.
+//
+//  Multisig signers who don't share their keys go through a PSBT instead:
+//
+//  pub fn message_psbt(message: &str, address: &Address, kind: &SpendKind)
+//      -> Result<PartiallySignedTransaction, MessageError>;
+//  pub fn sign_message_psbt<C: Signing>(..) -> Result<(), MessageError>;
+//  pub fn finalize_message_psbt(psbt: &PartiallySignedTransaction, format: SignatureFormat)
+//      -> Result<String, MessageError>;
+//
+// RESOURCES:
+//
+//  - https://github.com/bitcoin/bips/blob/master/bip-0137.mediawiki
+//
+//  - https://github.com/bitcoin/bips/blob/master/bip-0322.mediawiki
+//
+//  - https://developer.bitcoin.org/reference/rpc/verifymessage.html
+//
+//  - https://github.com/bitcoin/bips/blob/master/bip-0174.mediawiki
+//
+
+use std::str::FromStr;
+
+use bitcoin_basics::message::{self, MessageError, SignatureFormat};
+use bitcoin_basics::tx_builder::SpendKind;
+use bitcoin_basics::TestNode;
+use bitcoincore_rpc::bitcoin::{Address, Network, PrivateKey, PublicKey};
+use bitcoincore_rpc::RpcApi;
+use secp256k1::{rand, Secp256k1, SecretKey};
+use serde_json::json;
+
+fn main() {
+    let secp = Secp256k1::new();
+
+    // Offline: the test vectors of BIP322
+    assert_eq!(
+        message::bip322_message_hash("Hello World").to_string(),
+        "f0eb03b1a75ac6d9847f55c624a99169b5dccba2a31f5b23bea77ba270de0a7a"
+    );
+    let p2wpkh = Address::from_str("bc1q9vza2e8x573nczrlzms0wvx3gsqjx7vavgkx0l").unwrap();
+    message::verify_message(&secp, "Hello World", &p2wpkh, "AkcwRAIgZRfIY3p7/DoVTty6YZbWS71bc5Vct9p9Fia83eRmw2QCICK/ENGfwLtptFluMGs2KsqoNSk89pO7F29zJLUx9a/sASECx/EgAxlkQpQ9hYjgGu6EBCPMVPwVIVJqO4XCsMvViHI=").unwrap();
+    let p2tr = Address::from_str("bc1ppv609nr0vr25u07u95waq5lucwfm6tde4nydujnu8npg4q75mr5sxq8lt3")
+        .unwrap();
+    message::verify_message(&secp, "Hello World", &p2tr, "AUHd69PrJQEv+oKTfZ8l+WROBHuy9HKrbFCJu7U1iK2iiEy1vMU5EfMtjc+VSHM7aU0SDbak5IUZRVno2P5mjSafAQ==").unwrap();
+    assert!(matches!(
+        message::verify_message(&secp, "Hello", &p2tr, "AUHd69PrJQEv+oKTfZ8l+WROBHuy9HKrbFCJu7U1iK2iiEy1vMU5EfMtjc+VSHM7aU0SDbak5IUZRVno2P5mjSafAQ=="),
+        Err(MessageError::Invalid(_))
+    ));
+
+    // Every single key address in every format it has
+    let (sk, _) = secp.generate_keypair(&mut rand::thread_rng());
+    let pk = PublicKey::new(sk.public_key(&secp));
+    for (kind, formats) in [
+        (
+            SpendKind::P2pkh,
+            vec![SignatureFormat::Legacy, SignatureFormat::Full],
+        ),
+        (
+            SpendKind::P2wpkh,
+            vec![SignatureFormat::Simple, SignatureFormat::Full],
+        ),
+        (
+            SpendKind::P2tr,
+            vec![SignatureFormat::Simple, SignatureFormat::Full],
+        ),
+    ] {
+        let address = kind.address(Some(&pk));
+        for format in formats {
+            let sig =
+                message::sign_message(&secp, "I own this", &address, &kind, &[sk], format).unwrap();
+            message::verify_message(&secp, "I own this", &address, &sig).unwrap();
+            assert!(message::verify_message(&secp, "I own that", &address, &sig).is_err());
+        }
+    }
+    // BIP137 is for P2PKH only
+    let p2wpkh = SpendKind::P2wpkh.address(Some(&pk));
+    assert!(matches!(
+        message::sign_message(
+            &secp,
+            "x",
+            &p2wpkh,
+            &SpendKind::P2wpkh,
+            &[sk],
+            SignatureFormat::Legacy
+        ),
+        Err(MessageError::Unsupported(_))
+    ));
+
+    // 2-of-3 multisig, with each signer signing on their own
+    let keys: Vec<SecretKey> = (0..3)
+        .map(|_| secp.generate_keypair(&mut rand::thread_rng()).0)
+        .collect();
+    let pubkeys: Vec<PublicKey> = keys
+        .iter()
+        .map(|sk| PublicKey::new(sk.public_key(&secp)))
+        .collect();
+    for kind in [
+        SpendKind::P2shMulti {
+            threshold: 2,
+            pubkeys: pubkeys.clone(),
+        },
+        SpendKind::P2wshMulti {
+            threshold: 2,
+            pubkeys: pubkeys.clone(),
+        },
+    ] {
+        let address = kind.address(None);
+        let psbt = message::message_psbt("We own this", &address, &kind).unwrap();
+        let mut first = psbt.clone();
+        message::sign_message_psbt(&secp, &mut first, &keys[0]).unwrap();
+        assert!(matches!(
+            message::finalize_message_psbt(&first, SignatureFormat::Full),
+            Err(MessageError::NotEnoughSigs {
+                threshold: 2,
+                found: 1
+            })
+        ));
+        let mut third = psbt.clone();
+        message::sign_message_psbt(&secp, &mut third, &keys[2]).unwrap();
+        first.combine(third).unwrap();
+        let sig = message::finalize_message_psbt(&first, SignatureFormat::Full).unwrap();
+        message::verify_message(&secp, "We own this", &address, &sig).unwrap();
+
+        // Same thing with all the keys at hand
+        let sig = message::sign_message(
+            &secp,
+            "We own this",
+            &address,
+            &kind,
+            &keys[1..],
+            SignatureFormat::Full,
+        )
+        .unwrap();
+        message::verify_message(&secp, "We own this", &address, &sig).unwrap();
+
+        // Someone else's key doesn't help
+        let (outsider, _) = secp.generate_keypair(&mut rand::thread_rng());
+        assert!(message::sign_message_psbt(&secp, &mut psbt.clone(), &outsider).is_err());
+    }
+
+    // BIP137 signatures are the ones bitcoind makes and checks
+    let node = TestNode::new();
+    let client = node.client();
+    let address = Address::p2pkh(&pk, Network::Regtest);
+    let sig = message::sign_message(
+        &secp,
+        "Hello bitcoind",
+        &address,
+        &SpendKind::P2pkh,
+        &[sk],
+        SignatureFormat::Legacy,
+    )
+    .unwrap();
+    let valid: bool = client
+        .call(
+            "verifymessage",
+            &[json!(address), json!(sig), json!("Hello bitcoind")],
+        )
+        .unwrap();
+    assert!(valid);
+    let wif = PrivateKey::new(sk, Network::Regtest).to_wif();
+    let sig: String = client
+        .call(
+            "signmessagewithprivkey",
+            &[json!(wif), json!("Hello from bitcoind")],
+        )
+        .unwrap();
+    message::verify_message(&secp, "Hello from bitcoind", &address, &sig).unwrap();
+}
@@ -3,13 +3,30 @@
 // other.
 // If you are an advanced user however, feel free to break the rules!
 
+// 01 to 10 run on the shared node at `bitcoin_basics::rpc_url()`. From 11 on
+// each test starts a `TestNode` of its own, so those only need `bitcoind` on
+// the PATH.
+
 #[test]
 fn tests() {
     let t = trybuild::TestCases::new();
+    // Shared node
     t.pass("tests/01-setup.rs");
 //     t.pass("tests/02-wallet.rs");
 //     t.pass("tests/03-balance.rs");
 //     t.pass("tests/04-simple-transaction.rs");
 //     t.pass("tests/05-raw-tx-transmit.rs");
 //     t.pass("tests/06-multisig.rs");
+//     t.pass("tests/07-coin-selection.rs");
+//     t.pass("tests/08-fee-bump.rs");
+//     t.pass("tests/09-native-tx.rs");
+//     t.pass("tests/10-regtest-control.rs");
+    // Own node
+//     t.pass("tests/11-test-node.rs");
+    // Needs `cargo test --features zmq`
+    // #[cfg(feature = "zmq")]
+//     t.pass("tests/12-zmq.rs");
+//     t.pass("tests/13-wallet-report.rs");
+//     t.pass("tests/14-wallets.rs");
+//     t.pass("tests/15-message-signing.rs");
 }
@@ -0,0 +1,59 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+// The parts of a Core Lightning `config` file the workshop needs
+#[derive(Clone, Debug)]
+pub struct NodeConfig {
+    pub network: String,
+    pub alias: Option<String>,
+    pub bind_addr: Option<String>,
+    pub bitcoin_rpcuser: String,
+    pub bitcoin_rpcpassword: String,
+    pub bitcoin_rpcport: u16,
+}
+
+impl NodeConfig {
+    pub fn read(path: &Path) -> NodeConfig {
+        let contents = fs::read_to_string(path)
+            .unwrap_or_else(|e| panic!("Failed to read {}: {}", path.display(), e));
+        NodeConfig::parse(&contents)
+    }
+
+    pub fn parse(contents: &str) -> NodeConfig {
+        let options: HashMap<&str, &str> = contents
+            .lines()
+            .map(str::trim)
+            .filter(|l| !l.is_empty() && !l.starts_with('#'))
+            .filter_map(|l| l.split_once('='))
+            .map(|(k, v)| (k.trim(), v.trim()))
+            .collect();
+        let required = |key: &str| -> String {
+            options
+                .get(key)
+                .unwrap_or_else(|| panic!("Missing `{}` in node config", key))
+                .to_string()
+        };
+
+        NodeConfig {
+            network: required("network"),
+            alias: options.get("alias").map(|a| a.to_string()),
+            bind_addr: options.get("bind-addr").map(|a| a.to_string()),
+            bitcoin_rpcuser: required("bitcoin-rpcuser"),
+            bitcoin_rpcpassword: required("bitcoin-rpcpassword"),
+            bitcoin_rpcport: required("bitcoin-rpcport")
+                .parse()
+                .expect("Invalid bitcoin-rpcport"),
+        }
+    }
+}
+
+// The RPC socket lives in `<lightning-dir>/<network>/lightning-rpc`, the
+// config in `<lightning-dir>/config`
+pub fn config_path(socket_path: &Path) -> PathBuf {
+    socket_path
+        .parent()
+        .and_then(Path::parent)
+        .expect("Socket path should be <lightning-dir>/<network>/lightning-rpc")
+        .join("config")
+}
@@ -0,0 +1,422 @@
+use std::env;
+use std::fmt;
+use std::fs;
+use std::panic::{self, AssertUnwindSafe};
+use std::path::{Path, PathBuf};
+use std::process::{self, Child, Command, Stdio};
+use std::time::Duration;
+
+use bitcoin_basics::BitcoinClient;
+use rlnrpc::proto::node_event::Event;
+use rlnrpc::proto::{NodeEvent, PaymentStatus};
+use rlnrpc::{BlockingRlnClient, Streaming};
+use serde_json::json;
+
+use crate::orchestrator::{exe, free_port, LIGHTNINGD_EXE_ENV};
+use crate::{wait_for, LNClient, LNSession, WAIT_TIMEOUT};
+
+// Override to use an `ln_node` binary that is not on the PATH. It has to be
+// built with the `grpc` feature.
+pub const RLN_NODE_EXE_ENV: &str = "RLN_NODE_EXE";
+
+// rln-node's `API_TOKEN_FILE`, written to its `ln_dir`
+const RLN_API_TOKEN_FILE: &str = "api_token";
+
+const CHANNEL_SAT: u64 = 1_000_000;
+const INVOICE_MSAT: u64 = 10_000_000;
+const KEYSEND_MSAT: u64 = 5_000_000;
+const EVENT_TIMEOUT: Duration = Duration::from_secs(60);
+
+// Log lines that point at the two sides disagreeing on the protocol
+const CLN_LOG_PATTERNS: &[&str] = &[
+    "**BROKEN**",
+    "sent ERROR",
+    "received ERROR",
+    "Peer transient failure",
+    "Peer permanent failure",
+];
+const LDK_LOG_PATTERNS: &[&str] = &[
+    "Got Err message from",
+    "Got warning message from",
+    "Error handling message",
+    "Peer sent invalid data",
+    "Force-closing channel",
+];
+
+#[derive(Debug)]
+pub struct StepResult {
+    pub name: &'static str,
+    pub error: Option<String>,
+}
+
+#[derive(Debug)]
+pub struct LogIssue {
+    pub source: &'static str,
+    pub line: String,
+}
+
+#[derive(Debug, Default)]
+pub struct InteropReport {
+    pub steps: Vec<StepResult>,
+    pub log_issues: Vec<LogIssue>,
+}
+
+impl InteropReport {
+    pub fn passed(&self) -> bool {
+        self.steps.iter().all(|s| s.error.is_none())
+    }
+}
+
+impl fmt::Display for InteropReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for step in self.steps.iter() {
+            match &step.error {
+                None => writeln!(f, "[ok]   {}", step.name)?,
+                Some(e) => writeln!(f, "[fail] {}: {}", step.name, e)?,
+            }
+        }
+        if !self.log_issues.is_empty() {
+            writeln!(f, "\nPossible incompatibilities in the logs:")?;
+            for issue in self.log_issues.iter() {
+                writeln!(f, "  {}: {}", issue.source, issue.line.trim())?;
+            }
+        }
+        Ok(())
+    }
+}
+
+type Step = fn(&mut InteropHarness) -> Result<(), String>;
+
+// Kills the process when dropped, so one started before a later step of
+// `InteropHarness::start` fails doesn't keep running
+struct ProcessGuard(Child);
+
+impl Drop for ProcessGuard {
+    fn drop(&mut self) {
+        let _ = self.0.kill();
+        let _ = self.0.wait();
+    }
+}
+
+// An `rln-node` (LDK) and a `lightningd` set up like one of `ln/ln-nodes`, on
+// the bitcoind that node's config points at, driven through their RPCs. Both
+// processes are stopped on drop.
+pub struct InteropHarness {
+    dir: PathBuf,
+    _rln_process: ProcessGuard,
+    rln_port: u16,
+    rln: BlockingRlnClient,
+    _cln_process: ProcessGuard,
+    cln: LNSession,
+}
+
+impl InteropHarness {
+    // `cln_dir` is a lightning dir like `ln-nodes/node-1`. Its config is
+    // copied into a fresh dir with a free port, the way `LNCluster` sets up
+    // its nodes, so the checked in dir stays untouched.
+    pub fn start(cln_dir: &Path) -> InteropHarness {
+        let dir = env::temp_dir().join(format!("rln-interop-{}", process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let rln_dir = dir.join("rln");
+        fs::create_dir_all(&rln_dir).expect("Failed to create rln-node dir");
+        let lightning_dir = dir.join("cln");
+        fs::create_dir_all(&lightning_dir).expect("Failed to create lightning dir");
+        let config = fs::read_to_string(cln_dir.join("config"))
+            .unwrap_or_else(|e| panic!("Failed to read {}: {}", cln_dir.display(), e));
+        fs::write(
+            lightning_dir.join("config"),
+            with_bind_addr(&config, free_port()),
+        )
+        .expect("Failed to write node config");
+
+        let socket_path = lightning_dir.join("regtest").join("lightning-rpc");
+        let cln = LNSession::new(&socket_path);
+        // rln-node's `bitcoind` file, so both sides are on the same chain
+        fs::write(
+            rln_dir.join("bitcoind"),
+            format!(
+                "rpc-url=http://localhost:{}\n",
+                cln.config().bitcoin_rpcport
+            ),
+        )
+        .expect("Failed to write rln-node bitcoind file");
+        let cln_process = Command::new(exe(LIGHTNINGD_EXE_ENV, "lightningd"))
+            .arg(format!("--lightning-dir={}", lightning_dir.display()))
+            .arg(format!("--log-file={}", dir.join("cln.log").display()))
+            .stdout(Stdio::null())
+            .spawn()
+            .map(ProcessGuard)
+            .expect("Failed to start lightningd");
+
+        let rln_port = free_port();
+        let grpc_port = free_port();
+        let rln_log = fs::File::create(dir.join("rln.log")).expect("Failed to create log");
+        let rln_process = Command::new(exe(RLN_NODE_EXE_ENV, "ln_node"))
+            .arg(&rln_dir)
+            .arg(rln_port.to_string())
+            .arg("--grpc-port")
+            .arg(grpc_port.to_string())
+            .stdout(rln_log)
+            .spawn()
+            .map(ProcessGuard)
+            .expect("Failed to start ln_node");
+
+        wait_for("lightningd RPC", WAIT_TIMEOUT, || {
+            socket_path.exists() && cln.getinfo().is_ok()
+        });
+
+        // The token shows up once the gRPC server is up
+        let endpoint = format!("http://127.0.0.1:{}", grpc_port);
+        let token_file = rln_dir.join(RLN_API_TOKEN_FILE);
+        wait_for("rln-node gRPC", WAIT_TIMEOUT, || {
+            BlockingRlnClient::connect_with_token_file(&endpoint, &token_file)
+                .and_then(|mut c| Ok(c.get_info()?))
+                .is_ok()
+        });
+        let rln = BlockingRlnClient::connect_with_token_file(&endpoint, &token_file)
+            .expect("Failed to connect to rln-node");
+
+        InteropHarness {
+            dir,
+            _rln_process: rln_process,
+            rln_port,
+            rln,
+            _cln_process: cln_process,
+            cln,
+        }
+    }
+
+    // Runs every step in order and stops at the first failure
+    pub fn run(&mut self) -> InteropReport {
+        let mut report = InteropReport::default();
+        let steps: &[(&'static str, Step)] = &[
+            ("connect peers", InteropHarness::connect),
+            ("open channel LDK -> CLN", InteropHarness::open_from_ldk),
+            ("open channel CLN -> LDK", InteropHarness::open_from_cln),
+            ("BOLT11 payment LDK -> CLN", InteropHarness::bolt11_from_ldk),
+            ("BOLT11 payment CLN -> LDK", InteropHarness::bolt11_from_cln),
+            ("keysend LDK -> CLN", InteropHarness::keysend_from_ldk),
+            ("keysend CLN -> LDK", InteropHarness::keysend_from_cln),
+        ];
+        for (name, step) in steps {
+            // Helpers panic on RPC errors, count those as failures too
+            let error = match panic::catch_unwind(AssertUnwindSafe(|| step(self))) {
+                Ok(res) => res.err(),
+                Err(e) => Some(panic_message(e)),
+            };
+            let failed = error.is_some();
+            report.steps.push(StepResult { name, error });
+            if failed {
+                break;
+            }
+        }
+        report.log_issues = self.scan_logs();
+        report
+    }
+
+    fn cln_id(&self) -> String {
+        self.cln.getinfo().expect("Failed to get info").id
+    }
+
+    fn rln_id(&mut self) -> Result<String, String> {
+        Ok(self
+            .rln
+            .get_info()
+            .map_err(|e| e.to_string())?
+            .identity_pubkey)
+    }
+
+    fn connect(&mut self) -> Result<(), String> {
+        let cln_addr = self
+            .cln
+            .config()
+            .bind_addr
+            .clone()
+            .expect("lightningd config has no bind-addr");
+        let cln_id = self.cln_id();
+        self.rln
+            .connect_peer(&cln_id, &cln_addr)
+            .map_err(|e| e.to_string())
+    }
+
+    fn open_from_ldk(&mut self) -> Result<(), String> {
+        let cln_id = self.cln_id();
+        let mut events = self.rln.subscribe_events().map_err(|e| e.to_string())?;
+        self.rln
+            .open_channel(&cln_id, CHANNEL_SAT, 0, true)
+            .map_err(|e| e.to_string())?;
+        self.confirm_channel(&mut events, &cln_id)
+    }
+
+    fn open_from_cln(&mut self) -> Result<(), String> {
+        let rln_id = self.rln_id()?;
+        self.cln.add_funds();
+        let mut events = self.rln.subscribe_events().map_err(|e| e.to_string())?;
+        self.cln.open_channel(
+            &format!("{}@127.0.0.1:{}", rln_id, self.rln_port),
+            CHANNEL_SAT,
+            0,
+        );
+        let cln_id = self.cln_id();
+        self.confirm_channel(&mut events, &cln_id)
+    }
+
+    fn confirm_channel(
+        &mut self,
+        events: &mut Streaming<NodeEvent>,
+        cln_id: &str,
+    ) -> Result<(), String> {
+        self.cln.bitcoind().mine_blocks(6);
+        self.rln
+            .wait_for_event(
+                events,
+                |e| matches!(e, Event::ChannelReady(c) if c.counterparty_pubkey == cln_id),
+                EVENT_TIMEOUT,
+            )
+            .map_err(|e| format!("LDK never saw the channel ready: {}", e))?;
+        Ok(())
+    }
+
+    fn bolt11_from_ldk(&mut self) -> Result<(), String> {
+        let invoice = self
+            .cln
+            .create_invoice(INVOICE_MSAT, "interop-ldk-to-cln", "Interop test");
+        let mut events = self.rln.subscribe_events().map_err(|e| e.to_string())?;
+        let payment_hash = self
+            .rln
+            .pay_invoice(&invoice.bolt11)
+            .map_err(|e| e.to_string())?;
+        self.wait_for_payment_sent(&mut events, &payment_hash)
+    }
+
+    fn bolt11_from_cln(&mut self) -> Result<(), String> {
+        let invoice = self
+            .rln
+            .add_invoice(INVOICE_MSAT, "Interop test")
+            .map_err(|e| e.to_string())?;
+        let mut events = self.rln.subscribe_events().map_err(|e| e.to_string())?;
+        self.cln
+            .pay_invoice(&invoice.payment_request)
+            .map_err(|e| e.to_string())?;
+
+        // CLN only knows the HTLCs went through, LDK has to have claimed them
+        let payment_hash = invoice.payment_hash;
+        self.rln
+            .wait_for_event(
+                &mut events,
+                |e| matches!(e, Event::PaymentReceived(p) if p.payment_hash == payment_hash),
+                EVENT_TIMEOUT,
+            )
+            .map_err(|e| format!("LDK never claimed the payment: {}", e))?;
+        let payments = self.rln.list_payments().map_err(|e| e.to_string())?;
+        match payments.iter().find(|p| p.payment_hash == payment_hash) {
+            Some(p)
+                if p.inbound
+                    && p.status() == PaymentStatus::Succeeded
+                    && p.value_msat == INVOICE_MSAT =>
+            {
+                Ok(())
+            }
+            Some(p) => Err(format!("LDK recorded the payment as {:?}", p)),
+            None => Err(format!("LDK has no record of payment {}", payment_hash)),
+        }
+    }
+
+    fn keysend_from_ldk(&mut self) -> Result<(), String> {
+        let cln_id = self.cln_id();
+        let mut events = self.rln.subscribe_events().map_err(|e| e.to_string())?;
+        let payment_hash = self
+            .rln
+            .keysend(&cln_id, KEYSEND_MSAT)
+            .map_err(|e| e.to_string())?;
+        self.wait_for_payment_sent(&mut events, &payment_hash)
+    }
+
+    fn keysend_from_cln(&mut self) -> Result<(), String> {
+        let rln_id = self.rln_id()?;
+        let mut events = self.rln.subscribe_events().map_err(|e| e.to_string())?;
+        self.cln
+            .try_call_json(
+                "keysend",
+                json!({ "destination": rln_id, "amount_msat": KEYSEND_MSAT }),
+            )
+            .map_err(|e| format!("{:?}", e))?;
+        self.rln
+            .wait_for_event(
+                &mut events,
+                |e| matches!(e, Event::PaymentReceived(_)),
+                EVENT_TIMEOUT,
+            )
+            .map_err(|e| format!("LDK never received the keysend: {}", e))?;
+        Ok(())
+    }
+
+    fn wait_for_payment_sent(
+        &mut self,
+        events: &mut Streaming<NodeEvent>,
+        payment_hash: &str,
+    ) -> Result<(), String> {
+        let event = self
+            .rln
+            .wait_for_event(
+                events,
+                |e| match e {
+                    Event::PaymentSent(p) => p.payment_hash == payment_hash,
+                    Event::PaymentFailed(p) => p.payment_hash == payment_hash,
+                    _ => false,
+                },
+                EVENT_TIMEOUT,
+            )
+            .map_err(|e| e.to_string())?;
+        match event {
+            Event::PaymentSent(_) => Ok(()),
+            _ => Err(format!("Payment {} failed", payment_hash)),
+        }
+    }
+
+    fn scan_logs(&self) -> Vec<LogIssue> {
+        let mut issues = Vec::new();
+        for (source, file, patterns) in [
+            ("lightningd", "cln.log", CLN_LOG_PATTERNS),
+            ("rln-node", "rln.log", LDK_LOG_PATTERNS),
+        ] {
+            let log = fs::read_to_string(self.dir.join(file)).unwrap_or_default();
+            issues.extend(
+                log.lines()
+                    .filter(|l| patterns.iter().any(|p| l.contains(p)))
+                    .map(|l| LogIssue {
+                        source,
+                        line: l.to_owned(),
+                    }),
+            );
+        }
+        issues
+    }
+}
+
+// The process guards kill both nodes after this
+impl Drop for InteropHarness {
+    fn drop(&mut self) {
+        let _ = self.cln.try_call_json("stop", json!({}));
+        // Logs stay around for a closer look
+        println!("Interop logs in {}", self.dir.display());
+    }
+}
+
+// `config` listening on `port` instead of its own `bind-addr`
+fn with_bind_addr(config: &str, port: u16) -> String {
+    let mut config: String = config
+        .lines()
+        .filter(|l| !l.trim_start().starts_with("bind-addr"))
+        .map(|l| format!("{}\n", l))
+        .collect();
+    config.push_str(&format!("bind-addr=127.0.0.1:{}\n", port));
+    config
+}
+
+fn panic_message(e: Box<dyn std::any::Any + Send>) -> String {
+    e.downcast_ref::<String>()
+        .cloned()
+        .or_else(|| e.downcast_ref::<&str>().map(|s| s.to_string()))
+        .unwrap_or_else(|| "panicked".to_owned())
+}
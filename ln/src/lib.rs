@@ -1,13 +1,244 @@
-use std::path::Path;
+use std::fmt;
+use std::ops::Deref;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use bitcoin_basics::wallet::WalletOptions;
+use bitcoin_basics::BitcoinClient;
+use bitcoincore_rpc::bitcoin::hashes::hex::{self, FromHex, ToHex};
+use bitcoincore_rpc::bitcoin::{Address, Amount, Txid};
+use bitcoincore_rpc::{Auth, Client};
 use clightningrpc::LightningRPC;
+use serde_json::{json, Value};
+
+pub mod config;
+pub mod interop;
+pub mod orchestrator;
+pub mod payments;
+pub mod topology;
+
+use config::{config_path, NodeConfig};
+use payments::{ChannelBalances, Invoice, Payment, PaymentError};
+
+// bitcoind wallet that funds the lightning nodes
+pub const WALLET_NAME: &str = "test_wallet";
+
+const FUNDING_BTC: f64 = 1.0;
+const CHANNEL_SAT: u64 = 1_000_000;
+pub(crate) const WAIT_TIMEOUT: Duration = Duration::from_secs(60);
 
 pub trait LNClient {
-    fn get_client() -> LightningRPC;
+    // Takes the node's `lightning-rpc` socket path
+    fn get_client<P: AsRef<Path>>(socket_path: P) -> LNSession
+    where
+        Self: Sized;
+
+    // For RPCs where failing is an expected outcome
+    fn try_call_json(&self, method: &str, params: Value) -> Result<Value, clightningrpc::Error>;
+
+    // For RPCs whose responses `clightningrpc` doesn't model
+    fn call_json(&self, method: &str, params: Value) -> Value {
+        self.try_call_json(method, params)
+            .unwrap_or_else(|e| panic!("{} failed: {:?}", method, e))
+    }
+
+    // `label` has to be unique on this node
+    fn create_invoice(&self, amount_msat: u64, label: &str, description: &str) -> Invoice {
+        payments::create_invoice(self, amount_msat, label, description)
+    }
+
+    // Pays along a single route and blocks until the payment settles or fails
+    fn pay_invoice(&self, bolt11: &str) -> Result<Payment, PaymentError> {
+        self.pay_invoice_mpp(bolt11, 1)
+    }
+
+    // Splits the payment into `parts` equal parts. A part only avoids the
+    // channels earlier parts left without room for it, so parts share a
+    // route where it can carry them.
+    fn pay_invoice_mpp(&self, bolt11: &str, parts: u64) -> Result<Payment, PaymentError> {
+        payments::pay_invoice_mpp(self, bolt11, parts)
+    }
+
+    // Our side of each channel, by peer id
+    fn channel_balances(&self) -> ChannelBalances {
+        payments::channel_balances(self)
+    }
+
+    // Waits for our side of the channels with `peer_id` to change by
+    // `delta_msat` from `before`. Balances update a moment after the payment
+    // settles, hence the wait.
+    fn assert_balance_moved(&self, before: &ChannelBalances, peer_id: &str, delta_msat: i64) {
+        payments::assert_balance_moved(self, before, peer_id, delta_msat)
+    }
 }
 
 impl LNClient for LightningRPC {
-    fn get_client() -> LightningRPC {
-        unimplemented!()
+    fn get_client<P: AsRef<Path>>(socket_path: P) -> LNSession {
+        LNSession::new(socket_path.as_ref())
+    }
+
+    fn try_call_json(&self, method: &str, params: Value) -> Result<Value, clightningrpc::Error> {
+        self.call(method, params)
+    }
+}
+
+impl LNClient for LNSession {
+    fn get_client<P: AsRef<Path>>(socket_path: P) -> LNSession {
+        LNSession::new(socket_path.as_ref())
+    }
+
+    fn try_call_json(&self, method: &str, params: Value) -> Result<Value, clightningrpc::Error> {
+        self.rpc.try_call_json(method, params)
+    }
+}
+
+// 32 byte channel id as Core Lightning reports it, hex encoded
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct ChannelId(pub [u8; 32]);
+
+impl fmt::Display for ChannelId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0.to_hex())
+    }
+}
+
+impl FromStr for ChannelId {
+    type Err = hex::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(ChannelId(<[u8; 32]>::from_hex(s)?))
     }
 }
 
+// A Core Lightning node: its RPC connection along with the config it was
+// started with. Derefs to `LightningRPC` for the plain RPC calls.
+pub struct LNSession {
+    rpc: LightningRPC,
+    socket_path: PathBuf,
+    config: NodeConfig,
+}
+
+impl Deref for LNSession {
+    type Target = LightningRPC;
+
+    fn deref(&self) -> &LightningRPC {
+        &self.rpc
+    }
+}
+
+impl LNSession {
+    pub fn new(socket_path: &Path) -> LNSession {
+        LNSession {
+            rpc: LightningRPC::new(socket_path),
+            socket_path: socket_path.to_owned(),
+            config: NodeConfig::read(&config_path(socket_path)),
+        }
+    }
+
+    pub fn socket_path(&self) -> &Path {
+        &self.socket_path
+    }
+
+    pub fn config(&self) -> &NodeConfig {
+        &self.config
+    }
+
+    // bitcoind the node is backed by, using the funding wallet
+    pub fn bitcoind(&self) -> Client {
+        let client = Client::new(
+            &format!(
+                "http://localhost:{}/wallet/{}",
+                self.config.bitcoin_rpcport, WALLET_NAME
+            ),
+            Auth::UserPass(
+                self.config.bitcoin_rpcuser.clone(),
+                self.config.bitcoin_rpcpassword.clone(),
+            ),
+        )
+        .expect("Failed to create bitcoind client");
+        client
+            .ensure_wallet(WALLET_NAME, &WalletOptions::default())
+            .expect("Failed to load funding wallet");
+        client
+    }
+
+    // Sends on-chain funds to the node and waits until the node sees them
+    pub fn add_funds(&self) -> Txid {
+        self.add_funds_sat(Amount::from_btc(FUNDING_BTC).unwrap().to_sat())
+    }
+
+    // Same, for a given amount
+    pub fn add_funds_sat(&self, amount_sat: u64) -> Txid {
+        let bitcoind = self.bitcoind();
+        bitcoind.get_dough_if_broke();
+
+        let address = self.call_json("newaddr", json!({ "addresstype": "bech32" }));
+        let address = address["bech32"]
+            .as_str()
+            .expect("Missing bech32 address in newaddr response");
+        let address = Address::from_str(address).expect("Invalid node address");
+        let txid = bitcoind.transfer(&address, Amount::from_sat(amount_sat).to_btc());
+
+        wait_for("funds to show up", WAIT_TIMEOUT, || {
+            self.call_json("listfunds", json!({}))["outputs"]
+                .as_array()
+                .map(|outputs| outputs.iter().any(|o| o["txid"] == txid.to_string()))
+                .unwrap_or(false)
+        });
+        txid
+    }
+
+    // Opens a channel to `id` (`<pubkey>@<host>:<port>`) and waits until it
+    // can be used
+    pub fn create_channel(&self, id: &str) -> ChannelId {
+        let channel_id = self.open_channel(id, CHANNEL_SAT, 0);
+
+        // Funding needs to be buried before the channel becomes usable
+        self.bitcoind().mine_blocks(6);
+        let peer_id = id.split_once('@').map(|(p, _)| p).unwrap_or(id);
+        self.wait_for_channel(peer_id, &channel_id);
+        channel_id
+    }
+
+    // Connects and broadcasts the funding tx without waiting for it to confirm
+    pub fn open_channel(&self, id: &str, amount_sat: u64, push_msat: u64) -> ChannelId {
+        let (peer_id, host) = id.split_once('@').expect("Expected <pubkey>@<host>:<port>");
+        self.call_json("connect", json!({ "id": peer_id, "host": host }));
+
+        let funded = self.call_json(
+            "fundchannel",
+            json!({ "id": peer_id, "amount": amount_sat, "push_msat": push_msat }),
+        );
+        funded["channel_id"]
+            .as_str()
+            .expect("Missing channel_id in fundchannel response")
+            .parse()
+            .expect("Invalid channel id")
+    }
+
+    pub fn wait_for_channel(&self, peer_id: &str, channel_id: &ChannelId) {
+        wait_for("channel to be ready", WAIT_TIMEOUT, || {
+            self.channel_state(peer_id, channel_id).as_deref() == Some("CHANNELD_NORMAL")
+        });
+    }
+
+    pub fn channel_state(&self, peer_id: &str, channel_id: &ChannelId) -> Option<String> {
+        let channels = self.call_json("listpeerchannels", json!({ "id": peer_id }));
+        channels["channels"]
+            .as_array()?
+            .iter()
+            .find(|c| c["channel_id"] == channel_id.to_string())
+            .and_then(|c| c["state"].as_str())
+            .map(|s| s.to_owned())
+    }
+}
+
+pub(crate) fn wait_for<F: Fn() -> bool>(what: &str, timeout: Duration, done: F) {
+    let start = Instant::now();
+    while !done() {
+        assert!(start.elapsed() < timeout, "Timed out waiting for {}", what);
+        thread::sleep(Duration::from_millis(500));
+    }
+}
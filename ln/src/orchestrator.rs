@@ -0,0 +1,187 @@
+use std::env;
+use std::fs;
+use std::net::TcpListener;
+use std::path::{Path, PathBuf};
+use std::process::{self, Child, Command, Stdio};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use bitcoin_basics::wallet::WalletOptions;
+use bitcoin_basics::{BitcoinClient, TestNode, RPC_PASSWORD, RPC_USER};
+use bitcoincore_rpc::{Auth, Client};
+use clightningrpc::LightningRPC;
+use serde_json::{json, Value};
+
+use crate::{wait_for, LNSession, WAIT_TIMEOUT, WALLET_NAME};
+
+// Override to use a binary that is not on the PATH, bitcoind's is
+// `bitcoin_basics::test_node::BITCOIND_EXE_VAR`
+pub const LIGHTNINGD_EXE_ENV: &str = "LIGHTNINGD_EXE";
+
+// Tells clusters started by the same process apart
+static CLUSTER_COUNT: AtomicUsize = AtomicUsize::new(0);
+
+// A regtest bitcoind with N Core Lightning nodes on top, each in its own
+// temp directory on free ports, so tests can run side by side. Everything is
+// stopped and removed when the cluster is dropped.
+pub struct LNCluster {
+    dir: PathBuf,
+    nodes: Vec<ClusterNode>,
+    // Dropped after the nodes, which are stopped in `drop`
+    bitcoind: TestNode,
+}
+
+pub struct ClusterNode {
+    process: Child,
+    lightning_dir: PathBuf,
+    port: u16,
+}
+
+impl ClusterNode {
+    pub fn lightning_dir(&self) -> &Path {
+        &self.lightning_dir
+    }
+
+    pub fn port(&self) -> u16 {
+        self.port
+    }
+
+    pub fn socket_path(&self) -> PathBuf {
+        self.lightning_dir.join("regtest").join("lightning-rpc")
+    }
+
+    pub fn rpc(&self) -> LightningRPC {
+        LightningRPC::new(self.socket_path())
+    }
+
+    pub fn session(&self) -> LNSession {
+        LNSession::new(&self.socket_path())
+    }
+
+    // `<pubkey>@<host>:<port>`, what `connect` and `create_channel` take
+    pub fn id(&self) -> String {
+        let info = self.rpc().getinfo().expect("Failed to get info");
+        format!("{}@127.0.0.1:{}", info.id, self.port)
+    }
+}
+
+impl LNCluster {
+    pub fn start(num_nodes: usize) -> LNCluster {
+        let dir = env::temp_dir().join(format!(
+            "ln-workshop-{}-{}",
+            process::id(),
+            CLUSTER_COUNT.fetch_add(1, Ordering::SeqCst)
+        ));
+        let _ = fs::remove_dir_all(&dir);
+
+        // From here on drop cleans up, even if startup fails half way
+        let mut cluster = LNCluster {
+            dir,
+            nodes: Vec::new(),
+            bitcoind: TestNode::new(),
+        };
+
+        let wallet = cluster.bitcoind();
+        wallet
+            .ensure_wallet(WALLET_NAME, &WalletOptions::default())
+            .expect("Failed to create funding wallet");
+        wallet.get_dough_if_broke();
+
+        for i in 1..=num_nodes {
+            let node = cluster.start_node(i);
+            cluster.nodes.push(node);
+        }
+        for node in cluster.nodes.iter() {
+            let rpc = node.rpc();
+            wait_for("lightningd RPC", WAIT_TIMEOUT, || {
+                node.socket_path().exists() && rpc.getinfo().is_ok()
+            });
+        }
+        cluster
+    }
+
+    fn start_node(&self, i: usize) -> ClusterNode {
+        let lightning_dir = self.dir.join(format!("node-{}", i));
+        fs::create_dir_all(&lightning_dir).expect("Failed to create lightning dir");
+        let port = free_port();
+        fs::write(lightning_dir.join("config"), self.node_config(i, port))
+            .expect("Failed to write node config");
+
+        let log = fs::File::create(lightning_dir.join("log")).expect("Failed to create log");
+        let process = Command::new(exe(LIGHTNINGD_EXE_ENV, "lightningd"))
+            .arg(format!("--lightning-dir={}", lightning_dir.display()))
+            .stdout(log)
+            .stderr(Stdio::null())
+            .spawn()
+            .expect("Failed to start lightningd");
+        ClusterNode {
+            process,
+            lightning_dir,
+            port,
+        }
+    }
+
+    // Same shape as `ln/ln-nodes/*/config`, so `LNSession` reads it as is
+    fn node_config(&self, i: usize, port: u16) -> String {
+        format!(
+            "network=regtest\n\
+             alias=NODE_{}\n\
+             bind-addr=127.0.0.1:{}\n\
+             bitcoin-rpcconnect=127.0.0.1\n\
+             bitcoin-rpcuser={}\n\
+             bitcoin-rpcpassword={}\n\
+             bitcoin-rpcport={}\n\
+             log-level=debug\n\
+             allow-deprecated-apis=false\n",
+            i,
+            port,
+            RPC_USER,
+            RPC_PASSWORD,
+            self.bitcoind.rpc_port()
+        )
+    }
+
+    // bitcoind client on the funding wallet
+    pub fn bitcoind(&self) -> Client {
+        Client::new(
+            &format!("{}/wallet/{}", self.bitcoind.rpc_url(), WALLET_NAME),
+            Auth::UserPass(RPC_USER.to_owned(), RPC_PASSWORD.to_owned()),
+        )
+        .expect("Failed to create bitcoind client")
+    }
+
+    pub fn bitcoind_rpc_port(&self) -> u16 {
+        self.bitcoind.rpc_port()
+    }
+
+    pub fn node(&self, i: usize) -> &ClusterNode {
+        &self.nodes[i]
+    }
+
+    pub fn nodes(&self) -> &[ClusterNode] {
+        &self.nodes
+    }
+}
+
+impl Drop for LNCluster {
+    fn drop(&mut self) {
+        for node in self.nodes.iter_mut() {
+            let _: Result<Value, _> = node.rpc().call("stop", json!({}));
+            let _ = node.process.kill();
+            let _ = node.process.wait();
+        }
+        let _ = fs::remove_dir_all(&self.dir);
+    }
+}
+
+pub(crate) fn exe(env_var: &str, default: &str) -> String {
+    env::var(env_var).unwrap_or_else(|_| default.to_owned())
+}
+
+// Lets the OS pick a port. Another process could grab it before we bind it
+// again, which is unlikely enough for tests.
+pub(crate) fn free_port() -> u16 {
+    TcpListener::bind("127.0.0.1:0")
+        .and_then(|l| l.local_addr())
+        .expect("Failed to find a free port")
+        .port()
+}
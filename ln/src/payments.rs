@@ -0,0 +1,290 @@
+use std::collections::HashMap;
+use std::fmt;
+
+use serde_json::{json, Value};
+
+use crate::{wait_for, LNClient, WAIT_TIMEOUT};
+
+// How long `waitsendpay` blocks for each part. Longer than the minute a
+// payee holds on to parts of an incomplete payment.
+const SENDPAY_TIMEOUT_SECS: u64 = 90;
+
+#[derive(Clone, Debug)]
+pub struct Invoice {
+    pub bolt11: String,
+    pub payment_hash: String,
+}
+
+#[derive(Clone, Debug)]
+pub struct RouteHop {
+    pub node_id: String,
+    pub short_channel_id: String,
+    pub amount_msat: u64,
+    pub delay: u64,
+}
+
+// A settled payment. Single part payments have a single route.
+#[derive(Clone, Debug)]
+pub struct Payment {
+    pub payment_hash: String,
+    pub preimage: String,
+    // What the payee asked for
+    pub amount_msat: u64,
+    // What left our channels, including fees
+    pub amount_sent_msat: u64,
+    pub routes: Vec<Vec<RouteHop>>,
+}
+
+impl Payment {
+    pub fn fee_msat(&self) -> u64 {
+        self.amount_sent_msat - self.amount_msat
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct PaymentError {
+    pub payment_hash: String,
+    pub message: String,
+}
+
+impl fmt::Display for PaymentError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Payment {} failed: {}", self.payment_hash, self.message)
+    }
+}
+
+// Our side of each channel, by peer id
+pub type ChannelBalances = HashMap<String, u64>;
+
+pub(crate) fn create_invoice<C: LNClient + ?Sized>(
+    client: &C,
+    amount_msat: u64,
+    label: &str,
+    description: &str,
+) -> Invoice {
+    let invoice = client.call_json(
+        "invoice",
+        json!({
+            "amount_msat": amount_msat,
+            "label": label,
+            "description": description,
+        }),
+    );
+    Invoice {
+        bolt11: str_field(&invoice, "bolt11"),
+        payment_hash: str_field(&invoice, "payment_hash"),
+    }
+}
+
+pub(crate) fn pay_invoice_mpp<C: LNClient + ?Sized>(
+    client: &C,
+    bolt11: &str,
+    parts: u64,
+) -> Result<Payment, PaymentError> {
+    assert!(parts > 0, "Need at least one part");
+    let decoded = client.call_json("decode", json!({ "string": bolt11 }));
+    let payee = str_field(&decoded, "payee");
+    let payment_hash = str_field(&decoded, "payment_hash");
+    let payment_secret = str_field(&decoded, "payment_secret");
+    let fail = |message: String| PaymentError {
+        payment_hash: payment_hash.clone(),
+        message,
+    };
+    let amount_msat = match decoded.get("amount_msat") {
+        Some(amount) => msat(amount),
+        None => return Err(fail("Invoice has no amount".to_owned())),
+    };
+
+    // What the parts so far put through each `<scid>/<direction>`
+    let mut used: HashMap<String, u64> = HashMap::new();
+    let mut limits: HashMap<String, u64> = local_spendable(client);
+    let mut routes = Vec::new();
+    let mut sent = 0;
+    let mut error = None;
+    for part in 0..parts {
+        let part_msat = if part == parts - 1 {
+            amount_msat - (amount_msat / parts) * (parts - 1)
+        } else {
+            amount_msat / parts
+        };
+        let exclude: Vec<&String> = used
+            .iter()
+            .filter(|(hop, used_msat)| {
+                let limit = *limits
+                    .entry(hop.to_string())
+                    .or_insert_with(|| channel_capacity(client, hop));
+                **used_msat + part_msat > limit
+            })
+            .map(|(hop, _)| hop)
+            .collect();
+        let route = match client.try_call_json(
+            "getroute",
+            json!({
+                "id": payee,
+                "amount_msat": part_msat,
+                "riskfactor": 1,
+                "exclude": exclude,
+            }),
+        ) {
+            Ok(route) => route["route"].clone(),
+            Err(e) => {
+                error = Some(format!("No route for part {}: {:?}", part, e));
+                break;
+            }
+        };
+
+        if let Err(e) = client.try_call_json(
+            "sendpay",
+            json!({
+                "route": route,
+                "payment_hash": payment_hash,
+                "payment_secret": payment_secret,
+                "amount_msat": amount_msat,
+                "partid": part + 1,
+                "groupid": 1,
+            }),
+        ) {
+            error = Some(format!("sendpay failed for part {}: {:?}", part, e));
+            break;
+        }
+        for hop in route.as_array().into_iter().flatten() {
+            *used.entry(hop_key(hop)).or_insert(0) += msat(&hop["amount_msat"]);
+        }
+        routes.push(route);
+        sent += 1;
+    }
+
+    // Parts already out stay in flight until the payee gives up on the
+    // rest, so every one of them is waited on before returning
+    let mut preimage = String::new();
+    let mut amount_sent_msat = 0;
+    for part in 0..sent {
+        let result = client.try_call_json(
+            "waitsendpay",
+            json!({
+                "payment_hash": payment_hash,
+                "timeout": SENDPAY_TIMEOUT_SECS,
+                "partid": part + 1,
+                "groupid": 1,
+            }),
+        );
+        match result {
+            Ok(result) => {
+                preimage = str_field(&result, "payment_preimage");
+                amount_sent_msat += msat(&result["amount_sent_msat"]);
+            }
+            Err(e) => {
+                error.get_or_insert(format!("Part {} failed: {:?}", part, e));
+            }
+        }
+    }
+    if let Some(message) = error {
+        return Err(fail(message));
+    }
+
+    Ok(Payment {
+        payment_hash: payment_hash.clone(),
+        preimage,
+        amount_msat,
+        amount_sent_msat,
+        routes: routes.iter().map(route_hops).collect(),
+    })
+}
+
+// What each of our channels can send right now, by `<scid>/<direction>`
+fn local_spendable<C: LNClient + ?Sized>(client: &C) -> HashMap<String, u64> {
+    let channels = client.call_json("listpeerchannels", json!({}));
+    channels["channels"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .filter(|c| c.get("short_channel_id").is_some())
+        .map(|c| (hop_key(c), msat(&c["spendable_msat"])))
+        .collect()
+}
+
+// Other channels' balances are private, their capacity is as far as the
+// graph goes
+fn channel_capacity<C: LNClient + ?Sized>(client: &C, hop: &str) -> u64 {
+    let (scid, direction) = hop
+        .split_once('/')
+        .and_then(|(scid, direction)| Some((scid, direction.parse::<u64>().ok()?)))
+        .expect("Expected <scid>/<direction>");
+    let channels = client.call_json("listchannels", json!({ "short_channel_id": scid }));
+    channels["channels"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .find(|c| c["direction"].as_u64() == Some(direction))
+        .map(|c| msat(&c["amount_msat"]))
+        .unwrap_or(0)
+}
+
+pub(crate) fn channel_balances<C: LNClient + ?Sized>(client: &C) -> ChannelBalances {
+    let funds = client.call_json("listfunds", json!({}));
+    let mut balances = ChannelBalances::new();
+    for channel in funds["channels"].as_array().into_iter().flatten() {
+        *balances.entry(str_field(channel, "peer_id")).or_insert(0) +=
+            msat(&channel["our_amount_msat"]);
+    }
+    balances
+}
+
+pub(crate) fn assert_balance_moved<C: LNClient + ?Sized>(
+    client: &C,
+    before: &ChannelBalances,
+    peer_id: &str,
+    delta_msat: i64,
+) {
+    let start = *before.get(peer_id).unwrap_or(&0) as i64;
+    let expected = (start + delta_msat) as u64;
+    let what = format!("balance with {} to reach {} msat", peer_id, expected);
+    wait_for(&what, WAIT_TIMEOUT, || {
+        client.channel_balances().get(peer_id).copied().unwrap_or(0) == expected
+    });
+}
+
+fn route_hops(route: &Value) -> Vec<RouteHop> {
+    route
+        .as_array()
+        .into_iter()
+        .flatten()
+        .map(|hop| RouteHop {
+            node_id: str_field(hop, "id"),
+            short_channel_id: str_field(hop, "channel"),
+            amount_msat: msat(&hop["amount_msat"]),
+            delay: hop["delay"].as_u64().unwrap_or(0),
+        })
+        .collect()
+}
+
+// `<scid>/<direction>`, how `getroute` takes channels to exclude. Route
+// hops and `listpeerchannels` entries both carry the two.
+fn hop_key(value: &Value) -> String {
+    let scid = value
+        .get("channel")
+        .or_else(|| value.get("short_channel_id"))
+        .and_then(Value::as_str)
+        .unwrap_or_else(|| panic!("Missing short channel id in {}", value));
+    format!("{}/{}", scid, value["direction"].as_u64().unwrap_or(0))
+}
+
+fn str_field(value: &Value, name: &str) -> String {
+    value[name]
+        .as_str()
+        .unwrap_or_else(|| panic!("Missing `{}` in {}", name, value))
+        .to_owned()
+}
+
+// Core Lightning used to report amounts as "1000msat" strings, newer
+// versions use plain numbers
+pub fn msat(value: &Value) -> u64 {
+    match value {
+        Value::Number(n) => n.as_u64().expect("Invalid msat amount"),
+        Value::String(s) => s
+            .trim_end_matches("msat")
+            .parse()
+            .expect("Invalid msat amount"),
+        _ => panic!("Expected an msat amount, got {}", value),
+    }
+}
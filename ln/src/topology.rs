@@ -0,0 +1,337 @@
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::Path;
+use std::time::Duration;
+
+use bitcoin_basics::BitcoinClient;
+use serde::Deserialize;
+use serde_json::json;
+
+use crate::orchestrator::{ClusterNode, LNCluster};
+use crate::{wait_for, ChannelId, LNClient, LNSession};
+
+// Without dev builds Core Lightning batches gossip about once a minute
+const GOSSIP_TIMEOUT: Duration = Duration::from_secs(300);
+// On top of each channel's capacity, for the funding tx fee
+const OPEN_FEE_RESERVE_SAT: u64 = 50_000;
+
+// Nodes and the channels between them, e.g. in YAML
+//
+//  nodes: [alice, bob, carol]
+//  channels:
+//    - { from: alice, to: bob, capacity_sat: 1000000 }
+//    - { from: bob, to: carol, capacity_sat: 1000000, push_msat: 500000000 }
+//
+// or the same in TOML with `[[channels]]` tables. `from` opens and funds the
+// channel, `push_msat` starts out on the `to` side.
+#[derive(Clone, Debug, Default, Deserialize)]
+pub struct Topology {
+    pub nodes: Vec<String>,
+    #[serde(default)]
+    pub channels: Vec<ChannelSpec>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct ChannelSpec {
+    pub from: String,
+    pub to: String,
+    pub capacity_sat: u64,
+    #[serde(default)]
+    pub push_msat: u64,
+}
+
+impl Topology {
+    pub fn new() -> Topology {
+        Topology::default()
+    }
+
+    pub fn node(mut self, name: &str) -> Topology {
+        if !self.nodes.iter().any(|n| n == name) {
+            self.nodes.push(name.to_owned());
+        }
+        self
+    }
+
+    // Adds both nodes if they are not there yet
+    pub fn channel(self, from: &str, to: &str, capacity_sat: u64, push_msat: u64) -> Topology {
+        let mut topology = self.node(from).node(to);
+        topology.channels.push(ChannelSpec {
+            from: from.to_owned(),
+            to: to.to_owned(),
+            capacity_sat,
+            push_msat,
+        });
+        topology
+    }
+
+    // A-B-C-D, each node opening to the next
+    pub fn line(names: &[&str], capacity_sat: u64) -> Topology {
+        let topology = names.iter().fold(Topology::new(), |t, n| t.node(n));
+        names.windows(2).fold(topology, |t, pair| {
+            t.channel(pair[0], pair[1], capacity_sat, 0)
+        })
+    }
+
+    // The hub opens a channel to every spoke
+    pub fn hub_and_spoke(hub: &str, spokes: &[&str], capacity_sat: u64) -> Topology {
+        spokes.iter().fold(Topology::new().node(hub), |t, spoke| {
+            t.channel(hub, spoke, capacity_sat, 0)
+        })
+    }
+
+    pub fn from_yaml(s: &str) -> Topology {
+        let topology: Topology = serde_yaml::from_str(s).expect("Invalid YAML topology");
+        topology.validate();
+        topology
+    }
+
+    pub fn from_toml(s: &str) -> Topology {
+        let topology: Topology = toml::from_str(s).expect("Invalid TOML topology");
+        topology.validate();
+        topology
+    }
+
+    // Picks the format from the file extension
+    pub fn from_file(path: &Path) -> Topology {
+        let contents = fs::read_to_string(path)
+            .unwrap_or_else(|e| panic!("Failed to read {}: {}", path.display(), e));
+        match path.extension().and_then(|e| e.to_str()) {
+            Some("yaml") | Some("yml") => Topology::from_yaml(&contents),
+            Some("toml") => Topology::from_toml(&contents),
+            _ => panic!("Unknown topology format {}", path.display()),
+        }
+    }
+
+    // Channels are looked up by their two ends and nodes by name, so both
+    // have to be unique
+    fn validate(&self) {
+        let mut names = HashSet::new();
+        for name in self.nodes.iter() {
+            assert!(names.insert(name), "Node {} is listed twice", name);
+        }
+        let mut ends = HashSet::new();
+        for channel in self.channels.iter() {
+            for name in [&channel.from, &channel.to] {
+                assert!(
+                    names.contains(name),
+                    "Channel {} -> {} uses unknown node {}",
+                    channel.from,
+                    channel.to,
+                    name
+                );
+            }
+            assert!(
+                ends.insert((&channel.from, &channel.to)),
+                "Channel {} -> {} is listed twice",
+                channel.from,
+                channel.to
+            );
+        }
+    }
+
+    // Starts a cluster with the nodes, opens every channel and returns once
+    // each node sees the whole graph
+    pub fn build(&self) -> LNNetwork {
+        self.validate();
+        let cluster = LNCluster::start(self.nodes.len());
+        let mut network = LNNetwork {
+            cluster,
+            names: self
+                .nodes
+                .iter()
+                .enumerate()
+                .map(|(i, name)| (name.clone(), i))
+                .collect(),
+            channels: HashMap::new(),
+        };
+
+        // One confirmed UTXO per channel a node opens, so the openings don't
+        // wait on each other's change outputs. Together they cover the
+        // capacity of every channel the node opens.
+        for channel in self.channels.iter() {
+            network
+                .session(&channel.from)
+                .add_funds_sat(channel.capacity_sat + OPEN_FEE_RESERVE_SAT);
+        }
+
+        let mut opened = Vec::new();
+        for channel in self.channels.iter() {
+            let to = network.node(&channel.to);
+            let channel_id = network.session(&channel.from).open_channel(
+                &to.id(),
+                channel.capacity_sat,
+                channel.push_msat,
+            );
+            opened.push((channel, channel_id));
+        }
+
+        // Announcements need 6 confirmations
+        network.cluster.bitcoind().mine_blocks(6);
+        for (channel, channel_id) in opened {
+            let peer_id = network.node_id(&channel.to);
+            network
+                .session(&channel.from)
+                .wait_for_channel(&peer_id, &channel_id);
+            network
+                .channels
+                .insert((channel.from.clone(), channel.to.clone()), channel_id);
+        }
+        network.wait_for_gossip();
+        network
+    }
+}
+
+// A running topology, nodes are looked up by the names they were given
+pub struct LNNetwork {
+    cluster: LNCluster,
+    names: HashMap<String, usize>,
+    channels: HashMap<(String, String), ChannelId>,
+}
+
+impl LNNetwork {
+    pub fn cluster(&self) -> &LNCluster {
+        &self.cluster
+    }
+
+    pub fn node(&self, name: &str) -> &ClusterNode {
+        let i = self
+            .names
+            .get(name)
+            .unwrap_or_else(|| panic!("No node named {}", name));
+        self.cluster.node(*i)
+    }
+
+    pub fn session(&self, name: &str) -> LNSession {
+        self.node(name).session()
+    }
+
+    pub fn node_id(&self, name: &str) -> String {
+        self.node(name)
+            .rpc()
+            .getinfo()
+            .expect("Failed to get info")
+            .id
+    }
+
+    // Channel opened by `from` to `to`
+    pub fn channel_id(&self, from: &str, to: &str) -> Option<ChannelId> {
+        self.channels
+            .get(&(from.to_owned(), to.to_owned()))
+            .copied()
+    }
+
+    // Each channel shows up once per direction after both ends have sent
+    // their channel update
+    fn wait_for_gossip(&self) {
+        let expected = 2 * self.channels.len();
+        for node in self.cluster.nodes() {
+            let session = node.session();
+            wait_for("gossip to propagate", GOSSIP_TIMEOUT, || {
+                session.call_json("listchannels", json!({}))["channels"]
+                    .as_array()
+                    .map(|c| c.len() >= expected)
+                    .unwrap_or(false)
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn from_yaml() {
+        let topology = Topology::from_yaml(
+            r#"
+nodes: [alice, bob, carol]
+channels:
+  - { from: alice, to: bob, capacity_sat: 1000000 }
+  - { from: bob, to: carol, capacity_sat: 500000, push_msat: 100000000 }
+"#,
+        );
+        assert_eq!(topology.nodes, ["alice", "bob", "carol"]);
+        assert_eq!(topology.channels.len(), 2);
+        assert_eq!(topology.channels[0].from, "alice");
+        assert_eq!(topology.channels[0].to, "bob");
+        assert_eq!(topology.channels[0].push_msat, 0);
+        assert_eq!(topology.channels[1].capacity_sat, 500_000);
+        assert_eq!(topology.channels[1].push_msat, 100_000_000);
+    }
+
+    #[test]
+    fn from_toml() {
+        let topology = Topology::from_toml(
+            r#"
+nodes = ["alice", "bob", "carol"]
+
+[[channels]]
+from = "alice"
+to = "bob"
+capacity_sat = 1000000
+
+[[channels]]
+from = "bob"
+to = "carol"
+capacity_sat = 500000
+push_msat = 100000000
+"#,
+        );
+        assert_eq!(topology.nodes, ["alice", "bob", "carol"]);
+        assert_eq!(topology.channels.len(), 2);
+        assert_eq!(topology.channels[0].push_msat, 0);
+        assert_eq!(topology.channels[1].from, "bob");
+        assert_eq!(topology.channels[1].to, "carol");
+        assert_eq!(topology.channels[1].push_msat, 100_000_000);
+    }
+
+    #[test]
+    fn nodes_without_channels() {
+        let topology = Topology::from_yaml("nodes: [alice]");
+        assert_eq!(topology.nodes, ["alice"]);
+        assert!(topology.channels.is_empty());
+    }
+
+    #[test]
+    #[should_panic(expected = "Channel alice -> dave uses unknown node dave")]
+    fn rejects_unknown_nodes() {
+        Topology::from_yaml(
+            r#"
+nodes: [alice, bob]
+channels:
+  - { from: alice, to: dave, capacity_sat: 1000000 }
+"#,
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "Node bob is listed twice")]
+    fn rejects_duplicate_nodes() {
+        Topology::from_toml(r#"nodes = ["alice", "bob", "bob"]"#);
+    }
+
+    #[test]
+    #[should_panic(expected = "Channel alice -> bob is listed twice")]
+    fn rejects_duplicate_channels() {
+        Topology::new()
+            .channel("alice", "bob", 1_000_000, 0)
+            .channel("alice", "bob", 500_000, 0)
+            .validate();
+    }
+
+    #[test]
+    fn builders() {
+        let line = Topology::line(&["a", "b", "c"], 1_000);
+        assert_eq!(line.nodes, ["a", "b", "c"]);
+        let ends: Vec<_> = line
+            .channels
+            .iter()
+            .map(|c| (c.from.as_str(), c.to.as_str()))
+            .collect();
+        assert_eq!(ends, [("a", "b"), ("b", "c")]);
+
+        let hub = Topology::hub_and_spoke("hub", &["x", "y"], 1_000);
+        assert_eq!(hub.nodes, ["hub", "x", "y"]);
+        assert!(hub.channels.iter().all(|c| c.from == "hub"));
+    }
+}
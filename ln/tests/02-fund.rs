@@ -1,12 +1,13 @@
-use ln_workshop::LNClient;
 use clightningrpc::LightningRPC;
+use ln_workshop::orchestrator::LNCluster;
+use ln_workshop::LNClient;
 
 fn main() {
-    let node = "ln-nodes/node-1/regtest/lightning-rpc";
-    let client = LightningRPC::get_client(node);
+    // A node of our own, see 04-cluster for how it's started
+    let cluster = LNCluster::start(1);
+    let client = LightningRPC::get_client(cluster.node(0).socket_path());
     client.add_funds();
     let funds_list = client.listfunds();
     assert!(funds_list.is_ok());
     assert!(funds_list.unwrap().outputs.len() > 0);
 }
-
@@ -1,13 +1,15 @@
 use clightningrpc::responses::NetworkAddress;
 use clightningrpc::LightningRPC;
+use ln_workshop::orchestrator::LNCluster;
 use ln_workshop::LNClient;
 
 fn main() {
+    // Two nodes of our own, see 04-cluster for how they're started
+    let cluster = LNCluster::start(2);
+
     // RPC clients
-    let node1 = "ln-nodes/node-1/regtest/lightning-rpc";
-    let node2 = "ln-nodes/node-2/regtest/lightning-rpc";
-    let client1 = LightningRPC::get_client(node1);
-    let client2 = LightningRPC::get_client(node2);
+    let client1 = LightningRPC::get_client(cluster.node(0).socket_path());
+    let client2 = LightningRPC::get_client(cluster.node(1).socket_path());
 
     // Add funds
     client1.add_funds();
@@ -23,4 +25,6 @@ fn main() {
 
     // Create channel
     let channel_id = client1.create_channel(&id);
+    let state = client1.channel_state(&info.id, &channel_id);
+    assert_eq!(state.as_deref(), Some("CHANNELD_NORMAL"));
 }
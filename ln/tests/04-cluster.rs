@@ -0,0 +1,35 @@
+// Running the nodes by hand gets old quickly. `LNCluster` starts a fresh
+// regtest `bitcoind` with as many `lightningd` nodes as we ask for, each
+// in a temp directory with its own ports, and stops them all when dropped.
+//
+//  let cluster = LNCluster::start(2);
+//  let alice = cluster.node(0).session();
+//
+// The nodes get a config in the same format as `ln-nodes/*/config`, so
+// everything from the previous exercises works on them unchanged.
+//
+// `bitcoind` and `lightningd` need to be on the PATH, or point
+// `BITCOIND_EXE` and `LIGHTNINGD_EXE` at them.
+//
+// RESOURCES:
+//
+//  - https://docs.corelightning.org/docs/configuration
+//    Core Lightning config options
+//
+//  - https://doc.rust-lang.org/std/process/struct.Command.html
+//    Spawning processes from Rust
+
+use ln_workshop::orchestrator::LNCluster;
+
+fn main() {
+    let cluster = LNCluster::start(2);
+    let alice = cluster.node(0).session();
+    let bob = cluster.node(1);
+
+    alice.add_funds();
+    let channel_id = alice.create_channel(&bob.id());
+
+    let info = bob.rpc().getinfo().expect("Failed to get info");
+    let state = alice.channel_state(&info.id, &channel_id);
+    assert_eq!(state.as_deref(), Some("CHANNELD_NORMAL"));
+}
@@ -0,0 +1,43 @@
+// Multi-hop tests need a network first. Instead of wiring it up channel by
+// channel, we describe it and let `Topology::build` do the rest: start the
+// nodes, fund them, open the channels, mine until they are announced and
+// wait until every node has heard about every channel.
+//
+// Topologies can be built in code
+//
+//  Topology::line(&["alice", "bob", "carol"], 1_000_000);
+//  Topology::new().channel("alice", "bob", 1_000_000, 0);
+//
+// or read from YAML and TOML files, see `ln_workshop::topology`.
+//
+// RESOURCES:
+//
+//  - https://github.com/lightning/bolts/blob/master/07-routing-gossip.md
+//    What nodes tell each other about channels, and when
+//
+//  - https://docs.corelightning.org/reference/lightning-listchannels
+//    How a node's view of the graph looks
+
+use ln_workshop::topology::Topology;
+use ln_workshop::LNClient;
+use serde_json::json;
+
+fn main() {
+    let topology = Topology::from_yaml(
+        r#"
+nodes: [alice, bob, carol, dave]
+channels:
+  - { from: alice, to: bob, capacity_sat: 1000000 }
+  - { from: bob, to: carol, capacity_sat: 1000000, push_msat: 100000000 }
+  - { from: carol, to: dave, capacity_sat: 1000000 }
+"#,
+    );
+    let network = topology.build();
+
+    // Alice knows about the channels she isn't part of
+    let graph = network
+        .session("alice")
+        .call_json("listchannels", json!({}));
+    assert_eq!(graph["channels"].as_array().unwrap().len(), 6);
+    assert!(network.channel_id("carol", "dave").is_some());
+}
@@ -0,0 +1,71 @@
+// With a network in place we can finally move some sats. Paying an invoice
+// takes a few steps on Core Lightning's low level RPCs:
+//  - `decode` the invoice for the payee, amount and payment secret
+//  - `getroute` to find a path to the payee
+//  - `sendpay` to send the HTLCs along the route
+//  - `waitsendpay` to block until the payment settles or fails
+//
+// `pay_invoice` returns the route it took, the fee and the preimage, which
+// doubles as the proof of payment. `pay_invoice_mpp` does the same with the
+// payment split into parts, for amounts no single route can carry.
+//
+// Balances in `listfunds` change once the HTLCs are resolved on both sides,
+// `assert_balance_moved` waits for that.
+//
+// RESOURCES:
+//
+//  - https://docs.corelightning.org/reference/lightning-sendpay
+//  - https://docs.corelightning.org/reference/lightning-waitsendpay
+//
+// Extra Reading:
+// - https://github.com/lnbook/lnbook/blob/develop/08_routing_htlcs.asciidoc
+
+use bitcoincore_rpc::bitcoin::hashes::{hex::FromHex, sha256, Hash};
+use ln_workshop::topology::Topology;
+use ln_workshop::LNClient;
+
+fn main() {
+    // Two ways from alice to dave, through bob or through carol
+    let network = Topology::new()
+        .channel("alice", "bob", 1_000_000, 0)
+        .channel("alice", "carol", 1_000_000, 0)
+        .channel("bob", "dave", 1_000_000, 0)
+        .channel("carol", "dave", 1_000_000, 0)
+        .build();
+    let alice = network.session("alice");
+    let dave = network.session("dave");
+    let bob_id = network.node_id("bob");
+    let carol_id = network.node_id("carol");
+
+    let before = alice.channel_balances();
+    let invoice = dave.create_invoice(50_000_000, "coffee", "A coffee");
+    let payment = alice.pay_invoice(&invoice.bolt11).expect("Payment failed");
+
+    // Through bob or carol
+    let via = payment.routes[0][0].node_id.clone();
+    assert_eq!(payment.routes[0].len(), 2);
+    assert!(via == bob_id || via == carol_id);
+    assert!(payment.fee_msat() > 0);
+
+    // The preimage hashes to the payment hash
+    let preimage = Vec::<u8>::from_hex(&payment.preimage).unwrap();
+    assert_eq!(
+        sha256::Hash::hash(&preimage).to_string(),
+        invoice.payment_hash
+    );
+
+    alice.assert_balance_moved(&before, &via, -(payment.amount_sent_msat as i64));
+
+    // More than either channel of alice's can send, so it takes both
+    let before = alice.channel_balances();
+    let invoice = dave.create_invoice(1_200_000_000, "bike", "A bike");
+    let payment = alice
+        .pay_invoice_mpp(&invoice.bolt11, 2)
+        .expect("MPP payment failed");
+    assert_eq!(payment.routes.len(), 2);
+    assert_ne!(payment.routes[0][0].node_id, payment.routes[1][0].node_id);
+    for route in payment.routes.iter() {
+        let via = &route[0].node_id;
+        alice.assert_balance_moved(&before, via, -(route[0].amount_msat as i64));
+    }
+}
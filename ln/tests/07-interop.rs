@@ -0,0 +1,34 @@
+// Lightning only works if implementations agree on the BOLTs. Here our LDK
+// based `rln-node` meets Core Lightning: the harness starts both on the
+// workshop bitcoind, connects them, opens a channel each way and sends
+// BOLT11 and keysend payments in both directions.
+//
+// `rln-node` is driven over its gRPC API, so build it with
+//
+//  cargo build -p ln_node --features grpc
+//
+// and have `ln_node` on the PATH or point `RLN_NODE_EXE` at it. The
+// harness runs its own lightningd with the `ln-nodes/node-1` config, so that
+// node can keep running.
+//
+// If a step fails, the report lists lines from either side's logs that look
+// like the two disagreeing.
+//
+// RESOURCES:
+//
+//  - https://github.com/lightning/bolts
+//    The specs both implementations follow
+//
+//  - https://lightningdevkit.org/
+//    LDK docs
+
+use std::path::Path;
+
+use ln_workshop::interop::InteropHarness;
+
+fn main() {
+    let mut harness = InteropHarness::start(Path::new("ln-nodes/node-1"));
+    let report = harness.run();
+    println!("{}", report);
+    assert!(report.passed());
+}
@@ -3,8 +3,21 @@
 // other.
 // If you are an advanced user however, feel free to break the rules!
 
+// 01 and 07 use the `ln-nodes` lightningd's on the shared bitcoind at
+// 18443. 02 to 06 start an `LNCluster` with a bitcoind of its own, so those
+// only need `bitcoind` and `lightningd` on the PATH.
+
 #[test]
 fn tests() {
     let t = trybuild::TestCases::new();
+    // Shared node
     t.pass("tests/01-configure.rs");
+    // Own node
+//     t.pass("tests/02-fund.rs");
+//     t.pass("tests/03-channel.rs");
+//     t.pass("tests/04-cluster.rs");
+//     t.pass("tests/05-topology.rs");
+//     t.pass("tests/06-payments.rs");
+    // Shared node
+//     t.pass("tests/07-interop.rs");
 }
@@ -0,0 +1,637 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::str::FromStr;
+#[cfg(feature = "zmq")]
+use std::time::Duration;
+
+use bitcoin_basics::wallet::{DescriptorImport, WalletOptions};
+#[cfg(feature = "zmq")]
+use bitcoin_basics::zmq::Subscriber;
+use bitcoin_basics::BitcoinClient;
+use bitcoincore_rpc::bitcoin::secp256k1::{PublicKey, Secp256k1, SecretKey};
+use bitcoincore_rpc::bitcoin::util::bip32::ExtendedPrivKey;
+use bitcoincore_rpc::bitcoin::util::psbt::{self, PartiallySignedTransaction};
+use bitcoincore_rpc::bitcoin::util::sighash::SighashCache;
+use bitcoincore_rpc::bitcoin::{
+    Amount, EcdsaSig, EcdsaSighashType, Network, OutPoint, PackedLockTime, Script, Sequence,
+    Transaction, TxIn, TxOut, Txid, Witness,
+};
+use bitcoincore_rpc::json::ScanTxOutRequest;
+use bitcoincore_rpc::{Client, RpcApi};
+use miniscript::psbt::{PsbtExt, PsbtInputExt};
+use miniscript::{DefiniteDescriptorKey, Descriptor, DescriptorPublicKey};
+
+pub mod policy;
+pub mod ranged;
+pub mod roles;
+pub mod spend;
+pub mod taproot;
+
+use ranged::Keychain;
+use spend::{Signers, SpendError};
+use taproot::TapPath;
+
+pub const WALLET_NAME: &str = "test_wallet";
+// Descriptors without private keys can't go into `WALLET_NAME`
+pub const WATCH_ONLY_WALLET_NAME: &str = "watch_only";
+
+// nLockTime values below this are block heights, timestamps above
+const LOCK_TIME_THRESHOLD: u64 = 500_000_000;
+// Bits of nSequence holding a relative timelock's value
+const SEQUENCE_LOCKTIME_MASK: u32 = 0x0000_ffff;
+
+#[derive(Debug)]
+pub enum UtxoError {
+    // `txid` has no output paying to `script_pubkey`
+    NotFound { txid: Txid, script_pubkey: Script },
+    Rpc(String),
+}
+
+impl fmt::Display for UtxoError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            UtxoError::NotFound {
+                txid,
+                script_pubkey,
+            } => write!(f, "{} has no output paying to {}", txid, script_pubkey),
+            UtxoError::Rpc(e) => write!(f, "RPC error: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for UtxoError {}
+
+pub trait MiniscriptClient {
+    // Client on a loaded wallet with some premined BTC
+    fn configure_client() -> Client;
+
+    // Sends a raw tx and mines a block
+    fn sendrawtx(&self, tx: Transaction) -> Txid;
+
+    fn spend_pkh(
+        &self,
+        txid: Txid,
+        desc: Descriptor<DefiniteDescriptorKey>,
+        txout: TxOut,
+        secret_key: SecretKey,
+        pub_key: PublicKey,
+    );
+
+    // n of m multisig, sorted so the order of `pubkeys` doesn't matter
+    fn multisig_desc(&self, n: usize, pubkeys: &[PublicKey]) -> Descriptor<DefiniteDescriptorKey>;
+
+    // First output of `txid` paying to `script_pubkey`
+    fn utxo_details(
+        &self,
+        script_pubkey: Script,
+        txid: Txid,
+    ) -> Result<(OutPoint, TxOut), UtxoError>;
+
+    fn spend_multi(
+        &self,
+        txid: Txid,
+        desc: Descriptor<DefiniteDescriptorKey>,
+        out_txout: TxOut,
+        secrets: &[SecretKey],
+        pubkeys: &[PublicKey],
+    );
+
+    // Unspent output at `outpoint`, from the node's UTXO set
+    fn prevout(&self, outpoint: OutPoint) -> Result<TxOut, SpendError>;
+
+    // Spends `outpoints`, all locked by `desc`, to `outputs` using the
+    // cheapest satisfaction `signers` allow, then broadcasts and mines it.
+    // Works for sh, wsh, sh-wsh, wpkh and tr descriptors.
+    fn spend_descriptor(
+        &self,
+        outpoints: &[OutPoint],
+        desc: &Descriptor<DefiniteDescriptorKey>,
+        outputs: Vec<TxOut>,
+        signers: &Signers,
+    ) -> Result<Txid, SpendError>;
+
+    // Signed tx for `spend_descriptor`, without broadcasting it. Timelocks
+    // `signers` leaves unset are picked from `desc`, the fewest that
+    // satisfy it.
+    fn build_spend(
+        &self,
+        outpoints: &[OutPoint],
+        desc: &Descriptor<DefiniteDescriptorKey>,
+        outputs: Vec<TxOut>,
+        signers: &Signers,
+    ) -> Result<Transaction, SpendError>;
+
+    // Mines the blocks `tx` needs before its nLockTime and the relative
+    // timelocks of its inputs let it into the mempool. Time based locks
+    // aren't handled.
+    fn mine_to_maturity(&self, tx: &Transaction);
+
+    // Sends `amount` to `desc` and mines it
+    fn fund_descriptor(&self, desc: &Descriptor<DefiniteDescriptorKey>, amount: Amount)
+        -> OutPoint;
+
+    // Like `spend_descriptor`, going down `path` of a tr descriptor instead
+    // of the cheapest one
+    fn spend_taproot(
+        &self,
+        outpoints: &[OutPoint],
+        desc: &Descriptor<DefiniteDescriptorKey>,
+        outputs: Vec<TxOut>,
+        signers: &Signers,
+        path: TapPath,
+    ) -> Result<Txid, SpendError>;
+
+    // Imports `desc` into the watch-only wallet, so the node tracks funds
+    // sent to it. Returns a client on that wallet, which stays loaded until
+    // `unload_wallet_in_node(WATCH_ONLY_WALLET_NAME)`.
+    fn import_watch_only(&self, desc: &Descriptor<DefiniteDescriptorKey>) -> Client;
+
+    // Imports addresses 0 to `range_end` of a ranged descriptor into the
+    // watch-only wallet. Returns a client on that wallet, loaded until
+    // unloaded like above.
+    fn import_ranged(
+        &self,
+        desc: &Descriptor<DescriptorPublicKey>,
+        range_end: u32,
+        keychain: Keychain,
+    ) -> Client;
+
+    // Unspent outputs paying to addresses 0 to `range_end` of `desc`, along
+    // with the index each address is at
+    fn scan_ranged(
+        &self,
+        desc: &Descriptor<DescriptorPublicKey>,
+        range_end: u32,
+    ) -> Vec<(u32, OutPoint, TxOut)>;
+
+    // Spends `utxos`, each at the given index of `desc`, signing with keys
+    // derived from `xprvs` along the paths in the descriptor
+    fn spend_ranged(
+        &self,
+        utxos: &[(u32, OutPoint)],
+        desc: &Descriptor<DescriptorPublicKey>,
+        outputs: Vec<TxOut>,
+        xprvs: &[ExtendedPrivKey],
+    ) -> Result<Txid, SpendError>;
+
+    // BIP174 updater for PSBTs whose inputs are all locked by `desc`, looks
+    // the prevouts up in the node
+    fn update_inputs(
+        &self,
+        psbt: &mut PartiallySignedTransaction,
+        desc: &Descriptor<DefiniteDescriptorKey>,
+    ) -> Result<(), SpendError>;
+
+    // Empty PSBT, inputs and outputs get added by `update_psbt`
+    fn psbt_new() -> PartiallySignedTransaction;
+
+    // Adds an input spending `outp` (holding `txout`, locked by `desc`) and
+    // an `output`
+    fn update_psbt(
+        psbt: &mut PartiallySignedTransaction,
+        outp: OutPoint,
+        output: TxOut,
+        desc: &Descriptor<DefiniteDescriptorKey>,
+        txout: TxOut,
+    );
+
+    // Signs every input with each of the keys, `secrets[i]` belonging to
+    // `pubkeys[i]`, then finalizes and extracts the tx
+    fn sign_and_finalize(
+        psbt: &mut PartiallySignedTransaction,
+        secrets: &[SecretKey],
+        pubkeys: &[PublicKey],
+    ) -> Transaction;
+}
+
+impl MiniscriptClient for Client {
+    fn configure_client() -> Client {
+        Client::setup()
+            .ensure_wallet(WALLET_NAME, &WalletOptions::default())
+            .expect("Failed to load wallet");
+        // Other wallets might be loaded too
+        let client = Client::with_custom_path(&format!("wallet/{}", WALLET_NAME));
+        client.get_dough_if_broke();
+        client
+    }
+
+    fn sendrawtx(&self, tx: Transaction) -> Txid {
+        let txid = self
+            .send_raw_transaction(&tx)
+            .expect("Failed to send raw tx");
+        self.mine_blocks(1);
+        txid
+    }
+
+    fn spend_pkh(
+        &self,
+        txid: Txid,
+        desc: Descriptor<DefiniteDescriptorKey>,
+        txout: TxOut,
+        secret_key: SecretKey,
+        pub_key: PublicKey,
+    ) {
+        let (outpoint, _) = self
+            .utxo_details(desc.script_pubkey(), txid)
+            .unwrap_or_else(|e| panic!("{}", e));
+        assert_eq!(secret_key.public_key(&Secp256k1::new()), pub_key);
+
+        let signers = Signers::new().key(secret_key);
+        self.spend_descriptor(&[outpoint], &desc, vec![txout], &signers)
+            .unwrap_or_else(|e| panic!("Failed to spend pkh output: {}", e));
+    }
+
+    fn multisig_desc(&self, n: usize, pubkeys: &[PublicKey]) -> Descriptor<DefiniteDescriptorKey> {
+        let keys: Vec<String> = pubkeys.iter().map(|pk| pk.to_string()).collect();
+        let s = format!("wsh(sortedmulti({},{}))", n, keys.join(","));
+        Descriptor::<DefiniteDescriptorKey>::from_str(&s).expect("Invalid multisig descriptor")
+    }
+
+    fn utxo_details(
+        &self,
+        script_pubkey: Script,
+        txid: Txid,
+    ) -> Result<(OutPoint, TxOut), UtxoError> {
+        let tx = self
+            .get_transaction(&txid, None)
+            .map_err(|e| UtxoError::Rpc(e.to_string()))?
+            .transaction()
+            .map_err(|e| UtxoError::Rpc(e.to_string()))?;
+        tx.output
+            .iter()
+            .enumerate()
+            .find(|(_, out)| out.script_pubkey == script_pubkey)
+            .map(|(vout, out)| (OutPoint::new(txid, vout as u32), out.clone()))
+            .ok_or(UtxoError::NotFound {
+                txid,
+                script_pubkey,
+            })
+    }
+
+    fn spend_multi(
+        &self,
+        txid: Txid,
+        desc: Descriptor<DefiniteDescriptorKey>,
+        out_txout: TxOut,
+        secrets: &[SecretKey],
+        pubkeys: &[PublicKey],
+    ) {
+        let (outpoint, _) = self
+            .utxo_details(desc.script_pubkey(), txid)
+            .unwrap_or_else(|e| panic!("{}", e));
+        assert_eq!(secrets.len(), pubkeys.len(), "One secret per pubkey");
+
+        let signers = Signers {
+            keys: secrets.to_vec(),
+            ..Signers::default()
+        };
+        self.spend_descriptor(&[outpoint], &desc, vec![out_txout], &signers)
+            .unwrap_or_else(|e| panic!("Failed to spend multisig output: {}", e));
+    }
+
+    fn prevout(&self, outpoint: OutPoint) -> Result<TxOut, SpendError> {
+        let utxo = self
+            .get_tx_out(&outpoint.txid, outpoint.vout, Some(false))
+            .map_err(|e| SpendError::Rpc(e.to_string()))?
+            .ok_or_else(|| SpendError::Rpc(format!("{} is spent or unknown", outpoint)))?;
+        Ok(TxOut {
+            value: utxo.value.to_sat(),
+            script_pubkey: Script::from(utxo.script_pub_key.hex),
+        })
+    }
+
+    fn spend_descriptor(
+        &self,
+        outpoints: &[OutPoint],
+        desc: &Descriptor<DefiniteDescriptorKey>,
+        outputs: Vec<TxOut>,
+        signers: &Signers,
+    ) -> Result<Txid, SpendError> {
+        spend(self, outpoints, desc, outputs, signers, None)
+    }
+
+    fn build_spend(
+        &self,
+        outpoints: &[OutPoint],
+        desc: &Descriptor<DefiniteDescriptorKey>,
+        outputs: Vec<TxOut>,
+        signers: &Signers,
+    ) -> Result<Transaction, SpendError> {
+        build(self, outpoints, desc, outputs, signers, None)
+    }
+
+    fn mine_to_maturity(&self, tx: &Transaction) {
+        let tip = self.get_block_count().expect("Failed to get block count");
+        // The next block is the first one the tx can go in
+        let lock_time = tx.lock_time.0 as u64;
+        let mut needed = if lock_time < LOCK_TIME_THRESHOLD {
+            lock_time.saturating_sub(tip)
+        } else {
+            0
+        };
+
+        for input in tx.input.iter() {
+            if !input.sequence.is_height_locked() {
+                continue;
+            }
+            let confirmations = self
+                .get_tx_out(
+                    &input.previous_output.txid,
+                    input.previous_output.vout,
+                    Some(false),
+                )
+                .expect("Failed to get prevout")
+                .expect("Prevout is spent or unknown")
+                .confirmations as u64;
+            let blocks = (input.sequence.0 & SEQUENCE_LOCKTIME_MASK) as u64;
+            needed = needed.max(blocks.saturating_sub(confirmations));
+        }
+        if needed > 0 {
+            self.mine_blocks(needed);
+        }
+    }
+
+    fn fund_descriptor(
+        &self,
+        desc: &Descriptor<DefiniteDescriptorKey>,
+        amount: Amount,
+    ) -> OutPoint {
+        let address = desc
+            .address(Network::Regtest)
+            .expect("Descriptor has no address");
+        let txid = self
+            .send_to_address(&address, amount, None, None, None, None, None, None)
+            .expect("Failed to send to descriptor");
+        self.mine_blocks(1);
+        let (outpoint, _) = self
+            .utxo_details(desc.script_pubkey(), txid)
+            .unwrap_or_else(|e| panic!("{}", e));
+        outpoint
+    }
+
+    fn spend_taproot(
+        &self,
+        outpoints: &[OutPoint],
+        desc: &Descriptor<DefiniteDescriptorKey>,
+        outputs: Vec<TxOut>,
+        signers: &Signers,
+        path: TapPath,
+    ) -> Result<Txid, SpendError> {
+        spend(self, outpoints, desc, outputs, signers, Some(path))
+    }
+
+    fn import_watch_only(&self, desc: &Descriptor<DefiniteDescriptorKey>) -> Client {
+        // Displayed descriptors carry their checksum, which bitcoind wants
+        import_descriptor(self, DescriptorImport::new(&desc.to_string()))
+    }
+
+    fn import_ranged(
+        &self,
+        desc: &Descriptor<DescriptorPublicKey>,
+        range_end: u32,
+        keychain: Keychain,
+    ) -> Client {
+        let mut request = DescriptorImport::new(&desc.to_string()).range(0, range_end);
+        if keychain == Keychain::Change {
+            request = request.internal();
+        }
+        import_descriptor(self, request)
+    }
+
+    fn scan_ranged(
+        &self,
+        desc: &Descriptor<DescriptorPublicKey>,
+        range_end: u32,
+    ) -> Vec<(u32, OutPoint, TxOut)> {
+        let request = ScanTxOutRequest::Extended {
+            desc: desc.to_string(),
+            range: (0, range_end as u64),
+        };
+        let result = self
+            .scan_tx_out_set_blocking(&[request])
+            .expect("Failed to scan UTXO set");
+
+        let indices: HashMap<Script, u32> = (0..=range_end)
+            .map(|i| (ranged::derive(desc, i).script_pubkey(), i))
+            .collect();
+        let mut utxos: Vec<_> = result
+            .unspents
+            .into_iter()
+            .filter_map(|utxo| {
+                let index = *indices.get(&utxo.script_pub_key)?;
+                let txout = TxOut {
+                    value: utxo.amount.to_sat(),
+                    script_pubkey: utxo.script_pub_key,
+                };
+                Some((index, OutPoint::new(utxo.txid, utxo.vout), txout))
+            })
+            .collect();
+        utxos.sort_by_key(|(index, outpoint, _)| (*index, *outpoint));
+        utxos
+    }
+
+    fn spend_ranged(
+        &self,
+        utxos: &[(u32, OutPoint)],
+        desc: &Descriptor<DescriptorPublicKey>,
+        outputs: Vec<TxOut>,
+        xprvs: &[ExtendedPrivKey],
+    ) -> Result<Txid, SpendError> {
+        let secp = Secp256k1::new();
+        let outpoints: Vec<OutPoint> = utxos.iter().map(|(_, outpoint)| *outpoint).collect();
+        let mut psbt = spend::unsigned_psbt(&outpoints, outputs, &Signers::default())?;
+
+        // Every input has its own derived descriptor
+        let descs: Vec<_> = utxos
+            .iter()
+            .map(|(index, _)| ranged::derive(desc, *index))
+            .collect();
+        for (i, outpoint) in outpoints.iter().enumerate() {
+            spend::update_input(&mut psbt, i, &descs[i], self.prevout(*outpoint)?)?;
+        }
+        for (i, desc) in descs.iter().enumerate() {
+            let signers = Signers {
+                keys: ranged::derive_keys(&secp, &psbt.inputs[i], xprvs),
+                ..Signers::default()
+            };
+            spend::sign_input(&secp, &mut psbt, i, desc, &signers)?;
+        }
+        let tx = spend::finalize(&secp, &mut psbt)?;
+        broadcast(self, &tx)
+    }
+
+    fn update_inputs(
+        &self,
+        psbt: &mut PartiallySignedTransaction,
+        desc: &Descriptor<DefiniteDescriptorKey>,
+    ) -> Result<(), SpendError> {
+        let outpoints: Vec<OutPoint> = psbt
+            .unsigned_tx
+            .input
+            .iter()
+            .map(|txin| txin.previous_output)
+            .collect();
+        for (i, outpoint) in outpoints.into_iter().enumerate() {
+            roles::update(psbt, i, desc, self.prevout(outpoint)?)?;
+        }
+        Ok(())
+    }
+
+    fn psbt_new() -> PartiallySignedTransaction {
+        let tx = Transaction {
+            version: 2,
+            lock_time: PackedLockTime::ZERO,
+            input: vec![],
+            output: vec![],
+        };
+        PartiallySignedTransaction::from_unsigned_tx(tx).expect("Failed to create PSBT")
+    }
+
+    fn update_psbt(
+        psbt: &mut PartiallySignedTransaction,
+        outp: OutPoint,
+        output: TxOut,
+        desc: &Descriptor<DefiniteDescriptorKey>,
+        txout: TxOut,
+    ) {
+        psbt.unsigned_tx.input.push(TxIn {
+            previous_output: outp,
+            script_sig: Script::new(),
+            sequence: Sequence::MAX,
+            witness: Witness::default(),
+        });
+        psbt.unsigned_tx.output.push(output);
+
+        let mut input = psbt::Input::default();
+        input
+            .update_with_descriptor_unchecked(desc)
+            .expect("Failed to update input with descriptor");
+        input.witness_utxo = Some(txout);
+        psbt.inputs.push(input);
+        psbt.outputs.push(psbt::Output::default());
+    }
+
+    fn sign_and_finalize(
+        psbt: &mut PartiallySignedTransaction,
+        secrets: &[SecretKey],
+        pubkeys: &[PublicKey],
+    ) -> Transaction {
+        let secp = Secp256k1::new();
+
+        // Sighashes borrow the unsigned tx, compute them all before signing
+        let mut sighash_cache = SighashCache::new(&psbt.unsigned_tx);
+        let msgs: Vec<_> = (0..psbt.inputs.len())
+            .map(|i| {
+                psbt.sighash_msg(i, &mut sighash_cache, None)
+                    .expect("Failed to compute sighash")
+                    .to_secp_msg()
+            })
+            .collect();
+
+        for (i, msg) in msgs.iter().enumerate() {
+            for (secret, pub_key) in secrets.iter().zip(pubkeys) {
+                let sig = secp.sign_ecdsa(msg, secret);
+                psbt.inputs[i].partial_sigs.insert(
+                    bitcoincore_rpc::bitcoin::PublicKey::new(*pub_key),
+                    EcdsaSig {
+                        sig,
+                        hash_ty: EcdsaSighashType::All,
+                    },
+                );
+            }
+        }
+
+        psbt.finalize_mut(&secp).expect("Failed to finalize PSBT");
+        psbt.extract(&secp).expect("Failed to extract tx")
+    }
+}
+
+fn build(
+    client: &Client,
+    outpoints: &[OutPoint],
+    desc: &Descriptor<DefiniteDescriptorKey>,
+    outputs: Vec<TxOut>,
+    signers: &Signers,
+    path: Option<TapPath>,
+) -> Result<Transaction, SpendError> {
+    let secp = Secp256k1::new();
+    let inputs = outpoints
+        .iter()
+        .map(|outpoint| Ok((*outpoint, client.prevout(*outpoint)?)))
+        .collect::<Result<Vec<_>, SpendError>>()?;
+
+    // Keeps the error of the option without timelocks, it says the most
+    let mut unsatisfied = None;
+    for signers in spend::timelock_options(desc, signers)? {
+        let mut psbt = spend::build_psbt(&inputs, desc, outputs.clone(), &signers)?;
+        if let Some(path) = path {
+            taproot::select_path(&mut psbt, desc, path)?;
+        }
+        spend::sign_psbt(&secp, &mut psbt, desc, &signers)?;
+        if let Some(path) = path {
+            taproot::drop_key_path(&mut psbt, path);
+        }
+        match spend::finalize(&secp, &mut psbt) {
+            Ok(tx) => return Ok(tx),
+            Err(e @ SpendError::CannotSatisfy { .. }) => {
+                unsatisfied.get_or_insert(e);
+            }
+            Err(e) => return Err(e),
+        }
+    }
+    Err(unsatisfied.expect("There's always at least one option"))
+}
+
+fn spend(
+    client: &Client,
+    outpoints: &[OutPoint],
+    desc: &Descriptor<DefiniteDescriptorKey>,
+    outputs: Vec<TxOut>,
+    signers: &Signers,
+    path: Option<TapPath>,
+) -> Result<Txid, SpendError> {
+    let tx = build(client, outpoints, desc, outputs, signers, path)?;
+    broadcast(client, &tx)
+}
+
+// Sends `tx` and mines it
+fn broadcast(client: &Client, tx: &Transaction) -> Result<Txid, SpendError> {
+    let txid = client
+        .send_raw_transaction(tx)
+        .map_err(|e| SpendError::Rpc(e.to_string()))?;
+    client.mine_blocks(1);
+    Ok(txid)
+}
+
+// Sends `tx` without mining it, and returns once bitcoind announces it in its
+// mempool. `events` needs the sequence topic and has to be connected before
+// the call, or the announcement could go by unheard.
+#[cfg(feature = "zmq")]
+pub async fn send_to_mempool(
+    client: &Client,
+    events: &mut Subscriber,
+    tx: &Transaction,
+    timeout: Duration,
+) -> Result<Txid, SpendError> {
+    let txid = client
+        .send_raw_transaction(tx)
+        .map_err(|e| SpendError::Rpc(e.to_string()))?;
+    events
+        .wait_for_mempool(&txid, timeout)
+        .await
+        .map_err(|e| SpendError::Rpc(e.to_string()))?;
+    Ok(txid)
+}
+
+// Imports a single `importdescriptors` request into the watch-only wallet,
+// which gets created on first use
+fn import_descriptor(client: &Client, request: DescriptorImport) -> Client {
+    client
+        .ensure_wallet(WATCH_ONLY_WALLET_NAME, &WalletOptions::watch_only())
+        .expect("Failed to load watch-only wallet");
+    let watch = Client::with_custom_path(&format!("wallet/{}", WATCH_ONLY_WALLET_NAME));
+    watch
+        .import_into_wallet(&[request])
+        .unwrap_or_else(|e| panic!("{}", e));
+    watch
+}
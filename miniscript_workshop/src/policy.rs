@@ -0,0 +1,122 @@
+use std::fmt;
+use std::fs;
+use std::path::Path;
+use std::str::FromStr;
+
+use bitcoincore_rpc::bitcoin::{Address, Network, Script};
+use miniscript::policy::Concrete;
+use miniscript::{DefiniteDescriptorKey, Descriptor, Segwitv0};
+
+// Internal key for taproot policies without a key to lift out of them. It's
+// the point from BIP341 nobody knows the discrete log of.
+pub const UNSPENDABLE_KEY: &str =
+    "50929b74c1a04954b78b4b6035e97a5e078a5a0f28ec96d547bfee9ace803ac0";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Target {
+    Wsh,
+    Tr,
+}
+
+#[derive(Debug)]
+pub enum PolicyError {
+    Io(String),
+    Parse(String),
+    Compile(String),
+}
+
+impl fmt::Display for PolicyError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PolicyError::Io(e) => write!(f, "Failed to read policy: {}", e),
+            PolicyError::Parse(e) => write!(f, "Invalid policy: {}", e),
+            PolicyError::Compile(e) => write!(f, "Failed to compile policy: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for PolicyError {}
+
+#[derive(Clone, Debug)]
+pub struct CompiledPolicy {
+    pub descriptor: Descriptor<DefiniteDescriptorKey>,
+    // The witness script for wsh, one per leaf for tr
+    pub scripts: Vec<Script>,
+    // Sum of the script sizes, in bytes
+    pub script_size: usize,
+    // Worst case weight of the scriptSig and witness needed to spend
+    pub max_satisfaction_weight: usize,
+}
+
+impl CompiledPolicy {
+    pub fn address(&self) -> Address {
+        self.descriptor
+            .address(Network::Regtest)
+            .expect("Compiled descriptors always have an address")
+    }
+}
+
+impl fmt::Display for CompiledPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "descriptor: {}", self.descriptor)?;
+        for script in self.scripts.iter() {
+            writeln!(f, "script: {}", script.asm())?;
+        }
+        writeln!(f, "script size: {} bytes", self.script_size)?;
+        write!(
+            f,
+            "max satisfaction weight: {} WU",
+            self.max_satisfaction_weight
+        )
+    }
+}
+
+// Compiles e.g. `or(99@pk(A),and(pk(B),older(1000)))`, keys being anything a
+// descriptor takes. Whitespace is ignored so policies can span lines.
+pub fn compile_policy(policy: &str, target: Target) -> Result<CompiledPolicy, PolicyError> {
+    let policy: String = policy.chars().filter(|c| !c.is_whitespace()).collect();
+    let policy = Concrete::<DefiniteDescriptorKey>::from_str(&policy)
+        .map_err(|e| PolicyError::Parse(e.to_string()))?;
+
+    let descriptor = match target {
+        Target::Wsh => {
+            let ms = policy
+                .compile::<Segwitv0>()
+                .map_err(|e| PolicyError::Compile(e.to_string()))?;
+            Descriptor::new_wsh(ms).map_err(|e| PolicyError::Compile(e.to_string()))?
+        }
+        Target::Tr => {
+            let unspendable =
+                DefiniteDescriptorKey::from_str(UNSPENDABLE_KEY).expect("Invalid unspendable key");
+            // Lifts the likeliest key into the key path where it can
+            policy
+                .compile_tr(Some(unspendable))
+                .map_err(|e| PolicyError::Compile(e.to_string()))?
+        }
+    };
+
+    let scripts = match &descriptor {
+        Descriptor::Tr(tr) => tr.iter_scripts().map(|(_, ms)| ms.encode()).collect(),
+        desc => vec![desc
+            .explicit_script()
+            .map_err(|e| PolicyError::Compile(e.to_string()))?],
+    };
+    let max_satisfaction_weight = descriptor
+        .max_satisfaction_weight()
+        .map_err(|e| PolicyError::Compile(e.to_string()))?;
+
+    Ok(CompiledPolicy {
+        script_size: scripts.iter().map(|s| s.len()).sum(),
+        descriptor,
+        scripts,
+        max_satisfaction_weight,
+    })
+}
+
+pub fn compile_policy_file<P: AsRef<Path>>(
+    path: P,
+    target: Target,
+) -> Result<CompiledPolicy, PolicyError> {
+    let policy = fs::read_to_string(path).map_err(|e| PolicyError::Io(e.to_string()))?;
+    compile_policy(&policy, target)
+}
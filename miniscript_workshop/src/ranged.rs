@@ -0,0 +1,77 @@
+use std::str::FromStr;
+
+use bitcoincore_rpc::bitcoin::secp256k1::{Secp256k1, SecretKey, Signing};
+use bitcoincore_rpc::bitcoin::util::bip32::{ExtendedPrivKey, ExtendedPubKey};
+use bitcoincore_rpc::bitcoin::util::psbt;
+use bitcoincore_rpc::bitcoin::{Address, Network};
+use miniscript::{DefiniteDescriptorKey, Descriptor, DescriptorPublicKey};
+
+// Last step of the derivation path, as wallets use it
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Keychain {
+    Receive,
+    Change,
+}
+
+impl Keychain {
+    pub fn index(self) -> u32 {
+        match self {
+            Keychain::Receive => 0,
+            Keychain::Change => 1,
+        }
+    }
+}
+
+// `wsh(sortedmulti(n,xpub1/<keychain>/*,..))`
+pub fn multisig_ranged_desc(
+    n: usize,
+    xpubs: &[ExtendedPubKey],
+    keychain: Keychain,
+) -> Descriptor<DescriptorPublicKey> {
+    let keys: Vec<String> = xpubs
+        .iter()
+        .map(|xpub| format!("{}/{}/*", xpub, keychain.index()))
+        .collect();
+    let s = format!("wsh(sortedmulti({},{}))", n, keys.join(","));
+    Descriptor::<DescriptorPublicKey>::from_str(&s).expect("Invalid ranged multisig descriptor")
+}
+
+pub fn derive(
+    desc: &Descriptor<DescriptorPublicKey>,
+    index: u32,
+) -> Descriptor<DefiniteDescriptorKey> {
+    desc.at_derivation_index(index)
+}
+
+pub fn address_at(desc: &Descriptor<DescriptorPublicKey>, index: u32) -> Address {
+    derive(desc, index)
+        .address(Network::Regtest)
+        .expect("Descriptor has no address")
+}
+
+// Secret keys for every key of `input` one of `xprvs` is at the origin of.
+// `xprvs` can be masters, or the very keys whose xpubs are in the descriptor.
+pub fn derive_keys<C: Signing>(
+    secp: &Secp256k1<C>,
+    input: &psbt::Input,
+    xprvs: &[ExtendedPrivKey],
+) -> Vec<SecretKey> {
+    let sources = input
+        .bip32_derivation
+        .values()
+        .chain(input.tap_key_origins.values().map(|(_, source)| source));
+
+    let mut keys = Vec::new();
+    for (fingerprint, path) in sources {
+        for xprv in xprvs.iter() {
+            if xprv.fingerprint(secp) != *fingerprint {
+                continue;
+            }
+            let derived = xprv
+                .derive_priv(secp, path)
+                .expect("Failed to derive private key");
+            keys.push(derived.private_key);
+        }
+    }
+    keys
+}
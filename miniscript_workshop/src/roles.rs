@@ -0,0 +1,201 @@
+// The BIP174 roles, each its own step so the PSBT can travel between
+// co-signers as a file in between.
+use std::fs;
+use std::path::Path;
+
+use bitcoincore_rpc::bitcoin::consensus::encode::{deserialize, serialize};
+use bitcoincore_rpc::bitcoin::secp256k1::{Parity, Secp256k1, SecretKey, Signing, Verification};
+use bitcoincore_rpc::bitcoin::util::psbt::PartiallySignedTransaction;
+use bitcoincore_rpc::bitcoin::{OutPoint, PublicKey, Transaction, TxOut};
+use miniscript::psbt::PsbtExt;
+use miniscript::{DefiniteDescriptorKey, Descriptor};
+
+use crate::spend::{self, Signers, SpendError};
+
+// Binary PSBTs start with this
+const PSBT_MAGIC: &[u8] = b"psbt\xff";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Format {
+    Base64,
+    Binary,
+}
+
+// What an input still needs before it can be finalized
+#[derive(Clone, Debug)]
+pub struct MissingSigs {
+    pub input: usize,
+    pub signed: Vec<PublicKey>,
+    pub unsigned: Vec<PublicKey>,
+    // Whether the signatures so far are enough
+    pub satisfiable: bool,
+}
+
+// Creator
+pub fn create(
+    outpoints: &[OutPoint],
+    outputs: Vec<TxOut>,
+) -> Result<PartiallySignedTransaction, SpendError> {
+    spend::unsigned_psbt(outpoints, outputs, &Signers::default())
+}
+
+// Updater, input `i` holds `txout` locked by `desc`
+pub fn update(
+    psbt: &mut PartiallySignedTransaction,
+    i: usize,
+    desc: &Descriptor<DefiniteDescriptorKey>,
+    txout: TxOut,
+) -> Result<(), SpendError> {
+    spend::update_input(psbt, i, desc, txout)
+}
+
+// Signer, going only by what the updater put in the PSBT. Returns how many
+// inputs `secret` signed.
+pub fn sign<C: Signing + Verification>(
+    secp: &Secp256k1<C>,
+    psbt: &mut PartiallySignedTransaction,
+    secret: &SecretKey,
+) -> Result<usize, SpendError> {
+    let pk = PublicKey::new(secret.public_key(secp));
+    let mut signed = 0;
+    for i in 0..psbt.inputs.len() {
+        if !input_keys(psbt, i)
+            .iter()
+            .any(|k| same_key(psbt, i, k, &pk))
+        {
+            continue;
+        }
+        spend::sign_with_keys(secp, psbt, i..i + 1, &[(*secret, pk)])?;
+        signed += 1;
+    }
+    Ok(signed)
+}
+
+// Combiner, errors out on PSBTs for different transactions
+pub fn combine(
+    psbts: Vec<PartiallySignedTransaction>,
+) -> Result<PartiallySignedTransaction, SpendError> {
+    let mut psbts = psbts.into_iter();
+    let mut combined = psbts
+        .next()
+        .ok_or_else(|| SpendError::Psbt("Nothing to combine".to_owned()))?;
+    let txid = combined.unsigned_tx.txid();
+    for (n, psbt) in psbts.enumerate() {
+        if psbt.unsigned_tx.txid() != txid {
+            return Err(SpendError::Psbt(format!(
+                "PSBT {} spends {}, not {}",
+                n + 1,
+                psbt.unsigned_tx.txid(),
+                txid
+            )));
+        }
+        combined
+            .combine(psbt)
+            .map_err(|e| SpendError::Psbt(e.to_string()))?;
+    }
+    Ok(combined)
+}
+
+// Which keys of every input have signed, and whether that's enough
+pub fn missing_sigs<C: Verification>(
+    secp: &Secp256k1<C>,
+    psbt: &PartiallySignedTransaction,
+) -> Vec<MissingSigs> {
+    (0..psbt.inputs.len())
+        .map(|i| {
+            let (signed, unsigned) = input_keys(psbt, i)
+                .into_iter()
+                .partition(|pk| has_signed(psbt, i, pk));
+            MissingSigs {
+                input: i,
+                signed,
+                unsigned,
+                satisfiable: psbt.clone().finalize_inp_mut(secp, i).is_ok(),
+            }
+        })
+        .collect()
+}
+
+// Finalizer
+pub fn finalize<C: Verification>(
+    secp: &Secp256k1<C>,
+    psbt: &mut PartiallySignedTransaction,
+) -> Result<(), SpendError> {
+    for i in 0..psbt.inputs.len() {
+        psbt.finalize_inp_mut(secp, i)
+            .map_err(|e| SpendError::CannotSatisfy {
+                input: i,
+                reason: e.to_string(),
+            })?;
+    }
+    Ok(())
+}
+
+// Extractor
+pub fn extract<C: Verification>(
+    secp: &Secp256k1<C>,
+    psbt: &PartiallySignedTransaction,
+) -> Result<Transaction, SpendError> {
+    psbt.extract(secp)
+        .map_err(|e| SpendError::Psbt(e.to_string()))
+}
+
+pub fn save<P: AsRef<Path>>(
+    psbt: &PartiallySignedTransaction,
+    path: P,
+    format: Format,
+) -> Result<(), SpendError> {
+    let bytes = serialize(psbt);
+    let contents = match format {
+        Format::Base64 => base64::encode(bytes).into_bytes(),
+        Format::Binary => bytes,
+    };
+    fs::write(path, contents).map_err(|e| SpendError::Psbt(e.to_string()))
+}
+
+// Takes either format
+pub fn load<P: AsRef<Path>>(path: P) -> Result<PartiallySignedTransaction, SpendError> {
+    let contents = fs::read(path).map_err(|e| SpendError::Psbt(e.to_string()))?;
+    let bytes = if contents.starts_with(PSBT_MAGIC) {
+        contents
+    } else {
+        let text = String::from_utf8_lossy(&contents);
+        base64::decode(text.trim()).map_err(|e| SpendError::Psbt(e.to_string()))?
+    };
+    deserialize(&bytes).map_err(|e| SpendError::Psbt(e.to_string()))
+}
+
+// Keys the updater listed for input `i`
+fn input_keys(psbt: &PartiallySignedTransaction, i: usize) -> Vec<PublicKey> {
+    let input = &psbt.inputs[i];
+    let mut keys: Vec<PublicKey> = input
+        .bip32_derivation
+        .keys()
+        .map(|pk| PublicKey::new(*pk))
+        .collect();
+    // Taproot keys are x-only, listed with an even y
+    keys.extend(
+        input
+            .tap_key_origins
+            .keys()
+            .map(|xonly| PublicKey::new(xonly.public_key(Parity::Even))),
+    );
+    keys
+}
+
+// Taproot only cares about the x coordinate
+fn same_key(psbt: &PartiallySignedTransaction, i: usize, a: &PublicKey, b: &PublicKey) -> bool {
+    if psbt.inputs[i].tap_internal_key.is_some() {
+        a.inner.x_only_public_key().0 == b.inner.x_only_public_key().0
+    } else {
+        a == b
+    }
+}
+
+fn has_signed(psbt: &PartiallySignedTransaction, i: usize, pk: &PublicKey) -> bool {
+    let input = &psbt.inputs[i];
+    let xonly = pk.inner.x_only_public_key().0;
+    input.partial_sigs.contains_key(pk)
+        || (input.tap_internal_key == Some(xonly) && input.tap_key_sig.is_some())
+        || input.tap_script_sigs.keys().any(|(k, _)| *k == xonly)
+}
@@ -0,0 +1,372 @@
+use std::fmt;
+use std::ops::Range;
+
+use bitcoincore_rpc::bitcoin::hashes::{hash160, ripemd160, sha256, sha256d, Hash};
+use bitcoincore_rpc::bitcoin::secp256k1::{KeyPair, Secp256k1, SecretKey, Signing, Verification};
+use bitcoincore_rpc::bitcoin::util::psbt::{self, PartiallySignedTransaction};
+use bitcoincore_rpc::bitcoin::util::schnorr::{SchnorrSig, TapTweak};
+use bitcoincore_rpc::bitcoin::util::sighash::{SchnorrSighashType, SighashCache};
+use bitcoincore_rpc::bitcoin::util::taproot::TapLeafHash;
+use bitcoincore_rpc::bitcoin::{
+    EcdsaSig, EcdsaSighashType, OutPoint, PackedLockTime, PublicKey, Script, Sequence, Transaction,
+    TxIn, TxOut, Witness,
+};
+use miniscript::policy::Liftable;
+use miniscript::psbt::{PsbtExt, PsbtInputExt};
+use miniscript::{DefiniteDescriptorKey, Descriptor, ForEachKey};
+
+// Everything that might go into satisfying a descriptor. Miniscript picks
+// the cheapest satisfaction out of what is there, so it is fine to hand over
+// more than a single spending path needs.
+#[derive(Clone, Debug, Default)]
+pub struct Signers {
+    pub keys: Vec<SecretKey>,
+    // 32 byte preimages, for sha256, hash256, ripemd160 and hash160 locks
+    pub preimages: Vec<[u8; 32]>,
+    // For `after(..)`, the spending tx's nLockTime. See `timelock_options`
+    // for what happens when neither this nor `sequence` is set.
+    pub lock_time: Option<PackedLockTime>,
+    // For `older(..)`, the nSequence of every input
+    pub sequence: Option<Sequence>,
+}
+
+impl Signers {
+    pub fn new() -> Signers {
+        Signers::default()
+    }
+
+    pub fn key(mut self, key: SecretKey) -> Signers {
+        self.keys.push(key);
+        self
+    }
+
+    pub fn preimage(mut self, preimage: [u8; 32]) -> Signers {
+        self.preimages.push(preimage);
+        self
+    }
+
+    pub fn lock_time(mut self, lock_time: u32) -> Signers {
+        self.lock_time = Some(PackedLockTime(lock_time));
+        self
+    }
+
+    pub fn sequence(mut self, sequence: Sequence) -> Signers {
+        self.sequence = Some(sequence);
+        self
+    }
+}
+
+#[derive(Debug)]
+pub enum SpendError {
+    Descriptor(String),
+    Psbt(String),
+    // The keys, preimages and timelocks given can't satisfy the policy
+    CannotSatisfy { input: usize, reason: String },
+    Rpc(String),
+}
+
+impl fmt::Display for SpendError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SpendError::Descriptor(e) => write!(f, "Invalid descriptor: {}", e),
+            SpendError::Psbt(e) => write!(f, "Invalid PSBT: {}", e),
+            SpendError::CannotSatisfy { input, reason } => write!(
+                f,
+                "Input {} can't be satisfied with the given material: {}",
+                input, reason
+            ),
+            SpendError::Rpc(e) => write!(f, "RPC error: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for SpendError {}
+
+// `signers` with each combination of the `after` and `older` values in
+// `desc` filled in, the ones needing the fewest and lowest timelocks first.
+// When `signers` already sets a timelock that's the only option.
+pub fn timelock_options(
+    desc: &Descriptor<DefiniteDescriptorKey>,
+    signers: &Signers,
+) -> Result<Vec<Signers>, SpendError> {
+    if signers.lock_time.is_some() || signers.sequence.is_some() {
+        return Ok(vec![signers.clone()]);
+    }
+    let policy = desc
+        .lift()
+        .map_err(|e| SpendError::Descriptor(e.to_string()))?;
+
+    let mut lock_times: Vec<Option<PackedLockTime>> = vec![None];
+    let mut absolute = policy.absolute_timelocks();
+    absolute.sort_unstable();
+    lock_times.extend(absolute.into_iter().map(|n| Some(PackedLockTime(n))));
+    let mut sequences: Vec<Option<Sequence>> = vec![None];
+    let mut relative = policy.relative_timelocks();
+    relative.sort_unstable();
+    sequences.extend(relative.into_iter().map(|n| Some(Sequence(n))));
+
+    let mut options = Vec::new();
+    for lock_time in lock_times.iter() {
+        for sequence in sequences.iter() {
+            options.push(Signers {
+                lock_time: *lock_time,
+                sequence: *sequence,
+                ..signers.clone()
+            });
+        }
+    }
+    options.sort_by_key(|s| s.lock_time.is_some() as u8 + s.sequence.is_some() as u8);
+    Ok(options)
+}
+
+// Unsigned PSBT spending `inputs`, all locked by `desc`, to `outputs`
+pub fn build_psbt(
+    inputs: &[(OutPoint, TxOut)],
+    desc: &Descriptor<DefiniteDescriptorKey>,
+    outputs: Vec<TxOut>,
+    signers: &Signers,
+) -> Result<PartiallySignedTransaction, SpendError> {
+    let outpoints: Vec<OutPoint> = inputs.iter().map(|(outpoint, _)| *outpoint).collect();
+    let mut psbt = unsigned_psbt(&outpoints, outputs, signers)?;
+    for (i, (_, txout)) in inputs.iter().enumerate() {
+        update_input(&mut psbt, i, desc, txout.clone())?;
+    }
+    Ok(psbt)
+}
+
+// PSBT with bare inputs, timelocks set from `signers`
+pub fn unsigned_psbt(
+    outpoints: &[OutPoint],
+    outputs: Vec<TxOut>,
+    signers: &Signers,
+) -> Result<PartiallySignedTransaction, SpendError> {
+    // nLockTime only applies when some input isn't final
+    let sequence = signers.sequence.unwrap_or(Sequence::ENABLE_LOCKTIME_NO_RBF);
+    let tx = Transaction {
+        version: 2,
+        lock_time: signers.lock_time.unwrap_or(PackedLockTime::ZERO),
+        input: outpoints
+            .iter()
+            .map(|outpoint| TxIn {
+                previous_output: *outpoint,
+                script_sig: Script::new(),
+                sequence,
+                witness: Witness::default(),
+            })
+            .collect(),
+        output: outputs,
+    };
+    PartiallySignedTransaction::from_unsigned_tx(tx).map_err(|e| SpendError::Psbt(e.to_string()))
+}
+
+// Fills in input `i`, holding `txout` locked by `desc`
+pub fn update_input(
+    psbt: &mut PartiallySignedTransaction,
+    i: usize,
+    desc: &Descriptor<DefiniteDescriptorKey>,
+    txout: TxOut,
+) -> Result<(), SpendError> {
+    psbt.inputs[i]
+        .update_with_descriptor_unchecked(desc)
+        .map_err(|e| SpendError::Descriptor(e.to_string()))?;
+    psbt.inputs[i].witness_utxo = Some(txout);
+    Ok(())
+}
+
+// Adds signatures from every key that shows up in `desc`, and all the
+// preimages, to every input
+pub fn sign_psbt<C: Signing + Verification>(
+    secp: &Secp256k1<C>,
+    psbt: &mut PartiallySignedTransaction,
+    desc: &Descriptor<DefiniteDescriptorKey>,
+    signers: &Signers,
+) -> Result<(), SpendError> {
+    sign_inputs(secp, psbt, 0..psbt.inputs.len(), desc, signers)
+}
+
+// `sign_psbt` for input `i` alone, for inputs locked by different descriptors
+pub fn sign_input<C: Signing + Verification>(
+    secp: &Secp256k1<C>,
+    psbt: &mut PartiallySignedTransaction,
+    i: usize,
+    desc: &Descriptor<DefiniteDescriptorKey>,
+    signers: &Signers,
+) -> Result<(), SpendError> {
+    sign_inputs(secp, psbt, i..i + 1, desc, signers)
+}
+
+fn sign_inputs<C: Signing + Verification>(
+    secp: &Secp256k1<C>,
+    psbt: &mut PartiallySignedTransaction,
+    indices: Range<usize>,
+    desc: &Descriptor<DefiniteDescriptorKey>,
+    signers: &Signers,
+) -> Result<(), SpendError> {
+    let derived = desc
+        .derived_descriptor(secp)
+        .map_err(|e| SpendError::Descriptor(e.to_string()))?;
+    let mut desc_keys = Vec::new();
+    derived.for_each_key(|pk| {
+        desc_keys.push(*pk);
+        true
+    });
+    let is_taproot = matches!(desc, Descriptor::Tr(_));
+    // Taproot keys are x-only, the parity of ours doesn't matter
+    let in_desc = |pk: &PublicKey| {
+        desc_keys.iter().any(|k| {
+            if is_taproot {
+                k.inner.x_only_public_key().0 == pk.inner.x_only_public_key().0
+            } else {
+                k == pk
+            }
+        })
+    };
+    let keys: Vec<(SecretKey, PublicKey)> = signers
+        .keys
+        .iter()
+        .map(|sk| (*sk, PublicKey::new(sk.public_key(secp))))
+        .filter(|(_, pk)| in_desc(pk))
+        .collect();
+
+    sign_with_keys(secp, psbt, indices.clone(), &keys)?;
+    for i in indices {
+        add_preimages(&mut psbt.inputs[i], &signers.preimages);
+    }
+    Ok(())
+}
+
+// Signs the inputs at `indices` with every one of `keys`, which should all
+// belong there. Taproot inputs get signed for the key path and every leaf
+// the key is in.
+pub(crate) fn sign_with_keys<C: Signing + Verification>(
+    secp: &Secp256k1<C>,
+    psbt: &mut PartiallySignedTransaction,
+    indices: Range<usize>,
+    keys: &[(SecretKey, PublicKey)],
+) -> Result<(), SpendError> {
+    // Sighashes borrow the unsigned tx, work out what to sign first
+    let mut to_sign = Vec::new();
+    let mut sighash_cache = SighashCache::new(&psbt.unsigned_tx);
+    for i in indices {
+        let input = &psbt.inputs[i];
+        // `update_input` sets the internal key on every taproot input
+        let internal_key = match input.tap_internal_key {
+            Some(key) => key,
+            None => {
+                let msg = psbt
+                    .sighash_msg(i, &mut sighash_cache, None)
+                    .map_err(|e| SpendError::Psbt(e.to_string()))?
+                    .to_secp_msg();
+                for (sk, pk) in keys.iter() {
+                    to_sign.push((i, Signature::Ecdsa(*pk), *sk, msg));
+                }
+                continue;
+            }
+        };
+
+        // Key path, if we hold the internal key
+        if let Some((sk, _)) = keys
+            .iter()
+            .find(|(_, pk)| pk.inner.x_only_public_key().0 == internal_key)
+        {
+            let msg = psbt
+                .sighash_msg(i, &mut sighash_cache, None)
+                .map_err(|e| SpendError::Psbt(e.to_string()))?
+                .to_secp_msg();
+            to_sign.push((i, Signature::TapKey, *sk, msg));
+        }
+
+        // Script paths, for every leaf a key of ours is in. Keys appear in
+        // tapscript as plain 32 byte pushes.
+        for (script, leaf_version) in input.tap_scripts.values() {
+            let leaf_hash = TapLeafHash::from_script(script, *leaf_version);
+            for (sk, pk) in keys.iter() {
+                let xonly = pk.inner.x_only_public_key().0;
+                if !script
+                    .as_bytes()
+                    .windows(32)
+                    .any(|w| w == xonly.serialize())
+                {
+                    continue;
+                }
+                let msg = psbt
+                    .sighash_msg(i, &mut sighash_cache, Some(leaf_hash))
+                    .map_err(|e| SpendError::Psbt(e.to_string()))?
+                    .to_secp_msg();
+                to_sign.push((i, Signature::TapScript(leaf_hash), *sk, msg));
+            }
+        }
+    }
+
+    for (i, kind, sk, msg) in to_sign {
+        let input = &mut psbt.inputs[i];
+        match kind {
+            Signature::Ecdsa(pk) => {
+                let sig = EcdsaSig {
+                    sig: secp.sign_ecdsa(&msg, &sk),
+                    hash_ty: EcdsaSighashType::All,
+                };
+                input.partial_sigs.insert(pk, sig);
+            }
+            Signature::TapKey => {
+                let keypair = KeyPair::from_secret_key(secp, &sk)
+                    .tap_tweak(secp, input.tap_merkle_root)
+                    .to_inner();
+                input.tap_key_sig = Some(SchnorrSig {
+                    sig: secp.sign_schnorr_no_aux_rand(&msg, &keypair),
+                    hash_ty: SchnorrSighashType::Default,
+                });
+            }
+            Signature::TapScript(leaf_hash) => {
+                let keypair = KeyPair::from_secret_key(secp, &sk);
+                let sig = SchnorrSig {
+                    sig: secp.sign_schnorr_no_aux_rand(&msg, &keypair),
+                    hash_ty: SchnorrSighashType::Default,
+                };
+                input
+                    .tap_script_sigs
+                    .insert((keypair.x_only_public_key().0, leaf_hash), sig);
+            }
+        }
+    }
+    Ok(())
+}
+
+enum Signature {
+    Ecdsa(PublicKey),
+    TapKey,
+    TapScript(TapLeafHash),
+}
+
+fn add_preimages(input: &mut psbt::Input, preimages: &[[u8; 32]]) {
+    for preimage in preimages {
+        input
+            .sha256_preimages
+            .insert(sha256::Hash::hash(preimage), preimage.to_vec());
+        input
+            .hash256_preimages
+            .insert(sha256d::Hash::hash(preimage), preimage.to_vec());
+        input
+            .ripemd160_preimages
+            .insert(ripemd160::Hash::hash(preimage), preimage.to_vec());
+        input
+            .hash160_preimages
+            .insert(hash160::Hash::hash(preimage), preimage.to_vec());
+    }
+}
+
+// Picks the cheapest satisfaction for every input and extracts the tx
+pub fn finalize<C: Verification>(
+    secp: &Secp256k1<C>,
+    psbt: &mut PartiallySignedTransaction,
+) -> Result<Transaction, SpendError> {
+    for i in 0..psbt.inputs.len() {
+        psbt.finalize_inp_mut(secp, i)
+            .map_err(|e| SpendError::CannotSatisfy {
+                input: i,
+                reason: e.to_string(),
+            })?;
+    }
+    psbt.extract(secp)
+        .map_err(|e| SpendError::Psbt(e.to_string()))
+}
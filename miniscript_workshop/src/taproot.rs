@@ -0,0 +1,94 @@
+use std::str::FromStr;
+
+use bitcoincore_rpc::bitcoin::secp256k1::XOnlyPublicKey;
+use bitcoincore_rpc::bitcoin::util::psbt::PartiallySignedTransaction;
+use bitcoincore_rpc::bitcoin::util::taproot::LeafVersion;
+use bitcoincore_rpc::bitcoin::Script;
+use miniscript::{DefiniteDescriptorKey, Descriptor};
+
+use crate::spend::SpendError;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TapPath {
+    // Schnorr signature from the tweaked internal key
+    KeyPath,
+    // The leaf at this index, counting left to right in the descriptor
+    ScriptPath(usize),
+}
+
+// `tr(internal_key,{..})` with `leaves`, miniscript fragments such as
+// `pk(..)` or `multi_a(..)`, spread over a balanced tree
+pub fn tr_desc(
+    internal_key: &XOnlyPublicKey,
+    leaves: &[String],
+) -> Descriptor<DefiniteDescriptorKey> {
+    let s = if leaves.is_empty() {
+        format!("tr({})", internal_key)
+    } else {
+        format!("tr({},{})", internal_key, tree(leaves))
+    };
+    Descriptor::<DefiniteDescriptorKey>::from_str(&s).expect("Invalid taproot descriptor")
+}
+
+fn tree(leaves: &[String]) -> String {
+    if leaves.len() == 1 {
+        return leaves[0].clone();
+    }
+    let (left, right) = leaves.split_at(leaves.len() / 2);
+    format!("{{{},{}}}", tree(left), tree(right))
+}
+
+// Leaf scripts in the same order as `TapPath::ScriptPath` counts them
+pub fn leaf_scripts(desc: &Descriptor<DefiniteDescriptorKey>) -> Vec<Script> {
+    match desc {
+        Descriptor::Tr(tr) => tr.iter_scripts().map(|(_, ms)| ms.encode()).collect(),
+        _ => vec![],
+    }
+}
+
+// Leaves only what `path` needs in the taproot fields of every input, so
+// signing and finalizing can't go down another path. Call before signing.
+pub fn select_path(
+    psbt: &mut PartiallySignedTransaction,
+    desc: &Descriptor<DefiniteDescriptorKey>,
+    path: TapPath,
+) -> Result<(), SpendError> {
+    let tr = match desc {
+        Descriptor::Tr(tr) => tr,
+        _ => return Err(SpendError::Descriptor(format!("{} isn't taproot", desc))),
+    };
+
+    let leaf = match path {
+        TapPath::KeyPath => None,
+        TapPath::ScriptPath(i) => {
+            let script = leaf_scripts(desc)
+                .get(i)
+                .cloned()
+                .ok_or_else(|| SpendError::Descriptor(format!("No leaf {} in {}", i, desc)))?;
+            let leaf = (script, LeafVersion::TapScript);
+            let control_block = tr
+                .spend_info()
+                .control_block(&leaf)
+                .expect("Leaves of a descriptor are always in its tree");
+            Some((control_block, leaf))
+        }
+    };
+
+    for input in psbt.inputs.iter_mut() {
+        input.tap_scripts.clear();
+        if let Some((control_block, leaf)) = leaf.clone() {
+            input.tap_scripts.insert(control_block, leaf);
+        }
+    }
+    Ok(())
+}
+
+// Signing adds a key path signature whenever we hold the internal key, drop
+// it when the script path was asked for. Call after signing.
+pub fn drop_key_path(psbt: &mut PartiallySignedTransaction, path: TapPath) {
+    if let TapPath::ScriptPath(_) = path {
+        for input in psbt.inputs.iter_mut() {
+            input.tap_key_sig = None;
+        }
+    }
+}
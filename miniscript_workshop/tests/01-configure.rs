@@ -11,11 +11,20 @@
 //  }
 //
 //  Returns a RPC client
+//
+// This one runs on a bitcoind of its own. Like the rest of the workshop, the
+// client should connect to `bitcoin_basics::rpc_url()`, which is
+// BITCOIND_RPC_URL when set.
+
+use std::env;
 
+use bitcoin_basics::{TestNode, RPC_URL_VAR};
 use bitcoincore_rpc::{Client, RpcApi};
 use miniscript_workshop::MiniscriptClient;
 
 fn main() {
+    let node = TestNode::new();
+    env::set_var(RPC_URL_VAR, node.rpc_url());
     let client = Client::configure_client();
 
     // Assert
@@ -11,7 +11,11 @@
 //      // found for a given script_pubkey
 //      //
 //      // Used to get UTXO details to be used as an input to the raw tx we are constructing
-//      fn utxo_details(&self, script_pubkey: Script, txid: Txid) -> Result<(OutPoint, TxOut), ()>;
+//      fn utxo_details(
+//          &self,
+//          script_pubkey: Script,
+//          txid: Txid,
+//      ) -> Result<(OutPoint, TxOut), UtxoError>;
 //
 //      // Spends from a given multi sig output desc
 //      fn spend_multi(
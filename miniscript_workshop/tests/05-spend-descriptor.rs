@@ -0,0 +1,109 @@
+// Spending from any descriptor
+//
+// Write one spending function that works for every descriptor type to pass
+// the test.
+//
+//  impl MiniscriptClient for Client {
+//
+//      // Looks up an unspent output in the node's UTXO set
+//      fn prevout(&self, outpoint: OutPoint) -> Result<TxOut, SpendError>;
+//
+//      // Spends `outpoints`, all locked by `desc`, to `outputs`. Picks the
+//      // cheapest satisfaction the keys, preimages and timelocks in
+//      // `signers` allow, and errors out when they can't satisfy the policy.
+//      fn spend_descriptor(
+//          &self,
+//          outpoints: &[OutPoint],
+//          desc: &Descriptor<DefiniteDescriptorKey>,
+//          outputs: Vec<TxOut>,
+//          signers: &Signers,
+//      ) -> Result<Txid, SpendError>;
+//  }
+//
+// RESOURCES:
+//
+//  - https://bitcoin.sipa.be/miniscript/
+//
+//  - https://docs.rs/miniscript/latest/miniscript/trait.Satisfier.html
+//
+//  - https://docs.rs/miniscript/latest/miniscript/psbt/trait.PsbtExt.html#tymethod.finalize_inp_mut
+//
+//  - https://docs.rs/bitcoincore-rpc/0.16.0/bitcoincore_rpc/trait.RpcApi.html#method.get_tx_out
+//
+//  - https://github.com/bitcoin/bips/blob/master/bip-0341.mediawiki
+//
+
+use bitcoincore_rpc::{
+    bitcoin::{hashes::sha256, hashes::Hash, Amount, TxOut},
+    Client, RpcApi,
+};
+use miniscript::{DefiniteDescriptorKey, Descriptor};
+use miniscript_workshop::spend::{Signers, SpendError};
+use miniscript_workshop::MiniscriptClient;
+use secp256k1::{rand, Secp256k1};
+use std::str::FromStr;
+
+fn main() {
+    let client = Client::configure_client();
+    let secp = Secp256k1::new();
+    let (secret_a, pub_a) = secp.generate_keypair(&mut rand::thread_rng());
+    let (secret_b, pub_b) = secp.generate_keypair(&mut rand::thread_rng());
+    let preimage = [42u8; 32];
+    let value = 99_980_000;
+
+    // A key and a hashlock
+    let s = format!(
+        "wsh(and_v(v:pk({}),sha256({})))",
+        pub_a,
+        sha256::Hash::hash(&preimage)
+    );
+    let desc = Descriptor::<DefiniteDescriptorKey>::from_str(&s).unwrap();
+    let outpoint = client.fund_descriptor(&desc, Amount::ONE_BTC);
+    let recv_addr = client.get_new_address(None, None).unwrap();
+    let txout = TxOut {
+        value,
+        script_pubkey: recv_addr.script_pubkey(),
+    };
+
+    // Without the preimage there's no way to spend it
+    let err = client
+        .spend_descriptor(
+            &[outpoint],
+            &desc,
+            vec![txout.clone()],
+            &Signers::new().key(secret_a),
+        )
+        .unwrap_err();
+    assert!(matches!(err, SpendError::CannotSatisfy { input: 0, .. }));
+
+    let signers = Signers::new()
+        .key(secret_a)
+        .key(secret_b)
+        .preimage(preimage);
+    client
+        .spend_descriptor(&[outpoint], &desc, vec![txout], &signers)
+        .unwrap();
+    let bal = client.get_received_by_address(&recv_addr, Some(1)).unwrap();
+    assert_eq!(bal, Amount::from_sat(value));
+
+    // Taproot, with a script path next to the key path
+    let s = format!(
+        "tr({},pk({}))",
+        pub_a.x_only_public_key().0,
+        pub_b.x_only_public_key().0
+    );
+    let desc = Descriptor::<DefiniteDescriptorKey>::from_str(&s).unwrap();
+    for secret in [secret_a, secret_b] {
+        let outpoint = client.fund_descriptor(&desc, Amount::ONE_BTC);
+        let recv_addr = client.get_new_address(None, None).unwrap();
+        let txout = TxOut {
+            value,
+            script_pubkey: recv_addr.script_pubkey(),
+        };
+        client
+            .spend_descriptor(&[outpoint], &desc, vec![txout], &Signers::new().key(secret))
+            .unwrap();
+        let bal = client.get_received_by_address(&recv_addr, Some(1)).unwrap();
+        assert_eq!(bal, Amount::from_sat(value));
+    }
+}
@@ -0,0 +1,87 @@
+// Compiling spending policies
+//
+// Compile a high level spending policy into a descriptor, and have the node
+// watch it, to pass the test.
+//
+//  // Parses and compiles a policy into a wsh or tr descriptor, along with its
+//  // scripts, their size and the worst case satisfaction weight
+//  pub fn compile_policy(policy: &str, target: Target) -> Result<CompiledPolicy, PolicyError>;
+//
+//  impl MiniscriptClient for Client {
+//      // Imports a descriptor into a watch-only wallet
+//      fn import_watch_only(&self, desc: &Descriptor<DefiniteDescriptorKey>) -> Client;
+//  }
+//
+// RESOURCES:
+//
+//  - https://bitcoin.sipa.be/miniscript/
+//
+//  - https://docs.rs/miniscript/latest/miniscript/policy/concrete/enum.Policy.html#method.compile
+//
+//  - https://docs.rs/miniscript/latest/miniscript/policy/concrete/enum.Policy.html#method.compile_tr
+//
+//  - https://developer.bitcoin.org/reference/rpc/importdescriptors.html
+//
+
+use bitcoin_basics::BitcoinClient;
+use bitcoincore_rpc::{
+    bitcoin::{self as bitcoin, Amount},
+    Client, RpcApi,
+};
+use miniscript::Descriptor;
+use miniscript_workshop::policy::{compile_policy, Target};
+use miniscript_workshop::{MiniscriptClient, WATCH_ONLY_WALLET_NAME};
+use secp256k1::{rand, Secp256k1};
+
+fn main() {
+    let client = Client::configure_client();
+
+    let secp = Secp256k1::new();
+    let (_, pub_a) = secp.generate_keypair(&mut rand::thread_rng());
+    let (_, pub_b) = secp.generate_keypair(&mut rand::thread_rng());
+    let policy = format!("or(99@pk({}),and(pk({}),older(1000)))", pub_a, pub_b);
+
+    // Segwit v0
+    let wsh = compile_policy(&policy, Target::Wsh).unwrap();
+    println!("{}", wsh);
+    assert!(matches!(wsh.descriptor, Descriptor::Wsh(_)));
+    assert_eq!(wsh.scripts.len(), 1);
+    assert_eq!(wsh.script_size, wsh.scripts[0].len());
+    assert!(wsh.max_satisfaction_weight > 0);
+
+    // Taproot, the likely key should end up in the key path
+    let tr = compile_policy(&policy, Target::Tr).unwrap();
+    println!("{}", tr);
+    match &tr.descriptor {
+        Descriptor::Tr(t) => assert_eq!(t.internal_key().to_string(), pub_a.to_string()),
+        _ => panic!("Expected a tr descriptor"),
+    }
+    assert_eq!(tr.scripts.len(), 1);
+
+    // Garbage in
+    assert!(compile_policy("or(pk(", Target::Wsh).is_err());
+
+    // Funds sent to the compiled address show up in the watch-only wallet
+    let watch = client.import_watch_only(&wsh.descriptor);
+    client
+        .send_to_address(
+            &wsh.address(),
+            bitcoin::Amount::ONE_BTC,
+            None,
+            None,
+            None,
+            None,
+            None,
+            None,
+        )
+        .unwrap();
+    client
+        .generate_to_address(1, &client.get_new_address(None, None).unwrap())
+        .unwrap();
+    let bal = watch
+        .get_received_by_address(&wsh.address(), Some(1))
+        .unwrap();
+    // Left loaded, it gets in the way of clients without a wallet path
+    watch.unload_wallet_in_node(WATCH_ONLY_WALLET_NAME).unwrap();
+    assert_eq!(bal, Amount::ONE_BTC);
+}
@@ -0,0 +1,83 @@
+// Spending from a taproot output via the key path
+//
+// Spend from a tr descriptor with a Schnorr signature from the tweaked
+// internal key to pass the test.
+//
+//  impl MiniscriptClient for Client {
+//
+//      // Sends `amount` to `desc` and mines it
+//      fn fund_descriptor(&self, desc: &Descriptor<DefiniteDescriptorKey>, amount: Amount)
+//          -> OutPoint;
+//
+//      // Spends down `path` of a tr descriptor
+//      fn spend_taproot(
+//          &self,
+//          outpoints: &[OutPoint],
+//          desc: &Descriptor<DefiniteDescriptorKey>,
+//          outputs: Vec<TxOut>,
+//          signers: &Signers,
+//          path: TapPath,
+//      ) -> Result<Txid, SpendError>;
+//  }
+//
+// RESOURCES:
+//
+//  - https://github.com/bitcoin/bips/blob/master/bip-0341.mediawiki
+//
+//  - https://github.com/bitcoin/bips/blob/master/bip-0386.mediawiki
+//
+//  - https://docs.rs/bitcoin/0.29.2/bitcoin/util/schnorr/trait.TapTweak.html
+//
+//  - https://docs.rs/bitcoin/0.29.2/bitcoin/util/psbt/struct.Input.html#structfield.tap_key_sig
+//
+
+use bitcoincore_rpc::{
+    bitcoin::{Amount, TxOut},
+    Client, RpcApi,
+};
+use miniscript_workshop::spend::Signers;
+use miniscript_workshop::taproot::{tr_desc, TapPath};
+use miniscript_workshop::MiniscriptClient;
+use secp256k1::{rand, Secp256k1};
+
+fn main() {
+    let client = Client::configure_client();
+
+    // Generate keypairs, one for the key path and one for a leaf
+    let secp = Secp256k1::new();
+    let (secret_key, pub_key) = secp.generate_keypair(&mut rand::thread_rng());
+    let (_, leaf_key) = secp.generate_keypair(&mut rand::thread_rng());
+
+    // Create descriptor
+    let leaves = vec![format!("pk({})", leaf_key.x_only_public_key().0)];
+    let desc = tr_desc(&pub_key.x_only_public_key().0, &leaves);
+
+    // Send some bitcoin to descriptor
+    let outpoint = client.fund_descriptor(&desc, Amount::ONE_BTC);
+
+    // Create receiving address to assert against
+    let recv_addr = client.get_new_address(None, None).unwrap();
+    let value = 99_980_000;
+    let txout = TxOut {
+        value,
+        script_pubkey: recv_addr.script_pubkey(),
+    };
+
+    // Act
+    let signers = Signers::new().key(secret_key);
+    let txid = client
+        .spend_taproot(&[outpoint], &desc, vec![txout], &signers, TapPath::KeyPath)
+        .unwrap();
+
+    // Assert
+    let bal = client.get_received_by_address(&recv_addr, Some(1)).unwrap();
+    assert_eq!(bal, Amount::from_sat(value));
+
+    // A key path spend is a lone signature
+    let tx = client
+        .get_transaction(&txid, None)
+        .unwrap()
+        .transaction()
+        .unwrap();
+    assert_eq!(tx.input[0].witness.len(), 1);
+}
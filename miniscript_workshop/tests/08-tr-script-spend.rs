@@ -0,0 +1,93 @@
+// Spending from a taproot output via a script leaf
+//
+// Spend from a 2 of 3 multisig leaf of a tr descriptor, building the control
+// block for it, to pass the test.
+//
+//  // Leaves only the chosen path in the PSBT's taproot fields
+//  pub fn select_path(
+//      psbt: &mut PartiallySignedTransaction,
+//      desc: &Descriptor<DefiniteDescriptorKey>,
+//      path: TapPath,
+//  ) -> Result<(), SpendError>;
+//
+// RESOURCES:
+//
+//  - https://github.com/bitcoin/bips/blob/master/bip-0341.mediawiki#script-validation-rules
+//
+//  - https://github.com/bitcoin/bips/blob/master/bip-0342.mediawiki
+//
+//  - https://docs.rs/bitcoin/0.29.2/bitcoin/util/taproot/struct.TaprootSpendInfo.html#method.control_block
+//
+//  - https://docs.rs/bitcoin/0.29.2/bitcoin/util/psbt/struct.Input.html#structfield.tap_scripts
+//
+
+use bitcoincore_rpc::{
+    bitcoin::{Amount, TxOut},
+    Client, RpcApi,
+};
+use miniscript_workshop::spend::Signers;
+use miniscript_workshop::taproot::{leaf_scripts, tr_desc, TapPath};
+use miniscript_workshop::MiniscriptClient;
+use secp256k1::{rand, Secp256k1};
+
+fn main() {
+    let client = Client::configure_client();
+
+    // Generate keypairs
+    let secp = Secp256k1::new();
+    let (internal_secret, internal_key) = secp.generate_keypair(&mut rand::thread_rng());
+    let mut pubkeys = vec![];
+    let mut secrets = vec![];
+    for _ in 0..3 {
+        let (secret, pub_key) = secp.generate_keypair(&mut rand::thread_rng());
+        pubkeys.push(pub_key.x_only_public_key().0.to_string());
+        secrets.push(secret);
+    }
+
+    // Create descriptor, with the multisig as the second leaf
+    let leaves = vec![
+        format!("pk({})", pubkeys[0]),
+        format!("multi_a(2,{})", pubkeys.join(",")),
+    ];
+    let desc = tr_desc(&internal_key.x_only_public_key().0, &leaves);
+
+    // Send some bitcoin to descriptor
+    let outpoint = client.fund_descriptor(&desc, Amount::ONE_BTC);
+
+    // Create receiving address to assert against
+    let recv_addr = client.get_new_address(None, None).unwrap();
+    let value = 99_980_000;
+    let txout = TxOut {
+        value,
+        script_pubkey: recv_addr.script_pubkey(),
+    };
+
+    // Act, holding the internal key too, which must not get used
+    let signers = Signers::new()
+        .key(internal_secret)
+        .key(secrets[1])
+        .key(secrets[2]);
+    let txid = client
+        .spend_taproot(
+            &[outpoint],
+            &desc,
+            vec![txout],
+            &signers,
+            TapPath::ScriptPath(1),
+        )
+        .unwrap();
+
+    // Assert
+    let bal = client.get_received_by_address(&recv_addr, Some(1)).unwrap();
+    assert_eq!(bal, Amount::from_sat(value));
+
+    // Three signature slots, the leaf script and its control block
+    let tx = client
+        .get_transaction(&txid, None)
+        .unwrap()
+        .transaction()
+        .unwrap();
+    let witness = tx.input[0].witness.to_vec();
+    assert_eq!(witness.len(), 5);
+    assert_eq!(witness[3], leaf_scripts(&desc)[1].to_bytes());
+}
@@ -0,0 +1,116 @@
+// Timelocked and hashlocked spends
+//
+// Spend from an HTLC like descriptor, before and after its timelock, to pass
+// the test.
+//
+//  impl MiniscriptClient for Client {
+//
+//      // Builds and signs the spending tx without broadcasting it, setting
+//      // nSequence and nLockTime for the satisfaction that gets picked
+//      fn build_spend(
+//          &self,
+//          outpoints: &[OutPoint],
+//          desc: &Descriptor<DefiniteDescriptorKey>,
+//          outputs: Vec<TxOut>,
+//          signers: &Signers,
+//      ) -> Result<Transaction, SpendError>;
+//
+//      // Mines until the timelocks of `tx` have passed
+//      fn mine_to_maturity(&self, tx: &Transaction);
+//  }
+//
+// RESOURCES:
+//
+//  - https://github.com/bitcoin/bips/blob/master/bip-0065.mediawiki
+//
+//  - https://github.com/bitcoin/bips/blob/master/bip-0068.mediawiki
+//
+//  - https://github.com/bitcoin/bips/blob/master/bip-0112.mediawiki
+//
+//  - https://docs.rs/miniscript/latest/miniscript/policy/semantic/enum.Policy.html#method.relative_timelocks
+//
+
+use bitcoincore_rpc::{
+    bitcoin::{hashes::sha256, hashes::Hash, Amount, PackedLockTime, Sequence, TxOut},
+    Client, RpcApi,
+};
+use miniscript::{DefiniteDescriptorKey, Descriptor};
+use miniscript_workshop::spend::{Signers, SpendError};
+use miniscript_workshop::MiniscriptClient;
+use secp256k1::{rand, Secp256k1};
+use std::str::FromStr;
+
+fn main() {
+    let client = Client::configure_client();
+    let secp = Secp256k1::new();
+    let (secret_a, pub_a) = secp.generate_keypair(&mut rand::thread_rng());
+    let (secret_b, pub_b) = secp.generate_keypair(&mut rand::thread_rng());
+    let preimage = [7u8; 32];
+    let value = 99_980_000;
+    let txout = |client: &Client| TxOut {
+        value,
+        script_pubkey: client.get_new_address(None, None).unwrap().script_pubkey(),
+    };
+
+    // B can claim with the preimage, A gets a refund after 10 blocks
+    let s = format!(
+        "wsh(andor(pk({}),sha256({}),and_v(v:pk({}),older(10))))",
+        pub_b,
+        sha256::Hash::hash(&preimage),
+        pub_a
+    );
+    let htlc = Descriptor::<DefiniteDescriptorKey>::from_str(&s).unwrap();
+
+    // Claim, no timelock needed
+    let outpoint = client.fund_descriptor(&htlc, Amount::ONE_BTC);
+    let signers = Signers::new().key(secret_b).preimage(preimage);
+    let tx = client
+        .build_spend(&[outpoint], &htlc, vec![txout(&client)], &signers)
+        .unwrap();
+    assert!(!tx.input[0].sequence.is_relative_lock_time());
+    client.sendrawtx(tx);
+
+    // B without the preimage gets nowhere
+    let outpoint = client.fund_descriptor(&htlc, Amount::ONE_BTC);
+    let err = client
+        .build_spend(
+            &[outpoint],
+            &htlc,
+            vec![txout(&client)],
+            &Signers::new().key(secret_b),
+        )
+        .unwrap_err();
+    assert!(matches!(err, SpendError::CannotSatisfy { input: 0, .. }));
+
+    // Refund, bitcoind turns it down until it's 10 blocks deep
+    let tx = client
+        .build_spend(
+            &[outpoint],
+            &htlc,
+            vec![txout(&client)],
+            &Signers::new().key(secret_a),
+        )
+        .unwrap();
+    assert_eq!(tx.input[0].sequence, Sequence(10));
+    assert!(client.send_raw_transaction(&tx).is_err());
+    client.mine_to_maturity(&tx);
+    client.sendrawtx(tx);
+
+    // Absolute timelock a few blocks ahead
+    let height = client.get_block_count().unwrap() as u32 + 5;
+    let s = format!("wsh(and_v(v:pk({}),after({})))", pub_a, height);
+    let vault = Descriptor::<DefiniteDescriptorKey>::from_str(&s).unwrap();
+    let outpoint = client.fund_descriptor(&vault, Amount::ONE_BTC);
+    let tx = client
+        .build_spend(
+            &[outpoint],
+            &vault,
+            vec![txout(&client)],
+            &Signers::new().key(secret_a),
+        )
+        .unwrap();
+    assert_eq!(tx.lock_time, PackedLockTime(height));
+    assert!(client.send_raw_transaction(&tx).is_err());
+    client.mine_to_maturity(&tx);
+    client.sendrawtx(tx);
+}
@@ -0,0 +1,132 @@
+// Ranged xpub descriptors
+//
+// Derive addresses from a ranged multisig descriptor, have the node watch
+// them, and spend from them with the matching xprvs to pass the test.
+//
+//  // `wsh(sortedmulti(n,xpub1/<keychain>/*,..))`
+//  pub fn multisig_ranged_desc(
+//      n: usize,
+//      xpubs: &[ExtendedPubKey],
+//      keychain: Keychain,
+//  ) -> Descriptor<DescriptorPublicKey>;
+//
+//  impl MiniscriptClient for Client {
+//      fn import_ranged(
+//          &self,
+//          desc: &Descriptor<DescriptorPublicKey>,
+//          range_end: u32,
+//          keychain: Keychain,
+//      ) -> Client;
+//
+//      fn scan_ranged(
+//          &self,
+//          desc: &Descriptor<DescriptorPublicKey>,
+//          range_end: u32,
+//      ) -> Vec<(u32, OutPoint, TxOut)>;
+//
+//      fn spend_ranged(
+//          &self,
+//          utxos: &[(u32, OutPoint)],
+//          desc: &Descriptor<DescriptorPublicKey>,
+//          outputs: Vec<TxOut>,
+//          xprvs: &[ExtendedPrivKey],
+//      ) -> Result<Txid, SpendError>;
+//  }
+//
+// RESOURCES:
+//
+//  - https://github.com/bitcoin/bips/blob/master/bip-0032.mediawiki
+//
+//  - https://github.com/bitcoin/bitcoin/blob/master/doc/descriptors.md#key-origin-identification
+//
+//  - https://developer.bitcoin.org/reference/rpc/scantxoutset.html
+//
+//  - https://docs.rs/miniscript/latest/miniscript/descriptor/enum.Descriptor.html#method.at_derivation_index
+//
+
+use bitcoin_basics::BitcoinClient;
+use bitcoincore_rpc::{
+    bitcoin::{
+        util::bip32::{ExtendedPrivKey, ExtendedPubKey},
+        Amount, Network, TxOut,
+    },
+    Client, RpcApi,
+};
+use miniscript_workshop::ranged::{address_at, multisig_ranged_desc, Keychain};
+use miniscript_workshop::{MiniscriptClient, WATCH_ONLY_WALLET_NAME};
+use secp256k1::{rand, rand::RngCore, Secp256k1};
+
+fn main() {
+    let client = Client::configure_client();
+
+    // Generate master keys
+    let secp = Secp256k1::new();
+    let mut xprvs = vec![];
+    let mut xpubs = vec![];
+    for _ in 0..3 {
+        let mut seed = [0u8; 32];
+        rand::thread_rng().fill_bytes(&mut seed);
+        let xprv = ExtendedPrivKey::new_master(Network::Regtest, &seed).unwrap();
+        xpubs.push(ExtendedPubKey::from_priv(&secp, &xprv));
+        xprvs.push(xprv);
+    }
+
+    // Create descriptors
+    let receive = multisig_ranged_desc(2, &xpubs, Keychain::Receive);
+    let change = multisig_ranged_desc(2, &xpubs, Keychain::Change);
+    assert_ne!(address_at(&receive, 0), address_at(&receive, 1));
+    assert_ne!(address_at(&receive, 0), address_at(&change, 0));
+
+    // Send some bitcoin to a couple of derived addresses
+    let watch = client.import_ranged(&receive, 10, Keychain::Receive);
+    client.import_ranged(&change, 10, Keychain::Change);
+    for index in [2, 5] {
+        client
+            .send_to_address(
+                &address_at(&receive, index),
+                Amount::ONE_BTC,
+                None,
+                None,
+                None,
+                None,
+                None,
+                None,
+            )
+            .unwrap();
+    }
+    client
+        .generate_to_address(1, &client.get_new_address(None, None).unwrap())
+        .unwrap();
+    let bal = watch
+        .get_received_by_address(&address_at(&receive, 5), Some(1))
+        .unwrap();
+    // Left loaded, it gets in the way of clients without a wallet path
+    watch.unload_wallet_in_node(WATCH_ONLY_WALLET_NAME).unwrap();
+    assert_eq!(bal, Amount::ONE_BTC);
+
+    // Find them again
+    let utxos = client.scan_ranged(&receive, 10);
+    let indices: Vec<u32> = utxos.iter().map(|(index, _, _)| *index).collect();
+    assert_eq!(indices, vec![2, 5]);
+
+    // Create receiving address to assert against
+    let recv_addr = client.get_new_address(None, None).unwrap();
+    let value = 199_960_000;
+    let txout = TxOut {
+        value,
+        script_pubkey: recv_addr.script_pubkey(),
+    };
+
+    // Act, with two of the three keys
+    let utxos: Vec<_> = utxos
+        .iter()
+        .map(|(index, outpoint, _)| (*index, *outpoint))
+        .collect();
+    client
+        .spend_ranged(&utxos, &receive, vec![txout], &xprvs[1..])
+        .unwrap();
+
+    // Assert
+    let bal = client.get_received_by_address(&recv_addr, Some(1)).unwrap();
+    assert_eq!(bal, Amount::from_sat(value));
+}
@@ -0,0 +1,105 @@
+// PSBT roles
+//
+// Spend from a multi sig output with each co-signer signing on their own,
+// passing the PSBT around as a file, to pass the test.
+//
+//  // Creator, updater, signer, combiner, finalizer and extractor
+//  pub fn create(outpoints: &[OutPoint], outputs: Vec<TxOut>) -> Result<Psbt, SpendError>;
+//  pub fn sign(secp: &Secp256k1<C>, psbt: &mut Psbt, secret: &SecretKey) -> Result<usize, SpendError>;
+//  pub fn combine(psbts: Vec<Psbt>) -> Result<Psbt, SpendError>;
+//  pub fn finalize(secp: &Secp256k1<C>, psbt: &mut Psbt) -> Result<(), SpendError>;
+//  pub fn extract(secp: &Secp256k1<C>, psbt: &Psbt) -> Result<Transaction, SpendError>;
+//
+//  // Which keys have signed each input so far
+//  pub fn missing_sigs(secp: &Secp256k1<C>, psbt: &Psbt) -> Vec<MissingSigs>;
+//
+//  impl MiniscriptClient for Client {
+//      fn update_inputs(&self, psbt: &mut Psbt, desc: &Descriptor<DefiniteDescriptorKey>)
+//          -> Result<(), SpendError>;
+//  }
+//
+// RESOURCES:
+//
+//  - https://github.com/bitcoin/bips/blob/master/bip-0174.mediawiki#roles
+//
+//  - https://docs.rs/bitcoin/0.29.2/bitcoin/util/psbt/struct.PartiallySignedTransaction.html#method.combine
+//
+//  - https://docs.rs/miniscript/latest/miniscript/psbt/trait.PsbtExt.html#tymethod.finalize_inp_mut
+//
+
+use bitcoincore_rpc::{
+    bitcoin::{self as bitcoin, Amount, TxOut},
+    Client, RpcApi,
+};
+use miniscript_workshop::roles::{self, Format};
+use miniscript_workshop::MiniscriptClient;
+use secp256k1::{rand, Secp256k1};
+
+fn main() {
+    let client = Client::configure_client();
+    let dir = std::env::temp_dir().join(format!("psbt-roles-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+
+    // Generate keypairs
+    let secp = Secp256k1::new();
+    let mut pubkeys = vec![];
+    let mut secrets = vec![];
+    for _ in 0..3 {
+        let (secret, pub_key) = secp.generate_keypair(&mut rand::thread_rng());
+        pubkeys.push(pub_key);
+        secrets.push(secret);
+    }
+    let desc = client.multisig_desc(2, &pubkeys);
+    let outpoint = client.fund_descriptor(&desc, Amount::ONE_BTC);
+
+    // Create receiving address to assert against
+    let recv_addr = client.get_new_address(None, None).unwrap();
+    let value = 99_980_000;
+    let txout = TxOut {
+        value,
+        script_pubkey: recv_addr.script_pubkey(),
+    };
+
+    // Creator and updater
+    let mut psbt = roles::create(&[outpoint], vec![txout.clone()]).unwrap();
+    client.update_inputs(&mut psbt, &desc).unwrap();
+    roles::save(&psbt, dir.join("unsigned.psbt"), Format::Base64).unwrap();
+
+    // Co-signers, each with their own copy
+    for (n, format) in [(0, Format::Binary), (2, Format::Base64)] {
+        let mut psbt = roles::load(dir.join("unsigned.psbt")).unwrap();
+        assert_eq!(roles::sign(&secp, &mut psbt, &secrets[n]).unwrap(), 1);
+        roles::save(&psbt, dir.join(format!("signed-{}.psbt", n)), format).unwrap();
+    }
+
+    // One signature isn't enough
+    let signed_0 = roles::load(dir.join("signed-0.psbt")).unwrap();
+    let missing = roles::missing_sigs(&secp, &signed_0);
+    assert_eq!(missing[0].signed.len(), 1);
+    assert_eq!(missing[0].unsigned.len(), 2);
+    assert!(!missing[0].satisfiable);
+
+    // A PSBT for some other tx can't be combined in
+    let other = roles::create(&[bitcoin::OutPoint::null()], vec![txout]).unwrap();
+    assert!(roles::combine(vec![signed_0.clone(), other]).is_err());
+
+    // Combiner
+    let signed_2 = roles::load(dir.join("signed-2.psbt")).unwrap();
+    let mut psbt = roles::combine(vec![signed_0, signed_2]).unwrap();
+    let missing = roles::missing_sigs(&secp, &psbt);
+    assert_eq!(
+        missing[0].unsigned,
+        vec![bitcoin::PublicKey::new(pubkeys[1])]
+    );
+    assert!(missing[0].satisfiable);
+
+    // Finalizer and extractor
+    roles::finalize(&secp, &mut psbt).unwrap();
+    let tx = roles::extract(&secp, &psbt).unwrap();
+    client.sendrawtx(tx);
+
+    // Assert
+    let bal = client.get_received_by_address(&recv_addr, Some(1)).unwrap();
+    assert_eq!(bal, Amount::from_sat(value));
+    std::fs::remove_dir_all(&dir).unwrap();
+}
@@ -3,11 +3,23 @@
 // other.
 // If you are an advanced user however, feel free to break the rules!
 
+// 01 starts a `TestNode` of its own, so it only needs `bitcoind` on the
+// PATH. The rest run on the shared node at `bitcoin_basics::rpc_url()`.
+
 #[test]
 fn tests() {
     let t = trybuild::TestCases::new();
+    // Own node
     // t.pass("tests/01-configure.rs");
+    // Shared node
     t.pass("tests/02-pkh.rs");
     t.pass("tests/03-multi-desc.rs");
     t.pass("tests/04-multi-spend.rs");
+    t.pass("tests/05-spend-descriptor.rs");
+    t.pass("tests/06-policy.rs");
+    t.pass("tests/07-tr-key-spend.rs");
+    t.pass("tests/08-tr-script-spend.rs");
+    t.pass("tests/09-timelocks.rs");
+    t.pass("tests/10-ranged.rs");
+    t.pass("tests/11-psbt-roles.rs");
 }
@@ -0,0 +1,56 @@
+use std::fs::{self, OpenOptions};
+use std::io::{ErrorKind, Write};
+
+use bitcoincore_rpc::bitcoin::hashes::hex::ToHex;
+use bitcoincore_rpc::bitcoin::secp256k1::rand::{thread_rng, RngCore};
+
+// Clients of the REST and gRPC APIs send `Authorization: Bearer <token>`.
+// The token is created on first start and only readable from `ln_dir`.
+pub const API_TOKEN_FILE: &str = "api_token";
+
+pub fn get_api_token(ln_dir: &str) -> String {
+    let token_path = format!("{}/{}", ln_dir, API_TOKEN_FILE);
+    if let Ok(token) = fs::read_to_string(&token_path) {
+        return token.trim().to_owned();
+    }
+
+    let mut token = [0; 32];
+    thread_rng().fill_bytes(&mut token);
+    let token = token.to_hex();
+    let mut options = OpenOptions::new();
+    options.write(true).create_new(true);
+    // Owner only from the start, not just once the token is in
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    match options.open(&token_path) {
+        Ok(mut file) => file
+            .write_all(token.as_bytes())
+            .expect("Failed to write API token"),
+        // The other API server got there first
+        Err(e) if e.kind() == ErrorKind::AlreadyExists => {
+            return fs::read_to_string(&token_path)
+                .expect("Failed to read API token")
+                .trim()
+                .to_owned();
+        }
+        Err(e) => panic!("Failed to create API token: {}", e),
+    }
+    token
+}
+
+// Checks an `Authorization` header value against the token
+pub fn is_authorized(header: Option<&str>, token: &str) -> bool {
+    header
+        .and_then(|v| v.strip_prefix("Bearer "))
+        .map(|t| constant_time_eq(t.as_bytes(), token.as_bytes()))
+        .unwrap_or(false)
+}
+
+// Takes as long wherever the first difference is, so the token can't be
+// guessed a byte at a time from response times
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
@@ -0,0 +1,32 @@
+// Standalone watchtower.
+//
+//  rln-tower [tower_dir] [listen_addr]
+//
+// On start the tower logs its pubkey. Add `<pubkey>@<listen_addr>` to the
+// `towers` file in a node's `ln_dir`. Whenever its counterparty revokes a
+// state, the node sends the tower a justice transaction for that state,
+// encrypted under the revoked commitment's txid. The tower holds no channel
+// keys or monitors and can only open a blob once that commitment confirms.
+//
+// Checking it on regtest:
+//  - start the tower and two nodes on different ports, the first one with
+//    the tower in its `towers` file
+//  - open a channel between the nodes and make a payment, the tower logs
+//    `Holding justice tx <hint>` for each state the payment revokes
+//  - mine blocks with a revoked commitment of the second node in them, the
+//    tower broadcasts the justice transaction sweeping it to the first
+//    node's wallet
+//
+// A node can't be made to broadcast an old state, so
+// `cargo test -p ln_node --test watchtower -- --ignored` does the last step
+// from a copy of the second node's earlier monitor.
+
+use rlnnode::watchtower_server::start_tower;
+
+#[tokio::main]
+async fn main() {
+    let args: Vec<String> = std::env::args().collect();
+    let tower_dir = args.get(1).map(|d| d.as_str()).unwrap_or("./tower");
+    let listen_addr = args.get(2).map(|a| a.as_str()).unwrap_or("127.0.0.1:9911");
+    start_tower(tower_dir, listen_addr).await;
+}
@@ -1,19 +1,93 @@
-use bitcoin_basics::BitcoinClient;
+use std::fs;
+use std::io;
+
+use bitcoin_basics::wallet::{WalletOptions, DEFAULT_WALLET_NAME};
+use bitcoin_basics::zmq::ZMQ_URL;
+use bitcoin_basics::{rpc_url, BitcoinClient, WalletHandle};
 use bitcoincore_rpc::{
-    bitcoin::{util::uint::Uint256, BlockHash},
-    Client, RpcApi,
+    bitcoin::{util::uint::Uint256, BlockHash, PackedLockTime, Script, Transaction, TxOut, Txid},
+    json::GetBalancesResult,
+    RpcApi,
 };
 use lightning::chain::chaininterface::{BroadcasterInterface, FeeEstimator};
 use lightning_block_sync::{BlockData, BlockHeaderData, BlockSource};
 
+use crate::kv_store::invalid_data;
+
+// `<key>=<value>` lines, same as a Core Lightning config:
+//
+//  rpc-url=http://127.0.0.1:18443
+//  zmq-url=tcp://127.0.0.1:28332
+//  wallet=alice
+//
+// An empty `zmq-url` turns block notifications off.
+pub const BITCOIND_FILE: &str = "bitcoind";
+
+// Which bitcoind a node or tower runs on
+#[derive(Clone, Debug)]
+pub struct BitcoindConfig {
+    pub rpc_url: String,
+    // Where bitcoind publishes hashblock, see `-zmqpubhashblock`. Without
+    // it the node polls for new blocks.
+    pub zmq_url: Option<String>,
+    // Nodes sharing a bitcoind each want a wallet of their own
+    pub wallet_name: String,
+}
+
+impl Default for BitcoindConfig {
+    // The workshop node, or the one in `BITCOIND_RPC_URL`
+    fn default() -> Self {
+        Self {
+            rpc_url: rpc_url(),
+            zmq_url: Some(ZMQ_URL.to_owned()),
+            wallet_name: DEFAULT_WALLET_NAME.to_owned(),
+        }
+    }
+}
+
+impl BitcoindConfig {
+    pub fn parse(contents: &str) -> io::Result<Self> {
+        let mut config = Self::default();
+        for line in contents.lines() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let (key, value) = line
+                .split_once('=')
+                .ok_or_else(|| invalid_data(format!("Expected <key>=<value>, got {}", line)))?;
+            match key.trim() {
+                "rpc-url" => config.rpc_url = value.trim().to_owned(),
+                "wallet" => config.wallet_name = value.trim().to_owned(),
+                "zmq-url" => {
+                    config.zmq_url = Some(value.trim().to_owned()).filter(|url| !url.is_empty())
+                }
+                other => return Err(invalid_data(format!("Unknown bitcoind option {}", other))),
+            }
+        }
+        Ok(config)
+    }
+}
+
+// Reads `<dir>/bitcoind`, no file means the defaults
+pub fn read_bitcoind_config(dir: &str) -> io::Result<BitcoindConfig> {
+    match fs::read_to_string(format!("{}/{}", dir, BITCOIND_FILE)) {
+        Ok(contents) => BitcoindConfig::parse(&contents),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(BitcoindConfig::default()),
+        Err(e) => Err(e),
+    }
+}
+
 pub struct BitcoindClient {
-    client: Client,
+    client: WalletHandle,
 }
 
 impl BitcoindClient {
-    pub fn new() -> Self {
-        let client = Client::setup();
-        client.load_wallet_in_node("test_wallet");
+    pub fn new(config: &BitcoindConfig) -> Self {
+        let client = WalletHandle::at(&config.rpc_url, &config.wallet_name);
+        client
+            .ensure_wallet(&config.wallet_name, &WalletOptions::default())
+            .expect("Failed to load wallet");
         client.get_dough_if_broke();
         Self { client }
     }
@@ -26,10 +100,53 @@ impl BitcoindClient {
 
     pub fn get_block_height(&self, blockhash: &BlockHash) -> usize {
         self.client
-            .get_block_info(&blockhash)
+            .get_block_info(blockhash)
             .expect("Failed to get height of blockhash")
             .height
     }
+
+    // Funds and signs a transaction paying `value` to the channel funding
+    // script using the node's bitcoind wallet
+    pub fn create_funding_transaction(&self, value: u64, output_script: Script) -> Transaction {
+        let tx = Transaction {
+            version: 2,
+            lock_time: PackedLockTime::ZERO,
+            input: Vec::new(),
+            output: vec![TxOut {
+                value,
+                script_pubkey: output_script,
+            }],
+        };
+        let funded = self
+            .client
+            .fund_raw_transaction(&tx, None, None)
+            .expect("Failed to fund funding tx");
+        let signed = self
+            .client
+            .sign_raw_transaction_with_wallet(&funded.hex[..], None, None)
+            .expect("Failed to sign funding tx");
+        assert!(signed.complete, "Funding tx is not fully signed");
+        signed.transaction().expect("Failed to decode funding tx")
+    }
+
+    pub fn get_new_script_pubkey(&self) -> Script {
+        self.client
+            .get_new_address(None, None)
+            .expect("Failed to get new address")
+            .script_pubkey()
+    }
+
+    pub fn get_balances(&self) -> GetBalancesResult {
+        self.client.get_balances().expect("Failed to get balances")
+    }
+
+    // `broadcast_transaction` for callers that can live with bitcoind
+    // turning a transaction down
+    pub fn try_broadcast_transaction(&self, tx: &Transaction) -> Result<Txid, String> {
+        self.client
+            .send_raw_transaction(tx)
+            .map_err(|e| e.to_string())
+    }
 }
 
 impl FeeEstimator for BitcoindClient {
@@ -76,8 +193,8 @@ impl BlockSource for BitcoindClient {
             .client
             .get_block_header_info(header_hash)
             .expect("Failed to get header info");
-        let mut chainw = [0 as u8; 32];
-        for (i, v) in info.chainwork .iter() .enumerate() {
+        let mut chainw = [0u8; 32];
+        for (i, v) in info.chainwork.iter().enumerate() {
             chainw[i] = *v;
         }
         Box::pin(async move {
@@ -89,13 +206,11 @@ impl BlockSource for BitcoindClient {
         })
     }
 
-    fn get_best_block<'a>(
-        &'a self,
-    ) -> lightning_block_sync::AsyncBlockSourceResult<(BlockHash, Option<u32>)> {
+    fn get_best_block(
+        &self,
+    ) -> lightning_block_sync::AsyncBlockSourceResult<'_, (BlockHash, Option<u32>)> {
         let hash = self.get_best_blockhash();
         let height = self.get_block_height(&hash) as u32;
-        Box::pin(async move {
-            Ok((hash, Some(height)))
-        })
+        Box::pin(async move { Ok((hash, Some(height))) })
     }
 }
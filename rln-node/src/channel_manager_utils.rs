@@ -1,43 +1,34 @@
-use std::{fs, sync::Arc};
+use std::{io::Cursor, sync::Arc};
 
 use bitcoincore_rpc::bitcoin::BlockHash;
-use lightning::chain::chainmonitor;
 use lightning::chain::channelmonitor::ChannelMonitor;
 use lightning::util::ser::ReadableArgs;
 use lightning::{
     chain::{
-        keysinterface::{InMemorySigner, KeysManager},
-        BestBlock, Filter,
+        BestBlock,
     },
-    ln::channelmanager::{ChainParameters, ChannelManagerReadArgs, SimpleArcChannelManager},
+    ln::channelmanager::{ChainParameters, ChannelManagerReadArgs},
     util::config::UserConfig,
 };
-use lightning_persister::FilesystemPersister;
 
+use crate::node::{ChainMonitor, ChannelManager};
+use crate::persister::RLNPersister;
+use crate::watchtower_signer::{WatchtowerKeysManager, WatchtowerSigner};
 use crate::{bitcoin_client::BitcoindClient, logger::RLNLogger};
 
-type ChainMonitor = chainmonitor::ChainMonitor<
-    InMemorySigner,
-    Arc<dyn Filter + Send + Sync>,
-    Arc<BitcoindClient>,
-    Arc<BitcoindClient>,
-    Arc<RLNLogger>,
-    Arc<FilesystemPersister>,
->;
-
-type ChannelManager =
-    SimpleArcChannelManager<ChainMonitor, BitcoindClient, BitcoindClient, RLNLogger>;
-
 pub fn get_channel_manager(
     bitcoind_client: Arc<BitcoindClient>,
     chain_monitor: Arc<ChainMonitor>,
     logger: Arc<RLNLogger>,
-    keys_manager: Arc<KeysManager>,
-    ldk_data_dir: &str,
-    channelmonitors: &mut Vec<(BlockHash, ChannelMonitor<InMemorySigner>)>,
+    keys_manager: Arc<WatchtowerKeysManager>,
+    persister: &RLNPersister,
+    channelmonitors: &mut [(BlockHash, ChannelMonitor<WatchtowerSigner>)],
 ) -> (BlockHash, ChannelManager) {
     // Restarting
-    if let Ok(mut f) = fs::File::open(format!("{}/manager", ldk_data_dir)) {
+    if let Some(manager) = persister
+        .read_manager()
+        .expect("Failed to read channel manager")
+    {
         let mut channel_monitor_mut_references = Vec::new();
         for (_, channel_monitor) in channelmonitors.iter_mut() {
             channel_monitor_mut_references.push(channel_monitor);
@@ -51,7 +42,7 @@ pub fn get_channel_manager(
             UserConfig::default(),
             channel_monitor_mut_references,
         );
-        <(BlockHash, ChannelManager)>::read(&mut f, read_args).unwrap()
+        <(BlockHash, ChannelManager)>::read(&mut Cursor::new(&manager), read_args).unwrap()
     } else {
 
         // Create channel manager
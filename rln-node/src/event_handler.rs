@@ -1,9 +1,285 @@
-use lightning::util::events::{Event, EventHandler};
+use std::sync::Arc;
 
-pub struct RLNEventHandler;
+use bitcoincore_rpc::bitcoin::hashes::hex::ToHex;
+use bitcoincore_rpc::bitcoin::secp256k1::{PublicKey, Secp256k1};
+use bitcoincore_rpc::bitcoin::BlockHash;
+use lightning::chain::chaininterface::BroadcasterInterface;
+use lightning::chain::keysinterface::KeysManager;
+use lightning::ln::{PaymentHash, PaymentPreimage};
+use lightning::util::events::{Event, EventHandler, PaymentPurpose};
+use tokio::runtime::Handle;
+use tokio::sync::broadcast;
+
+use crate::bitcoin_client::BitcoindClient;
+use crate::kv_store::{KVStore, PaymentRecord, PaymentStatus};
+use crate::node::ChannelManager;
+use crate::watchtower_client::WatchtowerClient;
+
+// Buffered events per subscriber, slow subscribers lose the oldest ones
+pub(crate) const EVENTS_CAPACITY: usize = 1024;
+
+// What API subscribers get to see of the node, a subset of LDK's `Event`s
+// plus new blocks from the chain sync
+#[derive(Clone, Debug)]
+pub enum NodeEvent {
+    PaymentReceived {
+        payment_hash: PaymentHash,
+        amount_msat: u64,
+    },
+    PaymentSent {
+        payment_hash: PaymentHash,
+        payment_preimage: PaymentPreimage,
+        fee_paid_msat: Option<u64>,
+    },
+    PaymentFailed {
+        payment_hash: PaymentHash,
+    },
+    ChannelReady {
+        channel_id: [u8; 32],
+        counterparty_node_id: PublicKey,
+    },
+    ChannelClosed {
+        channel_id: [u8; 32],
+        reason: String,
+    },
+    NewBlock {
+        block_hash: BlockHash,
+        height: u32,
+    },
+}
+
+pub struct RLNEventHandler {
+    pub(crate) channel_manager: Arc<ChannelManager>,
+    pub(crate) bitcoind_client: Arc<BitcoindClient>,
+    pub(crate) keys_manager: Arc<KeysManager>,
+    pub(crate) store: Arc<dyn KVStore>,
+    pub(crate) tower_client: Arc<WatchtowerClient>,
+    pub(crate) events: broadcast::Sender<NodeEvent>,
+    // The node's runtime, events are handled on the background processor's
+    // own thread
+    pub(crate) runtime: Handle,
+}
+
+impl RLNEventHandler {
+    fn update_payment(
+        &self,
+        payment_hash: &PaymentHash,
+        status: PaymentStatus,
+        preimage: Option<[u8; 32]>,
+        amount_msat: Option<u64>,
+    ) {
+        let existing = self
+            .store
+            .read_payment(payment_hash)
+            .expect("Failed to read payment");
+        let payment = match existing {
+            Some(mut payment) => {
+                payment.status = status;
+                payment.preimage = preimage.map(|p| p.to_hex()).or(payment.preimage);
+                payment.amount_msat = amount_msat.or(payment.amount_msat);
+                payment
+            }
+            // Keysend or an invoice created outside this node
+            None => PaymentRecord {
+                payment_hash: payment_hash.0.to_hex(),
+                inbound: true,
+                status,
+                amount_msat,
+                preimage: preimage.map(|p| p.to_hex()),
+            },
+        };
+        self.store
+            .persist_payment(&payment)
+            .expect("Failed to persist payment");
+    }
+
+    fn notify(&self, event: NodeEvent) {
+        // Only fails when nobody is subscribed
+        let _ = self.events.send(event);
+    }
+}
 
 impl EventHandler for RLNEventHandler {
     fn handle_event(&self, event: Event) {
         println!("{:#?}", event);
+
+        match event {
+            Event::FundingGenerationReady {
+                temporary_channel_id,
+                counterparty_node_id,
+                channel_value_satoshis,
+                output_script,
+                ..
+            } => {
+                let funding_tx = self
+                    .bitcoind_client
+                    .create_funding_transaction(channel_value_satoshis, output_script);
+                if self
+                    .channel_manager
+                    .funding_transaction_generated(
+                        &temporary_channel_id,
+                        &counterparty_node_id,
+                        funding_tx,
+                    )
+                    .is_err()
+                {
+                    println!(
+                        "Channel with {} went away before funding",
+                        counterparty_node_id
+                    );
+                }
+            }
+            Event::PaymentClaimable {
+                payment_hash,
+                purpose,
+                amount_msat,
+                ..
+            } => {
+                let preimage = match purpose {
+                    PaymentPurpose::InvoicePayment {
+                        payment_preimage, ..
+                    } => payment_preimage,
+                    PaymentPurpose::SpontaneousPayment(preimage) => Some(preimage),
+                };
+                match preimage {
+                    Some(preimage) => self.channel_manager.claim_funds(preimage),
+                    None => println!("No preimage to claim {}", payment_hash.0.to_hex()),
+                }
+                self.update_payment(
+                    &payment_hash,
+                    PaymentStatus::Pending,
+                    None,
+                    Some(amount_msat),
+                );
+            }
+            Event::PaymentClaimed {
+                payment_hash,
+                purpose,
+                amount_msat,
+                ..
+            } => {
+                let preimage = match purpose {
+                    PaymentPurpose::InvoicePayment {
+                        payment_preimage, ..
+                    } => payment_preimage,
+                    PaymentPurpose::SpontaneousPayment(preimage) => Some(preimage),
+                };
+                self.update_payment(
+                    &payment_hash,
+                    PaymentStatus::Succeeded,
+                    preimage.map(|p| p.0),
+                    Some(amount_msat),
+                );
+                self.notify(NodeEvent::PaymentReceived {
+                    payment_hash,
+                    amount_msat,
+                });
+            }
+            Event::PaymentSent {
+                payment_hash,
+                payment_preimage,
+                fee_paid_msat,
+                ..
+            } => {
+                self.update_payment(
+                    &payment_hash,
+                    PaymentStatus::Succeeded,
+                    Some(payment_preimage.0),
+                    None,
+                );
+                self.notify(NodeEvent::PaymentSent {
+                    payment_hash,
+                    payment_preimage,
+                    fee_paid_msat,
+                });
+            }
+            Event::PaymentFailed { payment_hash, .. } => {
+                self.update_payment(&payment_hash, PaymentStatus::Failed, None, None);
+                self.notify(NodeEvent::PaymentFailed { payment_hash });
+            }
+            Event::ChannelReady {
+                channel_id,
+                counterparty_node_id,
+                ..
+            } => {
+                self.notify(NodeEvent::ChannelReady {
+                    channel_id,
+                    counterparty_node_id,
+                });
+            }
+            Event::ChannelClosed {
+                channel_id, reason, ..
+            } => {
+                self.tower_client.channel_closed(&channel_id);
+                self.notify(NodeEvent::ChannelClosed {
+                    channel_id,
+                    reason: format!("{:?}", reason),
+                });
+            }
+            Event::PendingHTLCsForwardable { time_forwardable } => {
+                // Waiting here would hold up every other event, so the
+                // forwards run from a task once the delay is up
+                let channel_manager = self.channel_manager.clone();
+                self.runtime.spawn(async move {
+                    tokio::time::sleep(time_forwardable).await;
+                    channel_manager.process_pending_htlc_forwards();
+                });
+            }
+            Event::SpendableOutputs { outputs } => {
+                let destination_script = self.bitcoind_client.get_new_script_pubkey();
+                let outputs = outputs.iter().collect::<Vec<_>>();
+                match self.keys_manager.spend_spendable_outputs(
+                    &outputs,
+                    Vec::new(),
+                    destination_script,
+                    253,
+                    &Secp256k1::new(),
+                ) {
+                    Ok(tx) => self.bitcoind_client.broadcast_transaction(&tx),
+                    Err(()) => println!("Failed to build sweep for spendable outputs"),
+                }
+            }
+            _ => {}
+        }
     }
 }
+
+// Outbound payments are tracked from the moment they are sent
+pub(crate) fn record_outbound_payment(
+    store: &dyn KVStore,
+    payment_hash: &PaymentHash,
+    amount_msat: Option<u64>,
+) {
+    store
+        .persist_payment(&PaymentRecord {
+            payment_hash: payment_hash.0.to_hex(),
+            inbound: false,
+            status: PaymentStatus::Pending,
+            amount_msat,
+            preimage: None,
+        })
+        .expect("Failed to persist payment");
+}
+
+// Only the status changes, the amount recorded when the payment was sent
+// stays
+pub(crate) fn set_outbound_payment_status(
+    store: &dyn KVStore,
+    payment_hash: &PaymentHash,
+    status: PaymentStatus,
+) {
+    let mut payment = store
+        .read_payment(payment_hash)
+        .expect("Failed to read payment")
+        .unwrap_or(PaymentRecord {
+            payment_hash: payment_hash.0.to_hex(),
+            inbound: false,
+            status,
+            amount_msat: None,
+            preimage: None,
+        });
+    payment.status = status;
+    store
+        .persist_payment(&payment)
+        .expect("Failed to persist payment");
+}
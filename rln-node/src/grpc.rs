@@ -0,0 +1,285 @@
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::str::FromStr;
+use std::sync::Arc;
+
+use bitcoincore_rpc::bitcoin::hashes::hex::ToHex;
+use bitcoincore_rpc::bitcoin::secp256k1::PublicKey;
+use lightning_invoice::Invoice;
+use rlnrpc::proto::lightning_server::{Lightning, LightningServer};
+use rlnrpc::proto::{self, node_event};
+use tokio_stream::wrappers::BroadcastStream;
+use tokio_stream::{Stream, StreamExt};
+use tonic::transport::Server;
+use tonic::{Request, Response, Status};
+
+use crate::auth::{get_api_token, is_authorized};
+use crate::event_handler::NodeEvent;
+use crate::kv_store::PaymentStatus;
+use crate::node::Node;
+
+type EventStream = Pin<Box<dyn Stream<Item = Result<proto::NodeEvent, Status>> + Send>>;
+
+struct LightningService {
+    node: Arc<Node>,
+}
+
+#[tonic::async_trait]
+impl Lightning for LightningService {
+    async fn get_info(
+        &self,
+        _req: Request<proto::GetInfoRequest>,
+    ) -> Result<Response<proto::GetInfoResponse>, Status> {
+        let node = &self.node;
+        let best_block = node.channel_manager.current_best_block();
+        Ok(Response::new(proto::GetInfoResponse {
+            identity_pubkey: node.channel_manager.get_our_node_id().to_string(),
+            num_active_channels: node.channel_manager.list_usable_channels().len() as u32,
+            num_peers: node.peer_manager.get_peer_node_ids().len() as u32,
+            block_height: best_block.height(),
+            block_hash: best_block.block_hash().to_string(),
+        }))
+    }
+
+    async fn list_peers(
+        &self,
+        _req: Request<proto::ListPeersRequest>,
+    ) -> Result<Response<proto::ListPeersResponse>, Status> {
+        let pub_keys = self
+            .node
+            .peer_manager
+            .get_peer_node_ids()
+            .iter()
+            .map(|id| id.to_string())
+            .collect();
+        Ok(Response::new(proto::ListPeersResponse { pub_keys }))
+    }
+
+    async fn connect_peer(
+        &self,
+        req: Request<proto::ConnectPeerRequest>,
+    ) -> Result<Response<proto::ConnectPeerResponse>, Status> {
+        let req = req.into_inner();
+        let pubkey = parse_pubkey(&req.pubkey)?;
+        self.node
+            .connect_peer(pubkey, &req.host)
+            .await
+            .map_err(Status::unavailable)?;
+        Ok(Response::new(proto::ConnectPeerResponse {}))
+    }
+
+    async fn list_channels(
+        &self,
+        _req: Request<proto::ListChannelsRequest>,
+    ) -> Result<Response<proto::ListChannelsResponse>, Status> {
+        let channels = self
+            .node
+            .channel_manager
+            .list_channels()
+            .iter()
+            .map(|c| proto::Channel {
+                channel_id: c.channel_id.to_hex(),
+                remote_pubkey: c.counterparty.node_id.to_string(),
+                channel_point: c
+                    .funding_txo
+                    .map(|o| format!("{}:{}", o.txid, o.index))
+                    .unwrap_or_default(),
+                chan_id: c.short_channel_id.unwrap_or(0),
+                capacity: c.channel_value_satoshis,
+                local_balance_msat: c.balance_msat,
+                outbound_capacity_msat: c.outbound_capacity_msat,
+                inbound_capacity_msat: c.inbound_capacity_msat,
+                active: c.is_usable,
+                public: c.is_public,
+            })
+            .collect();
+        Ok(Response::new(proto::ListChannelsResponse { channels }))
+    }
+
+    async fn open_channel(
+        &self,
+        req: Request<proto::OpenChannelRequest>,
+    ) -> Result<Response<proto::OpenChannelResponse>, Status> {
+        let req = req.into_inner();
+        let pubkey = parse_pubkey(&req.node_pubkey)?;
+        let temporary_channel_id = self
+            .node
+            .open_channel(pubkey, req.local_funding_amount, req.push_msat, req.public)
+            .map_err(Status::failed_precondition)?;
+        Ok(Response::new(proto::OpenChannelResponse {
+            temporary_channel_id: temporary_channel_id.to_hex(),
+        }))
+    }
+
+    async fn add_invoice(
+        &self,
+        req: Request<proto::AddInvoiceRequest>,
+    ) -> Result<Response<proto::AddInvoiceResponse>, Status> {
+        let req = req.into_inner();
+        let amount_msat = Some(req.value_msat).filter(|a| *a > 0);
+        let expiry_secs = if req.expiry == 0 { 3600 } else { req.expiry };
+        let invoice = self
+            .node
+            .create_invoice(amount_msat, req.memo, expiry_secs)
+            .map_err(Status::internal)?;
+        Ok(Response::new(proto::AddInvoiceResponse {
+            payment_request: invoice.to_string(),
+            payment_hash: invoice.payment_hash().to_hex(),
+        }))
+    }
+
+    async fn send_payment(
+        &self,
+        req: Request<proto::SendPaymentRequest>,
+    ) -> Result<Response<proto::SendPaymentResponse>, Status> {
+        let req = req.into_inner();
+        let amount_msat = Some(req.amt_msat).filter(|a| *a > 0);
+        let payment_hash = if !req.payment_request.is_empty() {
+            let invoice = Invoice::from_str(&req.payment_request)
+                .map_err(|e| Status::invalid_argument(format!("Invalid invoice: {:?}", e)))?;
+            self.node.pay_invoice(&invoice, amount_msat)
+        } else if !req.dest.is_empty() {
+            let amount_msat =
+                amount_msat.ok_or_else(|| Status::invalid_argument("Keysend needs `amt_msat`"))?;
+            self.node.keysend(parse_pubkey(&req.dest)?, amount_msat)
+        } else {
+            return Err(Status::invalid_argument(
+                "Either `payment_request` or `dest` is required",
+            ));
+        }
+        .map_err(Status::failed_precondition)?;
+        Ok(Response::new(proto::SendPaymentResponse {
+            payment_hash: payment_hash.0.to_hex(),
+        }))
+    }
+
+    async fn list_payments(
+        &self,
+        _req: Request<proto::ListPaymentsRequest>,
+    ) -> Result<Response<proto::ListPaymentsResponse>, Status> {
+        let payments = self
+            .node
+            .store
+            .list_payments()
+            .map_err(|e| Status::internal(e.to_string()))?
+            .into_iter()
+            .map(|p| proto::Payment {
+                payment_hash: p.payment_hash,
+                inbound: p.inbound,
+                status: match p.status {
+                    PaymentStatus::Pending => proto::PaymentStatus::Pending,
+                    PaymentStatus::Succeeded => proto::PaymentStatus::Succeeded,
+                    PaymentStatus::Failed => proto::PaymentStatus::Failed,
+                } as i32,
+                value_msat: p.amount_msat.unwrap_or(0),
+                payment_preimage: p.preimage.unwrap_or_default(),
+            })
+            .collect();
+        Ok(Response::new(proto::ListPaymentsResponse { payments }))
+    }
+
+    async fn wallet_balance(
+        &self,
+        _req: Request<proto::WalletBalanceRequest>,
+    ) -> Result<Response<proto::WalletBalanceResponse>, Status> {
+        let balances = self.node.bitcoind_client.get_balances();
+        Ok(Response::new(proto::WalletBalanceResponse {
+            confirmed_balance: balances.mine.trusted.to_sat(),
+            unconfirmed_balance: balances.mine.untrusted_pending.to_sat(),
+            immature_balance: balances.mine.immature.to_sat(),
+        }))
+    }
+
+    type SubscribeEventsStream = EventStream;
+
+    async fn subscribe_events(
+        &self,
+        _req: Request<proto::SubscribeEventsRequest>,
+    ) -> Result<Response<Self::SubscribeEventsStream>, Status> {
+        // A subscriber that falls behind skips what it missed rather than
+        // holding up the node. tonic wants `Status` errors in the stream.
+        #[allow(clippy::result_large_err)]
+        let events = BroadcastStream::new(self.node.subscribe_events())
+            .filter_map(|event| event.ok().map(|event| Ok(to_proto_event(event))));
+        Ok(Response::new(Box::pin(events)))
+    }
+}
+
+fn to_proto_event(event: NodeEvent) -> proto::NodeEvent {
+    let event = match event {
+        NodeEvent::PaymentReceived {
+            payment_hash,
+            amount_msat,
+        } => node_event::Event::PaymentReceived(proto::PaymentReceived {
+            payment_hash: payment_hash.0.to_hex(),
+            amount_msat,
+        }),
+        NodeEvent::PaymentSent {
+            payment_hash,
+            payment_preimage,
+            fee_paid_msat,
+        } => node_event::Event::PaymentSent(proto::PaymentSent {
+            payment_hash: payment_hash.0.to_hex(),
+            payment_preimage: payment_preimage.0.to_hex(),
+            fee_paid_msat: fee_paid_msat.unwrap_or(0),
+        }),
+        NodeEvent::PaymentFailed { payment_hash } => {
+            node_event::Event::PaymentFailed(proto::PaymentFailed {
+                payment_hash: payment_hash.0.to_hex(),
+            })
+        }
+        NodeEvent::ChannelReady {
+            channel_id,
+            counterparty_node_id,
+        } => node_event::Event::ChannelReady(proto::ChannelReady {
+            channel_id: channel_id.to_hex(),
+            counterparty_pubkey: counterparty_node_id.to_string(),
+        }),
+        NodeEvent::ChannelClosed { channel_id, reason } => {
+            node_event::Event::ChannelClosed(proto::ChannelClosed {
+                channel_id: channel_id.to_hex(),
+                reason,
+            })
+        }
+        NodeEvent::NewBlock { block_hash, height } => {
+            node_event::Event::NewBlock(proto::NewBlock {
+                block_hash: block_hash.to_string(),
+                height,
+            })
+        }
+    };
+    proto::NodeEvent { event: Some(event) }
+}
+
+// Handlers return `Status`, this does too so they can use `?`
+#[allow(clippy::result_large_err)]
+fn parse_pubkey(pubkey: &str) -> Result<PublicKey, Status> {
+    PublicKey::from_str(pubkey)
+        .map_err(|e| Status::invalid_argument(format!("Invalid pubkey {}: {}", pubkey, e)))
+}
+
+// Serves the API on localhost only, with the same bearer token as REST
+pub async fn start_grpc_server(node: Arc<Node>, port: u16) {
+    let token = Arc::new(get_api_token(&node.ln_dir));
+    let addr = SocketAddr::from(([127, 0, 0, 1], port));
+
+    // tonic's interceptor signature
+    #[allow(clippy::result_large_err)]
+    let auth = move |req: Request<()>| {
+        let header = req
+            .metadata()
+            .get("authorization")
+            .and_then(|v| v.to_str().ok());
+        if is_authorized(header, &token) {
+            Ok(req)
+        } else {
+            Err(Status::unauthenticated("Missing or invalid bearer token"))
+        }
+    };
+    let service = LightningServer::with_interceptor(LightningService { node }, auth);
+
+    println!("gRPC API listening on {}", addr);
+    if let Err(e) = Server::builder().add_service(service).serve(addr).await {
+        println!("gRPC server error: {}", e);
+    }
+}
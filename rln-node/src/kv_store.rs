@@ -0,0 +1,271 @@
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use bitcoincore_rpc::bitcoin::hashes::hex::ToHex;
+use lightning::ln::PaymentHash;
+
+// Keys used by LDK's `KVStorePersister` blanket impls
+pub const MANAGER_KEY: &str = "manager";
+pub const NETWORK_GRAPH_KEY: &str = "network_graph";
+pub const SCORER_KEY: &str = "scorer";
+pub const MONITORS_PREFIX: &str = "monitors";
+
+// Our own tables
+pub const PAYMENTS_PREFIX: &str = "payments";
+pub const PEERS_PREFIX: &str = "peers";
+
+// Watchtower client: counterparty commitments waiting to be revoked, by
+// channel id, and justice blobs waiting for a tower to take them
+pub const TOWER_COMMITMENTS_PREFIX: &str = "tower_commitments";
+pub const TOWER_QUEUE_PREFIX: &str = "tower_queue";
+
+// Watchtower server
+pub const JUSTICE_PREFIX: &str = "justice";
+pub const TOWER_BEST_BLOCK_KEY: &str = "tower_best_block";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PaymentStatus {
+    Pending,
+    Succeeded,
+    Failed,
+}
+
+impl PaymentStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            PaymentStatus::Pending => "pending",
+            PaymentStatus::Succeeded => "succeeded",
+            PaymentStatus::Failed => "failed",
+        }
+    }
+}
+
+impl FromStr for PaymentStatus {
+    type Err = io::Error;
+
+    fn from_str(s: &str) -> io::Result<Self> {
+        match s {
+            "pending" => Ok(PaymentStatus::Pending),
+            "succeeded" => Ok(PaymentStatus::Succeeded),
+            "failed" => Ok(PaymentStatus::Failed),
+            _ => Err(invalid_data(format!("Unknown payment status: {}", s))),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PaymentRecord {
+    pub payment_hash: String,
+    pub inbound: bool,
+    pub status: PaymentStatus,
+    pub amount_msat: Option<u64>,
+    pub preimage: Option<String>,
+}
+
+impl PaymentRecord {
+    // `inbound,status,amount_msat,preimage`, empty fields for `None`
+    fn to_line(&self) -> String {
+        format!(
+            "{},{},{},{}",
+            self.inbound,
+            self.status.as_str(),
+            self.amount_msat.map(|a| a.to_string()).unwrap_or_default(),
+            self.preimage.clone().unwrap_or_default(),
+        )
+    }
+
+    fn from_line(payment_hash: &str, line: &str) -> io::Result<Self> {
+        let fields: Vec<&str> = line.trim().split(',').collect();
+        if fields.len() != 4 {
+            return Err(invalid_data(format!("Malformed payment record: {}", line)));
+        }
+        let inbound = fields[0]
+            .parse::<bool>()
+            .map_err(|e| invalid_data(e.to_string()))?;
+        let amount_msat = match fields[2] {
+            "" => None,
+            a => Some(a.parse::<u64>().map_err(|e| invalid_data(e.to_string()))?),
+        };
+        let preimage = match fields[3] {
+            "" => None,
+            p => Some(p.to_owned()),
+        };
+        Ok(Self {
+            payment_hash: payment_hash.to_owned(),
+            inbound,
+            status: PaymentStatus::from_str(fields[1])?,
+            amount_msat,
+            preimage,
+        })
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PeerRecord {
+    pub pubkey: String,
+    pub address: String,
+}
+
+// Storage backend for everything the node persists.
+//
+// Keys are `/` separated paths, e.g. `manager` or `monitors/<txid>_<index>`,
+// matching the layout `lightning_persister::FilesystemPersister` uses on disk.
+pub trait KVStore: Send + Sync {
+    fn read(&self, key: &str) -> io::Result<Option<Vec<u8>>>;
+
+    fn write(&self, key: &str, value: &[u8]) -> io::Result<()>;
+
+    // Writes all entries or none of them, backends without transactions
+    // fall back to writing one by one.
+    fn write_all(&self, entries: &[(String, Vec<u8>)]) -> io::Result<()> {
+        for (key, value) in entries {
+            self.write(key, value)?;
+        }
+        Ok(())
+    }
+
+    fn remove(&self, key: &str) -> io::Result<()>;
+
+    // Returns the full keys stored under `prefix`
+    fn list(&self, prefix: &str) -> io::Result<Vec<String>>;
+
+    fn persist_payment(&self, payment: &PaymentRecord) -> io::Result<()>;
+
+    // `None` for a payment the node has no record of
+    fn read_payment(&self, payment_hash: &PaymentHash) -> io::Result<Option<PaymentRecord>>;
+
+    fn list_payments(&self) -> io::Result<Vec<PaymentRecord>>;
+
+    fn persist_peer(&self, peer: &PeerRecord) -> io::Result<()>;
+
+    fn list_peers(&self) -> io::Result<Vec<PeerRecord>>;
+}
+
+pub struct FilesystemStore {
+    ln_dir: PathBuf,
+}
+
+impl FilesystemStore {
+    pub fn new(ln_dir: &str) -> Self {
+        Self {
+            ln_dir: PathBuf::from(ln_dir),
+        }
+    }
+
+    fn path(&self, key: &str) -> PathBuf {
+        self.ln_dir.join(key)
+    }
+}
+
+impl KVStore for FilesystemStore {
+    fn read(&self, key: &str) -> io::Result<Option<Vec<u8>>> {
+        match fs::read(self.path(key)) {
+            Ok(bytes) => Ok(Some(bytes)),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    fn write(&self, key: &str, value: &[u8]) -> io::Result<()> {
+        let path = self.path(key);
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        // Write to a temp file and rename so a crash never leaves a
+        // half written object behind
+        let tmp_path = path.with_extension("tmp");
+        {
+            let mut f = fs::File::create(&tmp_path)?;
+            f.write_all(value)?;
+            f.sync_all()?;
+        }
+        fs::rename(&tmp_path, &path)
+    }
+
+    fn remove(&self, key: &str) -> io::Result<()> {
+        match fs::remove_file(self.path(key)) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+            _ => Ok(()),
+        }
+    }
+
+    fn list(&self, prefix: &str) -> io::Result<Vec<String>> {
+        let dir = self.path(prefix);
+        if !Path::new(&dir).exists() {
+            return Ok(Vec::new());
+        }
+
+        let mut keys = Vec::new();
+        for entry in fs::read_dir(dir)? {
+            let entry = entry?;
+            if !entry.file_type()?.is_file() {
+                continue;
+            }
+            let name = entry.file_name().into_string().map_err(|name| {
+                invalid_data(format!("Invalid file name in {}: {:?}", prefix, name))
+            })?;
+            // Leftovers from an interrupted write
+            if name.ends_with(".tmp") {
+                continue;
+            }
+            keys.push(format!("{}/{}", prefix, name));
+        }
+        keys.sort();
+        Ok(keys)
+    }
+
+    fn persist_payment(&self, payment: &PaymentRecord) -> io::Result<()> {
+        let key = format!("{}/{}", PAYMENTS_PREFIX, payment.payment_hash);
+        self.write(&key, payment.to_line().as_bytes())
+    }
+
+    fn read_payment(&self, payment_hash: &PaymentHash) -> io::Result<Option<PaymentRecord>> {
+        let payment_hash = payment_hash.0.to_hex();
+        let key = format!("{}/{}", PAYMENTS_PREFIX, payment_hash);
+        match self.read(&key)? {
+            Some(bytes) => {
+                let line = String::from_utf8(bytes).map_err(|e| invalid_data(e.to_string()))?;
+                PaymentRecord::from_line(&payment_hash, &line).map(Some)
+            }
+            None => Ok(None),
+        }
+    }
+
+    fn list_payments(&self) -> io::Result<Vec<PaymentRecord>> {
+        let mut payments = Vec::new();
+        for key in self.list(PAYMENTS_PREFIX)? {
+            let bytes = self.read(&key)?.unwrap_or_default();
+            let line = String::from_utf8(bytes).map_err(|e| invalid_data(e.to_string()))?;
+            let payment_hash = key.trim_start_matches(&format!("{}/", PAYMENTS_PREFIX));
+            payments.push(PaymentRecord::from_line(payment_hash, &line)?);
+        }
+        Ok(payments)
+    }
+
+    fn persist_peer(&self, peer: &PeerRecord) -> io::Result<()> {
+        let key = format!("{}/{}", PEERS_PREFIX, peer.pubkey);
+        self.write(&key, peer.address.as_bytes())
+    }
+
+    fn list_peers(&self) -> io::Result<Vec<PeerRecord>> {
+        let mut peers = Vec::new();
+        for key in self.list(PEERS_PREFIX)? {
+            let bytes = self.read(&key)?.unwrap_or_default();
+            let address = String::from_utf8(bytes).map_err(|e| invalid_data(e.to_string()))?;
+            peers.push(PeerRecord {
+                pubkey: key
+                    .trim_start_matches(&format!("{}/", PEERS_PREFIX))
+                    .to_owned(),
+                address: address.trim().to_owned(),
+            });
+        }
+        Ok(peers)
+    }
+}
+
+pub(crate) fn invalid_data(msg: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
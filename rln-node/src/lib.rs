@@ -3,4 +3,16 @@ pub mod bitcoin_client;
 pub mod keys_manager;
 pub mod event_handler;
 pub mod channel_manager_utils;
+pub mod kv_store;
+pub mod sqlite_store;
+pub mod persister;
+pub mod watchtower_wire;
+pub mod watchtower_signer;
+pub mod watchtower_client;
+pub mod watchtower_server;
 pub mod node;
+pub mod auth;
+#[cfg(feature = "rest")]
+pub mod rest;
+#[cfg(feature = "grpc")]
+pub mod grpc;
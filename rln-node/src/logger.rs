@@ -1,23 +1,66 @@
-use lightning::util::logger::Logger;
+use std::fmt;
+
+use lightning::util::logger::{Level, Logger, Record};
 use time::OffsetDateTime;
 
 #[derive(Clone, Debug)]
 pub struct RLNLogger;
 
+// For our own messages, with the caller's module and line like LDK's records.
+// LDK's `log_*!` macros check LDK's `max_level_*` features, which don't
+// exist in this crate.
+macro_rules! rln_error {
+    ($logger:expr, $($arg:tt)*) => {
+        $crate::logger::log_record(
+            &$logger,
+            lightning::util::logger::Level::Error,
+            format_args!($($arg)*),
+            module_path!(),
+            file!(),
+            line!(),
+        )
+    };
+}
+
+macro_rules! rln_info {
+    ($logger:expr, $($arg:tt)*) => {
+        $crate::logger::log_record(
+            &$logger,
+            lightning::util::logger::Level::Info,
+            format_args!($($arg)*),
+            module_path!(),
+            file!(),
+            line!(),
+        )
+    };
+}
+
+pub(crate) use {rln_error, rln_info};
+
+pub(crate) fn log_record(
+    logger: &RLNLogger,
+    level: Level,
+    args: fmt::Arguments,
+    module_path: &'static str,
+    file: &'static str,
+    line: u32,
+) {
+    logger.log(&Record::new(level, args, module_path, file, line));
+}
+
 impl Logger for RLNLogger {
     fn log(&self, record: &lightning::util::logger::Record) {
         let raw_log = record.args.to_string();
 
         let log = format!(
-			"{} {:<5} [{}:{}] {}\n",
-			OffsetDateTime::now_utc().to_string(),
-			record.level.to_string(),
-			record.module_path,
-			record.line,
-			raw_log
-		);
+            "{} {:<5} [{}:{}] {}\n",
+            OffsetDateTime::now_utc(),
+            record.level.to_string(),
+            record.module_path,
+            record.line,
+            raw_log
+        );
 
         println!("{}", log);
     }
 }
-
@@ -1,6 +1,72 @@
+use std::sync::Arc;
+
 use rlnnode::node::start_node;
+use rlnnode::persister::migrate_to_sqlite;
+
+fn usage() -> ! {
+    panic!("Usage: ln_node [ln_dir] [listen_port] [--rest-port <port>] [--grpc-port <port>]")
+}
 
 #[tokio::main]
 async fn main() {
-    start_node("./node_1").await;
+    let args: Vec<String> = std::env::args().collect();
+    match args.get(1).map(|a| a.as_str()) {
+        // ln_node migrate [ln_dir]
+        Some("migrate") => {
+            let ln_dir = args.get(2).map(|d| d.as_str()).unwrap_or("./node_1");
+            let migrated = migrate_to_sqlite(ln_dir).expect("Failed to migrate to SQLite");
+            println!("Migrated {} objects from {} to SQLite", migrated, ln_dir);
+        }
+        // ln_node [ln_dir] [listen_port] [--rest-port <port>] [--grpc-port <port>]
+        _ => {
+            let mut positional = Vec::new();
+            let mut rest_port: Option<u16> = None;
+            let mut grpc_port: Option<u16> = None;
+            let mut remaining = args.iter().skip(1);
+            while let Some(arg) = remaining.next() {
+                let port = match arg.as_str() {
+                    "--rest-port" => &mut rest_port,
+                    "--grpc-port" => &mut grpc_port,
+                    flag if flag.starts_with("--") => usage(),
+                    _ => {
+                        positional.push(arg.as_str());
+                        continue;
+                    }
+                };
+                let Some(value) = remaining.next() else {
+                    usage()
+                };
+                *port = Some(value.parse().expect("Invalid API port"));
+            }
+
+            let ln_dir = positional.first().copied().unwrap_or("./node_1");
+            let listen_port = positional
+                .get(1)
+                .map(|p| p.parse().expect("Invalid listen port"))
+                .unwrap_or(9735);
+            let _node = Arc::new(start_node(ln_dir, listen_port).await);
+
+            match rest_port {
+                #[cfg(feature = "rest")]
+                Some(rest_port) => {
+                    tokio::spawn(rlnnode::rest::start_rest_server(_node.clone(), rest_port));
+                }
+                #[cfg(not(feature = "rest"))]
+                Some(_) => println!("Built without the `rest` feature, no REST API"),
+                None => {}
+            }
+            match grpc_port {
+                #[cfg(feature = "grpc")]
+                Some(grpc_port) => {
+                    tokio::spawn(rlnnode::grpc::start_grpc_server(_node.clone(), grpc_port));
+                }
+                #[cfg(not(feature = "grpc"))]
+                Some(_) => println!("Built without the `grpc` feature, no gRPC API"),
+                None => {}
+            }
+
+            // Keep the node running until the process is killed
+            std::future::pending::<()>().await;
+        }
+    }
 }
@@ -1,49 +1,74 @@
+use std::net::SocketAddr;
+use std::str::FromStr;
 use std::sync::{Arc, Mutex};
 use std::time::{Duration, SystemTime};
 
-use crate::bitcoin_client::BitcoindClient;
+use crate::bitcoin_client::{read_bitcoind_config, BitcoindClient};
 use crate::channel_manager_utils::get_channel_manager;
-use crate::event_handler::RLNEventHandler;
+use crate::event_handler::{
+    record_outbound_payment, set_outbound_payment_status, NodeEvent, RLNEventHandler,
+    EVENTS_CAPACITY,
+};
 use crate::keys_manager::get_keys_manager;
-use crate::logger::RLNLogger;
+use crate::kv_store::{KVStore, PaymentRecord, PaymentStatus, PeerRecord};
+use crate::logger::{rln_error, RLNLogger};
+use crate::persister::{open_store, RLNPersister};
+use crate::watchtower_client::{read_towers, WatchtowerClient};
+use crate::watchtower_signer::{WatchtowerKeysManager, WatchtowerSigner};
+use bitcoin_basics::zmq::{Subscriber, SubscriberError, Topic};
 use bitcoincore_rpc::bitcoin::blockdata::constants::genesis_block;
+use bitcoincore_rpc::bitcoin::hashes::hex::ToHex;
+use bitcoincore_rpc::bitcoin::hashes::{sha256, Hash};
 use bitcoincore_rpc::bitcoin::secp256k1::rand::{thread_rng, RngCore};
+use bitcoincore_rpc::bitcoin::secp256k1::PublicKey;
 use bitcoincore_rpc::bitcoin::Network;
-use lightning::chain::keysinterface::{InMemorySigner, KeysInterface, KeysManager, Recipient};
+use lightning::chain::keysinterface::{KeysInterface, Recipient};
 use lightning::chain::{self, Filter};
 use lightning::chain::{chainmonitor, ChannelMonitorUpdateStatus, Watch};
-use lightning::ln::channelmanager::SimpleArcChannelManager;
-use lightning::ln::peer_handler::{IgnoringMessageHandler, MessageHandler, SimpleArcPeerManager};
+use lightning::ln::channelmanager::{self, ChannelDetails};
+use lightning::ln::peer_handler::{self, IgnoringMessageHandler, MessageHandler};
+use lightning::ln::{PaymentHash, PaymentPreimage};
 use lightning::onion_message::SimpleArcOnionMessenger;
 use lightning::routing::gossip::{self, P2PGossipSync};
 use lightning::routing::router::DefaultRouter;
-use lightning::routing::scoring::{ProbabilisticScorer, ProbabilisticScoringParameters};
+use lightning::routing::scoring::ProbabilisticScorer;
+use lightning::util::config::UserConfig;
 use lightning_background_processor::{BackgroundProcessor, GossipSync};
 use lightning_block_sync::init::synchronize_listeners;
 use lightning_block_sync::{poll, SpvClient, UnboundedCache};
-use lightning_invoice::payment;
+use lightning_invoice::utils::create_invoice_from_channelmanager;
+use lightning_invoice::{payment, Currency, Invoice};
 use lightning_net_tokio::SocketDescriptor;
-use lightning_persister::FilesystemPersister;
+use tokio::sync::broadcast;
+
+// How long to wait for a ZMQ block notification before polling anyway
+const ZMQ_POLL_FALLBACK: Duration = Duration::from_secs(30);
 
 pub(crate) type ChainMonitor = chainmonitor::ChainMonitor<
-    InMemorySigner,
+    WatchtowerSigner,
     Arc<dyn Filter + Send + Sync>,
     Arc<BitcoindClient>,
     Arc<BitcoindClient>,
     Arc<RLNLogger>,
-    Arc<FilesystemPersister>,
+    Arc<RLNPersister>,
 >;
 
-pub(crate) type ChannelManager =
-    SimpleArcChannelManager<ChainMonitor, BitcoindClient, BitcoindClient, RLNLogger>;
+// The `SimpleArc` aliases, with our own keys manager
+pub(crate) type ChannelManager = channelmanager::ChannelManager<
+    Arc<ChainMonitor>,
+    Arc<BitcoindClient>,
+    Arc<WatchtowerKeysManager>,
+    Arc<BitcoindClient>,
+    Arc<RLNLogger>,
+>;
 
-pub(crate) type PeerManager = SimpleArcPeerManager<
+pub(crate) type PeerManager = peer_handler::PeerManager<
     SocketDescriptor,
-    ChainMonitor,
-    BitcoindClient,
-    BitcoindClient,
-    dyn chain::Access + Send + Sync,
-    RLNLogger,
+    Arc<ChannelManager>,
+    Arc<P2PGossipSync<Arc<NetworkGraph>, Arc<dyn chain::Access + Send + Sync>, Arc<RLNLogger>>>,
+    Arc<OnionMessenger>,
+    Arc<RLNLogger>,
+    IgnoringMessageHandler,
 >;
 
 pub(crate) type NetworkGraph = gossip::NetworkGraph<Arc<RLNLogger>>;
@@ -57,25 +82,197 @@ pub(crate) type Router = DefaultRouter<
     Arc<Mutex<ProbabilisticScorer<Arc<NetworkGraph>, Arc<RLNLogger>>>>,
 >;
 
+// The APIs are the only readers of some fields, the others are only here to
+// live as long as the node
+#[allow(dead_code)]
 pub struct Node {
-    invoice_payer: Arc<InvoicePayer>,
-    peer_manager: Arc<PeerManager>,
-    channel_manager: Arc<ChannelManager>,
-    keys_manager: Arc<KeysManager>,
-    net_graph: Arc<NetworkGraph>,
-    onion_messenger: Arc<OnionMessenger>,
-    ln_dir: String,
-    logger: Arc<RLNLogger>,
+    pub(crate) invoice_payer: Arc<InvoicePayer>,
+    pub(crate) peer_manager: Arc<PeerManager>,
+    pub(crate) channel_manager: Arc<ChannelManager>,
+    pub(crate) keys_manager: Arc<WatchtowerKeysManager>,
+    pub(crate) net_graph: Arc<NetworkGraph>,
+    pub(crate) onion_messenger: Arc<OnionMessenger>,
+    pub(crate) bitcoind_client: Arc<BitcoindClient>,
+    pub(crate) store: Arc<dyn KVStore>,
+    pub(crate) ln_dir: String,
+    pub(crate) logger: Arc<RLNLogger>,
+    pub(crate) events: broadcast::Sender<NodeEvent>,
     bg_processor: BackgroundProcessor,
 }
 
-pub async fn start_node(ln_dir: &str) -> Node
-{
-    let bitcoind_client = Arc::new(BitcoindClient::new());
+// Operations shared by the REST and gRPC APIs. Errors are meant for the API
+// caller, failures of the node itself still panic.
+impl Node {
+    pub fn subscribe_events(&self) -> broadcast::Receiver<NodeEvent> {
+        self.events.subscribe()
+    }
+
+    pub fn node_id(&self) -> PublicKey {
+        self.channel_manager.get_our_node_id()
+    }
+
+    pub fn list_channels(&self) -> Vec<ChannelDetails> {
+        self.channel_manager.list_channels()
+    }
+
+    pub async fn connect_peer(&self, pubkey: PublicKey, address: &str) -> Result<(), String> {
+        let addr = SocketAddr::from_str(address).map_err(|e| e.to_string())?;
+        if lightning_net_tokio::connect_outbound(self.peer_manager.clone(), pubkey, addr)
+            .await
+            .is_none()
+        {
+            return Err(format!("Failed to connect to {}", address));
+        }
+
+        // The handshake finishes in the background, wait for the peer to show up
+        for _ in 0..100 {
+            if self.peer_manager.get_peer_node_ids().contains(&pubkey) {
+                let store = self.store.clone();
+                let peer = PeerRecord {
+                    pubkey: pubkey.to_string(),
+                    address: address.to_owned(),
+                };
+                // The store blocks, keep it off the async workers
+                tokio::task::spawn_blocking(move || store.persist_peer(&peer))
+                    .await
+                    .expect("Failed to persist peer")
+                    .expect("Failed to persist peer");
+                return Ok(());
+            }
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+        Err(format!("Handshake with {} timed out", pubkey))
+    }
+
+    // Returns the temporary channel id, funding happens in the event handler
+    pub fn open_channel(
+        &self,
+        pubkey: PublicKey,
+        amount_sat: u64,
+        push_msat: u64,
+        public: bool,
+    ) -> Result<[u8; 32], String> {
+        let mut config = UserConfig::default();
+        config.channel_handshake_config.announced_channel = public;
+        self.channel_manager
+            .create_channel(pubkey, amount_sat, push_msat, 0, Some(config))
+            .map_err(|e| format!("{:?}", e))
+    }
+
+    pub fn create_invoice(
+        &self,
+        amount_msat: Option<u64>,
+        description: String,
+        expiry_secs: u32,
+    ) -> Result<Invoice, String> {
+        let invoice = create_invoice_from_channelmanager(
+            &self.channel_manager,
+            self.keys_manager.clone(),
+            self.logger.clone(),
+            Currency::Regtest,
+            amount_msat,
+            description,
+            expiry_secs,
+        )
+        .map_err(|e| format!("{:?}", e))?;
+
+        self.store
+            .persist_payment(&PaymentRecord {
+                payment_hash: invoice.payment_hash().to_hex(),
+                inbound: true,
+                status: PaymentStatus::Pending,
+                amount_msat,
+                preimage: None,
+            })
+            .expect("Failed to persist payment");
+        Ok(invoice)
+    }
+
+    // Zero amount invoices need `amount_msat`. Sending only starts the
+    // payment, the event handler records how it ends.
+    pub fn pay_invoice(
+        &self,
+        invoice: &Invoice,
+        amount_msat: Option<u64>,
+    ) -> Result<PaymentHash, String> {
+        let payment_hash = PaymentHash(invoice.payment_hash().into_inner());
+        let amount_msat = match (invoice.amount_milli_satoshis(), amount_msat) {
+            (Some(amount_msat), _) | (None, Some(amount_msat)) => amount_msat,
+            (None, None) => return Err("Zero amount invoice needs an amount".to_owned()),
+        };
+        // Recorded first, the payment can end before the call returns
+        record_outbound_payment(&*self.store, &payment_hash, Some(amount_msat));
+        let res = match invoice.amount_milli_satoshis() {
+            Some(_) => self.invoice_payer.pay_invoice(invoice),
+            None => self
+                .invoice_payer
+                .pay_zero_value_invoice(invoice, amount_msat),
+        };
+        self.payment_result(
+            payment_hash,
+            res.map(|_| ()).map_err(|e| format!("{:?}", e)),
+        )
+    }
+
+    pub fn keysend(&self, pubkey: PublicKey, amount_msat: u64) -> Result<PaymentHash, String> {
+        let preimage = PaymentPreimage(self.keys_manager.get_secure_random_bytes());
+        let payment_hash = PaymentHash(sha256::Hash::hash(&preimage.0).into_inner());
+        record_outbound_payment(&*self.store, &payment_hash, Some(amount_msat));
+        let res = self
+            .invoice_payer
+            .pay_pubkey(pubkey, preimage, amount_msat, 40)
+            .map(|_| ())
+            .map_err(|e| format!("{:?}", e));
+        self.payment_result(payment_hash, res)
+    }
+
+    fn payment_result(
+        &self,
+        payment_hash: PaymentHash,
+        res: Result<(), String>,
+    ) -> Result<PaymentHash, String> {
+        if res.is_err() {
+            set_outbound_payment_status(&*self.store, &payment_hash, PaymentStatus::Failed);
+        }
+        res.map(|_| payment_hash)
+    }
+
+    pub fn lightning_balance_msat(&self) -> u64 {
+        self.channel_manager
+            .list_channels()
+            .iter()
+            .map(|c| c.balance_msat)
+            .sum()
+    }
+}
+
+pub async fn start_node(ln_dir: &str, listen_port: u16) -> Node {
+    let bitcoind_config = read_bitcoind_config(ln_dir).expect("Failed to read bitcoind file");
+    let bitcoind_client = Arc::new(BitcoindClient::new(&bitcoind_config));
     let logger = Arc::new(RLNLogger);
 
-    // Use sample LDK chain persistor
-    let persister = Arc::new(FilesystemPersister::new(ln_dir.to_owned()));
+    // Initialize key manager
+    let base_keys_manager = Arc::new(get_keys_manager(ln_dir));
+
+    // Filesystem or SQLite store, depending on what `ln_dir` holds
+    let store = open_store(ln_dir).expect("Failed to open node store");
+    let persister = Arc::new(RLNPersister::new(store));
+
+    // Channel signers hand justice txs for revoked states to any configured
+    // watchtowers
+    let towers = read_towers(ln_dir).expect("Failed to read towers file");
+    let tower_client = Arc::new(WatchtowerClient::new(
+        towers,
+        base_keys_manager.get_node_secret(Recipient::Node).unwrap(),
+        persister.store(),
+        logger.clone(),
+    ));
+    let keys_manager = Arc::new(WatchtowerKeysManager::new(
+        base_keys_manager.clone(),
+        tower_client.clone(),
+        bitcoind_client.clone(),
+    ));
+
     let chain_monitor: Arc<ChainMonitor> = Arc::new(chainmonitor::ChainMonitor::new(
         None,
         bitcoind_client.clone(),
@@ -84,8 +281,6 @@ pub async fn start_node(ln_dir: &str) -> Node
         persister.clone(),
     ));
 
-    // Initialize key manager
-    let keys_manager = Arc::new(get_keys_manager(ln_dir));
     let mut channel_monitors = persister
         .read_channelmonitors(keys_manager.clone())
         .unwrap();
@@ -96,7 +291,7 @@ pub async fn start_node(ln_dir: &str) -> Node
         chain_monitor.clone(),
         logger.clone(),
         keys_manager.clone(),
-        ln_dir,
+        &persister,
         &mut channel_monitors,
     );
 
@@ -106,16 +301,14 @@ pub async fn start_node(ln_dir: &str) -> Node
         channel_manager_blockhash,
         &channel_manager as &dyn chain::Listen,
     )];
-    let chain_tip = Some(
-        synchronize_listeners(
-            bitcoind_client.clone(),
-            bitcoincore_rpc::bitcoin::Network::Regtest,
-            &mut cache,
-            chain_listeners,
-        )
-        .await
-        .unwrap(),
-    );
+    let chain_tip = synchronize_listeners(
+        bitcoind_client.clone(),
+        bitcoincore_rpc::bitcoin::Network::Regtest,
+        &mut cache,
+        chain_listeners,
+    )
+    .await
+    .unwrap();
 
     // Channel monitors to chain monitor
     for (_, monitor) in channel_monitors.drain(..) {
@@ -126,7 +319,7 @@ pub async fn start_node(ln_dir: &str) -> Node
 
     // NetGraphMsgHandler
     let genesis_hash = genesis_block(Network::Regtest).header.block_hash();
-    let network_graph = Arc::new(NetworkGraph::new(genesis_hash, logger.clone()));
+    let network_graph = Arc::new(persister.read_network_graph(genesis_hash, logger.clone()));
     let gossip_sync = Arc::new(P2PGossipSync::new(
         Arc::clone(&network_graph),
         None::<Arc<dyn chain::Access + Send + Sync>>,
@@ -136,7 +329,7 @@ pub async fn start_node(ln_dir: &str) -> Node
     // Onion messenger
     let channel_manager = Arc::new(channel_manager);
     let onion_messenger = Arc::new(OnionMessenger::new(
-        keys_manager.clone(),
+        base_keys_manager.clone(),
         logger.clone(),
         IgnoringMessageHandler {},
     ));
@@ -164,7 +357,6 @@ pub async fn start_node(ln_dir: &str) -> Node
     ));
 
     // Initialize network
-    let listen_port = 9735;
     let listener = tokio::net::TcpListener::bind(format!("0.0.0.0:{}", listen_port))
         .await
         .unwrap();
@@ -186,32 +378,68 @@ pub async fn start_node(ln_dir: &str) -> Node
     // Keeping LKD up to date
     let channel_manager_spv = channel_manager.clone();
     let chain_monitor_spv = chain_monitor.clone();
+    let bitcoind_client_spv = bitcoind_client.clone();
+    let (events, _) = broadcast::channel(EVENTS_CAPACITY);
+    let events_spv = events.clone();
+    let logger_spv = logger.clone();
+    let zmq_url = bitcoind_config.zmq_url.clone();
 
     tokio::spawn(async move {
-        let chain_poller = poll::ChainPoller::new(bitcoind_client.clone(), Network::Regtest);
+        let chain_poller = poll::ChainPoller::new(bitcoind_client_spv, Network::Regtest);
         let chain_listener = (chain_monitor_spv, channel_manager_spv);
-        let mut spv_client = SpvClient::new(
-            chain_tip.unwrap(),
-            chain_poller,
-            &mut cache,
-            &chain_listener,
-        );
+        let mut spv_client = SpvClient::new(chain_tip, chain_poller, &mut cache, &chain_listener);
+        // Woken up by bitcoind's ZMQ when it publishes blocks, polling every
+        // second when it doesn't
+        let mut blocks = match &zmq_url {
+            Some(url) => match Subscriber::connect(url, &[Topic::HashBlock]).await {
+                Ok(subscriber) => Some(subscriber),
+                Err(e) => {
+                    rln_error!(logger_spv, "No block notifications, polling: {}", e);
+                    None
+                }
+            },
+            None => None,
+        };
         loop {
+            let best_block = chain_listener.1.current_best_block().block_hash();
             spv_client.poll_best_tip().await.unwrap();
-            tokio::time::sleep(Duration::from_secs(1)).await;
+            let new_best = chain_listener.1.current_best_block();
+            if new_best.block_hash() != best_block {
+                let _ = events_spv.send(NodeEvent::NewBlock {
+                    block_hash: new_best.block_hash(),
+                    height: new_best.height(),
+                });
+            }
+            match &mut blocks {
+                // Timing out polls anyway, in case a notification got lost
+                Some(subscriber) => {
+                    if let Err(e @ SubscriberError::Socket(_)) =
+                        subscriber.wait_for_block(ZMQ_POLL_FALLBACK).await
+                    {
+                        rln_error!(logger_spv, "Lost block notifications, polling: {}", e);
+                        blocks = None;
+                    }
+                }
+                None => tokio::time::sleep(Duration::from_secs(1)).await,
+            }
         }
     });
 
     // LDK event handler
-    let event_handler = RLNEventHandler;
+    let event_handler = RLNEventHandler {
+        channel_manager: channel_manager.clone(),
+        bitcoind_client: bitcoind_client.clone(),
+        keys_manager: base_keys_manager.clone(),
+        store: persister.store(),
+        tower_client,
+        events: events.clone(),
+        runtime: tokio::runtime::Handle::current(),
+    };
 
     // Prob. scorer
-    let scorer_params = ProbabilisticScoringParameters::default();
-    let scorer = Arc::new(Mutex::new(ProbabilisticScorer::new(
-        scorer_params,
-        network_graph.clone(),
-        logger.clone(),
-    )));
+    let scorer = Arc::new(Mutex::new(
+        persister.read_scorer(network_graph.clone(), logger.clone()),
+    ));
 
     // 	InvoicePayer
     let router = DefaultRouter::new(
@@ -230,7 +458,7 @@ pub async fn start_node(ln_dir: &str) -> Node
 
     // Background process
     let _bg_process = BackgroundProcessor::start(
-        persister,
+        persister.clone(),
         invoice_payer.clone(),
         chain_monitor.clone(),
         channel_manager.clone(),
@@ -247,8 +475,11 @@ pub async fn start_node(ln_dir: &str) -> Node
         keys_manager: keys_manager.clone(),
         net_graph: network_graph.clone(),
         onion_messenger: onion_messenger.clone(),
+        bitcoind_client: bitcoind_client.clone(),
+        store: persister.store(),
         ln_dir: ln_dir.to_owned(),
         logger: logger.clone(),
+        events,
         bg_processor: _bg_process,
     }
 }
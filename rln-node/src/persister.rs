@@ -0,0 +1,165 @@
+use std::io::{self, Cursor};
+use std::path::Path;
+use std::sync::Arc;
+
+use bitcoincore_rpc::bitcoin::BlockHash;
+use lightning::chain::channelmonitor::ChannelMonitor;
+use lightning::routing::scoring::{ProbabilisticScorer, ProbabilisticScoringParameters};
+use lightning::util::persist::KVStorePersister;
+use lightning::util::ser::{ReadableArgs, Writeable};
+
+use crate::kv_store::{
+    invalid_data, FilesystemStore, KVStore, JUSTICE_PREFIX, MANAGER_KEY, MONITORS_PREFIX,
+    NETWORK_GRAPH_KEY, SCORER_KEY, TOWER_BEST_BLOCK_KEY, TOWER_COMMITMENTS_PREFIX,
+    TOWER_QUEUE_PREFIX,
+};
+use crate::logger::RLNLogger;
+use crate::node::NetworkGraph;
+use crate::sqlite_store::{SqliteStore, SQLITE_DB_FILE};
+use crate::watchtower_signer::{WatchtowerKeysManager, WatchtowerSigner};
+
+pub(crate) type Scorer = ProbabilisticScorer<Arc<NetworkGraph>, Arc<RLNLogger>>;
+
+// Bridges a `KVStore` to LDK. Implementing `KVStorePersister` gives us
+// `chainmonitor::Persist` and the background processor `Persister` for free.
+pub struct RLNPersister {
+    store: Arc<dyn KVStore>,
+}
+
+impl RLNPersister {
+    pub fn new(store: Arc<dyn KVStore>) -> Self {
+        Self { store }
+    }
+
+    pub fn store(&self) -> Arc<dyn KVStore> {
+        self.store.clone()
+    }
+
+    pub fn read_channelmonitors(
+        &self,
+        keys_manager: Arc<WatchtowerKeysManager>,
+    ) -> io::Result<Vec<(BlockHash, ChannelMonitor<WatchtowerSigner>)>> {
+        let mut monitors = Vec::new();
+        for key in self.store.list(MONITORS_PREFIX)? {
+            let bytes = match self.store.read(&key)? {
+                Some(bytes) => bytes,
+                None => continue,
+            };
+            let (blockhash, monitor) = <(BlockHash, ChannelMonitor<WatchtowerSigner>)>::read(
+                &mut Cursor::new(&bytes),
+                &*keys_manager,
+            )
+            .map_err(|e| invalid_data(format!("Failed to read {}: {:?}", key, e)))?;
+
+            // Keys are `monitors/<txid>_<index>`, make sure we read what we expect
+            let (funding_txo, _) = monitor.get_funding_txo();
+            let expected = format!(
+                "{}/{}_{}",
+                MONITORS_PREFIX, funding_txo.txid, funding_txo.index
+            );
+            if key != expected {
+                return Err(invalid_data(format!(
+                    "Monitor stored under {} is for {}",
+                    key, expected
+                )));
+            }
+            monitors.push((blockhash, monitor));
+        }
+        Ok(monitors)
+    }
+
+    pub fn read_manager(&self) -> io::Result<Option<Vec<u8>>> {
+        self.store.read(MANAGER_KEY)
+    }
+
+    pub fn read_network_graph(
+        &self,
+        genesis_hash: BlockHash,
+        logger: Arc<RLNLogger>,
+    ) -> NetworkGraph {
+        if let Ok(Some(bytes)) = self.store.read(NETWORK_GRAPH_KEY) {
+            if let Ok(graph) = NetworkGraph::read(&mut Cursor::new(&bytes), logger.clone()) {
+                return graph;
+            }
+        }
+        NetworkGraph::new(genesis_hash, logger)
+    }
+
+    pub fn read_scorer(&self, graph: Arc<NetworkGraph>, logger: Arc<RLNLogger>) -> Scorer {
+        let params = ProbabilisticScoringParameters::default();
+        if let Ok(Some(bytes)) = self.store.read(SCORER_KEY) {
+            let args = (params.clone(), graph.clone(), logger.clone());
+            if let Ok(scorer) = Scorer::read(&mut Cursor::new(&bytes), args) {
+                return scorer;
+            }
+        }
+        Scorer::new(params, graph, logger)
+    }
+}
+
+impl KVStorePersister for RLNPersister {
+    fn persist<W: Writeable>(&self, key: &str, object: &W) -> io::Result<()> {
+        self.store.write(key, &object.encode())
+    }
+}
+
+// Uses the SQLite store once `ln_dir` has been migrated, the filesystem
+// layout otherwise.
+pub fn open_store(ln_dir: &str) -> io::Result<Arc<dyn KVStore>> {
+    let db_path = Path::new(ln_dir).join(SQLITE_DB_FILE);
+    if db_path.exists() {
+        Ok(Arc::new(SqliteStore::open(db_path.to_str().unwrap())?))
+    } else {
+        Ok(Arc::new(FilesystemStore::new(ln_dir)))
+    }
+}
+
+// Copies everything persisted under an `ln_dir` filesystem layout into
+// `<ln_dir>/rln.sqlite` in a single transaction. The original files are left
+// in place, delete them once the node has been checked against the new store.
+pub fn migrate_to_sqlite(ln_dir: &str) -> io::Result<usize> {
+    let db_path = Path::new(ln_dir).join(SQLITE_DB_FILE);
+    if db_path.exists() {
+        return Err(io::Error::new(
+            io::ErrorKind::AlreadyExists,
+            format!("{} already exists", db_path.display()),
+        ));
+    }
+
+    let fs_store = FilesystemStore::new(ln_dir);
+    let mut objects = Vec::new();
+    for key in [
+        MANAGER_KEY,
+        NETWORK_GRAPH_KEY,
+        SCORER_KEY,
+        TOWER_BEST_BLOCK_KEY,
+    ] {
+        if let Some(value) = fs_store.read(key)? {
+            objects.push((key.to_owned(), value));
+        }
+    }
+    for prefix in [
+        MONITORS_PREFIX,
+        TOWER_COMMITMENTS_PREFIX,
+        TOWER_QUEUE_PREFIX,
+        JUSTICE_PREFIX,
+    ] {
+        for key in fs_store.list(prefix)? {
+            if let Some(value) = fs_store.read(&key)? {
+                objects.push((key, value));
+            }
+        }
+    }
+    let payments = fs_store.list_payments()?;
+    let peers = fs_store.list_peers()?;
+
+    let sqlite_store = SqliteStore::open(db_path.to_str().unwrap())?;
+    if let Err(e) = sqlite_store.import(&objects, &payments, &peers) {
+        // Don't leave an empty database behind, the node would pick it up
+        drop(sqlite_store);
+        std::fs::remove_file(&db_path)?;
+        return Err(e);
+    }
+
+    Ok(objects.len() + payments.len() + peers.len())
+}
@@ -0,0 +1,554 @@
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use std::str::FromStr;
+use std::sync::Arc;
+
+use bitcoincore_rpc::bitcoin::hashes::hex::ToHex;
+use bitcoincore_rpc::bitcoin::secp256k1::PublicKey;
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{header, Body, Method, Request, Response, Server, StatusCode};
+use lightning_invoice::Invoice;
+use serde_json::{json, Value};
+
+use crate::auth::{get_api_token, is_authorized};
+use crate::kv_store::{PaymentRecord, PaymentStatus};
+use crate::node::Node;
+
+type ApiResult = Result<Value, (StatusCode, String)>;
+
+// Serves the API on localhost only
+pub async fn start_rest_server(node: Arc<Node>, port: u16) {
+    let token = Arc::new(get_api_token(&node.ln_dir));
+    let addr = SocketAddr::from(([127, 0, 0, 1], port));
+
+    let make_service = make_service_fn(move |_| {
+        let node = node.clone();
+        let token = token.clone();
+        async move {
+            Ok::<_, Infallible>(service_fn(move |req| {
+                handle_request(node.clone(), token.clone(), req)
+            }))
+        }
+    });
+
+    println!("REST API listening on {}", addr);
+    if let Err(e) = Server::bind(&addr).serve(make_service).await {
+        println!("REST server error: {}", e);
+    }
+}
+
+async fn handle_request(
+    node: Arc<Node>,
+    token: Arc<String>,
+    req: Request<Body>,
+) -> Result<Response<Body>, Infallible> {
+    if let Some(rejection) = reject_unauthorized(&req, &token) {
+        return Ok(rejection);
+    }
+
+    Ok(match route(node, req).await {
+        Ok(body) => json_response(StatusCode::OK, body),
+        Err((status, error)) => json_response(status, json!({ "error": error })),
+    })
+}
+
+// The response for a request without the right bearer token
+fn reject_unauthorized(req: &Request<Body>, token: &str) -> Option<Response<Body>> {
+    let auth_header = req
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|v| v.to_str().ok());
+    if is_authorized(auth_header, token) {
+        return None;
+    }
+    Some(json_response(
+        StatusCode::UNAUTHORIZED,
+        json!({ "error": "Missing or invalid bearer token" }),
+    ))
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Route {
+    Info,
+    ListPeers,
+    ConnectPeer,
+    ListChannels,
+    OpenChannel,
+    CreateInvoice,
+    ListPayments,
+    PayInvoice,
+    Keysend,
+    Balance,
+    GraphChannels,
+    GraphNodes,
+}
+
+impl Route {
+    fn parse(method: &Method, path: &str) -> Option<Route> {
+        let segments: Vec<&str> = path.trim_matches('/').split('/').collect();
+        let route = match (method.clone(), segments.as_slice()) {
+            (Method::GET, ["v1", "info"]) => Route::Info,
+            (Method::GET, ["v1", "peers"]) => Route::ListPeers,
+            (Method::POST, ["v1", "peers"]) => Route::ConnectPeer,
+            (Method::GET, ["v1", "channels"]) => Route::ListChannels,
+            (Method::POST, ["v1", "channels"]) => Route::OpenChannel,
+            (Method::POST, ["v1", "invoices"]) => Route::CreateInvoice,
+            (Method::GET, ["v1", "payments"]) => Route::ListPayments,
+            (Method::POST, ["v1", "payments"]) => Route::PayInvoice,
+            (Method::POST, ["v1", "keysend"]) => Route::Keysend,
+            (Method::GET, ["v1", "balance"]) => Route::Balance,
+            (Method::GET, ["v1", "graph", "channels"]) => Route::GraphChannels,
+            (Method::GET, ["v1", "graph", "nodes"]) => Route::GraphNodes,
+            _ => return None,
+        };
+        Some(route)
+    }
+}
+
+async fn route(node: Arc<Node>, req: Request<Body>) -> ApiResult {
+    let path = req.uri().path().to_owned();
+    let route = Route::parse(req.method(), &path)
+        .ok_or_else(|| (StatusCode::NOT_FOUND, format!("No route for {}", path)))?;
+
+    // Handlers hitting the store or bitcoind run on the blocking pool, the
+    // rest only read the node's memory
+    match route {
+        Route::Info => Ok(node_info(&node)),
+        Route::ListPeers => Ok(list_peers(&node)),
+        Route::ConnectPeer => connect_peer(&node, read_json(req).await?).await,
+        Route::ListChannels => Ok(list_channels(&node)),
+        Route::OpenChannel => {
+            let body = read_json(req).await?;
+            blocking(node, move |node| open_channel(node, body)).await
+        }
+        Route::CreateInvoice => {
+            let body = read_json(req).await?;
+            blocking(node, move |node| create_invoice(node, body)).await
+        }
+        Route::ListPayments => blocking(node, list_payments).await,
+        Route::PayInvoice => {
+            let body = read_json(req).await?;
+            blocking(node, move |node| pay_invoice(node, body)).await
+        }
+        Route::Keysend => {
+            let body = read_json(req).await?;
+            blocking(node, move |node| keysend(node, body)).await
+        }
+        Route::Balance => blocking(node, |node| Ok(balance(node))).await,
+        Route::GraphChannels => Ok(graph_channels(&node)),
+        Route::GraphNodes => Ok(graph_nodes(&node)),
+    }
+}
+
+async fn blocking<F>(node: Arc<Node>, handler: F) -> ApiResult
+where
+    F: FnOnce(&Node) -> ApiResult + Send + 'static,
+{
+    tokio::task::spawn_blocking(move || handler(&node))
+        .await
+        .map_err(internal_error)?
+}
+
+fn node_info(node: &Node) -> Value {
+    let channels = node.channel_manager.list_channels();
+    let best_block = node.channel_manager.current_best_block();
+    json!({
+        "node_id": node.channel_manager.get_our_node_id().to_string(),
+        "num_channels": channels.len(),
+        "num_usable_channels": channels.iter().filter(|c| c.is_usable).count(),
+        "num_peers": node.peer_manager.get_peer_node_ids().len(),
+        "best_block_hash": best_block.block_hash().to_string(),
+        "best_block_height": best_block.height(),
+    })
+}
+
+fn list_peers(node: &Node) -> Value {
+    let peers: Vec<String> = node
+        .peer_manager
+        .get_peer_node_ids()
+        .iter()
+        .map(|id| id.to_string())
+        .collect();
+    json!({ "peers": peers })
+}
+
+async fn connect_peer(node: &Node, body: Value) -> ApiResult {
+    let pubkey = pubkey_field(&body, "pubkey")?;
+    let address = str_field(&body, "address")?;
+    node.connect_peer(pubkey, address)
+        .await
+        .map_err(bad_request)?;
+    Ok(json!({ "connected": pubkey.to_string() }))
+}
+
+fn list_channels(node: &Node) -> Value {
+    let channels: Vec<Value> = node
+        .channel_manager
+        .list_channels()
+        .iter()
+        .map(|c| {
+            json!({
+                "channel_id": c.channel_id.to_hex(),
+                "counterparty": c.counterparty.node_id.to_string(),
+                "funding_txo": c.funding_txo.map(|o| format!("{}:{}", o.txid, o.index)),
+                "short_channel_id": c.short_channel_id,
+                "channel_value_sat": c.channel_value_satoshis,
+                "balance_msat": c.balance_msat,
+                "outbound_capacity_msat": c.outbound_capacity_msat,
+                "inbound_capacity_msat": c.inbound_capacity_msat,
+                "is_channel_ready": c.is_channel_ready,
+                "is_usable": c.is_usable,
+                "is_public": c.is_public,
+            })
+        })
+        .collect();
+    json!({ "channels": channels })
+}
+
+fn open_channel(node: &Node, body: Value) -> ApiResult {
+    let pubkey = pubkey_field(&body, "pubkey")?;
+    let amount_sat = u64_field(&body, "amount_sat")?;
+    let push_msat = body.get("push_msat").and_then(Value::as_u64).unwrap_or(0);
+    let public = body.get("public").and_then(Value::as_bool).unwrap_or(false);
+
+    let temporary_channel_id = node
+        .open_channel(pubkey, amount_sat, push_msat, public)
+        .map_err(bad_request)?;
+    Ok(json!({ "temporary_channel_id": temporary_channel_id.to_hex() }))
+}
+
+fn create_invoice(node: &Node, body: Value) -> ApiResult {
+    let InvoiceParams {
+        amount_msat,
+        description,
+        expiry_secs,
+    } = InvoiceParams::from_body(&body)?;
+
+    let invoice = node
+        .create_invoice(amount_msat, description, expiry_secs)
+        .map_err(internal_error)?;
+    Ok(json!({
+        "invoice": invoice.to_string(),
+        "payment_hash": invoice.payment_hash().to_hex(),
+    }))
+}
+
+#[derive(Debug, PartialEq, Eq)]
+struct InvoiceParams {
+    amount_msat: Option<u64>,
+    description: String,
+    expiry_secs: u32,
+}
+
+impl InvoiceParams {
+    fn from_body(body: &Value) -> Result<Self, (StatusCode, String)> {
+        let expiry_secs = match body.get("expiry_secs").and_then(Value::as_u64) {
+            Some(expiry_secs) => u32::try_from(expiry_secs)
+                .map_err(|_| bad_request(format!("`expiry_secs` is over {}", u32::MAX)))?,
+            None => 3600,
+        };
+        Ok(Self {
+            amount_msat: body.get("amount_msat").and_then(Value::as_u64),
+            description: body
+                .get("description")
+                .and_then(Value::as_str)
+                .unwrap_or("")
+                .to_owned(),
+            expiry_secs,
+        })
+    }
+}
+
+fn list_payments(node: &Node) -> ApiResult {
+    let payments: Vec<Value> = node
+        .store
+        .list_payments()
+        .map_err(internal_error)?
+        .iter()
+        .map(payment_json)
+        .collect();
+    Ok(json!({ "payments": payments }))
+}
+
+fn payment_json(p: &PaymentRecord) -> Value {
+    json!({
+        "payment_hash": p.payment_hash,
+        "direction": if p.inbound { "inbound" } else { "outbound" },
+        "status": p.status.as_str(),
+        "amount_msat": p.amount_msat,
+        "preimage": p.preimage,
+    })
+}
+
+fn pay_invoice(node: &Node, body: Value) -> ApiResult {
+    let invoice = Invoice::from_str(str_field(&body, "invoice")?)
+        .map_err(|e| bad_request(format!("Invalid invoice: {:?}", e)))?;
+    let amount_msat = body.get("amount_msat").and_then(Value::as_u64);
+    let payment_hash = node
+        .pay_invoice(&invoice, amount_msat)
+        .map_err(bad_request)?;
+    Ok(pending_payment(payment_hash.0.to_hex()))
+}
+
+fn keysend(node: &Node, body: Value) -> ApiResult {
+    let pubkey = pubkey_field(&body, "pubkey")?;
+    let amount_msat = u64_field(&body, "amount_msat")?;
+    let payment_hash = node.keysend(pubkey, amount_msat).map_err(bad_request)?;
+    Ok(pending_payment(payment_hash.0.to_hex()))
+}
+
+// Sending only starts the payment, its outcome shows up in `GET /v1/payments`
+fn pending_payment(payment_hash: String) -> Value {
+    json!({
+        "payment_hash": payment_hash,
+        "status": PaymentStatus::Pending.as_str(),
+    })
+}
+
+fn balance(node: &Node) -> Value {
+    let balances = node.bitcoind_client.get_balances();
+    json!({
+        "onchain": {
+            "trusted_sat": balances.mine.trusted.to_sat(),
+            "untrusted_pending_sat": balances.mine.untrusted_pending.to_sat(),
+            "immature_sat": balances.mine.immature.to_sat(),
+        },
+        "lightning_msat": node.lightning_balance_msat(),
+    })
+}
+
+fn graph_channels(node: &Node) -> Value {
+    let graph = node.net_graph.read_only();
+    let channels: Vec<Value> = graph
+        .channels()
+        .iter()
+        .map(|(scid, info)| {
+            json!({
+                "short_channel_id": scid,
+                "node_one": info.node_one.as_slice().to_hex(),
+                "node_two": info.node_two.as_slice().to_hex(),
+                "capacity_sat": info.capacity_sats,
+            })
+        })
+        .collect();
+    json!({ "channels": channels })
+}
+
+fn graph_nodes(node: &Node) -> Value {
+    let graph = node.net_graph.read_only();
+    let nodes: Vec<Value> = graph
+        .nodes()
+        .iter()
+        .map(|(node_id, info)| {
+            json!({
+                "node_id": node_id.as_slice().to_hex(),
+                "channels": info.channels.len(),
+            })
+        })
+        .collect();
+    json!({ "nodes": nodes })
+}
+
+async fn read_json(req: Request<Body>) -> Result<Value, (StatusCode, String)> {
+    let bytes = hyper::body::to_bytes(req.into_body())
+        .await
+        .map_err(|e| bad_request(e.to_string()))?;
+    if bytes.is_empty() {
+        return Ok(json!({}));
+    }
+    serde_json::from_slice(&bytes).map_err(|e| bad_request(format!("Invalid JSON: {}", e)))
+}
+
+fn str_field<'a>(body: &'a Value, name: &str) -> Result<&'a str, (StatusCode, String)> {
+    body.get(name)
+        .and_then(Value::as_str)
+        .ok_or_else(|| bad_request(format!("Missing string field `{}`", name)))
+}
+
+fn u64_field(body: &Value, name: &str) -> Result<u64, (StatusCode, String)> {
+    body.get(name)
+        .and_then(Value::as_u64)
+        .ok_or_else(|| bad_request(format!("Missing integer field `{}`", name)))
+}
+
+fn pubkey_field(body: &Value, name: &str) -> Result<PublicKey, (StatusCode, String)> {
+    PublicKey::from_str(str_field(body, name)?)
+        .map_err(|e| bad_request(format!("Invalid `{}`: {}", name, e)))
+}
+
+fn bad_request(msg: String) -> (StatusCode, String) {
+    (StatusCode::BAD_REQUEST, msg)
+}
+
+fn internal_error<E: ToString>(e: E) -> (StatusCode, String) {
+    (StatusCode::INTERNAL_SERVER_ERROR, e.to_string())
+}
+
+fn json_response(status: StatusCode, body: Value) -> Response<Body> {
+    Response::builder()
+        .status(status)
+        .header(header::CONTENT_TYPE, "application/json")
+        .body(Body::from(body.to_string()))
+        .unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TOKEN: &str = "00ff";
+
+    fn request(auth: Option<&str>) -> Request<Body> {
+        let mut req = Request::builder().uri("/v1/info");
+        if let Some(auth) = auth {
+            req = req.header(header::AUTHORIZATION, auth);
+        }
+        req.body(Body::empty()).unwrap()
+    }
+
+    async fn body_json(res: Response<Body>) -> Value {
+        let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        serde_json::from_slice(&bytes).unwrap()
+    }
+
+    #[test]
+    fn routes() {
+        let cases = [
+            (Method::GET, "/v1/info", Route::Info),
+            (Method::GET, "/v1/peers/", Route::ListPeers),
+            (Method::POST, "/v1/peers", Route::ConnectPeer),
+            (Method::GET, "/v1/channels", Route::ListChannels),
+            (Method::POST, "/v1/channels", Route::OpenChannel),
+            (Method::POST, "/v1/invoices", Route::CreateInvoice),
+            (Method::GET, "/v1/payments", Route::ListPayments),
+            (Method::POST, "/v1/payments", Route::PayInvoice),
+            (Method::POST, "/v1/keysend", Route::Keysend),
+            (Method::GET, "/v1/balance", Route::Balance),
+            (Method::GET, "/v1/graph/channels", Route::GraphChannels),
+            (Method::GET, "/v1/graph/nodes", Route::GraphNodes),
+        ];
+        for (method, path, route) in cases {
+            assert_eq!(
+                Route::parse(&method, path),
+                Some(route),
+                "{} {}",
+                method,
+                path
+            );
+        }
+    }
+
+    #[test]
+    fn unknown_routes() {
+        assert_eq!(Route::parse(&Method::DELETE, "/v1/peers"), None);
+        assert_eq!(Route::parse(&Method::POST, "/v1/info"), None);
+        assert_eq!(Route::parse(&Method::GET, "/v2/info"), None);
+        assert_eq!(Route::parse(&Method::GET, "/v1/graph"), None);
+        assert_eq!(Route::parse(&Method::GET, "/"), None);
+    }
+
+    #[tokio::test]
+    async fn rejects_missing_or_wrong_tokens() {
+        for auth in [None, Some("Bearer 00fe"), Some("00ff"), Some("Basic 00ff")] {
+            let res = reject_unauthorized(&request(auth), TOKEN).unwrap();
+            assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+            assert_eq!(
+                body_json(res).await,
+                json!({ "error": "Missing or invalid bearer token" })
+            );
+        }
+        assert!(reject_unauthorized(&request(Some("Bearer 00ff")), TOKEN).is_none());
+    }
+
+    #[tokio::test]
+    async fn reads_json_bodies() {
+        let req = |body: &'static str| Request::new(Body::from(body));
+        assert_eq!(read_json(req("")).await.unwrap(), json!({}));
+        assert_eq!(
+            read_json(req(r#"{"amount_sat":5}"#)).await.unwrap(),
+            json!({ "amount_sat": 5 })
+        );
+        let (status, _) = read_json(req("{")).await.unwrap_err();
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+    }
+
+    #[test]
+    fn invoice_params() {
+        assert_eq!(
+            InvoiceParams::from_body(&json!({})).unwrap(),
+            InvoiceParams {
+                amount_msat: None,
+                description: String::new(),
+                expiry_secs: 3600,
+            }
+        );
+        assert_eq!(
+            InvoiceParams::from_body(&json!({
+                "amount_msat": 1000,
+                "description": "coffee",
+                "expiry_secs": 60,
+            }))
+            .unwrap(),
+            InvoiceParams {
+                amount_msat: Some(1000),
+                description: "coffee".to_owned(),
+                expiry_secs: 60,
+            }
+        );
+        let (status, _) =
+            InvoiceParams::from_body(&json!({ "expiry_secs": u64::from(u32::MAX) + 1 }))
+                .unwrap_err();
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+    }
+
+    #[test]
+    fn field_errors_are_bad_requests() {
+        let body = json!({ "pubkey": "02ff", "amount_msat": "1000" });
+        assert_eq!(
+            pubkey_field(&body, "pubkey").unwrap_err().0,
+            StatusCode::BAD_REQUEST
+        );
+        assert_eq!(
+            u64_field(&body, "amount_msat").unwrap_err().0,
+            StatusCode::BAD_REQUEST
+        );
+        assert_eq!(
+            str_field(&body, "invoice").unwrap_err().0,
+            StatusCode::BAD_REQUEST
+        );
+    }
+
+    #[test]
+    fn payment_shapes() {
+        let payment = PaymentRecord {
+            payment_hash: "ab".repeat(32),
+            inbound: false,
+            status: PaymentStatus::Succeeded,
+            amount_msat: Some(5000),
+            preimage: Some("cd".repeat(32)),
+        };
+        assert_eq!(
+            payment_json(&payment),
+            json!({
+                "payment_hash": "ab".repeat(32),
+                "direction": "outbound",
+                "status": "succeeded",
+                "amount_msat": 5000,
+                "preimage": "cd".repeat(32),
+            })
+        );
+        let payment = PaymentRecord {
+            inbound: true,
+            status: PaymentStatus::Pending,
+            amount_msat: None,
+            preimage: None,
+            ..payment
+        };
+        assert_eq!(payment_json(&payment)["direction"], "inbound");
+        assert_eq!(payment_json(&payment)["amount_msat"], Value::Null);
+        assert_eq!(
+            pending_payment("ab".to_owned()),
+            json!({ "payment_hash": "ab", "status": "pending" })
+        );
+    }
+}
@@ -0,0 +1,230 @@
+use std::io;
+use std::str::FromStr;
+use std::sync::Mutex;
+
+use bitcoincore_rpc::bitcoin::hashes::hex::ToHex;
+use lightning::ln::PaymentHash;
+use rusqlite::{params, Connection, OptionalExtension, Row};
+
+use crate::kv_store::{KVStore, PaymentRecord, PaymentStatus, PeerRecord};
+
+pub const SQLITE_DB_FILE: &str = "rln.sqlite";
+
+// Embedded store keeping LDK objects, payments and peers in a single file
+pub struct SqliteStore {
+    conn: Mutex<Connection>,
+}
+
+impl SqliteStore {
+    pub fn open(db_path: &str) -> io::Result<Self> {
+        let conn = Connection::open(db_path).map_err(sqlite_err)?;
+        conn.execute_batch(
+            "CREATE TABLE IF NOT EXISTS ldk_objects (
+                key TEXT PRIMARY KEY NOT NULL,
+                value BLOB NOT NULL
+            );
+            CREATE TABLE IF NOT EXISTS payments (
+                payment_hash TEXT PRIMARY KEY NOT NULL,
+                inbound INTEGER NOT NULL,
+                status TEXT NOT NULL,
+                amount_msat INTEGER,
+                preimage TEXT
+            );
+            CREATE TABLE IF NOT EXISTS peers (
+                pubkey TEXT PRIMARY KEY NOT NULL,
+                address TEXT NOT NULL
+            );",
+        )
+        .map_err(sqlite_err)?;
+
+        Ok(Self {
+            conn: Mutex::new(conn),
+        })
+    }
+
+    // Imports objects, payments and peers in one transaction. Used when
+    // migrating from another store.
+    pub fn import(
+        &self,
+        objects: &[(String, Vec<u8>)],
+        payments: &[PaymentRecord],
+        peers: &[PeerRecord],
+    ) -> io::Result<()> {
+        let mut conn = self.conn.lock().unwrap();
+        let tx = conn.transaction().map_err(sqlite_err)?;
+        for (key, value) in objects {
+            upsert_object(&tx, key, value)?;
+        }
+        for payment in payments {
+            upsert_payment(&tx, payment)?;
+        }
+        for peer in peers {
+            upsert_peer(&tx, peer)?;
+        }
+        tx.commit().map_err(sqlite_err)
+    }
+}
+
+impl KVStore for SqliteStore {
+    fn read(&self, key: &str) -> io::Result<Option<Vec<u8>>> {
+        let conn = self.conn.lock().unwrap();
+        conn.query_row(
+            "SELECT value FROM ldk_objects WHERE key = ?1",
+            params![key],
+            |row| row.get(0),
+        )
+        .optional()
+        .map_err(sqlite_err)
+    }
+
+    fn write(&self, key: &str, value: &[u8]) -> io::Result<()> {
+        let conn = self.conn.lock().unwrap();
+        upsert_object(&conn, key, value)
+    }
+
+    fn write_all(&self, entries: &[(String, Vec<u8>)]) -> io::Result<()> {
+        self.import(entries, &[], &[])
+    }
+
+    fn remove(&self, key: &str) -> io::Result<()> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute("DELETE FROM ldk_objects WHERE key = ?1", params![key])
+            .map_err(sqlite_err)?;
+        Ok(())
+    }
+
+    fn list(&self, prefix: &str) -> io::Result<Vec<String>> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn
+            .prepare("SELECT key FROM ldk_objects WHERE substr(key, 1, ?1) = ?2 ORDER BY key")
+            .map_err(sqlite_err)?;
+        let dir = format!("{}/", prefix);
+        let keys = stmt
+            .query_map(params![dir.len() as i64, dir], |row| row.get(0))
+            .map_err(sqlite_err)?
+            .collect::<Result<Vec<String>, _>>()
+            .map_err(sqlite_err)?;
+        Ok(keys)
+    }
+
+    fn persist_payment(&self, payment: &PaymentRecord) -> io::Result<()> {
+        let conn = self.conn.lock().unwrap();
+        upsert_payment(&conn, payment)
+    }
+
+    fn read_payment(&self, payment_hash: &PaymentHash) -> io::Result<Option<PaymentRecord>> {
+        let conn = self.conn.lock().unwrap();
+        conn.query_row(
+            "SELECT payment_hash, inbound, status, amount_msat, preimage
+            FROM payments WHERE payment_hash = ?1",
+            params![payment_hash.0.to_hex()],
+            payment_row,
+        )
+        .optional()
+        .map_err(sqlite_err)?
+        .map(to_payment)
+        .transpose()
+    }
+
+    fn list_payments(&self) -> io::Result<Vec<PaymentRecord>> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn
+            .prepare(
+                "SELECT payment_hash, inbound, status, amount_msat, preimage
+                FROM payments ORDER BY payment_hash",
+            )
+            .map_err(sqlite_err)?;
+        let rows = stmt.query_map([], payment_row).map_err(sqlite_err)?;
+
+        let mut payments = Vec::new();
+        for row in rows {
+            payments.push(to_payment(row.map_err(sqlite_err)?)?);
+        }
+        Ok(payments)
+    }
+
+    fn persist_peer(&self, peer: &PeerRecord) -> io::Result<()> {
+        let conn = self.conn.lock().unwrap();
+        upsert_peer(&conn, peer)
+    }
+
+    fn list_peers(&self) -> io::Result<Vec<PeerRecord>> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn
+            .prepare("SELECT pubkey, address FROM peers ORDER BY pubkey")
+            .map_err(sqlite_err)?;
+        let peers = stmt
+            .query_map([], |row| {
+                Ok(PeerRecord {
+                    pubkey: row.get(0)?,
+                    address: row.get(1)?,
+                })
+            })
+            .map_err(sqlite_err)?
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(sqlite_err)?;
+        Ok(peers)
+    }
+}
+
+// A `payments` row, before its status is parsed
+type PaymentRow = (String, bool, String, Option<i64>, Option<String>);
+
+fn payment_row(row: &Row) -> rusqlite::Result<PaymentRow> {
+    Ok((
+        row.get(0)?,
+        row.get(1)?,
+        row.get(2)?,
+        row.get(3)?,
+        row.get(4)?,
+    ))
+}
+
+fn to_payment(row: PaymentRow) -> io::Result<PaymentRecord> {
+    let (payment_hash, inbound, status, amount_msat, preimage) = row;
+    Ok(PaymentRecord {
+        payment_hash,
+        inbound,
+        status: PaymentStatus::from_str(&status)?,
+        amount_msat: amount_msat.map(|a| a as u64),
+        preimage,
+    })
+}
+
+fn upsert_object(conn: &Connection, key: &str, value: &[u8]) -> io::Result<()> {
+    conn.execute(
+        "INSERT OR REPLACE INTO ldk_objects (key, value) VALUES (?1, ?2)",
+        params![key, value],
+    )
+    .map_err(sqlite_err)?;
+    Ok(())
+}
+
+fn upsert_payment(conn: &Connection, payment: &PaymentRecord) -> io::Result<()> {
+    conn.execute(
+        "INSERT OR REPLACE INTO payments (payment_hash, inbound, status, amount_msat, preimage)
+        VALUES (?1, ?2, ?3, ?4, ?5)",
+        params![
+            payment.payment_hash,
+            payment.inbound,
+            payment.status.as_str(),
+            payment.amount_msat.map(|a| a as i64),
+            payment.preimage,
+        ],
+    )
+    .map_err(sqlite_err)?;
+    Ok(())
+}
+
+fn upsert_peer(conn: &Connection, peer: &PeerRecord) -> io::Result<()> {
+    conn.execute(
+        "INSERT OR REPLACE INTO peers (pubkey, address) VALUES (?1, ?2)",
+        params![peer.pubkey, peer.address],
+    )
+    .map_err(sqlite_err)?;
+    Ok(())
+}
+
+fn sqlite_err(e: rusqlite::Error) -> io::Error {
+    io::Error::other(e)
+}
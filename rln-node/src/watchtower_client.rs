@@ -0,0 +1,273 @@
+use std::fs;
+use std::io::{self, Cursor, Read};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::sync::mpsc::{self, RecvTimeoutError, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use bitcoincore_rpc::bitcoin::hashes::hex::{FromHex, ToHex};
+use bitcoincore_rpc::bitcoin::secp256k1::{PublicKey, Secp256k1, SecretKey};
+use bitcoincore_rpc::bitcoin::{Transaction, Txid};
+use lightning::ln::chan_utils::CommitmentTransaction;
+use lightning::util::ser::{Readable, Writeable};
+
+use crate::kv_store::{invalid_data, KVStore, TOWER_COMMITMENTS_PREFIX, TOWER_QUEUE_PREFIX};
+use crate::logger::{rln_error, RLNLogger};
+use crate::watchtower_wire::{
+    encrypt_justice_tx, justice_hint, write_message, JusticeHint, TowerMessage, ACK_OK,
+};
+
+// One tower per line, `<tower pubkey>@<host>:<port>`
+pub const TOWERS_FILE: &str = "towers";
+
+const TOWER_TIMEOUT: Duration = Duration::from_secs(10);
+
+// How often queued justice txs are retried while a tower is unreachable
+const RETRY_INTERVAL: Duration = Duration::from_secs(30);
+
+#[derive(Clone, Debug)]
+pub struct TowerConfig {
+    pub pubkey: PublicKey,
+    pub address: String,
+}
+
+impl TowerConfig {
+    pub fn parse(s: &str) -> io::Result<Self> {
+        let (pubkey, address) = s
+            .trim()
+            .split_once('@')
+            .ok_or_else(|| invalid_data(format!("Expected <pubkey>@<host>:<port>, got {}", s)))?;
+        let pubkey = pubkey
+            .parse::<PublicKey>()
+            .map_err(|e| invalid_data(format!("Invalid tower pubkey {}: {}", pubkey, e)))?;
+        Ok(Self {
+            pubkey,
+            address: address.to_owned(),
+        })
+    }
+}
+
+// Reads `<ln_dir>/towers`, no file means no towers
+pub fn read_towers(ln_dir: &str) -> io::Result<Vec<TowerConfig>> {
+    let contents = match fs::read_to_string(format!("{}/{}", ln_dir, TOWERS_FILE)) {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e),
+    };
+    contents
+        .lines()
+        .filter(|l| !l.trim().is_empty() && !l.trim_start().starts_with('#'))
+        .map(TowerConfig::parse)
+        .collect()
+}
+
+// Hands justice transactions to the configured towers. They are queued in
+// the node's store first and sent from a background thread, so a slow or
+// offline tower never holds up channel operations and gets them once it is
+// back.
+pub struct WatchtowerClient {
+    towers: Vec<TowerConfig>,
+    store: Arc<dyn KVStore>,
+    logger: Arc<RLNLogger>,
+    wake_up: Option<Mutex<Sender<()>>>,
+}
+
+impl WatchtowerClient {
+    pub fn new(
+        towers: Vec<TowerConfig>,
+        node_secret: SecretKey,
+        store: Arc<dyn KVStore>,
+        logger: Arc<RLNLogger>,
+    ) -> Self {
+        if towers.is_empty() {
+            return Self {
+                towers,
+                store,
+                logger,
+                wake_up: None,
+            };
+        }
+
+        let node_id = PublicKey::from_secret_key(&Secp256k1::new(), &node_secret);
+        let (sender, receiver) = mpsc::channel::<()>();
+        let thread_towers = towers.clone();
+        let thread_store = store.clone();
+        let thread_logger = logger.clone();
+        thread::spawn(move || loop {
+            for tower in thread_towers.iter() {
+                if let Err(e) = flush_queue(tower, &*thread_store, &node_secret, &node_id) {
+                    rln_error!(
+                        thread_logger,
+                        "Failed to send justice txs to tower {}: {}",
+                        tower.address,
+                        e
+                    );
+                }
+            }
+            match receiver.recv_timeout(RETRY_INTERVAL) {
+                Ok(()) | Err(RecvTimeoutError::Timeout) => {}
+                Err(RecvTimeoutError::Disconnected) => return,
+            }
+        });
+
+        Self {
+            towers,
+            store,
+            logger,
+            wake_up: Some(Mutex::new(sender)),
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        !self.towers.is_empty()
+    }
+
+    // Keeps a counterparty commitment around until it is revoked
+    pub(crate) fn commitment_signed(
+        &self,
+        channel_id: &[u8; 32],
+        commitment_tx: &CommitmentTransaction,
+    ) {
+        let key = commitment_key(channel_id, commitment_tx.commitment_number());
+        if let Err(e) = self.store.write(&key, &commitment_tx.encode()) {
+            rln_error!(self.logger, "Failed to persist {}: {}", key, e);
+        }
+    }
+
+    pub(crate) fn signed_commitment(
+        &self,
+        channel_id: &[u8; 32],
+        commitment_number: u64,
+    ) -> Option<CommitmentTransaction> {
+        let key = commitment_key(channel_id, commitment_number);
+        let bytes = match self.store.read(&key) {
+            Ok(Some(bytes)) => bytes,
+            Ok(None) => {
+                rln_error!(self.logger, "No signed commitment {}, towers miss it", key);
+                return None;
+            }
+            Err(e) => {
+                rln_error!(self.logger, "Failed to read {}: {}", key, e);
+                return None;
+            }
+        };
+        match CommitmentTransaction::read(&mut Cursor::new(&bytes)) {
+            Ok(commitment_tx) => Some(commitment_tx),
+            Err(e) => {
+                rln_error!(self.logger, "Failed to read {}: {:?}", key, e);
+                None
+            }
+        }
+    }
+
+    pub(crate) fn forget_commitment(&self, channel_id: &[u8; 32], commitment_number: u64) {
+        let key = commitment_key(channel_id, commitment_number);
+        if let Err(e) = self.store.remove(&key) {
+            rln_error!(self.logger, "Failed to remove {}: {}", key, e);
+        }
+    }
+
+    // The last commitments of a channel are never revoked, once it closes
+    // nothing needs them
+    pub(crate) fn channel_closed(&self, channel_id: &[u8; 32]) {
+        let prefix = format!("{}/{}_", TOWER_COMMITMENTS_PREFIX, channel_id.to_hex());
+        let keys = match self.store.list(TOWER_COMMITMENTS_PREFIX) {
+            Ok(keys) => keys,
+            Err(e) => {
+                rln_error!(
+                    self.logger,
+                    "Failed to list {}: {}",
+                    TOWER_COMMITMENTS_PREFIX,
+                    e
+                );
+                return;
+            }
+        };
+        for key in keys.iter().filter(|key| key.starts_with(&prefix)) {
+            if let Err(e) = self.store.remove(key) {
+                rln_error!(self.logger, "Failed to remove {}: {}", key, e);
+            }
+        }
+    }
+
+    pub(crate) fn queue_justice_tx(&self, commitment_txid: &Txid, justice_tx: &Transaction) {
+        let hint = justice_hint(commitment_txid);
+        let blob = encrypt_justice_tx(commitment_txid, justice_tx);
+        for tower in self.towers.iter() {
+            let key = queue_key(&tower.pubkey, &hint);
+            if let Err(e) = self.store.write(&key, &blob) {
+                rln_error!(self.logger, "Failed to queue {}: {}", key, e);
+            }
+        }
+        if let Some(wake_up) = &self.wake_up {
+            wake_up
+                .lock()
+                .unwrap()
+                .send(())
+                .expect("Watchtower client thread exited");
+        }
+    }
+}
+
+fn commitment_key(channel_id: &[u8; 32], commitment_number: u64) -> String {
+    format!(
+        "{}/{}_{}",
+        TOWER_COMMITMENTS_PREFIX,
+        channel_id.to_hex(),
+        commitment_number
+    )
+}
+
+fn queue_key(tower_pubkey: &PublicKey, hint: &JusticeHint) -> String {
+    format!("{}/{}_{}", TOWER_QUEUE_PREFIX, tower_pubkey, hint.to_hex())
+}
+
+// Sends everything queued for `tower` over one connection, dropping each
+// blob from the queue once the tower acks it
+fn flush_queue(
+    tower: &TowerConfig,
+    store: &dyn KVStore,
+    node_secret: &SecretKey,
+    node_id: &PublicKey,
+) -> io::Result<()> {
+    let tower_prefix = format!("{}/{}_", TOWER_QUEUE_PREFIX, tower.pubkey);
+    let queued: Vec<String> = store
+        .list(TOWER_QUEUE_PREFIX)?
+        .into_iter()
+        .filter(|key| key.starts_with(&tower_prefix))
+        .collect();
+    if queued.is_empty() {
+        return Ok(());
+    }
+
+    let addr = tower
+        .address
+        .to_socket_addrs()?
+        .next()
+        .ok_or_else(|| invalid_data(format!("Could not resolve {}", tower.address)))?;
+    let mut stream = TcpStream::connect_timeout(&addr, TOWER_TIMEOUT)?;
+    stream.set_read_timeout(Some(TOWER_TIMEOUT))?;
+    stream.set_write_timeout(Some(TOWER_TIMEOUT))?;
+
+    for key in queued {
+        let blob = match store.read(&key)? {
+            Some(blob) => blob,
+            None => continue,
+        };
+        let hint = Vec::<u8>::from_hex(&key[tower_prefix.len()..])
+            .ok()
+            .and_then(|hint| JusticeHint::try_from(hint).ok())
+            .ok_or_else(|| invalid_data(format!("Invalid queue key {}", key)))?;
+        let msg = TowerMessage::Justice { hint, blob };
+        write_message(&mut stream, &msg, node_secret, node_id, &tower.pubkey)?;
+
+        let mut ack = [0; 1];
+        stream.read_exact(&mut ack)?;
+        if ack[0] != ACK_OK {
+            return Err(io::Error::other("Tower rejected a justice tx"));
+        }
+        store.remove(&key)?;
+    }
+    Ok(())
+}
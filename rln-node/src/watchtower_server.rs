@@ -0,0 +1,360 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io::{self, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use bitcoincore_rpc::bitcoin::hashes::hex::{FromHex, ToHex};
+use bitcoincore_rpc::bitcoin::hashes::Hash;
+use bitcoincore_rpc::bitcoin::secp256k1::{PublicKey, Secp256k1, SecretKey};
+use bitcoincore_rpc::bitcoin::{BlockHash, BlockHeader, Network};
+use lightning::chain::keysinterface::{KeysInterface, Recipient};
+use lightning::chain::transaction::TransactionData;
+use lightning::chain::{self, Listen};
+use lightning_block_sync::init::synchronize_listeners;
+use lightning_block_sync::{poll, SpvClient, UnboundedCache};
+
+use crate::bitcoin_client::{read_bitcoind_config, BitcoindClient};
+use crate::keys_manager::get_keys_manager;
+use crate::kv_store::{invalid_data, KVStore, JUSTICE_PREFIX, TOWER_BEST_BLOCK_KEY};
+use crate::logger::{rln_error, rln_info, RLNLogger};
+use crate::persister::open_store;
+use crate::watchtower_wire::{
+    decrypt_justice_tx, justice_hint, read_message, JusticeHint, TowerMessage, ACK_ERR, ACK_OK,
+};
+
+// A justice tx past the 400k weight standardness limit couldn't be broadcast
+// anyway, and can't take more bytes than weight. On top of it the blob has
+// its nonce and tag.
+const MAX_BLOB_LEN: usize = 400_000 + 12 + 16;
+
+// The tower can't tell when a client's channels close, so without a cap a
+// client could fill its disk
+const MAX_BLOBS_PER_CLIENT: usize = 50_000;
+
+// Holds encrypted justice transactions from its clients and checks every
+// transaction in new blocks against their hints. A match means a revoked
+// commitment confirmed: its txid opens the blob and the justice transaction
+// inside goes out through `BitcoindClient`. The tower never sees channel
+// keys or state, only blobs it can't read until they're needed.
+pub struct Watchtower {
+    blobs: Mutex<HeldBlobs>,
+    store: Arc<dyn KVStore>,
+    bitcoind_client: Arc<BitcoindClient>,
+    logger: Arc<RLNLogger>,
+}
+
+#[derive(Default)]
+struct HeldBlobs {
+    // Clients are kept apart so one can't overwrite another's blob
+    by_hint: HashMap<JusticeHint, HashMap<PublicKey, Vec<u8>>>,
+    per_client: HashMap<PublicKey, usize>,
+}
+
+impl HeldBlobs {
+    fn insert(&mut self, hint: JusticeHint, node_id: PublicKey, blob: Vec<u8>) {
+        if self
+            .by_hint
+            .entry(hint)
+            .or_default()
+            .insert(node_id, blob)
+            .is_none()
+        {
+            *self.per_client.entry(node_id).or_insert(0) += 1;
+        }
+    }
+
+    fn holds(&self, hint: &JusticeHint, node_id: &PublicKey) -> bool {
+        self.by_hint
+            .get(hint)
+            .map(|clients| clients.contains_key(node_id))
+            .unwrap_or(false)
+    }
+}
+
+impl Watchtower {
+    fn load(
+        store: Arc<dyn KVStore>,
+        bitcoind_client: Arc<BitcoindClient>,
+        logger: Arc<RLNLogger>,
+    ) -> io::Result<Self> {
+        let mut blobs = HeldBlobs::default();
+        for key in store.list(JUSTICE_PREFIX)? {
+            let (hint, node_id) = parse_justice_key(&key)?;
+            if let Some(blob) = store.read(&key)? {
+                blobs.insert(hint, node_id, blob);
+            }
+        }
+        Ok(Self {
+            blobs: Mutex::new(blobs),
+            store,
+            bitcoind_client,
+            logger,
+        })
+    }
+
+    // Where the tower left off, `None` on first start
+    fn best_block(&self) -> io::Result<Option<BlockHash>> {
+        match self.store.read(TOWER_BEST_BLOCK_KEY)? {
+            Some(bytes) => BlockHash::from_slice(&bytes)
+                .map(Some)
+                .map_err(|e| invalid_data(e.to_string())),
+            None => Ok(None),
+        }
+    }
+
+    fn handle_message(&self, node_id: &PublicKey, msg: TowerMessage) -> io::Result<()> {
+        match msg {
+            TowerMessage::Justice { hint, blob } => {
+                if blob.len() > MAX_BLOB_LEN {
+                    return Err(invalid_data(format!(
+                        "Justice blob of {} bytes, at most {} allowed",
+                        blob.len(),
+                        MAX_BLOB_LEN
+                    )));
+                }
+                // Held across the write so two connections from one client
+                // can't both slip in under the cap
+                let mut blobs = self.blobs.lock().unwrap();
+                let held = blobs.per_client.get(node_id).copied().unwrap_or(0);
+                if held >= MAX_BLOBS_PER_CLIENT && !blobs.holds(&hint, node_id) {
+                    return Err(invalid_data(format!(
+                        "{} already has {} justice txs held",
+                        node_id, MAX_BLOBS_PER_CLIENT
+                    )));
+                }
+
+                self.store.write(&justice_key(&hint, node_id), &blob)?;
+                rln_info!(
+                    self.logger,
+                    "Holding justice tx {} for {}",
+                    hint.to_hex(),
+                    node_id
+                );
+                blobs.insert(hint, *node_id, blob);
+                Ok(())
+            }
+        }
+    }
+
+    fn handle_connection(&self, mut stream: TcpStream, tower_secret: &SecretKey) {
+        loop {
+            let (node_id, msg) = match read_message(&mut stream, tower_secret) {
+                Ok(res) => res,
+                Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return,
+                Err(e) => {
+                    rln_error!(self.logger, "Dropping tower connection: {}", e);
+                    let _ = stream.write_all(&[ACK_ERR]);
+                    return;
+                }
+            };
+            let ack = match self.handle_message(&node_id, msg) {
+                Ok(()) => ACK_OK,
+                Err(e) => {
+                    rln_error!(self.logger, "Rejected message from {}: {}", node_id, e);
+                    ACK_ERR
+                }
+            };
+            if stream.write_all(&[ack]).is_err() {
+                return;
+            }
+        }
+    }
+}
+
+impl Listen for Watchtower {
+    fn filtered_block_connected(
+        &self,
+        header: &BlockHeader,
+        txdata: &TransactionData,
+        height: u32,
+    ) {
+        let blobs = self.blobs.lock().unwrap();
+        for (_, tx) in txdata.iter() {
+            let txid = tx.txid();
+            let clients = match blobs.by_hint.get(&justice_hint(&txid)) {
+                Some(clients) => clients,
+                None => continue,
+            };
+            for (node_id, blob) in clients.iter() {
+                // Anyone can claim any hint, only a blob that opens under the
+                // txid and spends from it is the real thing
+                let justice_tx = match decrypt_justice_tx(&txid, blob) {
+                    Ok(justice_tx) => justice_tx,
+                    Err(e) => {
+                        rln_error!(self.logger, "Bad blob from {} for {}: {}", node_id, txid, e);
+                        continue;
+                    }
+                };
+                if !justice_tx
+                    .input
+                    .iter()
+                    .all(|input| input.previous_output.txid == txid)
+                {
+                    rln_error!(
+                        self.logger,
+                        "Blob from {} for {} spends something else",
+                        node_id,
+                        txid
+                    );
+                    continue;
+                }
+                match self.bitcoind_client.try_broadcast_transaction(&justice_tx) {
+                    Ok(justice_txid) => rln_info!(
+                        self.logger,
+                        "Revoked commitment {} confirmed at {}, broadcast justice tx {} for {}",
+                        txid,
+                        height,
+                        justice_txid,
+                        node_id
+                    ),
+                    Err(e) => rln_error!(
+                        self.logger,
+                        "Failed to broadcast justice tx for {}: {}",
+                        txid,
+                        e
+                    ),
+                }
+            }
+        }
+        drop(blobs);
+
+        if let Err(e) = self
+            .store
+            .write(TOWER_BEST_BLOCK_KEY, &header.block_hash().into_inner())
+        {
+            rln_error!(self.logger, "Failed to persist best block: {}", e);
+        }
+    }
+
+    fn block_disconnected(&self, header: &BlockHeader, _height: u32) {
+        if let Err(e) = self
+            .store
+            .write(TOWER_BEST_BLOCK_KEY, &header.prev_blockhash.into_inner())
+        {
+            rln_error!(self.logger, "Failed to persist best block: {}", e);
+        }
+    }
+}
+
+// `justice/<hint>_<client node id>`
+fn justice_key(hint: &JusticeHint, node_id: &PublicKey) -> String {
+    format!("{}/{}_{}", JUSTICE_PREFIX, hint.to_hex(), node_id)
+}
+
+fn parse_justice_key(key: &str) -> io::Result<(JusticeHint, PublicKey)> {
+    let invalid = || invalid_data(format!("Invalid justice key {}", key));
+    let (hint, node_id) = key
+        .trim_start_matches(&format!("{}/", JUSTICE_PREFIX))
+        .split_once('_')
+        .ok_or_else(invalid)?;
+    let hint = Vec::<u8>::from_hex(hint)
+        .ok()
+        .and_then(|hint| JusticeHint::try_from(hint).ok())
+        .ok_or_else(invalid)?;
+    let node_id = node_id.parse::<PublicKey>().map_err(|_| invalid())?;
+    Ok((hint, node_id))
+}
+
+// Runs a tower until the process exits. Clients need the logged tower
+// pubkey along with `listen_addr` in their `towers` file.
+pub async fn start_tower(tower_dir: &str, listen_addr: &str) {
+    fs::create_dir_all(tower_dir).expect("Failed to create tower dir");
+
+    let bitcoind_config = read_bitcoind_config(tower_dir).expect("Failed to read bitcoind file");
+    let bitcoind_client = Arc::new(BitcoindClient::new(&bitcoind_config));
+    let logger = Arc::new(RLNLogger);
+    // Only the tower's own identity, for the encrypted connections
+    let tower_secret = get_keys_manager(tower_dir)
+        .get_node_secret(Recipient::Node)
+        .unwrap();
+    let tower_pubkey = PublicKey::from_secret_key(&Secp256k1::new(), &tower_secret);
+
+    let store = open_store(tower_dir).expect("Failed to open tower store");
+    let watchtower = Arc::new(
+        Watchtower::load(store, bitcoind_client.clone(), logger.clone())
+            .expect("Failed to read stored justice txs"),
+    );
+
+    // Go over the blocks mined while the tower was down
+    let best_block = watchtower
+        .best_block()
+        .expect("Failed to read best block")
+        .unwrap_or_else(|| bitcoind_client.get_best_blockhash());
+    let mut cache = UnboundedCache::new();
+    let chain_tip = synchronize_listeners(
+        bitcoind_client.clone(),
+        Network::Regtest,
+        &mut cache,
+        vec![(best_block, &*watchtower as &dyn chain::Listen)],
+    )
+    .await
+    .unwrap();
+
+    // Accept justice txs from clients
+    let listener = TcpListener::bind(listen_addr).expect("Failed to bind tower listener");
+    rln_info!(
+        logger,
+        "Watchtower {} listening on {}",
+        tower_pubkey,
+        listen_addr
+    );
+    let watchtower_listener = watchtower.clone();
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let stream = match stream {
+                Ok(stream) => stream,
+                Err(_) => continue,
+            };
+            let watchtower = watchtower_listener.clone();
+            thread::spawn(move || watchtower.handle_connection(stream, &tower_secret));
+        }
+    });
+
+    // Follow the chain, looking for revoked commitments
+    let chain_poller = poll::ChainPoller::new(bitcoind_client, Network::Regtest);
+    let mut spv_client = SpvClient::new(chain_tip, chain_poller, &mut cache, &*watchtower);
+    loop {
+        spv_client.poll_best_tip().await.unwrap();
+        tokio::time::sleep(Duration::from_secs(1)).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::watchtower_wire::HINT_LEN;
+
+    fn node_id(byte: u8) -> PublicKey {
+        let secret = SecretKey::from_slice(&[byte; 32]).unwrap();
+        PublicKey::from_secret_key(&Secp256k1::new(), &secret)
+    }
+
+    #[test]
+    fn counts_blobs_per_client() {
+        let (alice, bob) = (node_id(1), node_id(2));
+        let mut blobs = HeldBlobs::default();
+        blobs.insert([1; HINT_LEN], alice, vec![1]);
+        blobs.insert([2; HINT_LEN], alice, vec![2]);
+        // Same hint from another client is its own blob
+        blobs.insert([1; HINT_LEN], bob, vec![3]);
+        // A client replacing its own blob doesn't hold more
+        blobs.insert([1; HINT_LEN], alice, vec![4]);
+
+        assert_eq!(blobs.per_client[&alice], 2);
+        assert_eq!(blobs.per_client[&bob], 1);
+        assert_eq!(blobs.by_hint[&[1; HINT_LEN]][&alice], vec![4]);
+        assert!(blobs.holds(&[2; HINT_LEN], &alice));
+        assert!(!blobs.holds(&[2; HINT_LEN], &bob));
+    }
+
+    #[test]
+    fn justice_keys_round_trip() {
+        let hint = [7; HINT_LEN];
+        let key = justice_key(&hint, &node_id(3));
+        assert_eq!(parse_justice_key(&key).unwrap(), (hint, node_id(3)));
+        assert!(parse_justice_key("justice/nothex_02").is_err());
+    }
+}
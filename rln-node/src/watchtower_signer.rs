@@ -0,0 +1,424 @@
+use std::sync::Arc;
+
+use bitcoincore_rpc::bitcoin::bech32::u5;
+use bitcoincore_rpc::bitcoin::secp256k1::ecdh::SharedSecret;
+use bitcoincore_rpc::bitcoin::secp256k1::ecdsa::{RecoverableSignature, Signature};
+use bitcoincore_rpc::bitcoin::secp256k1::{self, PublicKey, Scalar, Secp256k1, SecretKey};
+use bitcoincore_rpc::bitcoin::{
+    EcdsaSighashType, OutPoint, PackedLockTime, Script, Sequence, Transaction, TxIn, TxOut, Witness,
+};
+use lightning::chain::chaininterface::{ConfirmationTarget, FeeEstimator};
+use lightning::chain::keysinterface::{
+    BaseSign, InMemorySigner, KeyMaterial, KeysInterface, KeysManager, Recipient, Sign,
+};
+use lightning::ln::chan_utils::{
+    get_htlc_redeemscript, get_revokeable_redeemscript, ChannelPublicKeys,
+    ChannelTransactionParameters, ClosingTransaction, CommitmentTransaction,
+    HTLCOutputInCommitment, HolderCommitmentTransaction,
+};
+use lightning::ln::msgs::{DecodeError, UnsignedChannelAnnouncement};
+use lightning::ln::script::ShutdownScript;
+use lightning::ln::PaymentPreimage;
+use lightning::util::ser::{Writeable, Writer};
+
+use crate::bitcoin_client::BitcoindClient;
+use crate::watchtower_client::WatchtowerClient;
+
+// Below this the justice transaction isn't worth its fee
+const JUSTICE_DUST_LIMIT_SAT: u64 = 546;
+
+// `InMemorySigner` that also prepares what watchtowers need. Every
+// counterparty commitment it signs is kept until the counterparty revokes it,
+// then the revocation secret goes into a signed justice transaction for the
+// towers. Nothing else leaves the node, so a tower can punish a cheater but
+// never touch the channel.
+#[derive(Clone)]
+pub struct WatchtowerSigner {
+    inner: InMemorySigner,
+    client: Arc<WatchtowerClient>,
+    destination_script: Script,
+    fee_estimator: Arc<BitcoindClient>,
+}
+
+impl WatchtowerSigner {
+    // Commitments are kept by channel id, what `Event::ChannelClosed` tells
+    // the client to drop. Counterparty commitments only get signed once the
+    // funding outpoint is known.
+    fn channel_id(&self) -> [u8; 32] {
+        self.inner.funding_outpoint().to_channel_id()
+    }
+
+    // Sweeps every output of a revoked counterparty commitment the revocation
+    // key unlocks, their balance and any HTLCs in flight, to our destination
+    // script. `None` when there is nothing worth sweeping.
+    fn build_justice_tx(
+        &self,
+        commitment_tx: &CommitmentTransaction,
+        per_commitment_key: &SecretKey,
+    ) -> Option<Transaction> {
+        let secp_ctx = Secp256k1::new();
+        let trusted = commitment_tx.trust();
+        let keys = trusted.keys();
+        let commitment_txid = trusted.txid();
+        let outputs = &trusted.built_transaction().transaction.output;
+
+        let to_local_script = get_revokeable_redeemscript(
+            &keys.revocation_key,
+            self.inner.holder_selected_contest_delay(),
+            &keys.broadcaster_delayed_payment_key,
+        );
+        let to_local_spk = to_local_script.to_v0_p2wsh();
+
+        // (output index, amount, witness script, HTLC)
+        let mut inputs: Vec<(u32, u64, Script, Option<&HTLCOutputInCommitment>)> = Vec::new();
+        if let Some(vout) = outputs.iter().position(|o| o.script_pubkey == to_local_spk) {
+            inputs.push((vout as u32, outputs[vout].value, to_local_script, None));
+        }
+        for htlc in commitment_tx.htlcs() {
+            if let Some(vout) = htlc.transaction_output_index {
+                let script = get_htlc_redeemscript(htlc, trusted.opt_anchors(), keys);
+                inputs.push((vout, outputs[vout as usize].value, script, Some(htlc)));
+            }
+        }
+        if inputs.is_empty() {
+            return None;
+        }
+
+        // Placeholder witnesses of the final size to work out the fee
+        let mut justice_tx = Transaction {
+            version: 2,
+            lock_time: PackedLockTime::ZERO,
+            input: inputs
+                .iter()
+                .map(|(vout, _, script, htlc)| TxIn {
+                    previous_output: OutPoint {
+                        txid: commitment_txid,
+                        vout: *vout,
+                    },
+                    script_sig: Script::new(),
+                    sequence: Sequence::ENABLE_RBF_NO_LOCKTIME,
+                    witness: Witness::from_vec(vec![
+                        vec![0; 73],
+                        revocation_witness_item(keys.revocation_key, htlc.is_some()),
+                        script.to_bytes(),
+                    ]),
+                })
+                .collect(),
+            output: vec![TxOut {
+                value: 0,
+                script_pubkey: self.destination_script.clone(),
+            }],
+        };
+        let total: u64 = inputs.iter().map(|(_, amount, _, _)| amount).sum();
+        let feerate =
+            self.fee_estimator
+                .get_est_sat_per_1000_weight(ConfirmationTarget::HighPriority) as u64;
+        let fee = justice_tx.weight() as u64 * feerate / 1000;
+        justice_tx.output[0].value = total
+            .checked_sub(fee)
+            .filter(|value| *value >= JUSTICE_DUST_LIMIT_SAT)?;
+
+        for (i, (_, amount, script, htlc)) in inputs.iter().enumerate() {
+            let sig = match htlc {
+                None => self.inner.sign_justice_revoked_output(
+                    &justice_tx,
+                    i,
+                    *amount,
+                    per_commitment_key,
+                    &secp_ctx,
+                ),
+                Some(htlc) => self.inner.sign_justice_revoked_htlc(
+                    &justice_tx,
+                    i,
+                    *amount,
+                    per_commitment_key,
+                    htlc,
+                    &secp_ctx,
+                ),
+            }
+            .ok()?;
+            let mut sig = sig.serialize_der().to_vec();
+            sig.push(EcdsaSighashType::All as u8);
+            justice_tx.input[i].witness = Witness::from_vec(vec![
+                sig,
+                revocation_witness_item(keys.revocation_key, htlc.is_some()),
+                script.to_bytes(),
+            ]);
+        }
+        Some(justice_tx)
+    }
+}
+
+// The revocation branch of `to_local` is picked with a true value, HTLC
+// scripts check for the revocation pubkey instead
+fn revocation_witness_item(revocation_key: PublicKey, htlc: bool) -> Vec<u8> {
+    if htlc {
+        revocation_key.serialize().to_vec()
+    } else {
+        vec![1]
+    }
+}
+
+impl BaseSign for WatchtowerSigner {
+    fn get_per_commitment_point(
+        &self,
+        idx: u64,
+        secp_ctx: &Secp256k1<secp256k1::All>,
+    ) -> PublicKey {
+        self.inner.get_per_commitment_point(idx, secp_ctx)
+    }
+
+    fn release_commitment_secret(&self, idx: u64) -> [u8; 32] {
+        self.inner.release_commitment_secret(idx)
+    }
+
+    fn validate_holder_commitment(
+        &self,
+        holder_tx: &HolderCommitmentTransaction,
+        preimages: Vec<PaymentPreimage>,
+    ) -> Result<(), ()> {
+        self.inner.validate_holder_commitment(holder_tx, preimages)
+    }
+
+    fn pubkeys(&self) -> &ChannelPublicKeys {
+        self.inner.pubkeys()
+    }
+
+    fn channel_keys_id(&self) -> [u8; 32] {
+        self.inner.channel_keys_id()
+    }
+
+    fn sign_counterparty_commitment(
+        &self,
+        commitment_tx: &CommitmentTransaction,
+        preimages: Vec<PaymentPreimage>,
+        secp_ctx: &Secp256k1<secp256k1::All>,
+    ) -> Result<(Signature, Vec<Signature>), ()> {
+        let sigs = self
+            .inner
+            .sign_counterparty_commitment(commitment_tx, preimages, secp_ctx)?;
+        if self.client.is_enabled() {
+            self.client
+                .commitment_signed(&self.channel_id(), commitment_tx);
+        }
+        Ok(sigs)
+    }
+
+    fn validate_counterparty_revocation(&self, idx: u64, secret: &SecretKey) -> Result<(), ()> {
+        self.inner.validate_counterparty_revocation(idx, secret)?;
+        if !self.client.is_enabled() {
+            return Ok(());
+        }
+        let channel_id = self.channel_id();
+        if let Some(commitment_tx) = self.client.signed_commitment(&channel_id, idx) {
+            if let Some(justice_tx) = self.build_justice_tx(&commitment_tx, secret) {
+                self.client
+                    .queue_justice_tx(&commitment_tx.trust().txid(), &justice_tx);
+            }
+            self.client.forget_commitment(&channel_id, idx);
+        }
+        Ok(())
+    }
+
+    fn sign_holder_commitment_and_htlcs(
+        &self,
+        commitment_tx: &HolderCommitmentTransaction,
+        secp_ctx: &Secp256k1<secp256k1::All>,
+    ) -> Result<(Signature, Vec<Signature>), ()> {
+        self.inner
+            .sign_holder_commitment_and_htlcs(commitment_tx, secp_ctx)
+    }
+
+    fn sign_justice_revoked_output(
+        &self,
+        justice_tx: &Transaction,
+        input: usize,
+        amount: u64,
+        per_commitment_key: &SecretKey,
+        secp_ctx: &Secp256k1<secp256k1::All>,
+    ) -> Result<Signature, ()> {
+        self.inner.sign_justice_revoked_output(
+            justice_tx,
+            input,
+            amount,
+            per_commitment_key,
+            secp_ctx,
+        )
+    }
+
+    fn sign_justice_revoked_htlc(
+        &self,
+        justice_tx: &Transaction,
+        input: usize,
+        amount: u64,
+        per_commitment_key: &SecretKey,
+        htlc: &HTLCOutputInCommitment,
+        secp_ctx: &Secp256k1<secp256k1::All>,
+    ) -> Result<Signature, ()> {
+        self.inner.sign_justice_revoked_htlc(
+            justice_tx,
+            input,
+            amount,
+            per_commitment_key,
+            htlc,
+            secp_ctx,
+        )
+    }
+
+    fn sign_counterparty_htlc_transaction(
+        &self,
+        htlc_tx: &Transaction,
+        input: usize,
+        amount: u64,
+        per_commitment_point: &PublicKey,
+        htlc: &HTLCOutputInCommitment,
+        secp_ctx: &Secp256k1<secp256k1::All>,
+    ) -> Result<Signature, ()> {
+        self.inner.sign_counterparty_htlc_transaction(
+            htlc_tx,
+            input,
+            amount,
+            per_commitment_point,
+            htlc,
+            secp_ctx,
+        )
+    }
+
+    fn sign_closing_transaction(
+        &self,
+        closing_tx: &ClosingTransaction,
+        secp_ctx: &Secp256k1<secp256k1::All>,
+    ) -> Result<Signature, ()> {
+        self.inner.sign_closing_transaction(closing_tx, secp_ctx)
+    }
+
+    fn sign_holder_anchor_input(
+        &self,
+        anchor_tx: &Transaction,
+        input: usize,
+        secp_ctx: &Secp256k1<secp256k1::All>,
+    ) -> Result<Signature, ()> {
+        self.inner
+            .sign_holder_anchor_input(anchor_tx, input, secp_ctx)
+    }
+
+    fn sign_channel_announcement(
+        &self,
+        msg: &UnsignedChannelAnnouncement,
+        secp_ctx: &Secp256k1<secp256k1::All>,
+    ) -> Result<(Signature, Signature), ()> {
+        self.inner.sign_channel_announcement(msg, secp_ctx)
+    }
+
+    fn provide_channel_parameters(&mut self, channel_parameters: &ChannelTransactionParameters) {
+        self.inner.provide_channel_parameters(channel_parameters)
+    }
+}
+
+// Serialized exactly like `InMemorySigner`, so monitors written before the
+// towers were set up read back fine
+impl Writeable for WatchtowerSigner {
+    fn write<W: Writer>(&self, writer: &mut W) -> Result<(), std::io::Error> {
+        self.inner.write(writer)
+    }
+}
+
+impl Sign for WatchtowerSigner {}
+
+// `KeysManager` handing out `WatchtowerSigner`s. Everything but the channel
+// signers comes straight from the wrapped manager.
+pub struct WatchtowerKeysManager {
+    inner: Arc<KeysManager>,
+    client: Arc<WatchtowerClient>,
+    fee_estimator: Arc<BitcoindClient>,
+}
+
+impl WatchtowerKeysManager {
+    pub fn new(
+        inner: Arc<KeysManager>,
+        client: Arc<WatchtowerClient>,
+        fee_estimator: Arc<BitcoindClient>,
+    ) -> Self {
+        Self {
+            inner,
+            client,
+            fee_estimator,
+        }
+    }
+
+    fn wrap(&self, inner: InMemorySigner) -> WatchtowerSigner {
+        WatchtowerSigner {
+            inner,
+            client: self.client.clone(),
+            destination_script: self.inner.get_destination_script(),
+            fee_estimator: self.fee_estimator.clone(),
+        }
+    }
+}
+
+impl KeysInterface for WatchtowerKeysManager {
+    type Signer = WatchtowerSigner;
+
+    fn get_node_secret(&self, recipient: Recipient) -> Result<SecretKey, ()> {
+        self.inner.get_node_secret(recipient)
+    }
+
+    fn ecdh(
+        &self,
+        recipient: Recipient,
+        other_key: &PublicKey,
+        tweak: Option<&Scalar>,
+    ) -> Result<SharedSecret, ()> {
+        self.inner.ecdh(recipient, other_key, tweak)
+    }
+
+    fn get_destination_script(&self) -> Script {
+        self.inner.get_destination_script()
+    }
+
+    fn get_shutdown_scriptpubkey(&self) -> ShutdownScript {
+        self.inner.get_shutdown_scriptpubkey()
+    }
+
+    fn generate_channel_keys_id(
+        &self,
+        inbound: bool,
+        channel_value_satoshis: u64,
+        user_channel_id: u128,
+    ) -> [u8; 32] {
+        self.inner
+            .generate_channel_keys_id(inbound, channel_value_satoshis, user_channel_id)
+    }
+
+    fn derive_channel_signer(
+        &self,
+        channel_value_satoshis: u64,
+        channel_keys_id: [u8; 32],
+    ) -> Self::Signer {
+        self.wrap(
+            self.inner
+                .derive_channel_signer(channel_value_satoshis, channel_keys_id),
+        )
+    }
+
+    fn get_secure_random_bytes(&self) -> [u8; 32] {
+        self.inner.get_secure_random_bytes()
+    }
+
+    fn read_chan_signer(&self, reader: &[u8]) -> Result<Self::Signer, DecodeError> {
+        Ok(self.wrap(self.inner.read_chan_signer(reader)?))
+    }
+
+    fn sign_invoice(
+        &self,
+        hrp_bytes: &[u8],
+        invoice_data: &[u5],
+        recipient: Recipient,
+    ) -> Result<RecoverableSignature, ()> {
+        self.inner.sign_invoice(hrp_bytes, invoice_data, recipient)
+    }
+
+    fn get_inbound_payment_key_material(&self) -> KeyMaterial {
+        self.inner.get_inbound_payment_key_material()
+    }
+}
@@ -0,0 +1,147 @@
+use std::io::{self, Read, Write};
+
+use bitcoincore_rpc::bitcoin::consensus::encode::{deserialize, serialize};
+use bitcoincore_rpc::bitcoin::hashes::{sha256, Hash};
+use bitcoincore_rpc::bitcoin::secp256k1::ecdh::SharedSecret;
+use bitcoincore_rpc::bitcoin::secp256k1::rand::{thread_rng, RngCore};
+use bitcoincore_rpc::bitcoin::secp256k1::{PublicKey, SecretKey};
+use bitcoincore_rpc::bitcoin::{Transaction, Txid};
+use chacha20poly1305::aead::{Aead, KeyInit};
+use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce};
+
+use crate::kv_store::invalid_data;
+
+// Frames are `client pubkey (33) | nonce (12) | len (4) | ciphertext`.
+// The key is the ECDH secret between the client node key and the tower key,
+// so only the tower a blob was addressed to can read it.
+const MAX_FRAME_LEN: usize = 16 * 1024 * 1024;
+
+pub const ACK_OK: u8 = 0;
+pub const ACK_ERR: u8 = 1;
+
+const JUSTICE: u8 = 0;
+
+pub const HINT_LEN: usize = 16;
+pub type JusticeHint = [u8; HINT_LEN];
+
+#[derive(Clone, Debug)]
+pub enum TowerMessage {
+    // Justice transaction for one revoked counterparty commitment, encrypted
+    // under the commitment txid. The tower only learns the txid, and with it
+    // what the transaction does, once the revoked commitment confirms.
+    Justice { hint: JusticeHint, blob: Vec<u8> },
+}
+
+impl TowerMessage {
+    fn encode(&self) -> Vec<u8> {
+        match self {
+            TowerMessage::Justice { hint, blob } => {
+                let mut bytes = Vec::with_capacity(1 + HINT_LEN + blob.len());
+                bytes.push(JUSTICE);
+                bytes.extend_from_slice(hint);
+                bytes.extend_from_slice(blob);
+                bytes
+            }
+        }
+    }
+
+    fn decode(bytes: &[u8]) -> io::Result<Self> {
+        if bytes.len() < 1 + HINT_LEN {
+            return Err(invalid_data("Tower message too short".to_owned()));
+        }
+        match bytes[0] {
+            JUSTICE => {
+                let mut hint = [0; HINT_LEN];
+                hint.copy_from_slice(&bytes[1..1 + HINT_LEN]);
+                Ok(TowerMessage::Justice {
+                    hint,
+                    blob: bytes[1 + HINT_LEN..].to_vec(),
+                })
+            }
+            kind => Err(invalid_data(format!("Unknown tower message type {}", kind))),
+        }
+    }
+}
+
+// What the tower looks transactions up by. Hashing keeps the txid, which is
+// the blob key, out of the tower's hands.
+pub fn justice_hint(commitment_txid: &Txid) -> JusticeHint {
+    let hash = sha256::Hash::hash(&commitment_txid[..]);
+    let mut hint = [0; HINT_LEN];
+    hint.copy_from_slice(&hash[..HINT_LEN]);
+    hint
+}
+
+// Blobs are `nonce (12) | ciphertext`
+pub fn encrypt_justice_tx(commitment_txid: &Txid, justice_tx: &Transaction) -> Vec<u8> {
+    let mut nonce = [0; 12];
+    thread_rng().fill_bytes(&mut nonce);
+    let ciphertext = ChaCha20Poly1305::new(Key::from_slice(&commitment_txid[..]))
+        .encrypt(Nonce::from_slice(&nonce), serialize(justice_tx).as_slice())
+        .expect("Failed to encrypt justice tx");
+    let mut blob = nonce.to_vec();
+    blob.extend_from_slice(&ciphertext);
+    blob
+}
+
+pub fn decrypt_justice_tx(commitment_txid: &Txid, blob: &[u8]) -> io::Result<Transaction> {
+    if blob.len() < 12 {
+        return Err(invalid_data("Justice blob too short".to_owned()));
+    }
+    let plaintext = ChaCha20Poly1305::new(Key::from_slice(&commitment_txid[..]))
+        .decrypt(Nonce::from_slice(&blob[..12]), &blob[12..])
+        .map_err(|_| invalid_data(format!("Blob does not decrypt under {}", commitment_txid)))?;
+    deserialize(&plaintext).map_err(|e| invalid_data(e.to_string()))
+}
+
+fn cipher(their_pubkey: &PublicKey, our_secret: &SecretKey) -> ChaCha20Poly1305 {
+    let shared_secret = SharedSecret::new(their_pubkey, our_secret);
+    ChaCha20Poly1305::new(Key::from_slice(&shared_secret.secret_bytes()))
+}
+
+pub fn write_message<W: Write>(
+    writer: &mut W,
+    msg: &TowerMessage,
+    node_secret: &SecretKey,
+    node_id: &PublicKey,
+    tower_pubkey: &PublicKey,
+) -> io::Result<()> {
+    let mut nonce = [0; 12];
+    thread_rng().fill_bytes(&mut nonce);
+    let ciphertext = cipher(tower_pubkey, node_secret)
+        .encrypt(Nonce::from_slice(&nonce), msg.encode().as_slice())
+        .map_err(|_| invalid_data("Failed to encrypt tower message".to_owned()))?;
+
+    writer.write_all(&node_id.serialize())?;
+    writer.write_all(&nonce)?;
+    writer.write_all(&(ciphertext.len() as u32).to_be_bytes())?;
+    writer.write_all(&ciphertext)?;
+    writer.flush()
+}
+
+// Returns the sender's node id along with the decrypted message
+pub fn read_message<R: Read>(
+    reader: &mut R,
+    tower_secret: &SecretKey,
+) -> io::Result<(PublicKey, TowerMessage)> {
+    let mut node_id = [0; 33];
+    reader.read_exact(&mut node_id)?;
+    let node_id = PublicKey::from_slice(&node_id).map_err(|e| invalid_data(e.to_string()))?;
+
+    let mut nonce = [0; 12];
+    reader.read_exact(&mut nonce)?;
+
+    let mut len = [0; 4];
+    reader.read_exact(&mut len)?;
+    let len = u32::from_be_bytes(len) as usize;
+    if len > MAX_FRAME_LEN {
+        return Err(invalid_data(format!("Tower frame too large: {}", len)));
+    }
+    let mut ciphertext = vec![0; len];
+    reader.read_exact(&mut ciphertext)?;
+
+    let plaintext = cipher(&node_id, tower_secret)
+        .decrypt(Nonce::from_slice(&nonce), ciphertext.as_slice())
+        .map_err(|_| invalid_data(format!("Failed to decrypt message from {}", node_id)))?;
+    Ok((node_id, TowerMessage::decode(&plaintext)?))
+}
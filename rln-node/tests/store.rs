@@ -0,0 +1,181 @@
+// Round trips through both stores, and moving a node's files into SQLite
+use std::fs;
+
+use rlnnode::kv_store::{
+    FilesystemStore, KVStore, PaymentRecord, PaymentStatus, PeerRecord, MANAGER_KEY,
+    MONITORS_PREFIX, NETWORK_GRAPH_KEY, TOWER_COMMITMENTS_PREFIX,
+};
+use lightning::ln::PaymentHash;
+use rlnnode::persister::{migrate_to_sqlite, open_store};
+use rlnnode::sqlite_store::{SqliteStore, SQLITE_DB_FILE};
+
+mod common;
+use common::TempDir;
+
+fn payments() -> Vec<PaymentRecord> {
+    vec![
+        PaymentRecord {
+            payment_hash: "aa".repeat(32),
+            inbound: true,
+            status: PaymentStatus::Succeeded,
+            amount_msat: Some(1_000),
+            preimage: Some("bb".repeat(32)),
+        },
+        PaymentRecord {
+            payment_hash: "cc".repeat(32),
+            inbound: false,
+            status: PaymentStatus::Pending,
+            amount_msat: None,
+            preimage: None,
+        },
+    ]
+}
+
+fn peers() -> Vec<PeerRecord> {
+    vec![PeerRecord {
+        pubkey: format!("02{}", "dd".repeat(32)),
+        address: "127.0.0.1:9735".to_owned(),
+    }]
+}
+
+fn round_trip(store: &dyn KVStore) {
+    assert_eq!(store.read(MANAGER_KEY).unwrap(), None);
+    store.write(MANAGER_KEY, b"manager").unwrap();
+    store.write(MANAGER_KEY, b"manager v2").unwrap();
+    assert_eq!(
+        store.read(MANAGER_KEY).unwrap(),
+        Some(b"manager v2".to_vec())
+    );
+
+    let monitors = vec![
+        (
+            format!("{}/{}_1", MONITORS_PREFIX, "11".repeat(32)),
+            b"one".to_vec(),
+        ),
+        (
+            format!("{}/{}_0", MONITORS_PREFIX, "00".repeat(32)),
+            b"zero".to_vec(),
+        ),
+    ];
+    store.write_all(&monitors).unwrap();
+    let keys = store.list(MONITORS_PREFIX).unwrap();
+    assert_eq!(keys, vec![monitors[1].0.clone(), monitors[0].0.clone()]);
+    // A prefix only matches whole path segments
+    assert!(store.list("monitor").unwrap().is_empty());
+
+    store.remove(&monitors[0].0).unwrap();
+    assert_eq!(store.read(&monitors[0].0).unwrap(), None);
+    assert_eq!(store.list(MONITORS_PREFIX).unwrap().len(), 1);
+    // Removing what isn't there is fine
+    store.remove(&monitors[0].0).unwrap();
+
+    for payment in payments() {
+        store.persist_payment(&payment).unwrap();
+    }
+    // Updates replace the earlier record
+    let mut sent = payments()[1].clone();
+    sent.status = PaymentStatus::Failed;
+    store.persist_payment(&sent).unwrap();
+    assert_eq!(
+        store.list_payments().unwrap(),
+        vec![payments()[0].clone(), sent.clone()]
+    );
+    assert_eq!(
+        store.read_payment(&PaymentHash([0xcc; 32])).unwrap(),
+        Some(sent)
+    );
+    assert_eq!(store.read_payment(&PaymentHash([0xee; 32])).unwrap(), None);
+
+    for peer in peers() {
+        store.persist_peer(&peer).unwrap();
+    }
+    assert_eq!(store.list_peers().unwrap(), peers());
+}
+
+#[test]
+fn filesystem_round_trip() {
+    let dir = TempDir::new();
+    round_trip(&FilesystemStore::new(dir.path()));
+    // Nothing half written left behind
+    assert!(!fs::read_dir(dir.0.join(MONITORS_PREFIX))
+        .unwrap()
+        .any(|entry| entry.unwrap().path().extension() == Some("tmp".as_ref())));
+}
+
+#[test]
+fn sqlite_round_trip() {
+    let dir = TempDir::new();
+    let db_path = dir.0.join(SQLITE_DB_FILE);
+    round_trip(&SqliteStore::open(db_path.to_str().unwrap()).unwrap());
+
+    // And it's all still there after reopening
+    let store = SqliteStore::open(db_path.to_str().unwrap()).unwrap();
+    assert_eq!(
+        store.read(MANAGER_KEY).unwrap(),
+        Some(b"manager v2".to_vec())
+    );
+    assert_eq!(store.list_payments().unwrap().len(), 2);
+    assert_eq!(store.list_peers().unwrap(), peers());
+}
+
+#[test]
+fn migrate_filesystem_to_sqlite() {
+    let dir = TempDir::new();
+    let fs_store = FilesystemStore::new(dir.path());
+    let monitor_key = format!("{}/{}_0", MONITORS_PREFIX, "00".repeat(32));
+    fs_store.write(MANAGER_KEY, b"manager").unwrap();
+    fs_store.write(NETWORK_GRAPH_KEY, b"graph").unwrap();
+    fs_store.write(&monitor_key, b"monitor").unwrap();
+    let commitment_key = format!(
+        "{}/{}_281474976710655",
+        TOWER_COMMITMENTS_PREFIX,
+        "11".repeat(32)
+    );
+    fs_store.write(&commitment_key, b"commitment").unwrap();
+    for payment in payments() {
+        fs_store.persist_payment(&payment).unwrap();
+    }
+    for peer in peers() {
+        fs_store.persist_peer(&peer).unwrap();
+    }
+
+    // Manager, graph, monitor, pending commitment, two payments and a peer
+    assert_eq!(migrate_to_sqlite(dir.path()).unwrap(), 7);
+
+    // The node picks the database from now on
+    let store = open_store(dir.path()).unwrap();
+    assert_eq!(store.read(MANAGER_KEY).unwrap(), Some(b"manager".to_vec()));
+    assert_eq!(
+        store.read(NETWORK_GRAPH_KEY).unwrap(),
+        Some(b"graph".to_vec())
+    );
+    assert_eq!(
+        store.list(MONITORS_PREFIX).unwrap(),
+        vec![monitor_key.clone()]
+    );
+    assert_eq!(store.read(&monitor_key).unwrap(), Some(b"monitor".to_vec()));
+    assert_eq!(
+        store.read(&commitment_key).unwrap(),
+        Some(b"commitment".to_vec())
+    );
+    assert_eq!(store.list_payments().unwrap(), payments());
+    assert_eq!(store.list_peers().unwrap(), peers());
+
+    // The files are left alone, and migrating twice is refused
+    assert_eq!(
+        fs_store.read(MANAGER_KEY).unwrap(),
+        Some(b"manager".to_vec())
+    );
+    assert!(migrate_to_sqlite(dir.path()).is_err());
+}
+
+#[test]
+fn open_store_without_database_uses_files() {
+    let dir = TempDir::new();
+    open_store(dir.path())
+        .unwrap()
+        .write(MANAGER_KEY, b"manager")
+        .unwrap();
+    assert!(dir.0.join(MANAGER_KEY).exists());
+    assert!(!dir.0.join(SQLITE_DB_FILE).exists());
+}
@@ -0,0 +1,187 @@
+// A tower punishing a revoked commitment, end to end on regtest.
+//
+// Runs on a bitcoind of its own, with the `default` wallet funding the
+// channel. It needs `bitcoind` on the PATH, so it is ignored by default:
+//
+//  cargo test -p ln_node --test watchtower -- --ignored
+//
+// Alice has the tower in her `towers` file and opens a channel to Bob,
+// pushing him part of it. Bob's monitor is copied at that first state, which
+// Alice's payment to him revokes. With both nodes stopped, Bob broadcasts
+// the copied state: only the tower is left to notice, and it has to sweep
+// Bob's balance to Alice.
+use std::fs;
+use std::io::Cursor;
+use std::net::TcpListener;
+use std::path::Path;
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use bitcoin_basics::wallet::DEFAULT_WALLET_NAME;
+use bitcoin_basics::{BitcoinClient, TestNode};
+use bitcoincore_rpc::bitcoin::secp256k1::{PublicKey, Secp256k1};
+use bitcoincore_rpc::bitcoin::{BlockHash, Transaction};
+use bitcoincore_rpc::RpcApi;
+use lightning::chain::channelmonitor::ChannelMonitor;
+use lightning::chain::keysinterface::{InMemorySigner, KeysInterface, Recipient};
+use lightning::util::ser::ReadableArgs;
+use rlnnode::bitcoin_client::BITCOIND_FILE;
+use rlnnode::keys_manager::get_keys_manager;
+use rlnnode::kv_store::{FilesystemStore, KVStore, JUSTICE_PREFIX, MONITORS_PREFIX};
+use rlnnode::logger::RLNLogger;
+use rlnnode::node::start_node;
+use rlnnode::watchtower_client::TOWERS_FILE;
+use rlnnode::watchtower_server::start_tower;
+use tokio::runtime::Runtime;
+
+mod common;
+use common::TempDir;
+
+const CHANNEL_SAT: u64 = 1_000_000;
+const PUSH_MSAT: u64 = 400_000_000;
+const PAYMENT_MSAT: u64 = 50_000_000;
+const TIMEOUT: Duration = Duration::from_secs(120);
+
+fn free_port() -> u16 {
+    TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .port()
+}
+
+fn wait_for(what: &str, mut done: impl FnMut() -> bool) {
+    let start = Instant::now();
+    while !done() {
+        assert!(start.elapsed() < TIMEOUT, "Timed out waiting for {}", what);
+        thread::sleep(Duration::from_millis(500));
+    }
+}
+
+// Pointed at `node` through its `bitcoind` file
+fn dir(base: &TempDir, name: &str, node: &TestNode) -> String {
+    let dir = Path::new(base.path()).join(name);
+    fs::create_dir_all(&dir).unwrap();
+    fs::write(
+        dir.join(BITCOIND_FILE),
+        format!("rpc-url={}\n", node.rpc_url()),
+    )
+    .unwrap();
+    dir.to_str().unwrap().to_owned()
+}
+
+#[test]
+#[ignore = "needs bitcoind"]
+fn tower_sweeps_revoked_commitment() {
+    let node = TestNode::new();
+    let base = TempDir::new();
+    let tower_dir = dir(&base, "tower", &node);
+    let alice_dir = dir(&base, "alice", &node);
+    let bob_dir = dir(&base, "bob", &node);
+    let wallet = node.wallet(DEFAULT_WALLET_NAME);
+
+    // The tower picks up the seed this writes
+    let tower_secret = get_keys_manager(&tower_dir)
+        .get_node_secret(Recipient::Node)
+        .unwrap();
+    let tower_pubkey = PublicKey::from_secret_key(&Secp256k1::new(), &tower_secret);
+    let tower_addr = format!("127.0.0.1:{}", free_port());
+    fs::write(
+        Path::new(&alice_dir).join(TOWERS_FILE),
+        format!("{}@{}\n", tower_pubkey, tower_addr),
+    )
+    .unwrap();
+
+    // The tower runs for the rest of the test, the nodes each in a runtime
+    // of their own so dropping it stops the node
+    let (dir, addr) = (tower_dir.clone(), tower_addr.clone());
+    thread::spawn(move || Runtime::new().unwrap().block_on(start_tower(&dir, &addr)));
+    let alice_rt = Runtime::new().unwrap();
+    let alice = alice_rt.block_on(start_node(&alice_dir, free_port()));
+    let bob_rt = Runtime::new().unwrap();
+    let bob_port = free_port();
+    let bob = bob_rt.block_on(start_node(&bob_dir, bob_port));
+
+    alice_rt
+        .block_on(alice.connect_peer(bob.node_id(), &format!("127.0.0.1:{}", bob_port)))
+        .unwrap();
+    alice
+        .open_channel(bob.node_id(), CHANNEL_SAT, PUSH_MSAT, false)
+        .unwrap();
+    wait_for("the funding tx", || {
+        alice
+            .list_channels()
+            .iter()
+            .any(|c| c.funding_txo.is_some())
+            && !wallet.get_raw_mempool().unwrap().is_empty()
+    });
+    wallet.mine_blocks(6);
+    wait_for("the channel", || {
+        alice.list_channels().iter().any(|c| c.is_usable)
+            && bob.list_channels().iter().any(|c| c.is_usable)
+    });
+
+    // Bob's first state, with only the pushed amount on his side
+    let funding_txo = alice.list_channels()[0].funding_txo.unwrap();
+    let monitor_key = format!(
+        "{}/{}_{}",
+        MONITORS_PREFIX, funding_txo.txid, funding_txo.index
+    );
+    let bob_store = FilesystemStore::new(&bob_dir);
+    let old_monitor = bob_store.read(&monitor_key).unwrap().unwrap();
+
+    // Revokes it, and the one with the payment in flight
+    let invoice = bob
+        .create_invoice(Some(PAYMENT_MSAT), "revoke".to_owned(), 3600)
+        .unwrap();
+    alice.pay_invoice(&invoice, None).unwrap();
+    let tower_store = FilesystemStore::new(&tower_dir);
+    wait_for("the tower to hold both justice txs", || {
+        tower_store.list(JUSTICE_PREFIX).unwrap().len() >= 2
+    });
+
+    drop(alice);
+    drop(alice_rt);
+    drop(bob);
+    drop(bob_rt);
+
+    // Bob cheats
+    let bob_keys = get_keys_manager(&bob_dir);
+    let (_, monitor) = <(BlockHash, ChannelMonitor<InMemorySigner>)>::read(
+        &mut Cursor::new(&old_monitor),
+        &bob_keys,
+    )
+    .unwrap();
+    let revoked_tx = monitor.get_latest_holder_commitment_txn(&Arc::new(RLNLogger))[0].clone();
+    let revoked_txid = wallet.send_raw_transaction(&revoked_tx).unwrap();
+    wallet.mine_blocks(1);
+
+    // The tower answers with a sweep to Alice
+    let mut justice_tx: Option<Transaction> = None;
+    wait_for("the justice tx", || {
+        justice_tx = wallet
+            .get_raw_mempool()
+            .unwrap()
+            .iter()
+            .map(|txid| wallet.get_raw_transaction(txid, None).unwrap())
+            .find(|tx| {
+                tx.input
+                    .iter()
+                    .any(|input| input.previous_output.txid == revoked_txid)
+            });
+        justice_tx.is_some()
+    });
+    let justice_tx = justice_tx.unwrap();
+    let alice_script = get_keys_manager(&alice_dir).get_destination_script();
+    assert_eq!(justice_tx.output.len(), 1);
+    assert_eq!(justice_tx.output[0].script_pubkey, alice_script);
+    // Bob's side of that state was the pushed amount, less the fee
+    let bob_sat = PUSH_MSAT / 1000;
+    assert!(justice_tx.output[0].value < bob_sat);
+    assert!(justice_tx.output[0].value > bob_sat - 10_000);
+
+    let block_hash = wallet.mine_blocks(1)[0];
+    let block = wallet.get_block(&block_hash).unwrap();
+    assert!(block.txdata.iter().any(|tx| tx.txid() == justice_tx.txid()));
+}
@@ -0,0 +1,8 @@
+fn main() {
+    // Use a bundled protoc so building doesn't depend on the system one
+    std::env::set_var(
+        "PROTOC",
+        protoc_bin_vendored::protoc_bin_path().expect("No bundled protoc for this platform"),
+    );
+    tonic_build::compile_protos("proto/rlnrpc.proto").expect("Failed to compile rlnrpc.proto");
+}
@@ -0,0 +1,109 @@
+use std::future::Future;
+use std::path::Path;
+use std::time::Duration;
+
+use tokio::runtime::{Builder, Runtime};
+use tonic::{Status, Streaming};
+
+use crate::client::{wait_for_event, Error, RlnClient};
+use crate::proto::node_event::Event;
+use crate::proto::*;
+
+// `RlnClient` for synchronous programs such as the workshop tests. Each
+// client drives its calls on its own single threaded runtime. Errors are
+// boxed, `Status` is too big to pass around by value.
+pub struct BlockingRlnClient {
+    runtime: Runtime,
+    client: RlnClient,
+}
+
+impl BlockingRlnClient {
+    pub fn connect(endpoint: &str, token: &str) -> Result<Self, Error> {
+        let runtime = Builder::new_current_thread().enable_all().build()?;
+        let client = runtime.block_on(RlnClient::connect(endpoint, token))?;
+        Ok(Self { runtime, client })
+    }
+
+    pub fn connect_with_token_file(endpoint: &str, token_file: &Path) -> Result<Self, Error> {
+        let runtime = Builder::new_current_thread().enable_all().build()?;
+        let client = runtime.block_on(RlnClient::connect_with_token_file(endpoint, token_file))?;
+        Ok(Self { runtime, client })
+    }
+
+    pub fn get_info(&mut self) -> Result<GetInfoResponse, Box<Status>> {
+        block_on(&self.runtime, self.client.get_info())
+    }
+
+    pub fn list_peers(&mut self) -> Result<Vec<String>, Box<Status>> {
+        block_on(&self.runtime, self.client.list_peers())
+    }
+
+    pub fn connect_peer(&mut self, pubkey: &str, host: &str) -> Result<(), Box<Status>> {
+        block_on(&self.runtime, self.client.connect_peer(pubkey, host))
+    }
+
+    pub fn list_channels(&mut self) -> Result<Vec<Channel>, Box<Status>> {
+        block_on(&self.runtime, self.client.list_channels())
+    }
+
+    pub fn open_channel(
+        &mut self,
+        node_pubkey: &str,
+        amount_sat: u64,
+        push_msat: u64,
+        public: bool,
+    ) -> Result<String, Box<Status>> {
+        block_on(
+            &self.runtime,
+            self.client
+                .open_channel(node_pubkey, amount_sat, push_msat, public),
+        )
+    }
+
+    pub fn add_invoice(
+        &mut self,
+        value_msat: u64,
+        memo: &str,
+    ) -> Result<AddInvoiceResponse, Box<Status>> {
+        block_on(&self.runtime, self.client.add_invoice(value_msat, memo))
+    }
+
+    pub fn pay_invoice(&mut self, payment_request: &str) -> Result<String, Box<Status>> {
+        block_on(&self.runtime, self.client.pay_invoice(payment_request))
+    }
+
+    pub fn keysend(&mut self, dest: &str, amt_msat: u64) -> Result<String, Box<Status>> {
+        block_on(&self.runtime, self.client.keysend(dest, amt_msat))
+    }
+
+    pub fn list_payments(&mut self) -> Result<Vec<Payment>, Box<Status>> {
+        block_on(&self.runtime, self.client.list_payments())
+    }
+
+    pub fn wallet_balance(&mut self) -> Result<WalletBalanceResponse, Box<Status>> {
+        block_on(&self.runtime, self.client.wallet_balance())
+    }
+
+    pub fn subscribe_events(&mut self) -> Result<Streaming<NodeEvent>, Box<Status>> {
+        block_on(&self.runtime, self.client.subscribe_events())
+    }
+
+    pub fn wait_for_event<F>(
+        &mut self,
+        events: &mut Streaming<NodeEvent>,
+        pred: F,
+        timeout: Duration,
+    ) -> Result<Event, Box<Status>>
+    where
+        F: FnMut(&Event) -> bool,
+    {
+        block_on(&self.runtime, wait_for_event(events, pred, timeout))
+    }
+}
+
+fn block_on<T>(
+    runtime: &Runtime,
+    call: impl Future<Output = Result<T, Status>>,
+) -> Result<T, Box<Status>> {
+    runtime.block_on(call).map_err(Box::new)
+}
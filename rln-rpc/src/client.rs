@@ -0,0 +1,202 @@
+use std::fs;
+use std::path::Path;
+use std::time::Duration;
+
+use tonic::metadata::{Ascii, MetadataValue};
+use tonic::service::interceptor::InterceptedService;
+use tonic::service::Interceptor;
+use tonic::transport::{Channel as Transport, Endpoint};
+use tonic::{Request, Status, Streaming};
+
+use crate::proto::lightning_client::LightningClient;
+use crate::proto::node_event::Event;
+use crate::proto::*;
+
+pub type Error = Box<dyn std::error::Error + Send + Sync>;
+
+// Adds `authorization: Bearer <token>` to every call
+#[derive(Clone)]
+pub struct BearerAuth {
+    header: MetadataValue<Ascii>,
+}
+
+impl Interceptor for BearerAuth {
+    fn call(&mut self, mut req: Request<()>) -> Result<Request<()>, Status> {
+        req.metadata_mut()
+            .insert("authorization", self.header.clone());
+        Ok(req)
+    }
+}
+
+#[derive(Clone)]
+pub struct RlnClient {
+    inner: LightningClient<InterceptedService<Transport, BearerAuth>>,
+}
+
+impl RlnClient {
+    // `endpoint` is the node's gRPC address, e.g. `http://127.0.0.1:10009`
+    pub async fn connect(endpoint: &str, token: &str) -> Result<Self, Error> {
+        let channel = Endpoint::from_shared(endpoint.to_owned())?
+            .connect()
+            .await?;
+        let header = format!("Bearer {}", token).parse()?;
+        Ok(Self {
+            inner: LightningClient::with_interceptor(channel, BearerAuth { header }),
+        })
+    }
+
+    // Same as `connect`, reading the token from the node's token file
+    // (`api_token` in its `ln_dir`)
+    pub async fn connect_with_token_file(endpoint: &str, token_file: &Path) -> Result<Self, Error> {
+        let token = fs::read_to_string(token_file)?;
+        Self::connect(endpoint, token.trim()).await
+    }
+
+    pub async fn get_info(&mut self) -> Result<GetInfoResponse, Status> {
+        Ok(self.inner.get_info(GetInfoRequest {}).await?.into_inner())
+    }
+
+    pub async fn list_peers(&mut self) -> Result<Vec<String>, Status> {
+        Ok(self
+            .inner
+            .list_peers(ListPeersRequest {})
+            .await?
+            .into_inner()
+            .pub_keys)
+    }
+
+    pub async fn connect_peer(&mut self, pubkey: &str, host: &str) -> Result<(), Status> {
+        self.inner
+            .connect_peer(ConnectPeerRequest {
+                pubkey: pubkey.to_owned(),
+                host: host.to_owned(),
+            })
+            .await?;
+        Ok(())
+    }
+
+    pub async fn list_channels(&mut self) -> Result<Vec<Channel>, Status> {
+        Ok(self
+            .inner
+            .list_channels(ListChannelsRequest {})
+            .await?
+            .into_inner()
+            .channels)
+    }
+
+    // Returns the temporary channel id
+    pub async fn open_channel(
+        &mut self,
+        node_pubkey: &str,
+        amount_sat: u64,
+        push_msat: u64,
+        public: bool,
+    ) -> Result<String, Status> {
+        Ok(self
+            .inner
+            .open_channel(OpenChannelRequest {
+                node_pubkey: node_pubkey.to_owned(),
+                local_funding_amount: amount_sat,
+                push_msat,
+                public,
+            })
+            .await?
+            .into_inner()
+            .temporary_channel_id)
+    }
+
+    pub async fn add_invoice(
+        &mut self,
+        value_msat: u64,
+        memo: &str,
+    ) -> Result<AddInvoiceResponse, Status> {
+        Ok(self
+            .inner
+            .add_invoice(AddInvoiceRequest {
+                value_msat,
+                memo: memo.to_owned(),
+                expiry: 3600,
+            })
+            .await?
+            .into_inner())
+    }
+
+    // Starts paying a BOLT11 invoice and returns its payment hash. The result
+    // arrives as a `PaymentSent` or `PaymentFailed` event.
+    pub async fn pay_invoice(&mut self, payment_request: &str) -> Result<String, Status> {
+        self.send_payment(SendPaymentRequest {
+            payment_request: payment_request.to_owned(),
+            ..Default::default()
+        })
+        .await
+    }
+
+    pub async fn keysend(&mut self, dest: &str, amt_msat: u64) -> Result<String, Status> {
+        self.send_payment(SendPaymentRequest {
+            dest: dest.to_owned(),
+            amt_msat,
+            ..Default::default()
+        })
+        .await
+    }
+
+    async fn send_payment(&mut self, req: SendPaymentRequest) -> Result<String, Status> {
+        Ok(self
+            .inner
+            .send_payment(req)
+            .await?
+            .into_inner()
+            .payment_hash)
+    }
+
+    pub async fn list_payments(&mut self) -> Result<Vec<Payment>, Status> {
+        Ok(self
+            .inner
+            .list_payments(ListPaymentsRequest {})
+            .await?
+            .into_inner()
+            .payments)
+    }
+
+    pub async fn wallet_balance(&mut self) -> Result<WalletBalanceResponse, Status> {
+        Ok(self
+            .inner
+            .wallet_balance(WalletBalanceRequest {})
+            .await?
+            .into_inner())
+    }
+
+    // Subscribe before triggering whatever you want to wait on, events that
+    // happened earlier are not replayed
+    pub async fn subscribe_events(&mut self) -> Result<Streaming<NodeEvent>, Status> {
+        Ok(self
+            .inner
+            .subscribe_events(SubscribeEventsRequest {})
+            .await?
+            .into_inner())
+    }
+}
+
+// Waits for the first event on `events` matching `pred`
+pub async fn wait_for_event<F>(
+    events: &mut Streaming<NodeEvent>,
+    mut pred: F,
+    timeout: Duration,
+) -> Result<Event, Status>
+where
+    F: FnMut(&Event) -> bool,
+{
+    let wait = async {
+        while let Some(event) = events.message().await? {
+            if let Some(event) = event.event {
+                if pred(&event) {
+                    return Ok(event);
+                }
+            }
+        }
+        Err(Status::unavailable("Event stream closed"))
+    };
+    tokio::time::timeout(timeout, wait)
+        .await
+        .map_err(|_| Status::deadline_exceeded("Timed out waiting for event"))?
+}
@@ -0,0 +1,10 @@
+pub mod proto {
+    tonic::include_proto!("rlnrpc");
+}
+
+pub mod blocking;
+pub mod client;
+
+pub use blocking::BlockingRlnClient;
+pub use client::RlnClient;
+pub use tonic::{Status, Streaming};